@@ -20,22 +20,27 @@ pub fn delta_decode(
     base_info: &[u8],
 ) -> Result<Vec<u8>, GitDeltaError> {
     // Read declared base size and result size
-    let base_size = utils::read_size_encoding(&mut stream).unwrap();
+    let base_size = utils::read_size_encoding(&mut stream)
+        .map_err(|e| GitDeltaError::DeltaDecoderError(format!("Invalid base size: {e}")))?;
     if base_info.len() != base_size {
         return Err(GitDeltaError::DeltaDecoderError(
             "base object len is not equal".to_owned(),
         ));
     }
 
-    let result_size = utils::read_size_encoding(&mut stream).unwrap();
-    let mut buffer = Vec::with_capacity(result_size);
+    let result_size = utils::read_size_encoding(&mut stream)
+        .map_err(|e| GitDeltaError::DeltaDecoderError(format!("Invalid result size: {e}")))?;
+    // The declared size is untrusted, so only use it as a bounded capacity hint.
+    let mut buffer = Vec::with_capacity(result_size.min(base_info.len().saturating_mul(2) + 4096));
     loop {
         // Check if the stream has ended, meaning the new object is done
         let instruction = match utils::read_bytes(stream) {
             Ok([instruction]) => instruction,
             Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
             Err(err) => {
-                panic!("{}", format!("Wrong instruction in delta :{err}"));
+                return Err(GitDeltaError::DeltaDecoderError(format!(
+                    "Wrong instruction in delta :{err}"
+                )));
             }
         };
 
@@ -43,15 +48,16 @@ pub fn delta_decode(
             // Data instruction; the instruction byte specifies the number of data bytes
             if instruction == 0 {
                 // Appending 0 bytes doesn't make sense, so git disallows it
-                panic!(
-                    "{}",
-                    GitDeltaError::DeltaDecoderError(String::from("Invalid data instruction"))
-                );
+                return Err(GitDeltaError::DeltaDecoderError(String::from(
+                    "Invalid data instruction",
+                )));
             }
 
             // Append the provided bytes
             let mut data = vec![0; instruction as usize];
-            stream.read_exact(&mut data).unwrap();
+            stream.read_exact(&mut data).map_err(|e| {
+                GitDeltaError::DeltaDecoderError(format!("Truncated data instruction: {e}"))
+            })?;
             buffer.extend_from_slice(&data);
         // result.extend_from_slice(&data);
         } else {
@@ -59,17 +65,23 @@ pub fn delta_decode(
             let mut nonzero_bytes = instruction;
             let offset =
                 utils::read_partial_int(&mut stream, COPY_OFFSET_BYTES, &mut nonzero_bytes)
-                    .unwrap();
+                    .map_err(|e| {
+                        GitDeltaError::DeltaDecoderError(format!("Truncated copy offset: {e}"))
+                    })?;
             let mut size =
-                utils::read_partial_int(&mut stream, COPY_SIZE_BYTES, &mut nonzero_bytes).unwrap();
+                utils::read_partial_int(&mut stream, COPY_SIZE_BYTES, &mut nonzero_bytes).map_err(
+                    |e| GitDeltaError::DeltaDecoderError(format!("Truncated copy size: {e}")),
+                )?;
             if size == 0 {
                 // Copying 0 bytes doesn't make sense, so git assumes a different size
                 size = COPY_ZERO_SIZE;
             }
             // Copy bytes from the base object
-            let base_data = base_info.get(offset..(offset + size)).ok_or_else(|| {
-                GitDeltaError::DeltaDecoderError("Invalid copy instruction".to_string())
-            });
+            let base_data = base_info
+                .get(offset..offset.saturating_add(size))
+                .ok_or_else(|| {
+                    GitDeltaError::DeltaDecoderError("Invalid copy instruction".to_string())
+                });
 
             match base_data {
                 Ok(data) => buffer.extend_from_slice(data),
@@ -77,7 +89,12 @@ pub fn delta_decode(
             }
         }
     }
-    assert!(buffer.len() == result_size);
+    if buffer.len() != result_size {
+        return Err(GitDeltaError::DeltaDecoderError(format!(
+            "Result size mismatch: expected {result_size}, got {}",
+            buffer.len()
+        )));
+    }
     Ok(buffer)
 }
 
@@ -112,4 +129,16 @@ mod tests {
         let err = delta_decode(&mut cursor, b"xx").unwrap_err();
         assert!(matches!(err, GitDeltaError::DeltaDecoderError(_)));
     }
+
+    /// A truncated instruction stream should be reported instead of panicking.
+    #[test]
+    fn truncated_stream_returns_error() {
+        let old = b"hello world, hello world";
+        let new = b"hello rust, hello world!";
+        let mut delta = DeltaDiff::new(old, new).encode();
+        delta.truncate(delta.len() - 1);
+
+        let mut cursor = Cursor::new(delta);
+        assert!(delta_decode(&mut cursor, old).is_err());
+    }
 }
@@ -12,6 +12,8 @@
 
 use thiserror::Error;
 
+use crate::internal::pack::limits::DecodeLimit;
+
 #[derive(Error, Debug)]
 /// Unified error enumeration for the Git-Internal library.
 ///
@@ -106,10 +108,39 @@ pub enum GitError {
     #[error("The `{0}` is not a valid idx file.")]
     InvalidIdxFile(String),
 
+    /// Malformed or unsupported reverse index (.rev) file.
+    #[error("The `{0}` is not a valid rev file.")]
+    InvalidRevFile(String),
+
+    /// Malformed or unsupported multi-pack-index file.
+    #[error("The `{0}` is not a valid multi-pack-index file.")]
+    InvalidMultiPackIndex(String),
+
+    /// Malformed or unsupported reachability bitmap (.bitmap) file.
+    #[error("The `{0}` is not a valid bitmap file.")]
+    InvalidBitmapFile(String),
+
+    /// Malformed or unsupported commit-graph file.
+    #[error("The `{0}` is not a valid commit-graph file.")]
+    InvalidCommitGraph(String),
+
+    /// Malformed loose object file.
+    #[error("The `{0}` is not a valid loose object.")]
+    InvalidLooseObject(String),
+
     /// Malformed or unsupported pack file.
     #[error("The `{0}` is not a valid pack file.")]
     InvalidPackFile(String),
 
+    /// A pack exceeded a configured [`DecodeLimits`](crate::internal::pack::limits::DecodeLimits)
+    /// value.
+    #[error("Pack decode limit exceeded: {limit} {value} is over the limit of {max}")]
+    DecodeLimitExceeded {
+        limit: DecodeLimit,
+        value: u64,
+        max: u64,
+    },
+
     /// Invalid pack header magic or version.
     #[error("The `{0}` is not a valid pack header.")]
     InvalidPackHeader(String),
@@ -166,10 +197,23 @@ pub enum GitError {
     #[error("Can't encode entries to pack: {0}")]
     PackEncodeError(String),
 
+    /// The operation was stopped through its
+    /// [`CancellationToken`](crate::internal::pack::cancel::CancellationToken).
+    #[error("Operation cancelled")]
+    Cancelled,
+
     /// Object missing from caches or storage.
     #[error("Can't find specific object: {0}")]
     ObjectNotFound(String),
 
+    /// A reference update whose expected old value did not match.
+    #[error("Reference update rejected: {0}")]
+    RefUpdateRejected(String),
+
+    /// Failure reported by a database storage backend.
+    #[error("Database error: {0}")]
+    DatabaseError(String),
+
     /// Repository not found.
     #[error("Repository not found")]
     RepoNotFound,
@@ -189,7 +189,12 @@ impl ObjectHash {
     }
     /// Create `ObjectHash` from raw bytes matching the current hash size.
     pub fn from_bytes(bytes: &[u8]) -> Result<ObjectHash, String> {
-        let expected_len = get_hash_kind().size();
+        Self::from_bytes_with_kind(bytes, get_hash_kind())
+    }
+    /// Create `ObjectHash` from raw bytes of an explicit hash kind, independent of the
+    /// thread-local setting. Useful for readers that remember the format of the file they opened.
+    pub fn from_bytes_with_kind(bytes: &[u8], kind: HashKind) -> Result<ObjectHash, String> {
+        let expected_len = kind.size();
         if bytes.len() != expected_len {
             return Err(format!(
                 "Invalid byte length: got {}, expected {}",
@@ -198,7 +203,7 @@ impl ObjectHash {
             ));
         }
 
-        match get_hash_kind() {
+        match kind {
             HashKind::Sha1 => {
                 let mut h = [0u8; 20];
                 h.copy_from_slice(bytes);
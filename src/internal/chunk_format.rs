@@ -0,0 +1,106 @@
+//! Git's [chunk-based file format](https://git-scm.com/docs/gitformat-chunk), shared by
+//! multi-pack-index and commit-graph files: a fixed header, a table of (4-byte id, 8-byte offset)
+//! entries closed by a zero id whose offset marks the end of the last chunk, the chunk bodies, and
+//! a trailing checksum of everything before it.
+//!
+//! Errors are returned as plain messages so each file format can wrap them in its own
+//! [`GitError`](crate::errors::GitError) variant.
+
+use std::ops::Range;
+
+use crate::{hash::HashKind, utils::HashAlgorithm};
+
+/// Size of one chunk table entry: 4-byte id plus 8-byte offset.
+pub(crate) const CHUNK_LOOKUP_WIDTH: usize = 12;
+
+/// Append the chunk table and `chunks` to `header`, then the checksum of the whole file.
+pub(crate) fn write_chunk_file(
+    header: Vec<u8>,
+    chunks: &[([u8; 4], Vec<u8>)],
+    kind: HashKind,
+) -> Vec<u8> {
+    let mut out = header;
+    let mut offset = (out.len() + (chunks.len() + 1) * CHUNK_LOOKUP_WIDTH) as u64;
+    for (id, data) in chunks {
+        out.extend_from_slice(id);
+        out.extend_from_slice(&offset.to_be_bytes());
+        offset += data.len() as u64;
+    }
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&offset.to_be_bytes());
+    for (_, data) in chunks {
+        out.extend_from_slice(data);
+    }
+    let mut hasher = HashAlgorithm::new_with_kind(kind);
+    hasher.update(&out);
+    out.extend(hasher.finalize());
+    out
+}
+
+/// Whether the trailing checksum of `data` matches its contents.
+pub(crate) fn checksum_matches(data: &[u8], kind: HashKind) -> bool {
+    let Some(start) = data.len().checked_sub(kind.size()) else {
+        return false;
+    };
+    let mut hasher = HashAlgorithm::new_with_kind(kind);
+    hasher.update(&data[..start]);
+    hasher.finalize() == data[start..]
+}
+
+/// Chunk ids and the byte ranges they occupy.
+pub(crate) struct ChunkTable {
+    chunks: Vec<([u8; 4], Range<usize>)>,
+}
+
+impl ChunkTable {
+    /// Read `count` entries starting at `table_start`; every chunk must lie between the end of
+    /// the table and the trailing checksum.
+    pub(crate) fn parse(
+        data: &[u8],
+        table_start: usize,
+        count: usize,
+        kind: HashKind,
+    ) -> Result<Self, String> {
+        let table_end = table_start + (count + 1) * CHUNK_LOOKUP_WIDTH;
+        let Some(checksum_start) = data
+            .len()
+            .checked_sub(kind.size())
+            .filter(|&start| start >= table_end)
+        else {
+            return Err(format!(
+                "file is {} bytes, too short for {count} chunks",
+                data.len()
+            ));
+        };
+        let read_u64 = |at: usize| u64::from_be_bytes(data[at..at + 8].try_into().unwrap());
+        let mut chunks = Vec::with_capacity(count);
+        for i in 0..count {
+            let at = table_start + i * CHUNK_LOOKUP_WIDTH;
+            let id: [u8; 4] = data[at..at + 4].try_into().unwrap();
+            let start = read_u64(at + 4);
+            let end = read_u64(at + 4 + CHUNK_LOOKUP_WIDTH);
+            if start < table_end as u64 || start > end || end > checksum_start as u64 {
+                return Err(format!(
+                    "chunk {} has invalid bounds {start}..{end}",
+                    String::from_utf8_lossy(&id)
+                ));
+            }
+            chunks.push((id, start as usize..end as usize));
+        }
+        Ok(ChunkTable { chunks })
+    }
+
+    /// Byte range of chunk `id`, if present.
+    pub(crate) fn get(&self, id: [u8; 4]) -> Option<Range<usize>> {
+        self.chunks
+            .iter()
+            .find(|(c, _)| *c == id)
+            .map(|(_, range)| range.clone())
+    }
+
+    /// Byte range of chunk `id`, which the format requires.
+    pub(crate) fn require(&self, id: [u8; 4]) -> Result<Range<usize>, String> {
+        self.get(id)
+            .ok_or_else(|| format!("missing required {} chunk", String::from_utf8_lossy(&id)))
+    }
+}
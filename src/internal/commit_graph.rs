@@ -0,0 +1,869 @@
+//! Writer and reader for Git's
+//! [commit-graph](https://git-scm.com/docs/gitformat-commit-graph) file, which stores each
+//! commit's root tree, parents, commit time and generation numbers in a compact table so history
+//! walks and merge-base computations can run without inflating and parsing commit objects.
+//!
+//! Supported chunks are OIDF, OIDL, CDAT, GDA2, GDO2 and EDGE, for both SHA-1 and SHA-256. CDAT
+//! holds topological levels; GDA2/GDO2 hold corrected commit dates (generation number v2). The
+//! writer lays the file out exactly as `git commit-graph write` does, so its output passes
+//! `git commit-graph verify`. Split commit-graph chains are not supported.
+
+use std::{
+    cmp::Ordering,
+    collections::{HashSet, VecDeque},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use tempfile::NamedTempFile;
+
+use crate::{
+    errors::GitError,
+    hash::{HashKind, ObjectHash, get_hash_kind},
+    internal::{
+        chunk_format::{ChunkTable, checksum_matches, write_chunk_file},
+        object::commit::Commit,
+        pack::{mmap::MappedFile, pack_index::read_u32},
+    },
+};
+
+/// File name Git expects inside `objects/info/`.
+pub const COMMIT_GRAPH_FILE_NAME: &str = "commit-graph";
+
+const GRAPH_SIGNATURE: [u8; 4] = *b"CGPH";
+const GRAPH_VERSION: u8 = 1;
+const GRAPH_HEADER_SIZE: usize = 8;
+const GRAPH_FANOUT_SIZE: usize = 256 * 4;
+/// Parent slot value for "no parent".
+const GRAPH_PARENT_NONE: u32 = 0x7000_0000;
+/// MSB of the second parent slot marks an index into the EDGE chunk (octopus merges).
+const GRAPH_EXTRA_EDGES_NEEDED: u32 = 0x8000_0000;
+/// MSB of an EDGE entry marks the last parent of a commit.
+const GRAPH_LAST_EDGE: u32 = 0x8000_0000;
+/// Largest topological level CDAT can hold; deeper commits are clamped to it.
+const GENERATION_NUMBER_V1_MAX: u32 = 0x3FFF_FFFF;
+/// Largest corrected date offset GDA2 holds directly; larger ones move to GDO2.
+const GENERATION_NUMBER_V2_OFFSET_MAX: u64 = 0x7FFF_FFFF;
+/// MSB of a GDA2 entry marks an index into the GDO2 chunk.
+const CORRECTED_COMMIT_DATE_OFFSET_OVERFLOW: u32 = 0x8000_0000;
+
+const CHUNK_OID_FANOUT: [u8; 4] = *b"OIDF";
+const CHUNK_OID_LOOKUP: [u8; 4] = *b"OIDL";
+const CHUNK_COMMIT_DATA: [u8; 4] = *b"CDAT";
+const CHUNK_GENERATION_DATA: [u8; 4] = *b"GDA2";
+const CHUNK_GENERATION_DATA_OVERFLOW: [u8; 4] = *b"GDO2";
+const CHUNK_EXTRA_EDGES: [u8; 4] = *b"EDGE";
+
+/// OID version byte stored in the commit-graph header.
+fn oid_version(kind: HashKind) -> u8 {
+    match kind {
+        HashKind::Sha1 => 1,
+        HashKind::Sha256 => 2,
+    }
+}
+
+struct PendingCommit {
+    id: ObjectHash,
+    tree: ObjectHash,
+    parents: Vec<ObjectHash>,
+    commit_time: u64,
+}
+
+/// Builds a commit-graph from a set of commits.
+///
+/// The set must be closed under parents: every parent of an added commit has to be added too,
+/// as `git commit-graph write --reachable` guarantees.
+#[derive(Default)]
+pub struct CommitGraphBuilder {
+    commits: Vec<PendingCommit>,
+}
+
+impl CommitGraphBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a parsed commit, using its committer time as the commit date.
+    pub fn add_commit(&mut self, commit: &Commit) -> &mut Self {
+        self.add(
+            commit.id,
+            commit.tree_id,
+            commit.parent_commit_ids.clone(),
+            commit.committer.timestamp as u64,
+        )
+    }
+
+    /// Add a commit by its id, root tree, parents (in order) and committer time.
+    pub fn add(
+        &mut self,
+        id: ObjectHash,
+        tree: ObjectHash,
+        parents: Vec<ObjectHash>,
+        commit_time: u64,
+    ) -> &mut Self {
+        self.commits.push(PendingCommit {
+            id,
+            tree,
+            parents,
+            commit_time,
+        });
+        self
+    }
+
+    /// Serialize the commit-graph, including its trailing checksum.
+    pub fn build(&self) -> Result<Vec<u8>, GitError> {
+        let invalid = |msg: String| GitError::InvalidCommitGraph(msg);
+        let kind = self
+            .commits
+            .first()
+            .map(|c| c.id.kind())
+            .unwrap_or_else(get_hash_kind);
+        let mut commits: Vec<&PendingCommit> = self.commits.iter().collect();
+        commits.sort_by_key(|c| c.id);
+        commits.dedup_by_key(|c| c.id);
+        if commits.len() >= GRAPH_PARENT_NONE as usize {
+            return Err(invalid("too many commits".to_string()));
+        }
+
+        let position = |hash: &ObjectHash| commits.binary_search_by_key(hash, |c| c.id).ok();
+        let mut parents = Vec::with_capacity(commits.len());
+        for c in &commits {
+            if c.id.kind() != kind || c.tree.kind() != kind {
+                return Err(invalid(format!("commit {} does not use {kind}", c.id)));
+            }
+            let positions = c
+                .parents
+                .iter()
+                .map(|p| {
+                    position(p).ok_or_else(|| {
+                        invalid(format!("parent {p} of commit {} is not in the graph", c.id))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            parents.push(positions);
+        }
+        let times: Vec<u64> = commits.iter().map(|c| c.commit_time).collect();
+        let (levels, corrected) = compute_generations(&parents, &times)?;
+
+        let mut chunks: Vec<([u8; 4], Vec<u8>)> = Vec::new();
+
+        let mut fanout = [0u32; 256];
+        for c in &commits {
+            fanout[c.id.as_ref()[0] as usize] += 1;
+        }
+        for i in 1..fanout.len() {
+            fanout[i] += fanout[i - 1];
+        }
+        chunks.push((
+            CHUNK_OID_FANOUT,
+            fanout.iter().flat_map(|c| c.to_be_bytes()).collect(),
+        ));
+        chunks.push((
+            CHUNK_OID_LOOKUP,
+            commits.iter().flat_map(|c| c.id.to_data()).collect(),
+        ));
+
+        let mut data = Vec::with_capacity(commits.len() * (kind.size() + 16));
+        let mut edges = Vec::new();
+        for (pos, c) in commits.iter().enumerate() {
+            data.extend_from_slice(c.tree.as_ref());
+            let parents = &parents[pos];
+            let first = parents.first().map_or(GRAPH_PARENT_NONE, |&p| p as u32);
+            let second = match parents.len() {
+                0 | 1 => GRAPH_PARENT_NONE,
+                2 => parents[1] as u32,
+                _ => GRAPH_EXTRA_EDGES_NEEDED | (edges.len() / 4) as u32,
+            };
+            if parents.len() > 2 {
+                for (i, &p) in parents.iter().enumerate().skip(1) {
+                    let last = if i + 1 == parents.len() {
+                        GRAPH_LAST_EDGE
+                    } else {
+                        0
+                    };
+                    edges.extend_from_slice(&(p as u32 | last).to_be_bytes());
+                }
+            }
+            data.extend_from_slice(&first.to_be_bytes());
+            data.extend_from_slice(&second.to_be_bytes());
+            let time = c.commit_time;
+            let packed = (levels[pos] << 2) | ((time >> 32) & 0x3) as u32;
+            data.extend_from_slice(&packed.to_be_bytes());
+            data.extend_from_slice(&(time as u32).to_be_bytes());
+        }
+        chunks.push((CHUNK_COMMIT_DATA, data));
+
+        let mut generation = Vec::with_capacity(commits.len() * 4);
+        let mut overflow = Vec::new();
+        for (pos, c) in commits.iter().enumerate() {
+            let offset = corrected[pos] - c.commit_time;
+            let value = if offset > GENERATION_NUMBER_V2_OFFSET_MAX {
+                let slot = (overflow.len() / 8) as u32;
+                overflow.extend_from_slice(&offset.to_be_bytes());
+                CORRECTED_COMMIT_DATE_OFFSET_OVERFLOW | slot
+            } else {
+                offset as u32
+            };
+            generation.extend_from_slice(&value.to_be_bytes());
+        }
+        chunks.push((CHUNK_GENERATION_DATA, generation));
+        if !overflow.is_empty() {
+            chunks.push((CHUNK_GENERATION_DATA_OVERFLOW, overflow));
+        }
+        if !edges.is_empty() {
+            chunks.push((CHUNK_EXTRA_EDGES, edges));
+        }
+
+        let mut header = GRAPH_SIGNATURE.to_vec();
+        header.push(GRAPH_VERSION);
+        header.push(oid_version(kind));
+        header.push(chunks.len() as u8);
+        header.push(0); // no base commit-graph files
+        Ok(write_chunk_file(header, &chunks, kind))
+    }
+
+    /// Write `commit-graph` into `info_dir` (normally `objects/info`) via a temporary file and
+    /// rename, returning its path.
+    pub fn write_to(&self, info_dir: impl AsRef<Path>) -> Result<PathBuf, GitError> {
+        let data = self.build()?;
+        let path = info_dir.as_ref().join(COMMIT_GRAPH_FILE_NAME);
+        let mut tmp = NamedTempFile::new_in(info_dir.as_ref())?;
+        tmp.write_all(&data)?;
+        tmp.persist(&path).map_err(|e| GitError::IOError(e.error))?;
+        Ok(path)
+    }
+}
+
+/// Topological levels and corrected commit dates, computed parents-first.
+///
+/// A commit's level is one more than its highest parent (roots are 1), clamped to what CDAT can
+/// hold; its corrected date is its commit time, raised to one past its parents' corrected dates
+/// where clocks were skewed.
+fn compute_generations(
+    parents: &[Vec<usize>],
+    times: &[u64],
+) -> Result<(Vec<u32>, Vec<u64>), GitError> {
+    let n = parents.len();
+    let mut levels = vec![0u32; n];
+    let mut corrected = vec![0u64; n];
+    let mut visiting = vec![false; n];
+    let mut stack = Vec::new();
+    for start in 0..n {
+        stack.push(start);
+        while let Some(&pos) = stack.last() {
+            if levels[pos] != 0 {
+                stack.pop();
+            } else if !visiting[pos] {
+                visiting[pos] = true;
+                for &p in &parents[pos] {
+                    if visiting[p] && levels[p] == 0 {
+                        return Err(GitError::InvalidCommitGraph(format!(
+                            "commit history has a cycle through position {p}"
+                        )));
+                    }
+                    if levels[p] == 0 {
+                        stack.push(p);
+                    }
+                }
+            } else {
+                let max_level = parents[pos].iter().map(|&p| levels[p]).max();
+                let max_corrected = parents[pos].iter().map(|&p| corrected[p]).max();
+                levels[pos] = (max_level.unwrap_or(0) + 1).min(GENERATION_NUMBER_V1_MAX);
+                corrected[pos] = times[pos].max(max_corrected.unwrap_or(0) + 1);
+                stack.pop();
+            }
+        }
+    }
+    Ok((levels, corrected))
+}
+
+/// Random-access reader for a commit-graph file.
+///
+/// Commits are addressed by graph position: their rank in sorted object id order. The file is
+/// memory-mapped and fully validated on open (header, chunk table, chunk sizes, object order,
+/// parent references, and trailer checksum), so the accessors only panic on out-of-range
+/// positions.
+pub struct CommitGraph {
+    data: MappedFile,
+    kind: HashKind,
+    commit_count: usize,
+    fanout_start: usize,
+    names_start: usize,
+    commit_data_start: usize,
+    generation_data_start: Option<usize>,
+    generation_overflow_start: Option<usize>,
+    extra_edges_start: Option<usize>,
+}
+
+impl CommitGraph {
+    /// Open and validate the commit-graph at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, GitError> {
+        let path = path.as_ref();
+        let data = MappedFile::open(path).map_err(|e| {
+            GitError::InvalidCommitGraph(format!("failed to read {}: {e}", path.display()))
+        })?;
+        Self::parse(data)
+    }
+
+    /// Parse and validate an in-memory commit-graph.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, GitError> {
+        Self::parse(data.into())
+    }
+
+    fn parse(data: MappedFile) -> Result<Self, GitError> {
+        let invalid = |msg: String| GitError::InvalidCommitGraph(msg);
+        if data.len() < GRAPH_HEADER_SIZE {
+            return Err(invalid(format!("file is only {} bytes", data.len())));
+        }
+        if data[..4] != GRAPH_SIGNATURE {
+            return Err(invalid("missing CGPH signature".to_string()));
+        }
+        if data[4] != GRAPH_VERSION {
+            return Err(invalid(format!("unsupported version {}", data[4])));
+        }
+        let kind = match data[5] {
+            1 => HashKind::Sha1,
+            2 => HashKind::Sha256,
+            other => return Err(invalid(format!("unknown hash version {other}"))),
+        };
+        if data[7] != 0 {
+            return Err(invalid(
+                "split commit-graph chains are not supported".to_string(),
+            ));
+        }
+        let chunks =
+            ChunkTable::parse(&data, GRAPH_HEADER_SIZE, data[6] as usize, kind).map_err(invalid)?;
+        if !checksum_matches(&data, kind) {
+            return Err(invalid(
+                "trailer checksum does not match file contents".to_string(),
+            ));
+        }
+        let required = |id: [u8; 4]| chunks.require(id).map_err(invalid);
+
+        let fanout = required(CHUNK_OID_FANOUT)?;
+        if fanout.len() != GRAPH_FANOUT_SIZE {
+            return Err(invalid(format!("OIDF chunk is {} bytes", fanout.len())));
+        }
+        let mut prev = 0u32;
+        for i in 0..256 {
+            let count = read_u32(&data, fanout.start + i * 4);
+            if count < prev {
+                return Err(invalid(format!(
+                    "fanout table is not monotonic at entry {i}"
+                )));
+            }
+            prev = count;
+        }
+        let commit_count = prev as usize;
+
+        let names = required(CHUNK_OID_LOOKUP)?;
+        if names.len() != commit_count * kind.size() {
+            return Err(invalid(format!(
+                "OIDL chunk is {} bytes for {commit_count} commits",
+                names.len()
+            )));
+        }
+        let commit_data = required(CHUNK_COMMIT_DATA)?;
+        if commit_data.len() != commit_count * (kind.size() + 16) {
+            return Err(invalid(format!(
+                "CDAT chunk is {} bytes for {commit_count} commits",
+                commit_data.len()
+            )));
+        }
+        let generation_data = chunks.get(CHUNK_GENERATION_DATA);
+        if let Some(gda) = &generation_data
+            && gda.len() != commit_count * 4
+        {
+            return Err(invalid(format!(
+                "GDA2 chunk is {} bytes for {commit_count} commits",
+                gda.len()
+            )));
+        }
+        let generation_overflow = chunks.get(CHUNK_GENERATION_DATA_OVERFLOW);
+        if let Some(gdo) = &generation_overflow
+            && !gdo.len().is_multiple_of(8)
+        {
+            return Err(invalid(format!("GDO2 chunk is {} bytes", gdo.len())));
+        }
+        let extra_edges = chunks.get(CHUNK_EXTRA_EDGES);
+        if let Some(edge) = &extra_edges
+            && !edge.len().is_multiple_of(4)
+        {
+            return Err(invalid(format!("EDGE chunk is {} bytes", edge.len())));
+        }
+
+        let graph = CommitGraph {
+            data,
+            kind,
+            commit_count,
+            fanout_start: fanout.start,
+            names_start: names.start,
+            commit_data_start: commit_data.start,
+            generation_data_start: generation_data.map(|r| r.start),
+            generation_overflow_start: generation_overflow.as_ref().map(|r| r.start),
+            extra_edges_start: extra_edges.as_ref().map(|r| r.start),
+        };
+        let edge_count = extra_edges.map_or(0, |r| r.len() / 4);
+        let overflow_count = generation_overflow.map_or(0, |r| r.len() / 8);
+        for pos in 0..commit_count {
+            if pos > 0 && graph.name_bytes(pos) <= graph.name_bytes(pos - 1) {
+                return Err(invalid(format!("commit ids out of order at {pos}")));
+            }
+            let (first, second) = graph.raw_parents(pos);
+            for parent in [first, second] {
+                if parent != GRAPH_PARENT_NONE
+                    && parent & GRAPH_EXTRA_EDGES_NEEDED == 0
+                    && parent as usize >= commit_count
+                {
+                    return Err(invalid(format!(
+                        "commit {pos} refers to missing parent {parent}"
+                    )));
+                }
+            }
+            if first == GRAPH_PARENT_NONE && second != GRAPH_PARENT_NONE {
+                return Err(invalid(format!(
+                    "commit {pos} has a second parent but no first"
+                )));
+            }
+            if second != GRAPH_PARENT_NONE && second & GRAPH_EXTRA_EDGES_NEEDED != 0 {
+                let mut edge = (second & !GRAPH_EXTRA_EDGES_NEEDED) as usize;
+                loop {
+                    if edge >= edge_count {
+                        return Err(invalid(format!("commit {pos} runs past the EDGE chunk")));
+                    }
+                    let value = graph.edge_at(edge);
+                    if (value & !GRAPH_LAST_EDGE) as usize >= commit_count {
+                        return Err(invalid(format!(
+                            "commit {pos} refers to missing parent {}",
+                            value & !GRAPH_LAST_EDGE
+                        )));
+                    }
+                    if value & GRAPH_LAST_EDGE != 0 {
+                        break;
+                    }
+                    edge += 1;
+                }
+            }
+            if let Some(raw) = graph.raw_generation(pos)
+                && raw & CORRECTED_COMMIT_DATE_OFFSET_OVERFLOW != 0
+                && (raw & !CORRECTED_COMMIT_DATE_OFFSET_OVERFLOW) as usize >= overflow_count
+            {
+                return Err(invalid(format!(
+                    "commit {pos} references missing generation overflow {}",
+                    raw & !CORRECTED_COMMIT_DATE_OFFSET_OVERFLOW
+                )));
+            }
+        }
+        Ok(graph)
+    }
+
+    /// Hash kind recorded in the header.
+    pub fn hash_kind(&self) -> HashKind {
+        self.kind
+    }
+
+    /// Number of commits in the graph.
+    pub fn commit_count(&self) -> usize {
+        self.commit_count
+    }
+
+    /// Checksum of the commit-graph file itself.
+    pub fn checksum(&self) -> ObjectHash {
+        self.hash_from(self.data.len() - self.kind.size())
+    }
+
+    /// Whether the file carries corrected commit dates (GDA2), which [`Self::generation`] then
+    /// reports instead of topological levels.
+    pub fn has_generation_data(&self) -> bool {
+        self.generation_data_start.is_some()
+    }
+
+    /// Graph position of commit `hash`, or `None` if the graph does not contain it.
+    pub fn find(&self, hash: &ObjectHash) -> Option<usize> {
+        if hash.kind() != self.kind {
+            return None;
+        }
+        let target = hash.as_ref();
+        let end = self.fanout(target[0]);
+        let start = if target[0] == 0 {
+            0
+        } else {
+            self.fanout(target[0] - 1)
+        };
+        let (mut lo, mut hi) = (start, end);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.name_bytes(mid).cmp(target) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    /// Whether the graph contains commit `hash`.
+    pub fn contains(&self, hash: &ObjectHash) -> bool {
+        self.find(hash).is_some()
+    }
+
+    /// Commit id at graph position `pos`.
+    ///
+    /// # Panics
+    /// Panics if `pos >= commit_count()`.
+    pub fn hash_at(&self, pos: usize) -> ObjectHash {
+        assert!(pos < self.commit_count, "graph position out of range");
+        self.hash_from(self.names_start + pos * self.kind.size())
+    }
+
+    /// Root tree of the commit at graph position `pos`.
+    ///
+    /// # Panics
+    /// Panics if `pos >= commit_count()`.
+    pub fn root_tree(&self, pos: usize) -> ObjectHash {
+        assert!(pos < self.commit_count, "graph position out of range");
+        self.hash_from(self.commit_data_offset(pos))
+    }
+
+    /// Graph positions of the parents of the commit at `pos`, in commit order.
+    ///
+    /// # Panics
+    /// Panics if `pos >= commit_count()`.
+    pub fn parents(&self, pos: usize) -> Vec<usize> {
+        assert!(pos < self.commit_count, "graph position out of range");
+        let (first, second) = self.raw_parents(pos);
+        let mut parents = Vec::new();
+        if first == GRAPH_PARENT_NONE {
+            return parents;
+        }
+        parents.push(first as usize);
+        if second == GRAPH_PARENT_NONE {
+            return parents;
+        }
+        if second & GRAPH_EXTRA_EDGES_NEEDED == 0 {
+            parents.push(second as usize);
+            return parents;
+        }
+        let mut edge = (second & !GRAPH_EXTRA_EDGES_NEEDED) as usize;
+        loop {
+            let value = self.edge_at(edge);
+            parents.push((value & !GRAPH_LAST_EDGE) as usize);
+            if value & GRAPH_LAST_EDGE != 0 {
+                return parents;
+            }
+            edge += 1;
+        }
+    }
+
+    /// Committer time (seconds since the epoch) of the commit at `pos`.
+    ///
+    /// # Panics
+    /// Panics if `pos >= commit_count()`.
+    pub fn commit_time(&self, pos: usize) -> u64 {
+        assert!(pos < self.commit_count, "graph position out of range");
+        let at = self.commit_data_offset(pos) + self.kind.size() + 8;
+        let high = (read_u32(&self.data, at) & 0x3) as u64;
+        (high << 32) | read_u32(&self.data, at + 4) as u64
+    }
+
+    /// Topological level (generation number v1) of the commit at `pos`: 1 for roots, otherwise
+    /// one more than the highest parent, clamped at `0x3FFFFFFF`.
+    ///
+    /// # Panics
+    /// Panics if `pos >= commit_count()`.
+    pub fn topo_level(&self, pos: usize) -> u32 {
+        assert!(pos < self.commit_count, "graph position out of range");
+        read_u32(
+            &self.data,
+            self.commit_data_offset(pos) + self.kind.size() + 8,
+        ) >> 2
+    }
+
+    /// Generation number of the commit at `pos`: its corrected commit date when the file has
+    /// GDA2 data, otherwise its topological level. Either way a commit's generation is strictly
+    /// greater than each of its parents'.
+    ///
+    /// # Panics
+    /// Panics if `pos >= commit_count()`.
+    pub fn generation(&self, pos: usize) -> u64 {
+        let Some(raw) = self.raw_generation(pos) else {
+            return self.topo_level(pos) as u64;
+        };
+        let offset = match self.generation_overflow_start {
+            Some(start) if raw & CORRECTED_COMMIT_DATE_OFFSET_OVERFLOW != 0 => {
+                let slot = (raw & !CORRECTED_COMMIT_DATE_OFFSET_OVERFLOW) as usize;
+                let at = start + slot * 8;
+                u64::from_be_bytes(self.data[at..at + 8].try_into().unwrap())
+            }
+            _ => raw as u64,
+        };
+        self.commit_time(pos) + offset
+    }
+
+    /// Whether the commit at `ancestor` is reachable from the commit at `descendant` (a commit is
+    /// its own ancestor).
+    ///
+    /// The walk never visits commits whose generation is below the ancestor's, so it stays short
+    /// when the two are close in history.
+    ///
+    /// # Panics
+    /// Panics if either position is out of range.
+    pub fn is_ancestor(&self, ancestor: usize, descendant: usize) -> bool {
+        let floor = self.generation(ancestor);
+        let mut seen = HashSet::from([descendant]);
+        let mut queue = VecDeque::from([descendant]);
+        while let Some(pos) = queue.pop_front() {
+            if pos == ancestor {
+                return true;
+            }
+            for parent in self.parents(pos) {
+                if self.generation(parent) >= floor && seen.insert(parent) {
+                    queue.push_back(parent);
+                }
+            }
+        }
+        false
+    }
+
+    fn fanout(&self, byte: u8) -> usize {
+        read_u32(&self.data, self.fanout_start + byte as usize * 4) as usize
+    }
+
+    fn name_bytes(&self, pos: usize) -> &[u8] {
+        let size = self.kind.size();
+        let start = self.names_start + pos * size;
+        &self.data[start..start + size]
+    }
+
+    fn commit_data_offset(&self, pos: usize) -> usize {
+        self.commit_data_start + pos * (self.kind.size() + 16)
+    }
+
+    fn raw_parents(&self, pos: usize) -> (u32, u32) {
+        let at = self.commit_data_offset(pos) + self.kind.size();
+        (read_u32(&self.data, at), read_u32(&self.data, at + 4))
+    }
+
+    fn raw_generation(&self, pos: usize) -> Option<u32> {
+        assert!(pos < self.commit_count, "graph position out of range");
+        self.generation_data_start
+            .map(|start| read_u32(&self.data, start + pos * 4))
+    }
+
+    fn edge_at(&self, edge: usize) -> u32 {
+        let start = self
+            .extra_edges_start
+            .expect("octopus parents are only read when EDGE is present");
+        read_u32(&self.data, start + edge * 4)
+    }
+
+    fn hash_from(&self, start: usize) -> ObjectHash {
+        ObjectHash::from_bytes_with_kind(&self.data[start..start + self.kind.size()], self.kind)
+            .expect("slice length matches hash kind")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path, process::Command};
+
+    use super::{COMMIT_GRAPH_FILE_NAME, CommitGraph, CommitGraphBuilder};
+    use crate::{
+        errors::GitError,
+        hash::{HashKind, ObjectHash, set_hash_kind_for_test},
+        internal::object::{ObjectTrait, commit::Commit},
+    };
+
+    /// A small history with a merge (5), an octopus merge (6), a commit dated before its parent
+    /// (3), and one dated past 2^32 seconds (7).
+    fn sample_builder(h: impl Fn(u8) -> ObjectHash) -> CommitGraphBuilder {
+        let mut builder = CommitGraphBuilder::new();
+        builder
+            .add(h(6), h(106), vec![h(5), h(4), h(7)], 600)
+            .add(h(1), h(101), vec![], 100)
+            .add(h(2), h(102), vec![h(1)], 200)
+            .add(h(3), h(103), vec![h(2)], 150) // clock skew: older than its parent
+            .add(h(4), h(104), vec![h(2)], 400)
+            .add(h(5), h(105), vec![h(3), h(4)], 500)
+            .add(h(7), h(107), vec![h(4)], 0x1_0000_0700);
+        builder
+    }
+
+    /// Parents (including octopus edges), trees, times and both generation numbers survive a
+    /// roundtrip for SHA-1 and SHA-256.
+    #[test]
+    fn test_commit_graph_roundtrip() {
+        for kind in [HashKind::Sha1, HashKind::Sha256] {
+            let h = |n: u8| match kind {
+                HashKind::Sha1 => ObjectHash::Sha1([n; 20]),
+                HashKind::Sha256 => ObjectHash::Sha256([n; 32]),
+            };
+            let data = sample_builder(h).build().unwrap();
+            assert!(data.windows(4).any(|w| w == b"EDGE"));
+            let graph = CommitGraph::from_bytes(data).unwrap();
+            assert_eq!(graph.hash_kind(), kind);
+            assert_eq!(graph.commit_count(), 7);
+            assert!(graph.has_generation_data());
+
+            let pos = |n: u8| graph.find(&h(n)).unwrap();
+            let hashes = |positions: Vec<usize>| -> Vec<ObjectHash> {
+                positions.into_iter().map(|p| graph.hash_at(p)).collect()
+            };
+            assert_eq!(hashes(graph.parents(pos(6))), vec![h(5), h(4), h(7)]);
+            assert_eq!(hashes(graph.parents(pos(5))), vec![h(3), h(4)]);
+            assert_eq!(hashes(graph.parents(pos(2))), vec![h(1)]);
+            assert!(graph.parents(pos(1)).is_empty());
+            assert_eq!(graph.root_tree(pos(4)), h(104));
+            assert_eq!(graph.commit_time(pos(7)), 0x1_0000_0700);
+
+            assert_eq!(graph.topo_level(pos(1)), 1);
+            assert_eq!(graph.topo_level(pos(5)), 4);
+            assert_eq!(graph.topo_level(pos(6)), 5);
+            // Commit 3 is dated before commit 2, so its corrected date is bumped past it.
+            assert_eq!(graph.generation(pos(3)), 201);
+            assert_eq!(graph.generation(pos(5)), 500);
+            assert_eq!(graph.generation(pos(6)), 0x1_0000_0701);
+            assert!(!graph.contains(&h(8)));
+        }
+    }
+
+    /// Ancestry answers follow the parent links, in both directions.
+    #[test]
+    fn test_commit_graph_is_ancestor() {
+        let h = |n: u8| ObjectHash::Sha1([n; 20]);
+        let graph = CommitGraph::from_bytes(sample_builder(h).build().unwrap()).unwrap();
+        let pos = |n: u8| graph.find(&h(n)).unwrap();
+        assert!(graph.is_ancestor(pos(1), pos(6)));
+        assert!(graph.is_ancestor(pos(7), pos(6)));
+        assert!(graph.is_ancestor(pos(3), pos(3)));
+        assert!(!graph.is_ancestor(pos(3), pos(4)));
+        assert!(!graph.is_ancestor(pos(6), pos(1)));
+        assert!(!graph.is_ancestor(pos(7), pos(5)));
+    }
+
+    /// Corrected date offsets that do not fit in 31 bits are stored in GDO2.
+    #[test]
+    fn test_commit_graph_generation_overflow() {
+        let h = |n: u8| ObjectHash::Sha1([n; 20]);
+        let mut builder = CommitGraphBuilder::new();
+        builder
+            .add(h(1), h(11), vec![], 0x2_0000_0000)
+            .add(h(2), h(12), vec![h(1)], 5);
+        let data = builder.build().unwrap();
+        assert!(data.windows(4).any(|w| w == b"GDO2"));
+        let graph = CommitGraph::from_bytes(data).unwrap();
+        assert_eq!(graph.generation(graph.find(&h(2)).unwrap()), 0x2_0000_0001);
+    }
+
+    /// Missing parents and corrupted files are reported as `InvalidCommitGraph`.
+    #[test]
+    fn test_commit_graph_rejects_invalid() {
+        let h = |n: u8| ObjectHash::Sha1([n; 20]);
+        let mut builder = CommitGraphBuilder::new();
+        builder.add(h(2), h(12), vec![h(1)], 5);
+        assert!(matches!(
+            builder.build(),
+            Err(GitError::InvalidCommitGraph(_))
+        ));
+
+        let mut data = sample_builder(h).build().unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        assert!(matches!(
+            CommitGraph::from_bytes(data),
+            Err(GitError::InvalidCommitGraph(_))
+        ));
+    }
+
+    fn git(dir: &Path, args: &[&str]) -> Option<std::process::Output> {
+        Command::new("git")
+            .current_dir(dir)
+            .args(args)
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("HOME", dir)
+            .output()
+            .ok()
+    }
+
+    /// A commit-graph written for a history made by Git, including a merge and a commit dated
+    /// before its parent, passes `git commit-graph verify`.
+    #[test]
+    fn test_commit_graph_git_verify() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path();
+        if git(repo, &["init", "-q", "-b", "main"]).is_none() {
+            return;
+        }
+        let run = |args: &[&str]| {
+            let output = git(repo, args).unwrap();
+            assert!(
+                output.status.success(),
+                "{}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            String::from_utf8(output.stdout).unwrap()
+        };
+        let commit = |n: usize, date: &str| {
+            fs::write(repo.join(format!("file{n}.txt")), format!("content {n}\n")).unwrap();
+            run(&["add", "."]);
+            let output = Command::new("git")
+                .current_dir(repo)
+                .args([
+                    "-c",
+                    "user.name=t",
+                    "-c",
+                    "user.email=t@t",
+                    "commit",
+                    "-qm",
+                    "c",
+                ])
+                .env("GIT_CONFIG_NOSYSTEM", "1")
+                .env("HOME", repo)
+                .env("GIT_COMMITTER_DATE", date)
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+        };
+        commit(1, "1700000000 +0000");
+        commit(2, "1700000100 +0000");
+        run(&["checkout", "-qb", "side", "HEAD~1"]);
+        commit(3, "1600000000 +0000"); // older than its parent
+        run(&["checkout", "-q", "main"]);
+        run(&[
+            "-c",
+            "user.name=t",
+            "-c",
+            "user.email=t@t",
+            "merge",
+            "-q",
+            "--no-edit",
+            "side",
+        ]);
+
+        let mut builder = CommitGraphBuilder::new();
+        let hashes = run(&["rev-list", "--all"]);
+        for hash in hashes.lines() {
+            let data = run(&["cat-file", "commit", hash]);
+            let commit = Commit::from_bytes(data.as_bytes(), hash.parse().unwrap()).unwrap();
+            builder.add_commit(&commit);
+        }
+        let info_dir = repo.join(".git/objects/info");
+        let path = builder.write_to(&info_dir).unwrap();
+        assert_eq!(CommitGraph::open(&path).unwrap().commit_count(), 4);
+
+        let verify = git(repo, &["commit-graph", "verify"]).unwrap();
+        assert!(
+            verify.status.success(),
+            "{}",
+            String::from_utf8_lossy(&verify.stderr)
+        );
+        // Only the commit-graph is left in the directory, not a temporary file.
+        let names: Vec<String> = fs::read_dir(&info_dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name != "exclude")
+            .collect();
+        assert_eq!(names, [COMMIT_GRAPH_FILE_NAME]);
+    }
+}
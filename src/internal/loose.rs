@@ -0,0 +1,422 @@
+//! Reader and writer for Git's loose objects, the `objects/xx/yyyy…` files that hold one
+//! zlib-compressed object each.
+//!
+//! A loose object file inflates to `<type> <size>\0<data>`, and the object id is the hash of exactly
+//! those bytes. Objects are written to a temporary file in the objects directory and renamed into
+//! place, so readers never observe a partial object. Reads validate the header and the content
+//! length and, for full reads, recompute the object id.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use flate2::{Compression, write::ZlibEncoder};
+use tempfile::NamedTempFile;
+
+use crate::{
+    errors::GitError,
+    hash::{HashKind, ObjectHash, get_hash_kind},
+    internal::{
+        object::{ObjectTrait, types::ObjectType},
+        pack::pack_index::HexPrefix,
+        zlib::stream::inflate::ReadBoxed,
+    },
+    utils::HashAlgorithm,
+};
+
+/// Longest header accepted: a type name, a space, a decimal `usize` and the NUL.
+const MAX_HEADER_LEN: usize = 64;
+
+/// Loose objects under one `objects` directory.
+///
+/// Object ids use the hash kind that was active when the store was created; see
+/// [`set_hash_kind`](crate::hash::set_hash_kind).
+#[derive(Debug, Clone)]
+pub struct LooseObjectStore {
+    objects_dir: PathBuf,
+    kind: HashKind,
+}
+
+impl LooseObjectStore {
+    /// Use `objects_dir` (normally `.git/objects`) with the current thread's hash kind. The
+    /// directory is created on the first write.
+    pub fn new(objects_dir: impl Into<PathBuf>) -> Self {
+        LooseObjectStore {
+            objects_dir: objects_dir.into(),
+            kind: get_hash_kind(),
+        }
+    }
+
+    /// The `objects` directory this store reads and writes.
+    pub fn objects_dir(&self) -> &Path {
+        &self.objects_dir
+    }
+
+    /// Hash kind of the store's object ids.
+    pub fn hash_kind(&self) -> HashKind {
+        self.kind
+    }
+
+    /// Path of the file holding `hash`: the first two hex digits name the directory, the rest the
+    /// file.
+    pub fn object_path(&self, hash: &ObjectHash) -> PathBuf {
+        let hex = hash.to_string();
+        self.objects_dir.join(&hex[..2]).join(&hex[2..])
+    }
+
+    /// Whether a loose object file for `hash` exists. Its content is not checked.
+    pub fn contains(&self, hash: &ObjectHash) -> bool {
+        hash.kind() == self.kind && self.object_path(hash).is_file()
+    }
+
+    /// Store `data` as an object of `obj_type` and return its id.
+    ///
+    /// Writing an object that already exists leaves the existing file untouched. Delta types have
+    /// no loose form and are rejected.
+    pub fn write(&self, obj_type: ObjectType, data: &[u8]) -> Result<ObjectHash, GitError> {
+        let type_name = obj_type.to_bytes().ok_or_else(|| {
+            GitError::InvalidObjectType(format!("{obj_type} cannot be stored as a loose object"))
+        })?;
+        let mut header = type_name.to_vec();
+        header.push(b' ');
+        header.extend(data.len().to_string().as_bytes());
+        header.push(0);
+
+        let mut hasher = HashAlgorithm::new_with_kind(self.kind);
+        hasher.update(&header);
+        hasher.update(data);
+        let hash = ObjectHash::from_bytes_with_kind(&hasher.finalize(), self.kind)
+            .map_err(GitError::InvalidHashValue)?;
+
+        let path = self.object_path(&hash);
+        if path.is_file() {
+            return Ok(hash);
+        }
+        let dir = path.parent().expect("object path has a fan-out directory");
+        fs::create_dir_all(dir)?;
+
+        let tmp = NamedTempFile::with_prefix_in("tmp_obj_", &self.objects_dir)?;
+        let mut encoder = ZlibEncoder::new(BufWriter::new(tmp), Compression::default());
+        encoder.write_all(&header)?;
+        encoder.write_all(data)?;
+        let tmp = encoder.finish()?.into_inner().map_err(|e| e.into_error())?;
+        tmp.as_file().sync_all()?;
+        // Like Git, objects are read-only once written.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            tmp.as_file()
+                .set_permissions(fs::Permissions::from_mode(0o444))?;
+        }
+
+        // Another writer may have stored the same object meanwhile; its content is identical.
+        match tmp.persist_noclobber(&path) {
+            Ok(_) => Ok(hash),
+            Err(e) if e.error.kind() == io::ErrorKind::AlreadyExists => Ok(hash),
+            Err(e) => Err(e.error.into()),
+        }
+    }
+
+    /// Store `object` and return its id.
+    pub fn write_object(&self, object: &impl ObjectTrait) -> Result<ObjectHash, GitError> {
+        self.write(object.get_type(), &object.to_data()?)
+    }
+
+    /// Type and size of `hash`, inflating only the header.
+    pub fn read_header(&self, hash: &ObjectHash) -> Result<(ObjectType, usize), GitError> {
+        let mut reader = self.open(hash)?;
+        read_header(&mut reader, hash)
+    }
+
+    /// Type and content of `hash`, after checking that the content hashes to `hash`.
+    pub fn read(&self, hash: &ObjectHash) -> Result<(ObjectType, Vec<u8>), GitError> {
+        let mut reader = self.open(hash)?;
+        let (obj_type, size) = read_header(&mut reader, hash)?;
+
+        // One byte past the declared size exposes trailing data.
+        let mut data = Vec::with_capacity(size.min(1 << 20));
+        (&mut reader)
+            .take(size as u64 + 1)
+            .read_to_end(&mut data)
+            .map_err(|e| invalid(hash, &format!("cannot inflate: {e}")))?;
+        if data.len() != size {
+            return Err(invalid(
+                hash,
+                &format!("header declares {size} bytes, found {}", data.len()),
+            ));
+        }
+
+        let actual = ObjectHash::from_bytes_with_kind(&reader.hash.finalize(), self.kind)
+            .map_err(GitError::InvalidHashValue)?;
+        if actual != *hash {
+            return Err(invalid(hash, &format!("content hashes to {actual}")));
+        }
+        Ok((obj_type, data))
+    }
+
+    /// Read `hash` as a `T`, which must match the stored object type.
+    pub fn read_object<T: ObjectTrait>(&self, hash: &ObjectHash) -> Result<T, GitError> {
+        let (obj_type, data) = self.read(hash)?;
+        let object = T::from_bytes(&data, *hash)?;
+        if object.get_type() != obj_type {
+            return Err(GitError::InvalidObjectType(format!(
+                "{hash} is a {obj_type}, not a {}",
+                object.get_type()
+            )));
+        }
+        Ok(object)
+    }
+
+    /// Ids of all loose objects, sorted. Temporary files and anything that is not named like an
+    /// object of the store's hash kind are skipped.
+    pub fn hashes(&self) -> Result<Vec<ObjectHash>, GitError> {
+        self.hashes_matching("")
+    }
+
+    /// Ids of the loose objects whose hex name starts with `hex_prefix`, sorted. Only the fan-out
+    /// directories the prefix can be in are listed.
+    pub fn lookup_prefix(&self, hex_prefix: &str) -> Result<Vec<ObjectHash>, GitError> {
+        HexPrefix::parse(hex_prefix, self.kind)?;
+        self.hashes_matching(&hex_prefix.to_ascii_lowercase())
+    }
+
+    fn hashes_matching(&self, prefix: &str) -> Result<Vec<ObjectHash>, GitError> {
+        let fan_outs = match fs::read_dir(&self.objects_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let is_hex = |s: &str| s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+        let (dir_prefix, file_prefix) = prefix.split_at(prefix.len().min(2));
+
+        let mut hashes = Vec::new();
+        for fan_out in fan_outs {
+            let fan_out = fan_out?;
+            let prefix = fan_out.file_name();
+            let Some(prefix) = prefix.to_str() else {
+                continue;
+            };
+            if prefix.len() != 2
+                || !is_hex(prefix)
+                || !prefix.starts_with(dir_prefix)
+                || !fan_out.file_type()?.is_dir()
+            {
+                continue;
+            }
+            for file in fs::read_dir(fan_out.path())? {
+                let file = file?;
+                let name = file.file_name();
+                let Some(rest) = name.to_str() else {
+                    continue;
+                };
+                if rest.len() + 2 != self.kind.hex_len()
+                    || !is_hex(rest)
+                    || !rest.starts_with(file_prefix)
+                {
+                    continue;
+                }
+                let hash = ObjectHash::from_str(&format!("{prefix}{rest}"))
+                    .map_err(GitError::InvalidHashValue)?;
+                hashes.push(hash);
+            }
+        }
+        hashes.sort();
+        Ok(hashes)
+    }
+
+    fn open(&self, hash: &ObjectHash) -> Result<ReadBoxed<BufReader<File>>, GitError> {
+        if hash.kind() != self.kind {
+            return Err(GitError::InvalidHashValue(format!(
+                "{hash} is not a {} object id",
+                self.kind
+            )));
+        }
+        let file = match File::open(self.object_path(hash)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(GitError::ObjectNotFound(hash.to_string()));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(ReadBoxed::new_for_loose(BufReader::new(file), self.kind))
+    }
+}
+
+fn invalid(hash: &ObjectHash, msg: &str) -> GitError {
+    GitError::InvalidLooseObject(format!("{hash}: {msg}"))
+}
+
+/// Parse `<type> <size>\0` from the start of an inflating loose object.
+fn read_header(reader: &mut impl Read, hash: &ObjectHash) -> Result<(ObjectType, usize), GitError> {
+    let mut header = Vec::with_capacity(32);
+    let mut byte = [0u8; 1];
+    loop {
+        let n = reader
+            .read(&mut byte)
+            .map_err(|e| invalid(hash, &format!("cannot inflate header: {e}")))?;
+        if n == 0 {
+            return Err(invalid(hash, "header is not terminated"));
+        }
+        if byte[0] == 0 {
+            break;
+        }
+        if header.len() == MAX_HEADER_LEN {
+            return Err(invalid(hash, "header is too long"));
+        }
+        header.push(byte[0]);
+    }
+
+    let header = std::str::from_utf8(&header).map_err(|_| invalid(hash, "header is not ASCII"))?;
+    let (type_name, size) = header
+        .split_once(' ')
+        .ok_or_else(|| invalid(hash, &format!("malformed header `{header}`")))?;
+    let obj_type = ObjectType::from_string(type_name)
+        .map_err(|_| invalid(hash, &format!("unknown object type `{type_name}`")))?;
+    // Git writes sizes without sign or leading zeros.
+    if size.is_empty()
+        || !size.bytes().all(|b| b.is_ascii_digit())
+        || (size.len() > 1 && size.starts_with('0'))
+    {
+        return Err(invalid(hash, &format!("malformed size `{size}`")));
+    }
+    let size = size
+        .parse()
+        .map_err(|_| invalid(hash, &format!("size `{size}` is too large")))?;
+    Ok((obj_type, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use flate2::{Compression, write::ZlibEncoder};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        hash::set_hash_kind_for_test,
+        internal::object::{blob::Blob, tree::Tree},
+    };
+
+    fn write_raw(store: &LooseObjectStore, hash: &ObjectHash, inflated: &[u8]) {
+        let path = store.object_path(hash);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(inflated).unwrap();
+        fs::write(path, encoder.finish().unwrap()).unwrap();
+    }
+
+    /// Objects round-trip through the Git layout and hash like `git hash-object -w`.
+    #[test]
+    fn test_write_and_read() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let dir = tempdir().unwrap();
+        let store = LooseObjectStore::new(dir.path().join("objects"));
+
+        let hash = store.write(ObjectType::Blob, b"hello\n").unwrap();
+        assert_eq!(
+            hash,
+            ObjectHash::from_str("ce013625030ba8dba906f756967f9e9ca394464a").unwrap()
+        );
+        assert!(
+            dir.path()
+                .join("objects/ce/013625030ba8dba906f756967f9e9ca394464a")
+                .is_file()
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(store.object_path(&hash))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o444);
+        }
+        assert!(store.contains(&hash));
+        assert_eq!(store.read_header(&hash).unwrap(), (ObjectType::Blob, 6));
+        assert_eq!(
+            store.read(&hash).unwrap(),
+            (ObjectType::Blob, b"hello\n".to_vec())
+        );
+        let blob: Blob = store.read_object(&hash).unwrap();
+        assert_eq!(blob.data, b"hello\n");
+        assert!(matches!(
+            store.read_object::<Tree>(&hash),
+            Err(GitError::InvalidObjectType(_) | GitError::InvalidTreeObject)
+        ));
+
+        // A second write keeps the first file and leaves no temporary file behind.
+        assert_eq!(store.write(ObjectType::Blob, b"hello\n").unwrap(), hash);
+        let other = store.write_object(&Blob::from_content("other")).unwrap();
+        assert_eq!(store.hashes().unwrap(), {
+            let mut expected = vec![hash, other];
+            expected.sort();
+            expected
+        });
+        let leftovers = fs::read_dir(store.objects_dir())
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().file_type().unwrap().is_file())
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn test_write_and_read_sha256() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha256);
+        let dir = tempdir().unwrap();
+        let store = LooseObjectStore::new(dir.path());
+
+        let blob = Blob::from_content("sha256 content");
+        let hash = store.write_object(&blob).unwrap();
+        assert_eq!(hash, blob.id);
+        assert_eq!(hash.kind(), HashKind::Sha256);
+        let read: Blob = store.read_object(&hash).unwrap();
+        assert_eq!(read.data, blob.data);
+        assert_eq!(store.hashes().unwrap(), vec![hash]);
+
+        let sha1 = ObjectHash::Sha1([1; 20]);
+        assert!(!store.contains(&sha1));
+        assert!(matches!(
+            store.read(&sha1),
+            Err(GitError::InvalidHashValue(_))
+        ));
+    }
+
+    /// Missing, misnamed and malformed files are reported instead of returning wrong content.
+    #[test]
+    fn test_read_rejects_bad_objects() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let dir = tempdir().unwrap();
+        let store = LooseObjectStore::new(dir.path());
+        let missing = ObjectHash::Sha1([7; 20]);
+        assert!(matches!(
+            store.read(&missing),
+            Err(GitError::ObjectNotFound(_))
+        ));
+
+        let cases: [(&[u8], &str); 6] = [
+            (b"blob 5\0hello", "content hashes to"),
+            (b"blob 6\0hello", "declares 6 bytes"),
+            (b"blob 4\0hello", "declares 4 bytes"),
+            (b"blob 05\0hello", "malformed size"),
+            (b"bolb 5\0hello", "unknown object type"),
+            (b"blob 5", "not terminated"),
+        ];
+        for (i, (inflated, expected)) in cases.into_iter().enumerate() {
+            let hash = ObjectHash::Sha1([i as u8 + 1; 20]);
+            write_raw(&store, &hash, inflated);
+            match store.read(&hash) {
+                Err(GitError::InvalidLooseObject(msg)) => {
+                    assert!(msg.contains(expected), "{msg} should mention {expected}")
+                }
+                other => panic!("{expected}: unexpected {other:?}"),
+            }
+        }
+        // Only the header is checked without a full read.
+        let misnamed = ObjectHash::Sha1([1; 20]);
+        assert_eq!(store.read_header(&misnamed).unwrap(), (ObjectType::Blob, 5));
+    }
+}
@@ -1,6 +1,10 @@
 //! Metadata container that accompanies pack entries to track file paths, on-disk offsets, CRC32
 //! checksums, and delta flags so downstream encoders/decoders can enrich responses.
 
+use std::sync::Arc;
+
+use crate::hash::ObjectHash;
+
 /// Metadata about a pack entry.
 #[derive(Debug, Clone, Default)]
 pub struct EntryMeta {
@@ -14,6 +18,21 @@ pub struct EntryMeta {
     pub crc32: Option<u32>,
 
     pub is_delta: Option<bool>,
+
+    /// The delta this entry was stored as in its source pack, when the decoder was asked to keep
+    /// it. The encoder copies it instead of searching for a new base if the base is also written.
+    pub reusable_delta: Option<ReusableDelta>,
+}
+
+/// A git delta exactly as it was stored in a pack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReusableDelta {
+    /// Object the delta applies to.
+    pub base: ObjectHash,
+    /// Inflated size of the delta instructions, as declared in the entry header.
+    pub delta_size: usize,
+    /// The entry's zlib stream, byte for byte.
+    pub compressed: Arc<[u8]>,
 }
 
 impl EntryMeta {
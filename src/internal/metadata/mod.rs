@@ -4,7 +4,7 @@
 mod entry_meta;
 use std::fmt;
 
-pub use entry_meta::EntryMeta;
+pub use entry_meta::{EntryMeta, ReusableDelta};
 
 /// Trait for types that can carry metadata.
 pub trait MetadataExt {
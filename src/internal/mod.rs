@@ -1,7 +1,11 @@
 //! Internal building blocks (index, metadata, object model, pack/zlib) that power the public APIs.
 
+pub(crate) mod chunk_format;
+pub mod commit_graph;
 pub mod index;
+pub mod loose;
 pub mod metadata;
 pub mod object;
+pub mod odb;
 pub mod pack;
 pub mod zlib;
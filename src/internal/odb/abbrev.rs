@@ -0,0 +1,206 @@
+//! Abbreviated object ids: resolving the short hashes users type, and choosing the shortest
+//! unambiguous form for display.
+
+use crate::{
+    errors::GitError,
+    hash::{HashKind, ObjectHash},
+    internal::pack::pack_index::HexPrefix,
+};
+
+/// Shortest abbreviation accepted as an object id, as in Git.
+pub const MIN_ABBREV_LEN: usize = 4;
+/// Abbreviation length for small repositories, Git's `core.abbrev` fallback.
+pub const DEFAULT_ABBREV_LEN: usize = 7;
+
+/// Outcome of resolving an abbreviated object id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrefixMatch {
+    /// Exactly one object has the prefix.
+    Unique(ObjectHash),
+    /// Several objects have the prefix; all of them, sorted.
+    Ambiguous(Vec<ObjectHash>),
+    /// No object has the prefix.
+    NotFound,
+}
+
+impl PrefixMatch {
+    /// Classify the sorted, deduplicated ids that matched a prefix.
+    pub(crate) fn from_candidates(mut candidates: Vec<ObjectHash>) -> Self {
+        match candidates.len() {
+            0 => PrefixMatch::NotFound,
+            1 => PrefixMatch::Unique(candidates.pop().unwrap()),
+            _ => PrefixMatch::Ambiguous(candidates),
+        }
+    }
+}
+
+/// Abbreviation length for a repository of about `object_count` objects, as `core.abbrev=auto`
+/// picks it: enough hex digits that a random collision is unlikely, and at least
+/// [`DEFAULT_ABBREV_LEN`].
+pub fn auto_abbrev_len(object_count: usize) -> usize {
+    // 2^bits objects expect a collision around 2^(bits/2); four bits per hex digit.
+    let bits = (usize::BITS - object_count.leading_zeros()) as usize;
+    bits.div_ceil(2).max(DEFAULT_ABBREV_LEN)
+}
+
+/// Check that `hex_prefix` is a hex prefix of a `kind` id and lowercase it, the form loose object
+/// names and `to_string` use.
+pub(crate) fn normalize_prefix(hex_prefix: &str, kind: HashKind) -> Result<String, GitError> {
+    HexPrefix::parse(hex_prefix, kind)?;
+    Ok(hex_prefix.to_ascii_lowercase())
+}
+
+/// Shortest prefix of `hash`, no shorter than `min_len`, that none of `candidates` shares.
+pub(crate) fn shortest_unique(
+    hash: &ObjectHash,
+    candidates: &[ObjectHash],
+    min_len: usize,
+) -> String {
+    let hex = hash.to_string();
+    let needed = candidates
+        .iter()
+        .filter(|other| *other != hash)
+        .map(|other| {
+            let other = other.to_string();
+            let common = hex
+                .bytes()
+                .zip(other.bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            common + 1
+        })
+        .max()
+        .unwrap_or(0);
+    let len = needed.max(min_len).clamp(MIN_ABBREV_LEN, hex.len());
+    hex[..len].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use tempfile::tempdir;
+
+    use super::{DEFAULT_ABBREV_LEN, PrefixMatch, auto_abbrev_len};
+    use crate::{
+        errors::GitError,
+        hash::{HashKind, ObjectHash, set_hash_kind_for_test},
+        internal::{
+            loose::LooseObjectStore,
+            object::types::ObjectType,
+            odb::{MemoryObjectDatabase, ObjectDatabase, ObjectDirectory},
+            pack::test_pack_builder::TestPackBuilder,
+        },
+    };
+
+    /// Blob contents whose ids share their first hex digits, found by brute force.
+    fn colliding_blobs(digits: usize) -> (Vec<u8>, Vec<u8>) {
+        let mut seen = std::collections::HashMap::new();
+        for n in 0u32.. {
+            let data = format!("blob {n}\n").into_bytes();
+            let hex = ObjectHash::from_type_and_data(ObjectType::Blob, &data).to_string();
+            if let Some(first) = seen.insert(hex[..digits].to_string(), data.clone()) {
+                return (first, data);
+            }
+        }
+        unreachable!()
+    }
+
+    #[test]
+    fn test_auto_abbrev_len() {
+        assert_eq!(auto_abbrev_len(0), DEFAULT_ABBREV_LEN);
+        assert_eq!(auto_abbrev_len(10_000), DEFAULT_ABBREV_LEN);
+        // Git itself needs 8 digits from 2^15 objects on, then one more per factor of four.
+        assert_eq!(auto_abbrev_len(1 << 15), 8);
+        assert_eq!(auto_abbrev_len(1 << 17), 9);
+        assert_eq!(auto_abbrev_len(5_000_000), 12);
+    }
+
+    /// Prefixes resolve to one object, several, or none, and abbreviations grow past collisions.
+    #[test]
+    fn test_resolve_prefix_and_abbreviate() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let (first, second) = colliding_blobs(5);
+        let dir = tempdir().unwrap();
+        let loose = LooseObjectStore::new(dir.path());
+        let memory = MemoryObjectDatabase::new();
+        for db in [&loose as &dyn ObjectDatabase, &memory] {
+            let a = db.write(ObjectType::Blob, &first).unwrap();
+            let b = db.write(ObjectType::Blob, &second).unwrap();
+            let other = db.write(ObjectType::Blob, b"unrelated").unwrap();
+            let (a_hex, b_hex) = (a.to_string(), b.to_string());
+
+            assert_eq!(
+                db.resolve_prefix(&a_hex[..5]).unwrap(),
+                PrefixMatch::Ambiguous({
+                    let mut both = vec![a, b];
+                    both.sort();
+                    both
+                })
+            );
+            let common = a_hex
+                .bytes()
+                .zip(b_hex.bytes())
+                .take_while(|(x, y)| x == y)
+                .count();
+            let a_abbrev = db.abbreviate(&a, DEFAULT_ABBREV_LEN).unwrap();
+            assert_eq!(a_abbrev.len(), (common + 1).max(DEFAULT_ABBREV_LEN));
+            assert_eq!(
+                db.resolve_prefix(&a_abbrev).unwrap(),
+                PrefixMatch::Unique(a)
+            );
+            assert_eq!(
+                db.resolve_prefix(&a_abbrev.to_ascii_uppercase()).unwrap(),
+                PrefixMatch::Unique(a)
+            );
+
+            let other_hex = other.to_string();
+            assert_eq!(db.abbreviate_auto(&other).unwrap(), other_hex[..7]);
+            assert_eq!(
+                db.resolve_prefix(&other_hex).unwrap(),
+                PrefixMatch::Unique(other)
+            );
+            assert_eq!(db.resolve_prefix("0000000").unwrap(), PrefixMatch::NotFound);
+            assert!(matches!(
+                db.resolve_prefix("ce0"),
+                Err(GitError::InvalidHashValue(_))
+            ));
+            assert!(matches!(
+                db.resolve_prefix("xyz123"),
+                Err(GitError::InvalidHashValue(_))
+            ));
+        }
+    }
+
+    /// Pack indexes and loose objects are searched together, including odd-length prefixes.
+    #[tokio::test]
+    async fn test_resolve_prefix_across_packs_and_loose() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let (first, second) = colliding_blobs(4);
+        let dir = tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("pack")).unwrap();
+        let mut builder = TestPackBuilder::new();
+        let packed = builder.add_base(ObjectType::Blob, &first);
+        builder.write_to(&dir.path().join("pack"), "one").await;
+
+        let odb = ObjectDirectory::open(dir.path()).unwrap();
+        let loose = odb.write(ObjectType::Blob, &second).unwrap();
+        let hex = packed.hash.to_string();
+        assert_eq!(odb.approximate_object_count().unwrap(), 2);
+        assert_eq!(odb.lookup_prefix(&hex[..3]).unwrap(), {
+            let mut both = vec![packed.hash, loose];
+            both.sort();
+            both
+        });
+        assert_eq!(
+            odb.resolve_prefix(&hex).unwrap(),
+            PrefixMatch::Unique(ObjectHash::from_str(&hex).unwrap())
+        );
+        let abbrev = odb.abbreviate(&loose, 4).unwrap();
+        assert!(abbrev.len() > 4);
+        assert_eq!(
+            odb.resolve_prefix(&abbrev).unwrap(),
+            PrefixMatch::Unique(loose)
+        );
+    }
+}
@@ -0,0 +1,358 @@
+//! A Git `objects` directory: loose objects, packs and alternates.
+
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use super::{ObjectDatabase, PackSet, hash_object};
+use crate::{
+    errors::GitError,
+    hash::{HashKind, ObjectHash},
+    internal::{loose::LooseObjectStore, object::types::ObjectType, pack::thin::FixedPack},
+};
+
+/// How deep alternates may chain, as in Git.
+const MAX_ALTERNATE_DEPTH: usize = 5;
+
+/// The objects of one repository, as Git lays them out under `.git/objects`.
+///
+/// Reads try the packs, then the loose objects, then each alternate in the order
+/// `objects/info/alternates` lists them. An object found nowhere makes the directory look for
+/// packs written since it was opened before giving up, as Git does. Writes go to the loose store,
+/// unless the object is already present anywhere in the chain.
+///
+/// Clones share the set of open packs.
+#[derive(Clone)]
+pub struct ObjectDirectory {
+    loose: LooseObjectStore,
+    pack_dir: PathBuf,
+    packs: Arc<RwLock<PackSet>>,
+    alternates: Vec<ObjectDirectory>,
+}
+
+impl ObjectDirectory {
+    /// Open `objects_dir` with its `pack` directory and alternates, using the current thread's
+    /// hash kind.
+    ///
+    /// Alternates are followed up to five levels deep; relative entries are resolved against the
+    /// directory that lists them, and a directory reached twice is only searched once.
+    pub fn open(objects_dir: impl AsRef<Path>) -> Result<Self, GitError> {
+        let mut seen = HashSet::new();
+        Self::open_chained(objects_dir.as_ref(), 0, &mut seen)
+    }
+
+    fn open_chained(
+        objects_dir: &Path,
+        depth: usize,
+        seen: &mut HashSet<PathBuf>,
+    ) -> Result<Self, GitError> {
+        seen.insert(fs::canonicalize(objects_dir).unwrap_or_else(|_| objects_dir.to_path_buf()));
+        let pack_dir = objects_dir.join("pack");
+        let mut directory = ObjectDirectory {
+            loose: LooseObjectStore::new(objects_dir),
+            packs: Arc::new(RwLock::new(PackSet::open(&pack_dir)?)),
+            pack_dir,
+            alternates: Vec::new(),
+        };
+
+        for alternate in read_alternates(objects_dir)? {
+            let canonical = fs::canonicalize(&alternate).map_err(|e| {
+                GitError::InvalidArgument(format!(
+                    "alternate object directory {}: {e}",
+                    alternate.display()
+                ))
+            })?;
+            if seen.contains(&canonical) {
+                continue;
+            }
+            if depth + 1 > MAX_ALTERNATE_DEPTH {
+                return Err(GitError::InvalidArgument(format!(
+                    "alternates of {} are nested too deep",
+                    objects_dir.display()
+                )));
+            }
+            let alternate = Self::open_chained(&alternate, depth + 1, seen)?;
+            directory.alternates.push(alternate);
+        }
+        Ok(directory)
+    }
+
+    /// The loose objects of this directory.
+    pub fn loose(&self) -> &LooseObjectStore {
+        &self.loose
+    }
+
+    /// The packs of this directory, as currently open.
+    pub fn packs(&self) -> PackSet {
+        self.packs.read().unwrap().clone()
+    }
+
+    /// Open packs written to `objects/pack` since the directory was opened (or last refreshed),
+    /// returning how many were added. Alternates are not refreshed.
+    pub fn refresh_packs(&self) -> Result<usize, GitError> {
+        self.packs.write().unwrap().refresh(&self.pack_dir)
+    }
+
+    /// Directories named in `objects/info/alternates`, each with its own alternates.
+    pub fn alternates(&self) -> &[ObjectDirectory] {
+        &self.alternates
+    }
+
+    /// Apply `f` to the first database holding `hash`, in lookup order. With `rescan`, packs
+    /// that appeared since the last refresh are tried before giving up.
+    fn with_object<T>(
+        &self,
+        hash: &ObjectHash,
+        rescan: bool,
+        f: &dyn Fn(&dyn ObjectDatabase) -> Result<T, GitError>,
+    ) -> Result<Option<T>, GitError> {
+        {
+            let packs = self.packs.read().unwrap();
+            if packs.contains(hash)? {
+                return f(&*packs).map(Some);
+            }
+        }
+        if ObjectDatabase::contains(&self.loose, hash)? {
+            return f(&self.loose).map(Some);
+        }
+        for alternate in &self.alternates {
+            if let Some(found) = alternate.with_object(hash, rescan, f)? {
+                return Ok(Some(found));
+            }
+        }
+        if rescan && self.refresh_packs()? > 0 {
+            let packs = self.packs.read().unwrap();
+            if packs.contains(hash)? {
+                return f(&*packs).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Apply `f` to every database of the chain: the packs, the loose objects, then each
+    /// alternate's.
+    fn for_each_database(
+        &self,
+        f: &mut dyn FnMut(&dyn ObjectDatabase) -> Result<(), GitError>,
+    ) -> Result<(), GitError> {
+        f(&*self.packs.read().unwrap())?;
+        f(&self.loose)?;
+        for alternate in &self.alternates {
+            alternate.for_each_database(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl ObjectDatabase for ObjectDirectory {
+    fn hash_kind(&self) -> HashKind {
+        self.loose.hash_kind()
+    }
+
+    fn contains(&self, hash: &ObjectHash) -> Result<bool, GitError> {
+        Ok(self.with_object(hash, true, &|_| Ok(()))?.is_some())
+    }
+
+    fn read_header(&self, hash: &ObjectHash) -> Result<(ObjectType, usize), GitError> {
+        self.with_object(hash, true, &|db| db.read_header(hash))?
+            .ok_or_else(|| GitError::ObjectNotFound(hash.to_string()))
+    }
+
+    fn read(&self, hash: &ObjectHash) -> Result<(ObjectType, Vec<u8>), GitError> {
+        self.with_object(hash, true, &|db| db.read(hash))?
+            .ok_or_else(|| GitError::ObjectNotFound(hash.to_string()))
+    }
+
+    fn write(&self, obj_type: ObjectType, data: &[u8]) -> Result<ObjectHash, GitError> {
+        let hash = hash_object(self.hash_kind(), obj_type, data)?;
+        // New objects are the common case here, so skip the rescan a miss would cost.
+        if self.with_object(&hash, false, &|_| Ok(()))?.is_some() {
+            return Ok(hash);
+        }
+        self.loose.write(obj_type, data)
+    }
+
+    /// Keep the pack and its index in `objects/pack`, as `git index-pack` does, and open it.
+    fn write_pack(&self, pack: FixedPack) -> Result<(), GitError> {
+        fs::create_dir_all(&self.pack_dir)?;
+        pack.write_to(&self.pack_dir)?;
+        self.refresh_packs()?;
+        Ok(())
+    }
+
+    fn hashes(&self) -> Result<Vec<ObjectHash>, GitError> {
+        let mut hashes = Vec::new();
+        self.for_each_database(&mut |db| {
+            hashes.extend(db.hashes()?);
+            Ok(())
+        })?;
+        hashes.sort();
+        hashes.dedup();
+        Ok(hashes)
+    }
+
+    fn lookup_prefix(&self, hex_prefix: &str) -> Result<Vec<ObjectHash>, GitError> {
+        let mut hashes = Vec::new();
+        self.for_each_database(&mut |db| {
+            hashes.extend(db.lookup_prefix(hex_prefix)?);
+            Ok(())
+        })?;
+        hashes.sort();
+        hashes.dedup();
+        Ok(hashes)
+    }
+
+    fn approximate_object_count(&self) -> Result<usize, GitError> {
+        let mut count = 0;
+        self.for_each_database(&mut |db| {
+            count += db.approximate_object_count()?;
+            Ok(())
+        })?;
+        Ok(count)
+    }
+}
+
+/// Directories listed in `objects/info/alternates`, one per line. Blank lines and `#` comments are
+/// skipped; relative paths are relative to `objects_dir`.
+fn read_alternates(objects_dir: &Path) -> Result<Vec<PathBuf>, GitError> {
+    let content = match fs::read_to_string(objects_dir.join("info").join("alternates")) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(content
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| objects_dir.join(line))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::ObjectDirectory;
+    use crate::{
+        errors::GitError,
+        hash::{HashKind, set_hash_kind_for_test},
+        internal::{
+            object::types::ObjectType,
+            odb::ObjectDatabase,
+            pack::{Pack, test_pack_builder::TestPackBuilder},
+        },
+    };
+
+    /// Packs, loose objects and a relative alternate are all visible; writes stay local.
+    #[tokio::test]
+    async fn test_object_directory_chain() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let root = tempdir().unwrap();
+        let shared = root.path().join("shared/objects");
+        let local = root.path().join("local/objects");
+        fs::create_dir_all(shared.join("pack")).unwrap();
+        fs::create_dir_all(local.join("info")).unwrap();
+        fs::write(
+            local.join("info/alternates"),
+            "# shared history\n../../shared/objects\n",
+        )
+        .unwrap();
+
+        let mut builder = TestPackBuilder::new();
+        let packed = builder.add_base(ObjectType::Blob, b"packed");
+        builder.write_to(&shared.join("pack"), "base").await;
+        let shared_loose = ObjectDirectory::open(&shared)
+            .unwrap()
+            .write(ObjectType::Blob, b"shared loose")
+            .unwrap();
+
+        let odb = ObjectDirectory::open(&local).unwrap();
+        assert_eq!(odb.alternates().len(), 1);
+        assert_eq!(odb.alternates()[0].packs().len(), 1);
+        assert_eq!(
+            odb.read(&packed.hash).unwrap(),
+            (ObjectType::Blob, b"packed".to_vec())
+        );
+        assert_eq!(
+            odb.read_header(&shared_loose).unwrap(),
+            (ObjectType::Blob, 12)
+        );
+
+        // Objects the alternate has are not copied; new ones become local loose objects.
+        assert_eq!(odb.write(ObjectType::Blob, b"packed").unwrap(), packed.hash);
+        let own = odb.write(ObjectType::Blob, b"local").unwrap();
+        assert_eq!(odb.loose().hashes().unwrap(), vec![own]);
+
+        let mut all = vec![packed.hash, shared_loose, own];
+        all.sort();
+        assert_eq!(odb.hashes().unwrap(), all);
+    }
+
+    /// Alternates that point back at each other are searched once; missing ones are errors.
+    #[test]
+    fn test_alternates_cycle_and_missing() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let root = tempdir().unwrap();
+        let a = root.path().join("a");
+        let b = root.path().join("b");
+        fs::create_dir_all(a.join("info")).unwrap();
+        fs::create_dir_all(b.join("info")).unwrap();
+        fs::write(a.join("info/alternates"), format!("{}\n", b.display())).unwrap();
+        fs::write(b.join("info/alternates"), format!("{}\n", a.display())).unwrap();
+
+        let odb = ObjectDirectory::open(&a).unwrap();
+        assert_eq!(odb.alternates().len(), 1);
+        assert!(odb.alternates()[0].alternates().is_empty());
+
+        fs::write(a.join("info/alternates"), "../missing\n").unwrap();
+        assert!(matches!(
+            ObjectDirectory::open(&a),
+            Err(GitError::InvalidArgument(_))
+        ));
+    }
+
+    /// Packs written after opening are found on a miss, and stored packs stay packs.
+    #[tokio::test]
+    async fn test_object_directory_new_packs() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let root = tempdir().unwrap();
+        let objects = root.path().join("objects");
+        fs::create_dir_all(objects.join("pack")).unwrap();
+        let odb = ObjectDirectory::open(&objects).unwrap();
+        let shared = odb.clone();
+
+        let mut builder = TestPackBuilder::new();
+        let base = builder.add_base(ObjectType::Blob, b"base content for a delta");
+        let delta = builder.add_ofs_delta(
+            base,
+            b"base content for a delta",
+            ObjectType::Blob,
+            b"base content for a delta, changed",
+        );
+        builder.write_to(&objects.join("pack"), "late").await;
+        assert!(odb.packs().is_empty());
+        assert_eq!(
+            odb.read_header(&delta.hash).unwrap(),
+            (ObjectType::Blob, 33)
+        );
+        assert_eq!(shared.packs().len(), 1);
+
+        let mut builder = TestPackBuilder::new();
+        let pushed = builder.add_base(ObjectType::Blob, b"pushed");
+        let fixed = Pack::fix_thin(builder.pack_bytes(), |_| None)
+            .await
+            .unwrap();
+        odb.write_pack(fixed).unwrap();
+        assert_eq!(odb.packs().len(), 2);
+        assert!(odb.loose().hashes().unwrap().is_empty());
+        assert_eq!(
+            odb.read(&pushed.hash).unwrap(),
+            (ObjectType::Blob, b"pushed".to_vec())
+        );
+    }
+}
@@ -0,0 +1,89 @@
+//! Object database held entirely in memory.
+
+use std::{collections::HashMap, sync::RwLock};
+
+use super::{ObjectDatabase, hash_object};
+use crate::{
+    errors::GitError,
+    hash::{HashKind, ObjectHash, get_hash_kind},
+    internal::object::types::ObjectType,
+};
+
+/// Objects kept in a map, for tests and for embedders that persist elsewhere.
+///
+/// Like [`LooseObjectStore`](crate::internal::loose::LooseObjectStore), object ids use the hash
+/// kind that was active when the database was created.
+#[derive(Debug)]
+pub struct MemoryObjectDatabase {
+    objects: RwLock<HashMap<ObjectHash, (ObjectType, Vec<u8>)>>,
+    kind: HashKind,
+}
+
+impl Default for MemoryObjectDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryObjectDatabase {
+    /// An empty database using the current thread's hash kind.
+    pub fn new() -> Self {
+        MemoryObjectDatabase {
+            objects: RwLock::new(HashMap::new()),
+            kind: get_hash_kind(),
+        }
+    }
+
+    /// Number of objects stored.
+    pub fn len(&self) -> usize {
+        self.objects.read().unwrap().len()
+    }
+
+    /// Whether no object is stored.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ObjectDatabase for MemoryObjectDatabase {
+    fn hash_kind(&self) -> HashKind {
+        self.kind
+    }
+
+    fn contains(&self, hash: &ObjectHash) -> Result<bool, GitError> {
+        Ok(self.objects.read().unwrap().contains_key(hash))
+    }
+
+    fn read_header(&self, hash: &ObjectHash) -> Result<(ObjectType, usize), GitError> {
+        let objects = self.objects.read().unwrap();
+        let (obj_type, data) = objects
+            .get(hash)
+            .ok_or_else(|| GitError::ObjectNotFound(hash.to_string()))?;
+        Ok((*obj_type, data.len()))
+    }
+
+    fn read(&self, hash: &ObjectHash) -> Result<(ObjectType, Vec<u8>), GitError> {
+        self.objects
+            .read()
+            .unwrap()
+            .get(hash)
+            .cloned()
+            .ok_or_else(|| GitError::ObjectNotFound(hash.to_string()))
+    }
+
+    fn write(&self, obj_type: ObjectType, data: &[u8]) -> Result<ObjectHash, GitError> {
+        let hash = hash_object(self.kind, obj_type, data)?;
+        self.objects
+            .write()
+            .unwrap()
+            .entry(hash)
+            .or_insert_with(|| (obj_type, data.to_vec()));
+        Ok(hash)
+    }
+
+    fn hashes(&self) -> Result<Vec<ObjectHash>, GitError> {
+        let mut hashes: Vec<ObjectHash> = self.objects.read().unwrap().keys().copied().collect();
+        hashes.sort();
+        Ok(hashes)
+    }
+}
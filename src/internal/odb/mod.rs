@@ -0,0 +1,338 @@
+//! Object databases: where objects live, independent of refs and transport.
+//!
+//! [`ObjectDatabase`] is the storage interface below
+//! [`RepositoryAccess`](crate::protocol::RepositoryAccess): it takes typed [`ObjectHash`] ids and
+//! only knows how to look up, read, write and list objects. The implementations compose like a
+//! Git `objects` directory does:
+//!
+//! - [`LooseObjectStore`]: one zlib file per object.
+//! - [`PackSet`]: the indexed packs of an `objects/pack` directory, read-only.
+//! - [`ObjectDirectory`]: loose objects, packs and the repositories named in
+//!   `objects/info/alternates`, searched in that order; writes go to the loose store.
+//! - [`MemoryObjectDatabase`]: a map in memory, for tests and embedding.
+//!
+//! Abbreviated ids resolve through [`ObjectDatabase::resolve_prefix`], and
+//! [`ObjectDatabase::abbreviate`] picks the shortest unambiguous form for display.
+//!
+//! [`OdbRepository`](crate::protocol::odb::OdbRepository) serves any of them, together with a ref
+//! store, over the Git protocol.
+
+pub mod abbrev;
+mod directory;
+mod memory;
+mod pack;
+
+use std::sync::Arc;
+
+use abbrev::{PrefixMatch, auto_abbrev_len, normalize_prefix, shortest_unique};
+pub use directory::ObjectDirectory;
+pub use memory::MemoryObjectDatabase;
+pub use pack::PackSet;
+
+use crate::{
+    errors::GitError,
+    hash::{HashKind, ObjectHash},
+    internal::{
+        loose::LooseObjectStore,
+        object::{ObjectTrait, types::ObjectType},
+        pack::{pack_index::PackIndex, reader::PackReader, thin::FixedPack},
+    },
+    utils::HashAlgorithm,
+};
+
+/// Content-addressed object storage.
+///
+/// Objects are the base types (commit, tree, blob, tag); contents are returned without the
+/// `<type> <size>\0` header. Implementations must be safe to share between threads, since the
+/// protocol layer serves concurrent requests from one database.
+pub trait ObjectDatabase: Send + Sync {
+    /// Hash kind of the object ids in this database.
+    fn hash_kind(&self) -> HashKind;
+
+    /// Whether the database holds `hash`.
+    fn contains(&self, hash: &ObjectHash) -> Result<bool, GitError>;
+
+    /// Type and size of `hash`.
+    ///
+    /// The default reads the whole object; implementations that can parse a header alone should
+    /// override it.
+    fn read_header(&self, hash: &ObjectHash) -> Result<(ObjectType, usize), GitError> {
+        let (obj_type, data) = self.read(hash)?;
+        Ok((obj_type, data.len()))
+    }
+
+    /// Type and content of `hash`. A missing object is [`GitError::ObjectNotFound`].
+    fn read(&self, hash: &ObjectHash) -> Result<(ObjectType, Vec<u8>), GitError>;
+
+    /// Store `data` as an object of `obj_type` and return its id. Writing an object that already
+    /// exists succeeds without changing it.
+    fn write(&self, obj_type: ObjectType, data: &[u8]) -> Result<ObjectHash, GitError>;
+
+    /// Store every object of a self-contained pack, such as a completed push.
+    ///
+    /// The default reads each object back out of the pack and [`write`](Self::write)s it;
+    /// databases that can keep the pack and its index as they are should override it.
+    fn write_pack(&self, pack: FixedPack) -> Result<(), GitError> {
+        let index = PackIndex::from_bytes(pack.index)?;
+        let reader = PackReader::from_bytes(pack.pack, index)?;
+        for entry in reader.index().iter() {
+            let object = reader.read_object(&entry.hash)?;
+            self.write(object.obj_type, &object.data)?;
+        }
+        Ok(())
+    }
+
+    /// Ids of all objects, sorted and without duplicates.
+    fn hashes(&self) -> Result<Vec<ObjectHash>, GitError>;
+
+    /// Ids whose hex name starts with `hex_prefix` (any case, odd lengths allowed), sorted and
+    /// without duplicates.
+    ///
+    /// The default filters [`hashes`](Self::hashes); implementations with an index should
+    /// override it.
+    fn lookup_prefix(&self, hex_prefix: &str) -> Result<Vec<ObjectHash>, GitError> {
+        let prefix = normalize_prefix(hex_prefix, self.hash_kind())?;
+        let mut hashes = self.hashes()?;
+        hashes.retain(|hash| hash.to_string().starts_with(&prefix));
+        Ok(hashes)
+    }
+
+    /// Rough number of objects, used to size abbreviations. Duplicates may be counted twice.
+    fn approximate_object_count(&self) -> Result<usize, GitError> {
+        Ok(self.hashes()?.len())
+    }
+
+    /// Resolve an abbreviated id, which must have at least [`abbrev::MIN_ABBREV_LEN`] digits.
+    fn resolve_prefix(&self, hex_prefix: &str) -> Result<PrefixMatch, GitError> {
+        if hex_prefix.len() < abbrev::MIN_ABBREV_LEN {
+            return Err(GitError::InvalidHashValue(hex_prefix.to_string()));
+        }
+        Ok(PrefixMatch::from_candidates(
+            self.lookup_prefix(hex_prefix)?,
+        ))
+    }
+
+    /// Shortest prefix of `hash`, at least `min_len` digits long, that names no other object.
+    fn abbreviate(&self, hash: &ObjectHash, min_len: usize) -> Result<String, GitError> {
+        let hex = hash.to_string();
+        let start = min_len.clamp(abbrev::MIN_ABBREV_LEN, hex.len());
+        let candidates = self.lookup_prefix(&hex[..start])?;
+        Ok(shortest_unique(hash, &candidates, min_len))
+    }
+
+    /// [`abbreviate`](Self::abbreviate) with the length `core.abbrev=auto` would start from.
+    fn abbreviate_auto(&self, hash: &ObjectHash) -> Result<String, GitError> {
+        let min_len = auto_abbrev_len(self.approximate_object_count()?);
+        self.abbreviate(hash, min_len)
+    }
+
+    /// Read `hash` as a `T`, which must match the stored object type.
+    fn read_object<T: ObjectTrait>(&self, hash: &ObjectHash) -> Result<T, GitError>
+    where
+        Self: Sized,
+    {
+        let (obj_type, data) = self.read(hash)?;
+        let object = T::from_bytes(&data, *hash)?;
+        if object.get_type() != obj_type {
+            return Err(GitError::InvalidObjectType(format!(
+                "{hash} is a {obj_type}, not a {}",
+                object.get_type()
+            )));
+        }
+        Ok(object)
+    }
+
+    /// Store `object` and return its id.
+    fn write_object(&self, object: &impl ObjectTrait) -> Result<ObjectHash, GitError>
+    where
+        Self: Sized,
+    {
+        self.write(object.get_type(), &object.to_data()?)
+    }
+}
+
+impl<T: ObjectDatabase + ?Sized> ObjectDatabase for Arc<T> {
+    fn hash_kind(&self) -> HashKind {
+        (**self).hash_kind()
+    }
+
+    fn contains(&self, hash: &ObjectHash) -> Result<bool, GitError> {
+        (**self).contains(hash)
+    }
+
+    fn read_header(&self, hash: &ObjectHash) -> Result<(ObjectType, usize), GitError> {
+        (**self).read_header(hash)
+    }
+
+    fn read(&self, hash: &ObjectHash) -> Result<(ObjectType, Vec<u8>), GitError> {
+        (**self).read(hash)
+    }
+
+    fn write(&self, obj_type: ObjectType, data: &[u8]) -> Result<ObjectHash, GitError> {
+        (**self).write(obj_type, data)
+    }
+
+    fn write_pack(&self, pack: FixedPack) -> Result<(), GitError> {
+        (**self).write_pack(pack)
+    }
+
+    fn hashes(&self) -> Result<Vec<ObjectHash>, GitError> {
+        (**self).hashes()
+    }
+
+    fn lookup_prefix(&self, hex_prefix: &str) -> Result<Vec<ObjectHash>, GitError> {
+        (**self).lookup_prefix(hex_prefix)
+    }
+
+    fn approximate_object_count(&self) -> Result<usize, GitError> {
+        (**self).approximate_object_count()
+    }
+}
+
+impl<T: ObjectDatabase + ?Sized> ObjectDatabase for Box<T> {
+    fn hash_kind(&self) -> HashKind {
+        (**self).hash_kind()
+    }
+
+    fn contains(&self, hash: &ObjectHash) -> Result<bool, GitError> {
+        (**self).contains(hash)
+    }
+
+    fn read_header(&self, hash: &ObjectHash) -> Result<(ObjectType, usize), GitError> {
+        (**self).read_header(hash)
+    }
+
+    fn read(&self, hash: &ObjectHash) -> Result<(ObjectType, Vec<u8>), GitError> {
+        (**self).read(hash)
+    }
+
+    fn write(&self, obj_type: ObjectType, data: &[u8]) -> Result<ObjectHash, GitError> {
+        (**self).write(obj_type, data)
+    }
+
+    fn write_pack(&self, pack: FixedPack) -> Result<(), GitError> {
+        (**self).write_pack(pack)
+    }
+
+    fn hashes(&self) -> Result<Vec<ObjectHash>, GitError> {
+        (**self).hashes()
+    }
+
+    fn lookup_prefix(&self, hex_prefix: &str) -> Result<Vec<ObjectHash>, GitError> {
+        (**self).lookup_prefix(hex_prefix)
+    }
+
+    fn approximate_object_count(&self) -> Result<usize, GitError> {
+        (**self).approximate_object_count()
+    }
+}
+
+impl ObjectDatabase for LooseObjectStore {
+    fn hash_kind(&self) -> HashKind {
+        LooseObjectStore::hash_kind(self)
+    }
+
+    fn contains(&self, hash: &ObjectHash) -> Result<bool, GitError> {
+        Ok(LooseObjectStore::contains(self, hash))
+    }
+
+    fn read_header(&self, hash: &ObjectHash) -> Result<(ObjectType, usize), GitError> {
+        LooseObjectStore::read_header(self, hash)
+    }
+
+    fn read(&self, hash: &ObjectHash) -> Result<(ObjectType, Vec<u8>), GitError> {
+        LooseObjectStore::read(self, hash)
+    }
+
+    fn write(&self, obj_type: ObjectType, data: &[u8]) -> Result<ObjectHash, GitError> {
+        LooseObjectStore::write(self, obj_type, data)
+    }
+
+    fn hashes(&self) -> Result<Vec<ObjectHash>, GitError> {
+        LooseObjectStore::hashes(self)
+    }
+
+    fn lookup_prefix(&self, hex_prefix: &str) -> Result<Vec<ObjectHash>, GitError> {
+        LooseObjectStore::lookup_prefix(self, hex_prefix)
+    }
+}
+
+/// Id of `data` stored as an object of `obj_type`, rejecting types that have no object form.
+pub(crate) fn hash_object(
+    kind: HashKind,
+    obj_type: ObjectType,
+    data: &[u8],
+) -> Result<ObjectHash, GitError> {
+    let type_name = obj_type.to_bytes().ok_or_else(|| {
+        GitError::InvalidObjectType(format!("{obj_type} cannot be stored as an object"))
+    })?;
+    let mut hasher = HashAlgorithm::new_with_kind(kind);
+    hasher.update(type_name);
+    hasher.update(b" ");
+    hasher.update(data.len().to_string().as_bytes());
+    hasher.update(b"\0");
+    hasher.update(data);
+    ObjectHash::from_bytes_with_kind(&hasher.finalize(), kind).map_err(GitError::InvalidHashValue)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tempfile::tempdir;
+
+    use super::{MemoryObjectDatabase, ObjectDatabase};
+    use crate::{
+        errors::GitError,
+        hash::{HashKind, ObjectHash, set_hash_kind_for_test},
+        internal::{
+            loose::LooseObjectStore,
+            object::{blob::Blob, types::ObjectType},
+        },
+    };
+
+    fn exercise(db: &dyn ObjectDatabase) {
+        let hash = db.write(ObjectType::Blob, b"hello\n").unwrap();
+        assert_eq!(hash.to_string(), "ce013625030ba8dba906f756967f9e9ca394464a");
+        assert!(db.contains(&hash).unwrap());
+        assert_eq!(db.read_header(&hash).unwrap(), (ObjectType::Blob, 6));
+        assert_eq!(
+            db.read(&hash).unwrap(),
+            (ObjectType::Blob, b"hello\n".to_vec())
+        );
+        assert_eq!(db.write(ObjectType::Blob, b"hello\n").unwrap(), hash);
+        assert_eq!(db.hashes().unwrap(), vec![hash]);
+
+        let missing = ObjectHash::Sha1([7; 20]);
+        assert!(!db.contains(&missing).unwrap());
+        assert!(matches!(
+            db.read(&missing),
+            Err(GitError::ObjectNotFound(_))
+        ));
+        assert!(db.write(ObjectType::OffsetDelta, b"delta").is_err());
+    }
+
+    /// Loose and in-memory databases behave the same through the trait, also behind an `Arc`.
+    #[test]
+    fn test_object_database_implementations() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let dir = tempdir().unwrap();
+        exercise(&LooseObjectStore::new(dir.path()));
+        exercise(&MemoryObjectDatabase::new());
+        exercise(&Arc::new(MemoryObjectDatabase::new()));
+    }
+
+    /// Typed reads check the stored object type.
+    #[test]
+    fn test_read_object_checks_type() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let db = MemoryObjectDatabase::new();
+        let blob = Blob::from_content("typed");
+        let hash = db.write_object(&blob).unwrap();
+        assert_eq!(hash, blob.id);
+        assert_eq!(db.read_object::<Blob>(&hash).unwrap().data, blob.data);
+
+        let tree_like = db.write(ObjectType::Tree, b"").unwrap();
+        assert!(db.read_object::<Blob>(&tree_like).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! The indexed packs of an `objects/pack` directory as one read-only object database.
+
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use super::ObjectDatabase;
+use crate::{
+    errors::GitError,
+    hash::{HashKind, ObjectHash, get_hash_kind},
+    internal::{object::types::ObjectType, pack::reader::PackReader},
+};
+
+/// A set of packs, each opened with its `.idx` through a [`PackReader`].
+///
+/// Lookups try the packs in the order they were added. Packs are immutable, so
+/// [`write`](ObjectDatabase::write) always fails; combine the set with a loose store through
+/// [`ObjectDirectory`](super::ObjectDirectory) to add objects.
+#[derive(Clone)]
+pub struct PackSet {
+    packs: Vec<Arc<PackReader>>,
+    kind: HashKind,
+    /// Pack files opened from a directory, so a refresh only opens new ones.
+    opened: HashSet<PathBuf>,
+}
+
+impl Default for PackSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PackSet {
+    /// An empty set using the current thread's hash kind.
+    pub fn new() -> Self {
+        PackSet {
+            packs: Vec::new(),
+            kind: get_hash_kind(),
+            opened: HashSet::new(),
+        }
+    }
+
+    /// Open every `.pack` in `pack_dir` that has a matching `.idx`, in file name order. A missing
+    /// directory gives an empty set.
+    pub fn open(pack_dir: impl AsRef<Path>) -> Result<Self, GitError> {
+        let mut set = Self::new();
+        set.refresh(pack_dir)?;
+        Ok(set)
+    }
+
+    /// Open the packs of `pack_dir` that the set does not have yet, as after another process
+    /// wrote new ones, and return how many were added. They go after the existing packs, in
+    /// file name order.
+    pub fn refresh(&mut self, pack_dir: impl AsRef<Path>) -> Result<usize, GitError> {
+        let entries = match fs::read_dir(pack_dir.as_ref()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        let mut pack_paths = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            // A pack without its index is still being written (or was abandoned).
+            if path.extension().is_some_and(|ext| ext == "pack")
+                && path.with_extension("idx").is_file()
+                && !self.opened.contains(&path)
+            {
+                pack_paths.push(path);
+            }
+        }
+        pack_paths.sort();
+        for path in &pack_paths {
+            self.add(PackReader::open(path)?)?;
+            self.opened.insert(path.clone());
+        }
+        Ok(pack_paths.len())
+    }
+
+    /// Add an opened pack, which must use the set's hash kind.
+    pub fn add(&mut self, reader: PackReader) -> Result<(), GitError> {
+        if reader.hash_kind() != self.kind {
+            return Err(GitError::InvalidPackFile(format!(
+                "pack uses {} but the set uses {}",
+                reader.hash_kind(),
+                self.kind
+            )));
+        }
+        self.packs.push(Arc::new(reader));
+        Ok(())
+    }
+
+    /// The packs of the set, in lookup order.
+    pub fn packs(&self) -> &[Arc<PackReader>] {
+        &self.packs
+    }
+
+    /// Number of packs.
+    pub fn len(&self) -> usize {
+        self.packs.len()
+    }
+
+    /// Whether the set has no pack.
+    pub fn is_empty(&self) -> bool {
+        self.packs.is_empty()
+    }
+
+    fn find(&self, hash: &ObjectHash) -> Option<&PackReader> {
+        self.packs
+            .iter()
+            .find(|pack| pack.contains(hash))
+            .map(|pack| pack.as_ref())
+    }
+}
+
+impl ObjectDatabase for PackSet {
+    fn hash_kind(&self) -> HashKind {
+        self.kind
+    }
+
+    fn contains(&self, hash: &ObjectHash) -> Result<bool, GitError> {
+        Ok(self.find(hash).is_some())
+    }
+
+    fn read_header(&self, hash: &ObjectHash) -> Result<(ObjectType, usize), GitError> {
+        self.find(hash)
+            .ok_or_else(|| GitError::ObjectNotFound(hash.to_string()))?
+            .read_header(hash)
+    }
+
+    fn read(&self, hash: &ObjectHash) -> Result<(ObjectType, Vec<u8>), GitError> {
+        let pack = self
+            .find(hash)
+            .ok_or_else(|| GitError::ObjectNotFound(hash.to_string()))?;
+        let entry = pack.read_object(hash)?;
+        Ok((entry.obj_type, entry.data))
+    }
+
+    fn write(&self, _obj_type: ObjectType, _data: &[u8]) -> Result<ObjectHash, GitError> {
+        Err(GitError::CustomError(
+            "objects cannot be written to a pack set".to_string(),
+        ))
+    }
+
+    fn hashes(&self) -> Result<Vec<ObjectHash>, GitError> {
+        let mut hashes: Vec<ObjectHash> = self
+            .packs
+            .iter()
+            .flat_map(|pack| pack.index().iter().map(|entry| entry.hash))
+            .collect();
+        hashes.sort();
+        hashes.dedup();
+        Ok(hashes)
+    }
+
+    fn lookup_prefix(&self, hex_prefix: &str) -> Result<Vec<ObjectHash>, GitError> {
+        let mut hashes = Vec::new();
+        for pack in &self.packs {
+            let index = pack.index();
+            hashes.extend(
+                index
+                    .lookup_prefix(hex_prefix)?
+                    .map(|pos| index.hash_at(pos)),
+            );
+        }
+        hashes.sort();
+        hashes.dedup();
+        Ok(hashes)
+    }
+
+    fn approximate_object_count(&self) -> Result<usize, GitError> {
+        Ok(self
+            .packs
+            .iter()
+            .map(|pack| pack.index().object_count())
+            .sum())
+    }
+}
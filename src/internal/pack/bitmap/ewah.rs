@@ -0,0 +1,342 @@
+//! Plain bitsets and their EWAH (Enhanced Word-Aligned Hybrid) serialization as used by Git's
+//! `.bitmap` files.
+//!
+//! Bitmaps are kept uncompressed in memory and only run-length encoded when written. The encoder
+//! follows Git's `bitmap_to_ewah` word for word, so a bitmap serializes to the same bytes as it
+//! does in Git's writer.
+
+use crate::errors::GitError;
+
+const RLW_RUNNING_BITS: u32 = 32;
+const RLW_LARGEST_RUNNING_COUNT: u64 = (1 << RLW_RUNNING_BITS) - 1;
+const RLW_LARGEST_LITERAL_COUNT: u64 = (1 << 31) - 1;
+
+/// Uncompressed bitset; bit `i` lives in word `i / 64` at bit `i % 64`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bitmap {
+    words: Vec<u64>,
+}
+
+impl Bitmap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bitmap with room for `bits` bits before it has to grow.
+    pub fn with_capacity(bits: usize) -> Self {
+        Bitmap {
+            words: Vec::with_capacity(bits.div_ceil(64)),
+        }
+    }
+
+    pub fn set(&mut self, pos: usize) {
+        let word = pos / 64;
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1 << (pos % 64);
+    }
+
+    pub fn get(&self, pos: usize) -> bool {
+        self.words
+            .get(pos / 64)
+            .is_some_and(|w| w & (1 << (pos % 64)) != 0)
+    }
+
+    /// Number of set bits.
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&w| w == 0)
+    }
+
+    /// `self |= other`
+    pub fn or_assign(&mut self, other: &Bitmap) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (a, b) in self.words.iter_mut().zip(&other.words) {
+            *a |= b;
+        }
+    }
+
+    /// `self &= !other`
+    pub fn and_not_assign(&mut self, other: &Bitmap) {
+        for (a, b) in self.words.iter_mut().zip(&other.words) {
+            *a &= !b;
+        }
+    }
+
+    /// `self ^ other`
+    pub fn xor(&self, other: &Bitmap) -> Bitmap {
+        let len = self.words.len().max(other.words.len());
+        let word = |bm: &Bitmap, i: usize| bm.words.get(i).copied().unwrap_or(0);
+        Bitmap {
+            words: (0..len).map(|i| word(self, i) ^ word(other, i)).collect(),
+        }
+    }
+
+    /// Positions of set bits in ascending order.
+    pub fn iter_ones(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, &word)| {
+            let mut rest = word;
+            std::iter::from_fn(move || {
+                if rest == 0 {
+                    return None;
+                }
+                let bit = rest.trailing_zeros() as usize;
+                rest &= rest - 1;
+                Some(i * 64 + bit)
+            })
+        })
+    }
+
+    /// Serialize as an EWAH bitmap: bit size, word count, words, and the last RLW position, all
+    /// big-endian.
+    pub fn to_ewah(&self) -> Vec<u8> {
+        let ewah = EwahWriter::from_words(&self.words);
+        let mut out = Vec::with_capacity(12 + ewah.buffer.len() * 8);
+        out.extend_from_slice(&(ewah.bit_size as u32).to_be_bytes());
+        out.extend_from_slice(&(ewah.buffer.len() as u32).to_be_bytes());
+        for word in &ewah.buffer {
+            out.extend_from_slice(&word.to_be_bytes());
+        }
+        out.extend_from_slice(&(ewah.rlw as u32).to_be_bytes());
+        out
+    }
+
+    /// Size in bytes of [`Self::to_ewah`], without building the output.
+    pub fn ewah_size(&self) -> usize {
+        12 + EwahWriter::from_words(&self.words).buffer.len() * 8
+    }
+
+    /// Parse an EWAH bitmap from the start of `data`, returning it and the number of bytes used.
+    pub fn from_ewah(data: &[u8]) -> Result<(Bitmap, usize), GitError> {
+        let invalid = |msg: &str| GitError::InvalidBitmapFile(format!("EWAH bitmap: {msg}"));
+        if data.len() < 8 {
+            return Err(invalid("truncated header"));
+        }
+        let bit_size = u32::from_be_bytes(data[0..4].try_into().unwrap()) as usize;
+        let word_count = u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize;
+        let end = word_count
+            .checked_mul(8)
+            .and_then(|n| n.checked_add(12))
+            .filter(|&end| end <= data.len())
+            .ok_or_else(|| invalid("truncated word buffer"))?;
+        let word_at =
+            |i: usize| u64::from_be_bytes(data[8 + i * 8..16 + i * 8].try_into().unwrap());
+
+        let mut words = Vec::new();
+        let mut i = 0;
+        while i < word_count {
+            let rlw = word_at(i);
+            let run_bit = rlw & 1 == 1;
+            let run_len = ((rlw >> 1) & RLW_LARGEST_RUNNING_COUNT) as usize;
+            let literals = (rlw >> (1 + RLW_RUNNING_BITS)) as usize;
+            if i + 1 + literals > word_count {
+                return Err(invalid("literal words run past the buffer"));
+            }
+            if words.len() + run_len + literals > bit_size.div_ceil(64) {
+                return Err(invalid("words run past the declared bit size"));
+            }
+            words.resize(words.len() + run_len, if run_bit { u64::MAX } else { 0 });
+            words.extend((i + 1..i + 1 + literals).map(word_at));
+            i += 1 + literals;
+        }
+        Ok((Bitmap { words }, end))
+    }
+}
+
+/// Port of Git's EWAH append logic (`ewah_add`, `ewah_add_empty_words`).
+struct EwahWriter {
+    buffer: Vec<u64>,
+    rlw: usize,
+    bit_size: usize,
+}
+
+impl EwahWriter {
+    fn new() -> Self {
+        EwahWriter {
+            buffer: vec![0],
+            rlw: 0,
+            bit_size: 0,
+        }
+    }
+
+    /// Equivalent of Git's `bitmap_to_ewah`: trailing zero words are dropped and an empty bitmap
+    /// becomes a single zero word.
+    fn from_words(words: &[u64]) -> Self {
+        let mut ewah = EwahWriter::new();
+        let mut running_empty = 0;
+        let mut last = 0;
+        for &word in words {
+            if word == 0 {
+                running_empty += 1;
+                continue;
+            }
+            if last != 0 {
+                ewah.add(last);
+            }
+            if running_empty > 0 {
+                ewah.add_empty_words(false, running_empty);
+                running_empty = 0;
+            }
+            last = word;
+        }
+        ewah.add(last);
+        ewah
+    }
+
+    fn run_bit(&self) -> bool {
+        self.buffer[self.rlw] & 1 == 1
+    }
+
+    fn set_run_bit(&mut self, bit: bool) {
+        let rlw = &mut self.buffer[self.rlw];
+        *rlw = (*rlw & !1) | bit as u64;
+    }
+
+    fn running_len(&self) -> u64 {
+        (self.buffer[self.rlw] >> 1) & RLW_LARGEST_RUNNING_COUNT
+    }
+
+    fn set_running_len(&mut self, len: u64) {
+        let rlw = &mut self.buffer[self.rlw];
+        *rlw = (*rlw & !(RLW_LARGEST_RUNNING_COUNT << 1)) | (len << 1);
+    }
+
+    fn literal_words(&self) -> u64 {
+        self.buffer[self.rlw] >> (1 + RLW_RUNNING_BITS)
+    }
+
+    fn set_literal_words(&mut self, count: u64) {
+        let mask = (1u64 << (1 + RLW_RUNNING_BITS)) - 1;
+        let rlw = &mut self.buffer[self.rlw];
+        *rlw = (*rlw & mask) | (count << (1 + RLW_RUNNING_BITS));
+    }
+
+    fn push_rlw(&mut self) {
+        self.buffer.push(0);
+        self.rlw = self.buffer.len() - 1;
+    }
+
+    fn add(&mut self, word: u64) {
+        self.bit_size += 64;
+        match word {
+            0 => self.add_empty_word(false),
+            u64::MAX => self.add_empty_word(true),
+            _ => self.add_literal(word),
+        }
+    }
+
+    fn add_empty_word(&mut self, bit: bool) {
+        let no_literal = self.literal_words() == 0;
+        let run_len = self.running_len();
+        if no_literal && run_len == 0 {
+            self.set_run_bit(bit);
+        }
+        if no_literal && self.run_bit() == bit && run_len < RLW_LARGEST_RUNNING_COUNT {
+            self.set_running_len(run_len + 1);
+        } else {
+            self.push_rlw();
+            self.set_run_bit(bit);
+            self.set_running_len(1);
+        }
+    }
+
+    fn add_literal(&mut self, word: u64) {
+        let count = self.literal_words();
+        if count >= RLW_LARGEST_LITERAL_COUNT {
+            self.push_rlw();
+            self.set_literal_words(1);
+        } else {
+            self.set_literal_words(count + 1);
+        }
+        self.buffer.push(word);
+    }
+
+    fn add_empty_words(&mut self, bit: bool, mut number: u64) {
+        self.bit_size += number as usize * 64;
+        let size = self.running_len() + self.literal_words();
+        if self.run_bit() != bit && size == 0 {
+            self.set_run_bit(bit);
+        } else if self.literal_words() != 0 || self.run_bit() != bit {
+            self.push_rlw();
+            self.set_run_bit(bit);
+        }
+        let run_len = self.running_len();
+        let can_add = number.min(RLW_LARGEST_RUNNING_COUNT - run_len);
+        self.set_running_len(run_len + can_add);
+        number -= can_add;
+        while number >= RLW_LARGEST_RUNNING_COUNT {
+            self.push_rlw();
+            self.set_run_bit(bit);
+            self.set_running_len(RLW_LARGEST_RUNNING_COUNT);
+            number -= RLW_LARGEST_RUNNING_COUNT;
+        }
+        if number > 0 {
+            self.push_rlw();
+            self.set_run_bit(bit);
+            self.set_running_len(number);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bitmap;
+
+    fn bitmap_of(bits: &[usize]) -> Bitmap {
+        let mut bm = Bitmap::new();
+        for &bit in bits {
+            bm.set(bit);
+        }
+        bm
+    }
+
+    /// Sparse, dense, and empty bitmaps survive an EWAH roundtrip, with all-ones words run-length
+    /// encoded.
+    #[test]
+    fn test_ewah_roundtrip() {
+        let sparse = bitmap_of(&[0, 3, 64, 1000, 5000]);
+        let dense = bitmap_of(&(0..640).chain(700..704).collect::<Vec<_>>());
+        for bm in [sparse, dense, Bitmap::new()] {
+            let bytes = bm.to_ewah();
+            assert_eq!(bytes.len(), bm.ewah_size());
+            let (decoded, used) = Bitmap::from_ewah(&bytes).unwrap();
+            assert_eq!(used, bytes.len());
+            assert_eq!(
+                decoded.iter_ones().collect::<Vec<_>>(),
+                bm.iter_ones().collect::<Vec<_>>()
+            );
+        }
+
+        // Ten all-ones words collapse into a single RLW that also counts the trailing literal.
+        let dense = bitmap_of(&(0..640).chain(700..704).collect::<Vec<_>>());
+        assert_eq!(dense.ewah_size(), 12 + 2 * 8);
+    }
+
+    /// Set operations match their definitions.
+    #[test]
+    fn test_bitmap_ops() {
+        let mut a = bitmap_of(&[1, 2, 200]);
+        let b = bitmap_of(&[2, 3]);
+        assert_eq!(a.xor(&b).iter_ones().collect::<Vec<_>>(), vec![1, 3, 200]);
+        a.or_assign(&b);
+        assert_eq!(a.count_ones(), 4);
+        a.and_not_assign(&b);
+        assert_eq!(a.iter_ones().collect::<Vec<_>>(), vec![1, 200]);
+        assert!(a.get(200) && !a.get(2));
+        assert!(!a.is_empty());
+    }
+
+    /// Truncated input is rejected instead of panicking.
+    #[test]
+    fn test_ewah_truncated() {
+        let bytes = bitmap_of(&[5, 900]).to_ewah();
+        assert!(Bitmap::from_ewah(&bytes[..bytes.len() - 5]).is_err());
+        assert!(Bitmap::from_ewah(&bytes[..6]).is_err());
+    }
+}
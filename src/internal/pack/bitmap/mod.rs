@@ -0,0 +1,677 @@
+//! Reachability bitmaps for packs, in Git's
+//! [pack-bitmap format](https://git-scm.com/docs/gitformat-pack#_bitmap_format) version 1.
+//!
+//! A `.bitmap` file stores, for a selection of commits, the set of objects reachable from each one
+//! as an EWAH-compressed bitmap over pack positions (object order by pack offset, see
+//! [`ReverseIndex`]).
+//!
+//! [`BitmapBuilder`] writes bitmaps for a complete (closed under reachability) pack;
+//! [`PackBitmap`] reads them back and answers reachability queries over a [`PackReader`].
+//!
+//! Nothing else in the crate reads bitmaps: upload-pack and
+//! [`RepositoryAccess`](crate::protocol::RepositoryAccess) still walk the object graph. A
+//! caller that holds a [`PackReader`] and its bitmap can get the objects to send with
+//! [`PackBitmap::want_minus_have`], which combines stored bitmaps with short walks from
+//! non-bitmapped tips.
+
+pub mod ewah;
+
+use std::{collections::HashMap, path::Path};
+
+pub use ewah::Bitmap;
+
+use crate::{
+    errors::GitError,
+    hash::{HashKind, ObjectHash},
+    internal::{
+        object::{
+            ObjectTrait,
+            commit::Commit,
+            tag::Tag,
+            tree::{Tree, TreeItemMode},
+            types::ObjectType,
+        },
+        pack::{
+            entry::Entry, mmap::MappedFile, pack_index::PackIndex, reader::PackReader,
+            reverse_index::ReverseIndex,
+        },
+    },
+    utils::HashAlgorithm,
+};
+
+const BITMAP_MAGIC: [u8; 4] = *b"BITM";
+const BITMAP_VERSION: u16 = 1;
+/// The pack is closed under reachability; Git refuses bitmaps without this flag.
+const BITMAP_OPT_FULL_DAG: u16 = 0x1;
+const BITMAP_HEADER_SIZE: usize = 12;
+/// Largest distance Git accepts between an entry and the entry it is XORed against.
+const MAX_XOR_OFFSET: usize = 160;
+/// How many preceding entries the writer tries as XOR bases, as in Git.
+const XOR_SEARCH_WINDOW: usize = 10;
+/// Every one of this many most recent commits gets a bitmap...
+const RECENT_COMMITS: usize = 100;
+/// ...and after that one commit out of this many.
+const SELECTION_INTERVAL: usize = 100;
+
+/// Type bitmaps in file order.
+const TYPE_ORDER: [ObjectType; 4] = [
+    ObjectType::Commit,
+    ObjectType::Tree,
+    ObjectType::Blob,
+    ObjectType::Tag,
+];
+
+/// Objects `entry` points at (a commit's tree and parents, a tree's entries except gitlinks, a
+/// tag's target), and the commit time for commits.
+fn parse_links(entry: &Entry) -> Result<(Vec<ObjectHash>, Option<usize>), GitError> {
+    Ok(match entry.obj_type {
+        ObjectType::Commit => {
+            let commit = Commit::from_bytes(&entry.data, entry.hash)?;
+            let mut links = vec![commit.tree_id];
+            links.extend(commit.parent_commit_ids);
+            (links, Some(commit.committer.timestamp))
+        }
+        ObjectType::Tree => {
+            let tree = Tree::from_bytes(&entry.data, entry.hash)?;
+            let links = tree
+                .tree_items
+                .into_iter()
+                .filter(|item| item.mode != TreeItemMode::Commit)
+                .map(|item| item.id)
+                .collect();
+            (links, None)
+        }
+        ObjectType::Tag => {
+            let tag = Tag::from_bytes(&entry.data, entry.hash)?;
+            (vec![tag.object_hash], None)
+        }
+        _ => (Vec::new(), None),
+    })
+}
+
+/// `pack_pos[i]` is the pack position of the object at sorted `.idx` position `i`.
+fn pack_positions(index: &PackIndex, rev: &ReverseIndex) -> Vec<u32> {
+    let mut pack_pos = vec![0u32; index.object_count()];
+    for pos in 0..index.object_count() {
+        pack_pos[rev.index_pos_at(pos)] = pos as u32;
+    }
+    pack_pos
+}
+
+/// Writes a `.bitmap` for every commit selected from a pack.
+///
+/// Selection follows Git's shape: all commits when there are few, otherwise the most recent
+/// commits by committer date plus one in every hundred older ones, and always the given ref tips
+/// (tags are peeled). Each bitmap is stored XORed against whichever of the previous ten entries
+/// makes it smallest.
+///
+/// The pack must be closed under reachability (every commit, tree, and blob an object refers to
+/// is in the same pack), which is what Git's `BITMAP_OPT_FULL_DAG` promises.
+pub struct BitmapBuilder<'a> {
+    reader: &'a PackReader,
+    tips: Vec<ObjectHash>,
+}
+
+impl<'a> BitmapBuilder<'a> {
+    pub fn new(reader: &'a PackReader) -> Self {
+        BitmapBuilder {
+            reader,
+            tips: Vec::new(),
+        }
+    }
+
+    /// Ref tips that must get their own bitmap.
+    pub fn with_tips(mut self, tips: impl IntoIterator<Item = ObjectHash>) -> Self {
+        self.tips.extend(tips);
+        self
+    }
+
+    /// Read every object of the pack, compute the selected bitmaps, and serialize the file.
+    pub fn build(&self) -> Result<Vec<u8>, GitError> {
+        let index = self.reader.index();
+        let n = index.object_count();
+        let rev = ReverseIndex::from_pack_index(index);
+        let pack_pos = pack_positions(index, &rev);
+        let pos_of = |hash: &ObjectHash| {
+            index
+                .find(hash)
+                .map(|i| pack_pos[i] as usize)
+                .ok_or_else(|| {
+                    GitError::InvalidPackFile(format!(
+                        "pack is not closed under reachability: {hash} is missing"
+                    ))
+                })
+        };
+
+        let mut types: [Bitmap; 4] = Default::default();
+        let mut links: Vec<Vec<u32>> = vec![Vec::new(); n];
+        let mut commit_time: HashMap<usize, usize> = HashMap::new();
+        for (pos, slot) in links.iter_mut().enumerate() {
+            let entry = self
+                .reader
+                .read_object_at(index.offset_at(rev.index_pos_at(pos)))?;
+            if let Some(t) = TYPE_ORDER.iter().position(|t| *t == entry.obj_type) {
+                types[t].set(pos);
+            }
+            let (targets, time) = parse_links(&entry)?;
+            for target in &targets {
+                slot.push(pos_of(target)? as u32);
+            }
+            if let Some(time) = time {
+                commit_time.insert(pos, time);
+            }
+        }
+        let is_commit = |pos: usize| types[0].get(pos);
+
+        // Newest first; ties broken by pack position so the selection is deterministic.
+        let mut commits: Vec<usize> = commit_time.keys().copied().collect();
+        commits.sort_by_key(|&pos| (std::cmp::Reverse(commit_time[&pos]), pos));
+        let mut selected = vec![false; n];
+        for (i, &pos) in commits.iter().enumerate() {
+            if i < RECENT_COMMITS || (i - RECENT_COMMITS).is_multiple_of(SELECTION_INTERVAL) {
+                selected[pos] = true;
+            }
+        }
+        for tip in &self.tips {
+            let mut pos = pos_of(tip)?;
+            while types[3].get(pos) {
+                pos = links[pos][0] as usize;
+            }
+            if is_commit(pos) {
+                selected[pos] = true;
+            }
+        }
+
+        // Parents before children, so a commit's walk can stop at already computed ancestors.
+        let mut order = Vec::new();
+        let mut visited = vec![false; n];
+        for &root in &commits {
+            let mut stack = vec![(root, false)];
+            while let Some((pos, expanded)) = stack.pop() {
+                if expanded {
+                    order.push(pos);
+                    continue;
+                }
+                if std::mem::replace(&mut visited[pos], true) {
+                    continue;
+                }
+                stack.push((pos, true));
+                for &parent in links[pos].iter().skip(1) {
+                    if !visited[parent as usize] {
+                        stack.push((parent as usize, false));
+                    }
+                }
+            }
+        }
+
+        let mut written: Vec<(usize, Bitmap)> = Vec::new();
+        let mut computed: HashMap<usize, usize> = HashMap::new();
+        for &commit in order.iter().filter(|&&pos| selected[pos]) {
+            let mut bits = Bitmap::with_capacity(n);
+            let mut stack = vec![commit];
+            while let Some(pos) = stack.pop() {
+                if bits.get(pos) {
+                    continue;
+                }
+                if let Some(&done) = computed.get(&pos) {
+                    bits.or_assign(&written[done].1);
+                    continue;
+                }
+                bits.set(pos);
+                stack.extend(
+                    links[pos]
+                        .iter()
+                        .map(|&p| p as usize)
+                        .filter(|&p| !bits.get(p)),
+                );
+            }
+            computed.insert(commit, written.len());
+            written.push((commit, bits));
+        }
+
+        let mut out = BITMAP_MAGIC.to_vec();
+        out.extend_from_slice(&BITMAP_VERSION.to_be_bytes());
+        out.extend_from_slice(&BITMAP_OPT_FULL_DAG.to_be_bytes());
+        out.extend_from_slice(&(written.len() as u32).to_be_bytes());
+        out.extend_from_slice(index.pack_hash().as_ref());
+        for bitmap in &types {
+            out.extend(bitmap.to_ewah());
+        }
+        for (i, (commit, bits)) in written.iter().enumerate() {
+            let mut best: (usize, usize, Option<Bitmap>) = (bits.ewah_size(), 0, None);
+            for offset in 1..=XOR_SEARCH_WINDOW.min(i) {
+                let xored = bits.xor(&written[i - offset].1);
+                let size = xored.ewah_size();
+                if size < best.0 {
+                    best = (size, offset, Some(xored));
+                }
+            }
+            out.extend_from_slice(&(rev.index_pos_at(*commit) as u32).to_be_bytes());
+            out.push(best.1 as u8);
+            out.push(0); // flags
+            out.extend(best.2.as_ref().unwrap_or(bits).to_ewah());
+        }
+
+        let mut hasher = HashAlgorithm::new_with_kind(index.hash_kind());
+        hasher.update(&out);
+        out.extend(hasher.finalize());
+        Ok(out)
+    }
+}
+
+struct StoredBitmap {
+    xor_with: Option<usize>,
+    start: usize,
+}
+
+/// Reader for a pack's `.bitmap` file.
+///
+/// Entries are validated and indexed on open but only decompressed (and un-XORed) when a query
+/// needs them. Bit positions are pack positions of the pack the bitmap was opened against.
+pub struct PackBitmap {
+    data: MappedFile,
+    kind: HashKind,
+    types: [Bitmap; 4],
+    entries: Vec<StoredBitmap>,
+    by_commit: HashMap<ObjectHash, usize>,
+    rev: ReverseIndex,
+    pack_pos: Vec<u32>,
+}
+
+impl PackBitmap {
+    /// Open the `.bitmap` at `path` for the pack described by `index`.
+    pub fn open(path: impl AsRef<Path>, index: &PackIndex) -> Result<Self, GitError> {
+        let path = path.as_ref();
+        let data = MappedFile::open(path).map_err(|e| {
+            GitError::InvalidBitmapFile(format!("failed to read {}: {e}", path.display()))
+        })?;
+        Self::parse(data, index)
+    }
+
+    /// Parse an in-memory `.bitmap` for the pack described by `index`.
+    pub fn from_bytes(data: Vec<u8>, index: &PackIndex) -> Result<Self, GitError> {
+        Self::parse(data.into(), index)
+    }
+
+    fn parse(data: MappedFile, index: &PackIndex) -> Result<Self, GitError> {
+        let invalid = |msg: String| GitError::InvalidBitmapFile(msg);
+        let kind = index.hash_kind();
+        let hash_size = kind.size();
+        if data.len() < BITMAP_HEADER_SIZE + 2 * hash_size {
+            return Err(invalid(format!("file is only {} bytes", data.len())));
+        }
+        if data[..4] != BITMAP_MAGIC {
+            return Err(invalid("missing BITM magic".to_string()));
+        }
+        let version = u16::from_be_bytes([data[4], data[5]]);
+        if version != BITMAP_VERSION {
+            return Err(invalid(format!("unsupported version {version}")));
+        }
+        let options = u16::from_be_bytes([data[6], data[7]]);
+        if options & BITMAP_OPT_FULL_DAG == 0 {
+            return Err(invalid(
+                "bitmaps without the full-DAG option are not supported".to_string(),
+            ));
+        }
+        let entry_count = u32::from_be_bytes(data[8..12].try_into().unwrap()) as usize;
+        if data[BITMAP_HEADER_SIZE..BITMAP_HEADER_SIZE + hash_size] != *index.pack_hash().as_ref() {
+            return Err(invalid("bitmap belongs to a different pack".to_string()));
+        }
+        let checksum_start = data.len() - hash_size;
+        let mut hasher = HashAlgorithm::new_with_kind(kind);
+        hasher.update(&data[..checksum_start]);
+        if hasher.finalize() != data[checksum_start..] {
+            return Err(invalid(
+                "trailer checksum does not match file contents".to_string(),
+            ));
+        }
+
+        let body = &data[..checksum_start];
+        let mut at = BITMAP_HEADER_SIZE + hash_size;
+        let mut types: [Bitmap; 4] = Default::default();
+        for bitmap in types.iter_mut() {
+            let (decoded, used) = Bitmap::from_ewah(&body[at..])?;
+            *bitmap = decoded;
+            at += used;
+        }
+
+        let mut entries = Vec::with_capacity(entry_count);
+        let mut by_commit = HashMap::with_capacity(entry_count);
+        for i in 0..entry_count {
+            if body.len() < at + 6 {
+                return Err(invalid(format!("entry {i} is truncated")));
+            }
+            let commit_pos = u32::from_be_bytes(body[at..at + 4].try_into().unwrap()) as usize;
+            let xor_offset = body[at + 4] as usize;
+            if commit_pos >= index.object_count() {
+                return Err(invalid(format!(
+                    "entry {i} names object {commit_pos} outside the pack"
+                )));
+            }
+            if xor_offset > MAX_XOR_OFFSET || xor_offset > i {
+                return Err(invalid(format!(
+                    "entry {i} has invalid XOR offset {xor_offset}"
+                )));
+            }
+            let start = at + 6;
+            let (_, used) = Bitmap::from_ewah(&body[start..])?;
+            at = start + used;
+            by_commit.insert(index.hash_at(commit_pos), i);
+            entries.push(StoredBitmap {
+                xor_with: (xor_offset > 0).then(|| i - xor_offset),
+                start,
+            });
+        }
+
+        let rev = ReverseIndex::from_pack_index(index);
+        let pack_pos = pack_positions(index, &rev);
+        Ok(PackBitmap {
+            data,
+            kind,
+            types,
+            entries,
+            by_commit,
+            rev,
+            pack_pos,
+        })
+    }
+
+    /// Number of commits with a stored bitmap.
+    pub fn entry_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether `commit` has a stored bitmap.
+    pub fn has_commit(&self, commit: &ObjectHash) -> bool {
+        self.by_commit.contains_key(commit)
+    }
+
+    /// Objects of `obj_type` in the pack; `None` for types that have no type bitmap.
+    pub fn type_bitmap(&self, obj_type: ObjectType) -> Option<&Bitmap> {
+        TYPE_ORDER
+            .iter()
+            .position(|t| *t == obj_type)
+            .map(|i| &self.types[i])
+    }
+
+    /// Everything reachable from `commit`, if it has a stored bitmap.
+    pub fn commit_bitmap(&self, commit: &ObjectHash) -> Option<Bitmap> {
+        let mut chain = vec![*self.by_commit.get(commit)?];
+        while let Some(base) = self.entries[*chain.last().unwrap()].xor_with {
+            chain.push(base);
+        }
+        let decode = |i: usize| {
+            Bitmap::from_ewah(&self.data[self.entries[i].start..])
+                .expect("validated on open")
+                .0
+        };
+        let mut bitmap = decode(chain.pop().unwrap());
+        while let Some(i) = chain.pop() {
+            bitmap = decode(i).xor(&bitmap);
+        }
+        Some(bitmap)
+    }
+
+    /// Objects reachable from `tips`, using stored bitmaps where possible and walking the pack
+    /// elsewhere. `reader` must be over the pack this bitmap was opened for.
+    ///
+    /// Returns `ObjectNotFound` if a tip is not in the pack.
+    pub fn reachable(&self, reader: &PackReader, tips: &[ObjectHash]) -> Result<Bitmap, GitError> {
+        self.walk(reader, tips, false)
+    }
+
+    /// Objects reachable from `wants` but not from `haves`, in pack order: the object set an
+    /// upload-pack response has to contain. Haves that are not in the pack are ignored, since
+    /// clients may advertise objects the server does not have.
+    pub fn want_minus_have(
+        &self,
+        reader: &PackReader,
+        wants: &[ObjectHash],
+        haves: &[ObjectHash],
+    ) -> Result<Vec<ObjectHash>, GitError> {
+        let mut bits = self.walk(reader, wants, false)?;
+        bits.and_not_assign(&self.walk(reader, haves, true)?);
+        Ok(self.objects(reader.index(), &bits))
+    }
+
+    /// Object names for the set bits of `bits`, in pack order.
+    pub fn objects(&self, index: &PackIndex, bits: &Bitmap) -> Vec<ObjectHash> {
+        bits.iter_ones()
+            .take_while(|&pos| pos < self.rev.object_count())
+            .map(|pos| index.hash_at(self.rev.index_pos_at(pos)))
+            .collect()
+    }
+
+    fn walk(
+        &self,
+        reader: &PackReader,
+        tips: &[ObjectHash],
+        skip_missing: bool,
+    ) -> Result<Bitmap, GitError> {
+        if reader.hash_kind() != self.kind {
+            return Err(GitError::InvalidBitmapFile(
+                "reader and bitmap use different hash kinds".to_string(),
+            ));
+        }
+        let index = reader.index();
+        let mut bits = Bitmap::with_capacity(index.object_count());
+        let mut stack: Vec<ObjectHash> = Vec::new();
+        for tip in tips {
+            if index.contains(tip) {
+                stack.push(*tip);
+            } else if !skip_missing {
+                return Err(GitError::ObjectNotFound(tip.to_string()));
+            }
+        }
+        while let Some(hash) = stack.pop() {
+            let Some(idx_pos) = index.find(&hash) else {
+                return Err(GitError::InvalidPackFile(format!(
+                    "pack is not closed under reachability: {hash} is missing"
+                )));
+            };
+            let pos = self.pack_pos[idx_pos] as usize;
+            if bits.get(pos) {
+                continue;
+            }
+            if let Some(stored) = self.commit_bitmap(&hash) {
+                bits.or_assign(&stored);
+                continue;
+            }
+            bits.set(pos);
+            if self.types[2].get(pos) {
+                continue; // blobs have no links; skip reading them
+            }
+            let entry = reader.read_object_at(index.offset_at(idx_pos))?;
+            stack.extend(parse_links(&entry)?.0);
+        }
+        Ok(bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path, process::Command};
+
+    use super::{BitmapBuilder, PackBitmap};
+    use crate::{
+        errors::GitError,
+        hash::{HashKind, ObjectHash, set_hash_kind_for_test},
+        internal::{
+            object::{
+                ObjectTrait,
+                blob::Blob,
+                commit::Commit,
+                signature::{Signature, SignatureType},
+                tree::{Tree, TreeItem, TreeItemMode},
+                types::ObjectType,
+            },
+            pack::{pack_index::PackIndex, reader::PackReader, test_pack_builder::TestPackBuilder},
+        },
+    };
+
+    /// A linear history of `n` commits, each adding one file; returns the reader and commit ids
+    /// (oldest first).
+    async fn linear_history(n: usize) -> (PackReader, Vec<ObjectHash>) {
+        let mut builder = TestPackBuilder::new();
+        let mut items = Vec::new();
+        let mut parents = Vec::new();
+        let mut commits = Vec::new();
+        for i in 0..n {
+            let blob = Blob::from_content(&format!("file {i}\n"));
+            builder.add_base(ObjectType::Blob, &blob.data);
+            items.push(TreeItem::new(
+                TreeItemMode::Blob,
+                blob.id,
+                format!("f{i:03}"),
+            ));
+            let tree = Tree::from_tree_items(items.clone()).unwrap();
+            builder.add_base(ObjectType::Tree, &tree.to_data().unwrap());
+            let mut sig = Signature::new(
+                SignatureType::Author,
+                "t".to_string(),
+                "t@example.com".to_string(),
+            );
+            sig.timestamp = 1_700_000_000 + i;
+            let mut committer = sig.clone();
+            committer.signature_type = SignatureType::Committer;
+            let commit = Commit::new(sig, committer, tree.id, parents.clone(), &format!("c{i}"));
+            builder.add_base(ObjectType::Commit, &commit.to_data().unwrap());
+            parents = vec![commit.id];
+            commits.push(commit.id);
+        }
+        let index = PackIndex::from_bytes(builder.idx_bytes().await).unwrap();
+        let reader = PackReader::from_bytes(builder.pack_bytes(), index).unwrap();
+        (reader, commits)
+    }
+
+    /// Commit `i` of a linear history reaches `3 * (i + 1)` objects; stored bitmaps, partial
+    /// walks, and want-minus-have all agree with that.
+    #[tokio::test]
+    async fn test_bitmap_roundtrip_and_queries() {
+        for kind in [HashKind::Sha1, HashKind::Sha256] {
+            let _guard = set_hash_kind_for_test(kind);
+            let (reader, commits) = linear_history(150).await;
+            let data = BitmapBuilder::new(&reader)
+                .with_tips([commits[149]])
+                .build()
+                .unwrap();
+            let bitmap = PackBitmap::from_bytes(data, reader.index()).unwrap();
+
+            // The 100 newest commits plus every hundredth older one.
+            assert_eq!(bitmap.entry_count(), 101);
+            assert!(bitmap.has_commit(&commits[149]) && bitmap.has_commit(&commits[49]));
+            assert!(!bitmap.has_commit(&commits[48]));
+            assert_eq!(
+                bitmap.type_bitmap(ObjectType::Commit).unwrap().count_ones(),
+                150
+            );
+            assert_eq!(
+                bitmap.commit_bitmap(&commits[149]).unwrap().count_ones(),
+                450
+            );
+
+            // commits[10] has no stored bitmap and is reached by walking.
+            let reach = bitmap.reachable(&reader, &[commits[10]]).unwrap();
+            assert_eq!(reach.count_ones(), 33);
+
+            let missing = bitmap
+                .want_minus_have(&reader, &[commits[120]], &[commits[100], commits[10]])
+                .unwrap();
+            assert_eq!(missing.len(), 60);
+            assert!(missing.contains(&commits[120]) && !missing.contains(&commits[100]));
+        }
+    }
+
+    /// Bitmaps for another pack, and wants outside the pack, are rejected.
+    #[tokio::test]
+    async fn test_bitmap_rejects_mismatch() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let (reader, commits) = linear_history(3).await;
+        let (other, _) = linear_history(4).await;
+        let data = BitmapBuilder::new(&reader).build().unwrap();
+        assert!(matches!(
+            PackBitmap::from_bytes(data.clone(), other.index()),
+            Err(GitError::InvalidBitmapFile(_))
+        ));
+
+        let bitmap = PackBitmap::from_bytes(data, reader.index()).unwrap();
+        assert_eq!(bitmap.entry_count(), 3);
+        let outside = ObjectHash::Sha1([0xab; 20]);
+        assert!(matches!(
+            bitmap.want_minus_have(&reader, &[outside], &[commits[0]]),
+            Err(GitError::ObjectNotFound(_))
+        ));
+    }
+
+    fn git(dir: &Path, args: &[&str]) -> Option<std::process::Output> {
+        Command::new("git")
+            .current_dir(dir)
+            .args(args)
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("HOME", dir)
+            .output()
+            .ok()
+    }
+
+    /// A bitmap written by `git repack -adb` reads back with the reachability Git reports, and
+    /// one we write for the same pack passes `git rev-list --test-bitmap`.
+    #[test]
+    fn test_bitmap_git_interop() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path();
+        if git(repo, &["init", "-q"]).is_none() {
+            return;
+        }
+        let run = |args: &[&str]| {
+            let output = git(repo, args).unwrap();
+            assert!(
+                output.status.success(),
+                "{}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            String::from_utf8(output.stdout).unwrap()
+        };
+        for n in 0..5 {
+            fs::write(repo.join(format!("file{n}.txt")), format!("content {n}\n")).unwrap();
+            run(&["add", "."]);
+            run(&[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "-qm",
+                "c",
+            ]);
+        }
+        run(&["repack", "-adbq"]);
+
+        let pack_dir = repo.join(".git/objects/pack");
+        let pack_path = fs::read_dir(&pack_dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "pack"))
+            .unwrap();
+        let bitmap_path = pack_path.with_extension("bitmap");
+        let reader = PackReader::open(&pack_path).unwrap();
+        let head: ObjectHash = run(&["rev-parse", "HEAD"]).trim().parse().unwrap();
+        let reachable = run(&["rev-list", "--objects", "HEAD"]).lines().count();
+
+        let theirs = PackBitmap::open(&bitmap_path, reader.index()).unwrap();
+        assert!(theirs.has_commit(&head));
+        assert_eq!(theirs.commit_bitmap(&head).unwrap().count_ones(), reachable);
+
+        let ours = BitmapBuilder::new(&reader)
+            .with_tips([head])
+            .build()
+            .unwrap();
+        fs::write(&bitmap_path, ours).unwrap();
+        let test = git(repo, &["rev-list", "--test-bitmap", "HEAD"]).unwrap();
+        assert!(
+            test.status.success(),
+            "{}",
+            String::from_utf8_lossy(&test.stderr)
+        );
+    }
+}
@@ -1,11 +1,13 @@
 //! Multi-tier cache for pack decoding that combines an in-memory LRU with spill-to-disk storage and
-//! bookkeeping for concurrent rebuild tasks.
+//! bookkeeping for concurrent rebuild tasks. When the pack being decoded is memory-mapped, evicted
+//! objects are rebuilt from the mapping instead of being spilled.
 
 use std::{
+    collections::HashSet,
     fs, io,
     path::{Path, PathBuf},
     sync::{
-        Arc, Mutex, Once,
+        Arc, Mutex, Once, RwLock,
         atomic::{AtomicBool, Ordering},
     },
     thread::sleep,
@@ -16,8 +18,13 @@ use lru_mem::{LruCache, entry_size};
 use threadpool::ThreadPool;
 
 use crate::{
+    errors::GitError,
     hash::ObjectHash,
-    internal::pack::cache_object::{ArcWrapper, CacheObject, FileLoadStore, MemSizeRecorder},
+    internal::pack::{
+        cache_object::{ArcWrapper, CacheObject, CacheObjectInfo, FileLoadStore, MemSizeRecorder},
+        reader::{EntryKind, MappedPack, apply_delta},
+        utils::calculate_object_hash,
+    },
     time_it,
 };
 
@@ -67,9 +74,101 @@ pub struct Caches {
     path_prefixes: [Once; 256],
     pool: Arc<ThreadPool>,
     complete_signal: Arc<AtomicBool>,
+    // pack being decoded, when it is memory-mapped; evicted objects are rebuilt from it
+    mapped_pack: RwLock<Option<Arc<MappedPack>>>,
+    hash_offsets: DashMap<ObjectHash, usize>, // hash to offset, only tracked with a mapped pack
 }
 
 impl Caches {
+    /// Attach (or detach with `None`) the memory-mapped pack that is being decoded.
+    ///
+    /// While attached, objects evicted from the LRU are not written to the temp directory; a later
+    /// lookup re-inflates them from the mapping (applying deltas against their cached bases).
+    pub(crate) fn set_mapped_pack(&self, pack: Option<Arc<MappedPack>>) {
+        if pack.is_none() {
+            self.hash_offsets.clear();
+        }
+        *self.mapped_pack.write().unwrap() = pack;
+    }
+
+    fn mapped_pack(&self) -> Option<Arc<MappedPack>> {
+        self.mapped_pack.read().unwrap().clone()
+    }
+
+    /// Rebuild the object stored at `offset` straight from the mapped pack.
+    fn rebuild_from_pack(&self, pack: &MappedPack, offset: usize) -> Result<CacheObject, GitError> {
+        let header = pack.read_entry_header(offset as u64)?;
+        let (payload, consumed) = pack.inflate(&header)?;
+        let entry_end = header.data_offset as usize + consumed;
+        let crc32 = crc32fast::hash(&pack.bytes()[offset..entry_end]);
+
+        // Walk down the delta chain to a full or cached object, then apply the deltas back up, so
+        // a long chain does not recurse once per link.
+        let is_delta_in_pack = !matches!(header.kind, EntryKind::Base(_));
+        let mut deltas = Vec::new();
+        let mut visited = HashSet::new();
+        let (mut kind, mut payload) = (header.kind, payload);
+        let (obj_type, data) =
+            loop {
+                let cached =
+                    match kind {
+                        EntryKind::Base(obj_type) => break (obj_type, payload),
+                        EntryKind::OffsetDelta(base) | EntryKind::OffsetZstdelta(base) => {
+                            let base = base as usize;
+                            self.get_by_offset(base).ok_or(base)
+                        }
+                        EntryKind::HashDelta(base_hash) => match self.get_by_hash(base_hash) {
+                            Some(obj) => Ok(obj),
+                            None => Err(self.hash_offsets.get(&base_hash).map(|x| *x).ok_or_else(
+                                || {
+                                    GitError::ObjectNotFound(format!(
+                                        "base {base_hash} of hash delta at offset {offset}"
+                                    ))
+                                },
+                            )?),
+                        },
+                    };
+                match cached {
+                    Ok(base_obj) => {
+                        let data = apply_delta(kind, &base_obj.data_decompressed, &payload)?;
+                        break (base_obj.object_type(), data);
+                    }
+                    Err(base) => {
+                        if !visited.insert(base) {
+                            return Err(GitError::InvalidPackFile(format!(
+                                "delta chain of the entry at offset {offset} loops at offset {base}"
+                            )));
+                        }
+                        deltas.push((kind, payload));
+                        let base_header = pack.read_entry_header(base as u64)?;
+                        payload = pack.inflate(&base_header)?.0;
+                        kind = base_header.kind;
+                    }
+                }
+            };
+        let data = deltas
+            .iter()
+            .rev()
+            .try_fold(data, |base, (kind, payload)| {
+                apply_delta(*kind, &base, payload)
+            })?;
+        let hash = match self.map_offset.get(&offset) {
+            Some(hash) => *hash,
+            None => calculate_object_hash(obj_type, &data),
+        };
+        Ok(CacheObject {
+            info: CacheObjectInfo::BaseObject(obj_type, hash),
+            offset,
+            crc32,
+            data_decompressed: data,
+            mem_recorder: None,
+            is_delta_in_pack,
+            known_hash: None,
+            compressed_delta: None,
+            reusable_delta: None,
+        })
+    }
+
     /// only get object from memory, not from tmp file
     fn try_get(&self, hash: ObjectHash) -> Option<Arc<CacheObject>> {
         let mut map = self.lru_cache.lock().unwrap();
@@ -102,7 +201,25 @@ impl Caches {
 
     /// !IMPORTANT: because of the process of pack, the file must be written / be writing before, so it won't be dead lock
     /// fall back to temp to get item. **invoker should ensure the hash is in the cache, or it will block forever**
+    /// <br> With a mapped pack attached, the object is rebuilt from the mapping instead.
     fn get_fallback(&self, hash: ObjectHash) -> io::Result<Arc<CacheObject>> {
+        if let Some(pack) = self.mapped_pack()
+            && let Some(offset) = self.hash_offsets.get(&hash).map(|x| *x)
+        {
+            let obj = self
+                .rebuild_from_pack(&pack, offset)
+                .map_err(io::Error::other)?;
+            let obj = Arc::new(obj);
+            let mut map = self.lru_cache.lock().unwrap();
+            let x = ArcWrapper::new(
+                obj.clone(),
+                self.complete_signal.clone(),
+                Some(self.pool.clone()),
+            );
+            self.insert_lru_resident(&mut map, hash, x);
+            return Ok(obj);
+        }
+
         let path = self.generate_temp_path(&self.tmp_path, hash);
         // read from tmp file
         let obj = {
@@ -207,6 +324,8 @@ impl Caches {
             self.resident_hash_set.shrink_to_fit();
             self.map_offset.clear();
             self.map_offset.shrink_to_fit();
+            self.hash_offsets.clear();
+            self.hash_offsets.shrink_to_fit();
         });
     }
 
@@ -282,6 +401,8 @@ impl _Cache for Caches {
             path_prefixes: [const { Once::new() }; 256],
             pool: Arc::new(ThreadPool::new(thread_num)),
             complete_signal: Arc::new(AtomicBool::new(false)),
+            mapped_pack: RwLock::new(None),
+            hash_offsets: DashMap::new(),
         }
     }
 
@@ -314,7 +435,10 @@ impl _Cache for Caches {
                 self.complete_signal.clone(),
                 Some(self.pool.clone()),
             );
-            if self.mem_size.is_some() {
+            if self.mapped_pack.read().unwrap().is_some() {
+                // no spilling: the object can be rebuilt from the mapped pack at `offset`
+                self.hash_offsets.insert(hash, offset);
+            } else if self.mem_size.is_some() {
                 a_obj.set_store_path(self.generate_temp_path(&self.tmp_path, hash));
             }
             self.insert_lru_resident(&mut map, hash, a_obj);
@@ -412,6 +536,8 @@ mod test {
             crc32: 0,
             is_delta_in_pack: false,
             known_hash: None,
+            compressed_delta: None,
+            reusable_delta: None,
         }
     }
 
@@ -493,6 +619,8 @@ mod test {
                 crc32: 0,
                 is_delta_in_pack: false,
                 known_hash: None,
+                compressed_delta: None,
+                reusable_delta: None,
             };
             cache_sha1.insert(obj.offset, hash, obj.clone());
             assert!(cache_sha1.hash_set.contains(&hash));
@@ -511,6 +639,8 @@ mod test {
                 crc32: 0,
                 is_delta_in_pack: false,
                 known_hash: None,
+                compressed_delta: None,
+                reusable_delta: None,
             };
             cache_sha256.insert(obj.offset, hash, obj.clone());
             assert!(cache_sha256.hash_set.contains(&hash));
@@ -591,4 +721,37 @@ mod test {
         assert!(!cache.resident_hash_set.contains(&a_hash));
         assert!(cache.resident_hash_set.contains(&b_hash));
     }
+
+    /// A long OFS_DELTA chain is rebuilt from the mapped pack without recursing once per link.
+    #[test]
+    fn test_rebuild_from_pack_long_chain() {
+        use crate::internal::pack::{mmap::MappedFile, test_pack_builder::TestPackBuilder};
+
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let mut builder = TestPackBuilder::new();
+        let mut data = b"revision 0 of a small blob\n".to_vec();
+        let mut last = builder.add_base(ObjectType::Blob, &data);
+        for i in 1..3000 {
+            let next = format!("revision {i} of a small blob\n").into_bytes();
+            last = builder.add_ofs_delta(last, &data, ObjectType::Blob, &next);
+            data = next;
+        }
+        let pack = MappedPack::new(MappedFile::from(builder.pack_bytes()), HashKind::Sha1);
+
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Caches::new(None, dir.path().join("tmp"), 1);
+        // A small stack, which one frame per link would overflow.
+        let rebuilt = std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || {
+                let _guard = set_hash_kind_for_test(HashKind::Sha1);
+                cache.rebuild_from_pack(&pack, last.offset as usize)
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+            .unwrap();
+        assert_eq!(rebuilt.data_decompressed, data);
+        assert_eq!(rebuilt.base_object_hash(), Some(last.hash));
+    }
 }
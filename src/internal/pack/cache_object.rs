@@ -29,7 +29,7 @@ use threadpool::ThreadPool;
 use crate::{
     hash::ObjectHash,
     internal::{
-        metadata::{EntryMeta, MetaAttached},
+        metadata::{EntryMeta, MetaAttached, ReusableDelta},
         object::types::ObjectType,
         pack::{entry::Entry, utils},
     },
@@ -111,16 +111,20 @@ pub(crate) enum CacheObjectInfo {
     /// The object is a hash delta with a specified [`ObjectHash`] hash,
     /// and the size of the expanded object (previously `delta_final_size`).
     HashDelta(ObjectHash, usize),
+    /// A trained zstd dictionary for objects of the given [`ObjectType`]. It has no object id and
+    /// only serves as the base of [`OffsetZstdelta`](CacheObjectInfo::OffsetZstdelta) entries.
+    ZstdDictionary(ObjectType),
 }
 
 impl CacheObjectInfo {
-    /// Get the [`ObjectType`] of the object.
+    /// Get the [`ObjectType`] of the object. A dictionary reports the type of the objects it serves.
     pub(crate) fn object_type(&self) -> ObjectType {
         match self {
             CacheObjectInfo::BaseObject(obj_type, _) => *obj_type,
             CacheObjectInfo::OffsetDelta(_, _) => ObjectType::OffsetDelta,
             CacheObjectInfo::OffsetZstdelta(_, _) => ObjectType::OffsetZstdelta,
             CacheObjectInfo::HashDelta(_, _) => ObjectType::HashDelta,
+            CacheObjectInfo::ZstdDictionary(obj_type) => *obj_type,
         }
     }
 }
@@ -135,6 +139,11 @@ pub struct CacheObject {
     pub mem_recorder: Option<Arc<AtomicUsize>>, // record mem-size of all CacheObjects of a Pack
     pub is_delta_in_pack: bool,
     pub(crate) known_hash: Option<ObjectHash>,
+    /// zlib stream of a delta entry as read from the pack, kept for
+    /// [`Pack::with_delta_reuse`](super::Pack::with_delta_reuse).
+    pub(crate) compressed_delta: Option<Arc<[u8]>>,
+    /// For an object rebuilt from a kept delta: that delta, handed to the callback in [`EntryMeta`].
+    pub(crate) reusable_delta: Option<ReusableDelta>,
 }
 
 #[derive(Debug, rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
@@ -179,6 +188,8 @@ impl From<CacheObjectOnDisk> for CacheObject {
             mem_recorder: None,
             is_delta_in_pack: value.is_delta_in_pack,
             known_hash: None,
+            compressed_delta: None,
+            reusable_delta: None,
         }
     }
 }
@@ -210,6 +221,8 @@ impl Clone for CacheObject {
             mem_recorder: self.mem_recorder.clone(),
             is_delta_in_pack: self.is_delta_in_pack,
             known_hash: self.known_hash,
+            compressed_delta: self.compressed_delta.clone(),
+            reusable_delta: self.reusable_delta.clone(),
         };
         obj.record_mem_size();
         obj
@@ -230,8 +243,10 @@ impl HeapSize for CacheObject {
     ///
     /// See [Comment in PR #755](https://github.com/web3infra-foundation/mega/pull/755#issuecomment-2543100481) for more details.
     fn heap_size(&self) -> usize {
-        match &self.info {
-            CacheObjectInfo::BaseObject(_, _) => self.data_decompressed.heap_size(),
+        let data_size = match &self.info {
+            CacheObjectInfo::BaseObject(_, _) | CacheObjectInfo::ZstdDictionary(_) => {
+                self.data_decompressed.heap_size()
+            }
             CacheObjectInfo::OffsetDelta(_, delta_final_size)
             | CacheObjectInfo::OffsetZstdelta(_, delta_final_size)
             | CacheObjectInfo::HashDelta(_, delta_final_size) => {
@@ -247,7 +262,14 @@ impl HeapSize for CacheObject {
                 // usage of `delta_obj` and `final_obj`.
                 self.data_decompressed.heap_size() + delta_final_size
             }
-        }
+        };
+        // Deltas kept for reuse live as long as the object does.
+        data_size
+            + self.compressed_delta.as_ref().map_or(0, |d| d.len())
+            + self
+                .reusable_delta
+                .as_ref()
+                .map_or(0, |d| d.compressed.len())
     }
 }
 
@@ -309,6 +331,8 @@ impl CacheObject {
             mem_recorder: None,
             is_delta_in_pack: false,
             known_hash: None,
+            compressed_delta: None,
+            reusable_delta: None,
         }
     }
 
@@ -377,6 +401,7 @@ impl CacheObject {
                     pack_offset: Some(self.offset),
                     crc32: Some(self.crc32),
                     is_delta: Some(self.is_delta_in_pack),
+                    reusable_delta: self.reusable_delta.clone(),
                     ..Default::default()
                 };
                 MetaAttached { inner: entry, meta }
@@ -408,6 +433,7 @@ impl CacheObject {
                     pack_offset: Some(self.offset),
                     crc32: Some(self.crc32),
                     is_delta: Some(self.is_delta_in_pack),
+                    reusable_delta: self.reusable_delta.take(),
                     ..Default::default()
                 };
                 MetaAttached { inner: entry, meta }
@@ -531,6 +557,8 @@ mod test {
             mem_recorder: None,
             is_delta_in_pack: false,
             known_hash: None,
+            compressed_delta: None,
+            reusable_delta: None,
         }
     }
 
@@ -0,0 +1,43 @@
+//! Cooperative cancellation for pack decoding and encoding.
+//!
+//! A [`CancellationToken`] is handed to [`Pack::with_cancellation`](super::Pack::with_cancellation)
+//! or [`PackEncoder::with_cancellation`](super::encode::PackEncoder::with_cancellation) and
+//! cancelled from anywhere else, for example when an HTTP client disconnects mid-fetch. The
+//! pipeline checks it between objects, stops its workers and returns [`GitError::Cancelled`].
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use crate::errors::GitError;
+
+/// A flag shared by every clone, set once by [`CancellationToken::cancel`].
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask every operation holding a clone of this token to stop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    /// `Err(GitError::Cancelled)` once the token has been cancelled.
+    pub(crate) fn check(&self) -> Result<(), GitError> {
+        if self.is_cancelled() {
+            Err(GitError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -10,7 +10,7 @@ use std::{
     io::{self, BufRead, Cursor, ErrorKind, Read, Write},
     path::{Path, PathBuf},
     sync::{
-        Arc, OnceLock,
+        Arc, Mutex, OnceLock,
         atomic::{AtomicUsize, Ordering},
     },
     thread::{self, JoinHandle},
@@ -32,14 +32,19 @@ use crate::{
     errors::GitError,
     hash::{HashKind, ObjectHash, get_hash_kind, set_hash_kind},
     internal::{
-        metadata::{EntryMeta, MetaAttached},
+        metadata::{EntryMeta, MetaAttached, ReusableDelta},
         object::types::ObjectType,
         pack::{
             DEFAULT_TMP_DIR, Pack,
             cache::{_Cache, Caches},
             cache_object::{CacheObject, CacheObjectInfo, MemSizeRecorder},
+            cancel::CancellationToken,
             channel_reader::StreamBufReader,
             entry::Entry,
+            limits::{DecodeBudget, DecodeLimits},
+            mmap::MappedFile,
+            progress::{ProgressObserver, ProgressPhase, ProgressReporter},
+            reader::MappedPack,
             utils,
             waitlist::Waitlist,
             wrapper::Wrapper,
@@ -54,6 +59,8 @@ struct CrcCountingReader<R> {
     inner: R,
     bytes_read: u64,
     crc: Option<crc32fast::Hasher>,
+    /// Copy of the bytes read while set, see [`CrcCountingReader::start_capture`].
+    captured: Option<Vec<u8>>,
 }
 
 struct HashingReader<R> {
@@ -100,6 +107,9 @@ impl<R: Read> Read for CrcCountingReader<R> {
         if let Some(crc) = &mut self.crc {
             crc.update(&buf[..n]);
         }
+        if let Some(captured) = &mut self.captured {
+            captured.extend_from_slice(&buf[..n]);
+        }
         Ok(n)
     }
 }
@@ -108,9 +118,15 @@ impl<R: BufRead> BufRead for CrcCountingReader<R> {
         self.inner.fill_buf()
     }
     fn consume(&mut self, amt: usize) {
-        if let Some(crc) = &mut self.crc {
+        if self.crc.is_some() || self.captured.is_some() {
             let buf = self.inner.fill_buf().unwrap_or(&[]);
-            crc.update(&buf[..amt.min(buf.len())]);
+            let buf = &buf[..amt.min(buf.len())];
+            if let Some(crc) = &mut self.crc {
+                crc.update(buf);
+            }
+            if let Some(captured) = &mut self.captured {
+                captured.extend_from_slice(buf);
+            }
         }
         self.bytes_read += amt as u64;
         self.inner.consume(amt);
@@ -124,6 +140,15 @@ impl<R> CrcCountingReader<R> {
             .map(crc32fast::Hasher::finalize)
             .unwrap_or(0)
     }
+
+    /// Start copying the bytes read from here on; used to keep a delta's zlib stream.
+    fn start_capture(&mut self) {
+        self.captured = Some(Vec::new());
+    }
+
+    fn take_captured(&mut self) -> Option<Arc<[u8]>> {
+        self.captured.take().map(Arc::from)
+    }
 }
 
 /// For the convenience of passing parameters
@@ -137,6 +162,17 @@ struct SharedParams {
     pub callback: Option<DecodeCallback>,
     pub retention: Option<Arc<DecodeRetention>>,
     pub skip_unneeded_objects: bool,
+    pub limits: DecodeLimits,
+    /// Delta depth of every rebuilt delta by offset; only tracked when the depth is limited.
+    pub delta_depths: DashMap<usize, usize>,
+    /// First error hit by a worker thread, checked by the decode loop.
+    pub failure: Mutex<Option<GitError>>,
+    /// Deltas rebuilt so far, for progress reporting.
+    pub deltas_resolved: AtomicUsize,
+    /// Workers skip their task once this is cancelled.
+    pub cancellation: CancellationToken,
+    /// Trained zstd dictionaries by pack offset, kept for the whole decode.
+    pub zstd_dictionaries: DashMap<usize, Arc<CacheObject>>,
 }
 
 #[derive(Default)]
@@ -375,6 +411,8 @@ const FILE_DECODE_BUFFER_SIZE: usize = 128 * 1024;
 const PACK_OBJECT_PREFIX_READ_SIZE: usize = 96;
 const PACK_SCAN_WINDOW_SIZE: usize = 8 * 1024;
 const SKIP_INFLATE_BUFFER_SIZE: usize = 20 * 1024;
+/// Initial buffer for an inflated entry; larger entries grow it as data arrives.
+const INFLATE_INITIAL_CAPACITY: usize = 64 * 1024;
 
 impl Drop for Pack {
     fn drop(&mut self) {
@@ -393,6 +431,23 @@ impl Pack {
         self.caches.shutdown();
     }
 
+    /// Treat cancellation as a normal end for the background decode entry points.
+    fn ignore_cancelled(e: GitError) -> Result<(), GitError> {
+        match e {
+            GitError::Cancelled => Ok(()),
+            e => Err(e),
+        }
+    }
+
+    /// Abort a cancelled decode and drop whatever it spilled to disk.
+    fn cancel_decode(&self) -> GitError {
+        self.abort_decode();
+        if let Err(e) = self.caches.remove_tmp_dir() {
+            tracing::warn!(error = %e, "failed to remove pack decode temp directory");
+        }
+        GitError::Cancelled
+    }
+
     fn low_memory_callback_entries() -> bool {
         static ENABLED: OnceLock<bool> = OnceLock::new();
         *ENABLED.get_or_init(|| {
@@ -505,9 +560,54 @@ impl Pack {
             mem_limit,
             cache_objs_mem: Arc::new(AtomicUsize::default()),
             clean_tmp,
+            limits: DecodeLimits::default(),
+            progress: None,
+            cancellation: CancellationToken::new(),
+            delta_reuse: false,
+            zstd_dictionary_limit: None,
         }
     }
 
+    /// Apply `limits` to every later decode, so that a pack from an untrusted source fails with
+    /// [`GitError::DecodeLimitExceeded`] instead of exhausting memory.
+    pub fn with_decode_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Report "Receiving objects" and "Resolving deltas" progress to `observer` during every later
+    /// decode.
+    pub fn with_progress(mut self, observer: Arc<dyn ProgressObserver>) -> Self {
+        self.progress = Some(observer);
+        self
+    }
+
+    /// Check `token` between objects during every later decode. Once it is cancelled, the decode
+    /// stops its workers, removes its temp directory and returns [`GitError::Cancelled`].
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = token;
+        self
+    }
+
+    /// Keep the compressed bytes of every offset or ref delta and hand them to the callback in
+    /// [`EntryMeta::reusable_delta`], together with the base they apply to. Passing such entries
+    /// to a [`PackEncoder`](super::encode::PackEncoder) copies the delta instead of searching
+    /// for a new one when the base is written too. Zstdelta entries are never kept.
+    pub fn with_delta_reuse(mut self, enabled: bool) -> Self {
+        self.delta_reuse = enabled;
+        self
+    }
+
+    /// Accept the zstd dictionary entries written by
+    /// [`PackEncoder::with_zstd_dictionaries`](super::encode::PackEncoder::with_zstd_dictionaries),
+    /// up to `max_total_size` inflated bytes per pack. Dictionaries stay in memory until the
+    /// decode ends and count towards the memory limit. Without this, a pack that contains one
+    /// fails to decode.
+    pub fn with_zstd_dictionaries(mut self, max_total_size: usize) -> Self {
+        self.zstd_dictionary_limit = Some(max_total_size);
+        self
+    }
+
     /// Checks and reads the header of a Git pack file.
     ///
     /// This function reads the first 12 bytes of a pack file, which include the b"PACK" magic identifier,
@@ -623,13 +723,20 @@ impl Pack {
         pack: &mut (impl BufRead + Send),
         expected_size: usize,
     ) -> Result<(Vec<u8>, usize), GitError> {
-        let mut buf = vec![0; expected_size];
+        // The size comes from the pack, so grow with the data actually inflated.
+        let mut buf = Vec::with_capacity(expected_size.min(INFLATE_INITIAL_CAPACITY));
 
         let mut counting_reader = CountingReader::new(pack);
         // Create a new Zlib decoder with the original data
         //let mut deflate = ZlibDecoder::new(pack);
         let mut deflate = ZlibDecoder::new(&mut counting_reader);
-        match deflate.read_exact(&mut buf) {
+        match (&mut deflate)
+            .take(expected_size as u64)
+            .read_to_end(&mut buf)
+        {
+            Ok(read) if read != expected_size => Err(GitError::InvalidPackFile(format!(
+                "The object size is smaller than the expected size {expected_size}"
+            ))),
             Ok(_) => {
                 let mut extra = [0; 1];
                 let extra_bytes = deflate
@@ -939,6 +1046,9 @@ impl Pack {
         let mut offset = init_offset;
         let (type_bits, _) = utils::read_type_and_varint_size(pack, &mut offset)
             .map_err(|e| GitError::InvalidPackFile(format!("Read error: {e}")))?;
+        if type_bits == utils::ZSTD_DICTIONARY_PACK_TYPE {
+            return Ok(());
+        }
         let obj_type = ObjectType::from_pack_type_u8(type_bits)?;
 
         match obj_type {
@@ -1021,6 +1131,10 @@ impl Pack {
             let init_offset = offset;
             let (type_bits, size) = utils::read_type_and_varint_size(pack, &mut offset)
                 .map_err(|e| GitError::InvalidPackFile(format!("Read error: {e}")))?;
+            if type_bits == utils::ZSTD_DICTIONARY_PACK_TYPE {
+                offset += Pack::skip_compressed_data(pack, size)?;
+                continue;
+            }
             let obj_type = ObjectType::from_pack_type_u8(type_bits)?;
 
             match obj_type {
@@ -1103,13 +1217,26 @@ impl Pack {
     /// * A tuple of the next offset in the pack and the original compressed data as `Vec<u8>`,
     /// * Or a `GitError` in case of any reading or decompression error.
     ///
+    /// Zstd dictionary entries are returned as [`CacheObjectInfo::ZstdDictionary`], since reading
+    /// a single raw entry does not retain them.
     pub fn decode_pack_object(
         pack: &mut (impl BufRead + Send),
         offset: &mut usize,
     ) -> Result<Option<CacheObject>, GitError> {
-        Self::decode_pack_object_with_crc(pack, offset, true, false, false, None, None)
+        Self::decode_pack_object_with_crc(
+            pack,
+            offset,
+            true,
+            false,
+            false,
+            None,
+            None,
+            &mut DecodeBudget::default().with_zstd_dictionaries(Some(usize::MAX)),
+            false,
+        )
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn decode_pack_object_with_crc(
         pack: &mut (impl BufRead + Send),
         offset: &mut usize,
@@ -1118,12 +1245,15 @@ impl Pack {
         emit_skipped_base_callback: bool,
         known_hash: Option<ObjectHash>,
         retention: Option<&DecodeRetention>,
+        budget: &mut DecodeBudget,
+        keep_delta: bool,
     ) -> Result<Option<CacheObject>, GitError> {
         let init_offset = *offset;
         let mut reader = CrcCountingReader {
             inner: pack,
             bytes_read: 0,
             crc: track_crc.then(crc32fast::Hasher::new),
+            captured: None,
         };
 
         // Attempt to read the type and size, handle potential errors
@@ -1137,8 +1267,45 @@ impl Pack {
             }
         };
 
+        // A trained zstd dictionary: the pack type it serves, then the dictionary itself.
+        if type_bits == utils::ZSTD_DICTIONARY_PACK_TYPE {
+            if !budget.accepts_zstd_dictionaries() {
+                return Err(GitError::InvalidPackFile(format!(
+                    "Unexpected zstd dictionary entry at offset {init_offset}; dictionaries are not enabled"
+                )));
+            }
+            budget.charge_entry(size)?;
+            budget.charge_dictionary(size)?;
+            let (mut data, raw_size) = Pack::decompress_data(&mut reader, size)?;
+            *offset += raw_size;
+            let obj_type = match data.first().map(|&t| ObjectType::from_pack_type_u8(t)) {
+                Some(Ok(
+                    t
+                    @ (ObjectType::Commit | ObjectType::Tree | ObjectType::Blob | ObjectType::Tag),
+                )) => t,
+                _ => {
+                    return Err(GitError::InvalidPackFile(format!(
+                        "Invalid zstd dictionary entry at offset {init_offset}"
+                    )));
+                }
+            };
+            data.remove(0);
+            return Ok(Some(CacheObject {
+                info: CacheObjectInfo::ZstdDictionary(obj_type),
+                offset: init_offset,
+                crc32: reader.crc32(),
+                data_decompressed: data,
+                mem_recorder: None,
+                is_delta_in_pack: false,
+                known_hash: None,
+                compressed_delta: None,
+                reusable_delta: None,
+            }));
+        }
+
         // Check if the object type is valid
         let t = ObjectType::from_pack_type_u8(type_bits)?;
+        budget.charge_entry(size)?;
 
         match t {
             ObjectType::Commit | ObjectType::Tree | ObjectType::Blob | ObjectType::Tag => {
@@ -1165,6 +1332,8 @@ impl Pack {
                             mem_recorder: None,
                             is_delta_in_pack: false,
                             known_hash: None,
+                            compressed_delta: None,
+                            reusable_delta: None,
                         }));
                     }
                     return Ok(None);
@@ -1182,6 +1351,8 @@ impl Pack {
                     mem_recorder: None,
                     is_delta_in_pack: false,
                     known_hash: None,
+                    compressed_delta: None,
+                    reusable_delta: None,
                 }))
             }
             ObjectType::OffsetDelta | ObjectType::OffsetZstdelta => {
@@ -1225,6 +1396,8 @@ impl Pack {
                         mem_recorder: None,
                         is_delta_in_pack: true,
                         known_hash,
+                        compressed_delta: None,
+                        reusable_delta: None,
                     }));
                 }
 
@@ -1255,14 +1428,21 @@ impl Pack {
                         mem_recorder: None,
                         is_delta_in_pack: true,
                         known_hash,
+                        compressed_delta: None,
+                        reusable_delta: None,
                     }));
                 }
 
+                if keep_delta && t == ObjectType::OffsetDelta {
+                    reader.start_capture();
+                }
                 let (data, raw_size) = Pack::decompress_data(&mut reader, size)?;
                 *offset += raw_size;
+                let compressed_delta = reader.take_captured();
 
                 let mut delta_reader = Cursor::new(&data);
                 let (_, final_size) = utils::read_delta_object_size(&mut delta_reader)?;
+                budget.limits.check_delta_result_size(final_size)?;
 
                 let obj_info = match t {
                     ObjectType::OffsetDelta => {
@@ -1282,6 +1462,8 @@ impl Pack {
                     mem_recorder: None,
                     is_delta_in_pack: true,
                     known_hash,
+                    compressed_delta,
+                    reusable_delta: None,
                 }))
             }
             ObjectType::HashDelta => {
@@ -1312,6 +1494,8 @@ impl Pack {
                         mem_recorder: None,
                         is_delta_in_pack: true,
                         known_hash,
+                        compressed_delta: None,
+                        reusable_delta: None,
                     }));
                 }
 
@@ -1335,14 +1519,21 @@ impl Pack {
                         mem_recorder: None,
                         is_delta_in_pack: true,
                         known_hash,
+                        compressed_delta: None,
+                        reusable_delta: None,
                     }));
                 }
 
+                if keep_delta {
+                    reader.start_capture();
+                }
                 let (data, raw_size) = Pack::decompress_data(&mut reader, size)?;
                 *offset += raw_size;
+                let compressed_delta = reader.take_captured();
 
                 let mut delta_reader = Cursor::new(&data);
                 let (_, final_size) = utils::read_delta_object_size(&mut delta_reader)?;
+                budget.limits.check_delta_result_size(final_size)?;
 
                 let crc32 = reader.crc32();
 
@@ -1354,6 +1545,8 @@ impl Pack {
                     mem_recorder: None,
                     is_delta_in_pack: true,
                     known_hash,
+                    compressed_delta,
+                    reusable_delta: None,
                 }))
             }
             // AI object types (ContextSnapshot, Decision, etc.) use u8 IDs >= 8
@@ -1718,23 +1911,35 @@ impl Pack {
                 None
             };
 
-        let file = File::open(pack_path)
-            .map_err(|e| GitError::InvalidPackFile(format!("Open pack file error: {e}")))?;
-        let mut reader = io::BufReader::with_capacity(FILE_DECODE_BUFFER_SIZE, file);
-        let decode_result = self.decode_inner(
-            &mut reader,
-            callback,
-            pack_id_callback,
-            DecodeOptions {
-                retention_mode,
-                known_hashes,
-                expected_pack_hash,
-                verify_pack_stream_hash: !skip_payload_hash_check
-                    && hash_check.is_none()
-                    && pack_hash_check.is_none(),
-                sync_base_callbacks,
-            },
-        );
+        let options = DecodeOptions {
+            retention_mode,
+            known_hashes,
+            expected_pack_hash,
+            verify_pack_stream_hash: !skip_payload_hash_check
+                && hash_check.is_none()
+                && pack_hash_check.is_none(),
+            sync_base_callbacks,
+        };
+        // On Unix the pack is memory-mapped: entries are inflated straight from the mapping, and
+        // evicted cache objects are re-read from it instead of being spilled to the temp dir.
+        #[cfg(unix)]
+        let decode_result = {
+            let mapped = MappedFile::open(pack_path)
+                .map_err(|e| GitError::InvalidPackFile(format!("Open pack file error: {e}")))?;
+            let mapped = Arc::new(MappedPack::new(mapped, get_hash_kind()));
+            self.caches.set_mapped_pack(Some(mapped.clone()));
+            let mut reader = mapped.bytes();
+            let result = self.decode_inner(&mut reader, callback, pack_id_callback, options);
+            self.caches.set_mapped_pack(None);
+            result
+        };
+        #[cfg(not(unix))]
+        let decode_result = {
+            let file = File::open(pack_path)
+                .map_err(|e| GitError::InvalidPackFile(format!("Open pack file error: {e}")))?;
+            let mut reader = io::BufReader::with_capacity(FILE_DECODE_BUFFER_SIZE, file);
+            self.decode_inner(&mut reader, callback, pack_id_callback, options)
+        };
 
         let hash_result = hash_check.map(|handle| {
             handle
@@ -1813,6 +2018,12 @@ impl Pack {
             callback,
             retention: retention_mode.retention,
             skip_unneeded_objects: retention_mode.skip_unneeded_objects,
+            limits: self.limits,
+            delta_depths: DashMap::new(),
+            failure: Mutex::new(None),
+            deltas_resolved: AtomicUsize::new(0),
+            cancellation: self.cancellation.clone(),
+            zstd_dictionaries: DashMap::new(),
         });
         let mut reader = if verify_pack_stream_hash {
             Wrapper::new(pack)
@@ -1823,12 +2034,20 @@ impl Pack {
         let result = Pack::check_header(&mut reader);
         match result {
             Ok((object_num, _)) => {
+                self.limits.check_object_count(object_num)?;
                 self.number = object_num as usize;
             }
             Err(e) => {
                 return Err(e);
             }
         }
+        let mut budget =
+            DecodeBudget::new(self.limits).with_zstd_dictionaries(self.zstd_dictionary_limit);
+        let mut receiving = ProgressReporter::new(
+            self.progress.clone(),
+            ProgressPhase::ReceivingObjects,
+            Some(self.number),
+        );
         tracing::info!("The pack file has {} objects", self.number);
         let mut offset: usize = 12;
         let mut i = 0;
@@ -1838,6 +2057,11 @@ impl Pack {
             self.mem_limit
         };
         while i < self.number {
+            receiving.update(
+                i,
+                offset as u64,
+                shared_params.deltas_resolved.load(Ordering::Relaxed),
+            );
             // log per 1000 objects and 1 second
             if log_enabled && i % 1000 == 0 {
                 let time_now = time.elapsed().as_millis();
@@ -1849,16 +2073,26 @@ impl Pack {
             // 3 parts: Waitlist + TheadPool + Caches
             // hardcode the limit of the tasks of threads_pool queue, to limit memory
             if let Some(mem_limit) = mem_limit {
-                while self.pool.queued_count() > MAX_QUEUED_DECODE_TASKS
-                    || self.memory_used() > mem_limit
+                while (self.pool.queued_count() > MAX_QUEUED_DECODE_TASKS
+                    || self.memory_used() > mem_limit)
+                    && !self.cancellation.is_cancelled()
                 {
                     thread::yield_now();
                 }
             } else {
-                while self.pool.queued_count() > MAX_QUEUED_DECODE_TASKS {
+                while self.pool.queued_count() > MAX_QUEUED_DECODE_TASKS
+                    && !self.cancellation.is_cancelled()
+                {
                     thread::yield_now();
                 }
             }
+            if self.cancellation.is_cancelled() {
+                return Err(self.cancel_decode());
+            }
+            if let Some(e) = shared_params.failure.lock().unwrap().take() {
+                self.abort_decode();
+                return Err(e);
+            }
             let known_hash = known_hashes.and_then(|hashes| hashes.get(i).copied());
             let r: Result<Option<CacheObject>, GitError> = Pack::decode_pack_object_with_crc(
                 &mut reader,
@@ -1868,8 +2102,18 @@ impl Pack {
                 shared_params.callback.is_some() && Self::low_memory_callback_entries(),
                 known_hash,
                 shared_params.retention.as_deref(),
+                &mut budget,
+                self.delta_reuse && shared_params.callback.is_some(),
             );
             match r {
+                // Dictionaries are no objects of their own; zstdelta entries look them up.
+                Ok(Some(mut obj)) if matches!(obj.info, CacheObjectInfo::ZstdDictionary(_)) => {
+                    obj.set_mem_recorder(self.cache_objs_mem.clone());
+                    obj.record_mem_size();
+                    shared_params
+                        .zstd_dictionaries
+                        .insert(obj.offset, Arc::new(obj));
+                }
                 Ok(Some(obj)) => {
                     let Some(mut obj) = Self::try_process_skipped_low_memory_callback_object(
                         &shared_params,
@@ -1906,6 +2150,9 @@ impl Pack {
                     let params = shared_params.clone();
                     let kind = get_hash_kind();
                     self.pool.execute(move || {
+                        if params.cancellation.is_cancelled() {
+                            return;
+                        }
                         set_hash_kind(kind);
                         match obj.info {
                             CacheObjectInfo::BaseObject(_, _) => {
@@ -1916,10 +2163,15 @@ impl Pack {
                             | CacheObjectInfo::HashDelta(_, _) => {
                                 Self::process_delta_dependency(params, obj);
                             }
+                            CacheObjectInfo::ZstdDictionary(_) => unreachable!(),
                         }
                     });
                 }
                 Ok(None) => {}
+                // A cancelled caller may have cut the stream off mid-entry.
+                Err(_) if self.cancellation.is_cancelled() => {
+                    return Err(self.cancel_decode());
+                }
                 Err(e) => {
                     self.abort_decode();
                     return Err(e);
@@ -1967,7 +2219,38 @@ impl Pack {
             ));
         }
 
+        receiving.finish(
+            i,
+            offset as u64 + self.signature.size() as u64,
+            shared_params.deltas_resolved.load(Ordering::Relaxed),
+        );
+
         self.pool.join(); // wait for all threads to finish
+        if self.cancellation.is_cancelled() {
+            return Err(self.cancel_decode());
+        }
+        if let Some(e) = shared_params.failure.lock().unwrap().take() {
+            self.abort_decode();
+            return Err(e);
+        }
+        // A REF_DELTA still waiting names a base that is neither in the pack nor supplied, as
+        // with a thin pack nobody completed.
+        if let Some(base) = self.waitlist.map_ref.iter().map(|e| *e.key()).min() {
+            let missing = self.waitlist.map_ref.len();
+            self.waitlist.map_ref.clear();
+            self.abort_decode();
+            return Err(GitError::ObjectNotFound(format!(
+                "REF_DELTA base {base} (and {} more) is not in the pack",
+                missing - 1
+            )));
+        }
+        let deltas_resolved = shared_params.deltas_resolved.load(Ordering::Relaxed);
+        ProgressReporter::new(
+            self.progress.clone(),
+            ProgressPhase::ResolvingDeltas,
+            Some(deltas_resolved),
+        )
+        .finish(deltas_resolved, 0, deltas_resolved);
 
         // send pack id for metadata
         if let Some(pack_callback) = pack_id_callback {
@@ -1976,7 +2259,6 @@ impl Pack {
         // !Attention: Caches threadpool may not stop, but it's not a problem (garbage file data)
         // So that files != self.number
         assert_eq!(self.waitlist.map_offset.len(), 0);
-        assert_eq!(self.waitlist.map_ref.len(), 0);
         // Because we may skip some objects (e.g. AI objects), we use >= instead of ==
         assert!(self.number >= self.caches.total_inserted());
         tracing::info!(
@@ -1984,6 +2266,7 @@ impl Pack {
             time.elapsed()
         );
         self.caches.clear(); // clear cached objects & stop threads
+        shared_params.zstd_dictionaries.clear();
         assert_eq!(self.cache_objs_mem_used(), 0); // all the objs should be dropped until here
 
         // impl in Drop Trait
@@ -1996,11 +2279,13 @@ impl Pack {
 
     /// Decode a Pack in a new thread and send the CacheObjects while decoding.
     /// <br> Attention: It will consume the `pack` and return in a JoinHandle.
+    /// <br> Cancelling [`Pack::cancellation`] ends the decode early with `Ok`; any other failure,
+    /// such as a breached [`DecodeLimits`] entry, is returned as the thread's `Err`.
     pub fn decode_async(
         mut self,
         mut pack: impl BufRead + Send + 'static,
         sender: UnboundedSender<Entry>,
-    ) -> JoinHandle<Pack> {
+    ) -> JoinHandle<Result<Pack, GitError>> {
         let kind = get_hash_kind();
         thread::spawn(move || {
             set_hash_kind(kind);
@@ -2013,24 +2298,37 @@ impl Pack {
                 },
                 None::<fn(ObjectHash)>,
             )
-            .unwrap();
-            self
+            .or_else(Self::ignore_cancelled)?;
+            Ok(self)
         })
     }
 
     /// Decodes a `Pack` from a `Stream` of `Bytes`, and sends the `Entry` while decoding.
+    /// <br> Cancelling [`Pack::cancellation`] stops reading `stream` and ends the decode early
+    /// with `Ok`. A stream error ends the input, so the decode fails on the truncated pack.
+    /// Failures, such as a breached [`DecodeLimits`] entry, are returned rather than panicking.
     pub async fn decode_stream(
         mut self,
         mut stream: impl Stream<Item = Result<Bytes, Error>> + Unpin + Send + 'static,
         sender: UnboundedSender<MetaAttached<Entry, EntryMeta>>,
         pack_hash_send: Option<UnboundedSender<ObjectHash>>,
-    ) -> Self {
+    ) -> Result<Self, GitError> {
         let kind = get_hash_kind();
         let (tx, rx) = std::sync::mpsc::channel();
         let mut reader = StreamBufReader::new(rx);
+        let cancellation = self.cancellation.clone();
         tokio::spawn(async move {
             while let Some(chunk) = stream.next().await {
-                let data = chunk.unwrap().to_vec();
+                if cancellation.is_cancelled() {
+                    break;
+                }
+                let data = match chunk {
+                    Ok(chunk) => chunk.to_vec(),
+                    Err(e) => {
+                        tracing::warn!(error = %e, "pack stream failed");
+                        break;
+                    }
+                };
                 if let Err(e) = tx.send(data) {
                     eprintln!("Sending Error: {e:?}");
                     break;
@@ -2057,8 +2355,8 @@ impl Pack {
                     }
                 }),
             )
-            .unwrap();
-            self
+            .or_else(Self::ignore_cancelled)?;
+            Ok(self)
         })
         .await
         .unwrap()
@@ -2156,6 +2454,7 @@ impl Pack {
                 Self::process_delta_dependency(shared_params.clone(), obj);
                 None
             }
+            CacheObjectInfo::ZstdDictionary(_) => Some(obj),
         }
     }
 
@@ -2205,6 +2504,16 @@ impl Pack {
 
     fn process_delta_dependency(shared_params: Arc<SharedParams>, obj: CacheObject) {
         match obj.info {
+            CacheObjectInfo::OffsetZstdelta(base_offset, _)
+                if shared_params.zstd_dictionaries.contains_key(&base_offset) =>
+            {
+                let dictionary = shared_params
+                    .zstd_dictionaries
+                    .get(&base_offset)
+                    .map(|dictionary| Arc::clone(dictionary.value()))
+                    .unwrap();
+                Self::process_delta(shared_params, obj, dictionary);
+            }
             CacheObjectInfo::OffsetDelta(base_offset, _)
             | CacheObjectInfo::OffsetZstdelta(base_offset, _) => {
                 if let Some(base_obj) = shared_params.caches.get_by_offset(base_offset) {
@@ -2236,7 +2545,9 @@ impl Pack {
                     }
                 }
             }
-            CacheObjectInfo::BaseObject(_, _) => unreachable!(),
+            CacheObjectInfo::BaseObject(_, _) | CacheObjectInfo::ZstdDictionary(_) => {
+                unreachable!()
+            }
         }
     }
 
@@ -2256,6 +2567,21 @@ impl Pack {
         }
 
         shared_params.pool.clone().execute(move || {
+            if shared_params.cancellation.is_cancelled() {
+                return;
+            }
+            if shared_params.limits.max_delta_depth.is_some() {
+                let depth = shared_params
+                    .delta_depths
+                    .get(&base_obj.offset)
+                    .map_or(0, |depth| *depth)
+                    + 1;
+                if let Err(e) = shared_params.limits.check_delta_depth(depth) {
+                    shared_params.failure.lock().unwrap().get_or_insert(e);
+                    return;
+                }
+                shared_params.delta_depths.insert(delta_obj.offset, depth);
+            }
             let known_hash = delta_obj.known_hash;
             let mut new_obj = match delta_obj.info {
                 CacheObjectInfo::OffsetDelta(_, _) | CacheObjectInfo::HashDelta(_, _) => {
@@ -2267,6 +2593,9 @@ impl Pack {
                 _ => unreachable!(),
             };
 
+            shared_params
+                .deltas_resolved
+                .fetch_add(1, Ordering::Relaxed);
             new_obj.set_mem_recorder(shared_params.cache_objs_mem_size.clone());
             new_obj.record_mem_size();
             Self::cache_obj_and_process_waitlist(&shared_params, new_obj); //Indirect Recursion
@@ -2301,6 +2630,9 @@ impl Pack {
             base_obj.object_type(),
             hash,
         ));
+        shared_params
+            .deltas_resolved
+            .fetch_add(1, Ordering::Relaxed);
         true
     }
 
@@ -2462,6 +2794,14 @@ impl Pack {
 
         let hash = known_hash
             .unwrap_or_else(|| utils::calculate_object_hash(base_obj.object_type(), &result));
+        let reusable_delta = delta_obj
+            .compressed_delta
+            .clone()
+            .map(|compressed| ReusableDelta {
+                base: base_obj.base_object_hash().unwrap(),
+                delta_size: delta_obj.data_decompressed.len(),
+                compressed,
+            });
         // create new obj from `delta_obj` & `result` instead of modifying `delta_obj` for heap-size recording
         CacheObject {
             info: CacheObjectInfo::BaseObject(base_obj.object_type(), hash),
@@ -2471,6 +2811,8 @@ impl Pack {
             mem_recorder: None,
             is_delta_in_pack: delta_obj.is_delta_in_pack,
             known_hash: None,
+            compressed_delta: None,
+            reusable_delta,
         } // Canonical form (Complete Object)
         // Memory recording will happen after this function returns. See `process_delta`
     }
@@ -2495,6 +2837,8 @@ impl Pack {
             mem_recorder: None,
             is_delta_in_pack: delta_obj.is_delta_in_pack,
             known_hash: None,
+            compressed_delta: None,
+            reusable_delta: None,
         } // Canonical form (Complete Object)
         // Memory recording will happen after this function returns. See `process_delta`
     }
@@ -2535,10 +2879,11 @@ mod tests {
         path::{Path, PathBuf},
         sync::{
             Arc, Mutex,
-            atomic::{AtomicUsize, Ordering},
+            atomic::{AtomicBool, AtomicUsize, Ordering},
         },
     };
 
+    use dashmap::DashMap;
     use flate2::{Compression, write::ZlibEncoder};
     use futures_util::TryStreamExt;
     use sha1::{Digest, Sha1};
@@ -2547,6 +2892,7 @@ mod tests {
     use tokio_util::io::ReaderStream;
 
     use crate::{
+        errors::GitError,
         hash::{HashKind, ObjectHash, get_hash_kind, set_hash_kind_for_test},
         internal::{
             object::types::ObjectType,
@@ -2554,6 +2900,10 @@ mod tests {
                 Pack,
                 cache::{_Cache, Caches},
                 cache_object::{CacheObject, CacheObjectInfo},
+                cancel::CancellationToken,
+                limits::{DecodeBudget, DecodeLimit, DecodeLimits},
+                progress::{Progress, ProgressPhase},
+                test_pack_builder::TestPackBuilder,
                 test_pack_download::download_pack_file,
                 tests::init_logger,
                 utils,
@@ -2606,6 +2956,17 @@ mod tests {
         }
     }
 
+    /// A declared size far beyond the compressed data is an error, not an allocation of that size.
+    #[test]
+    fn test_decompress_data_oversized_claim() {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"Hello, world!").unwrap();
+        let mut cursor = Cursor::new(encoder.finish().unwrap());
+
+        let result = Pack::decompress_data(&mut cursor, usize::MAX / 2);
+        assert!(matches!(result, Err(GitError::InvalidPackFile(_))));
+    }
+
     #[test]
     fn test_pack_decode_truncated_pack_returns_err_without_panic() {
         let _guard = set_hash_kind_for_test(HashKind::Sha1);
@@ -2692,6 +3053,8 @@ mod tests {
             false,
             Some(supplied_hash),
             None,
+            &mut DecodeBudget::default(),
+            false,
         )
         .unwrap()
         .unwrap();
@@ -2723,6 +3086,8 @@ mod tests {
             true,
             Some(supplied_hash),
             Some(&retention),
+            &mut DecodeBudget::default(),
+            false,
         )
         .unwrap()
         .unwrap();
@@ -2758,6 +3123,8 @@ mod tests {
             true,
             Some(supplied_hash),
             Some(&retention),
+            &mut DecodeBudget::default(),
+            false,
         )
         .unwrap()
         .unwrap();
@@ -2783,6 +3150,8 @@ mod tests {
             mem_recorder: None,
             is_delta_in_pack: true,
             known_hash: Some(hash),
+            compressed_delta: None,
+            reusable_delta: None,
         };
 
         let entry = Pack::low_memory_delta_callback_entry(&delta_obj, ObjectType::Blob, hash);
@@ -2816,6 +3185,12 @@ mod tests {
             callback: Some(callback),
             retention: Some(Arc::new(super::DecodeRetention::default())),
             skip_unneeded_objects: true,
+            limits: DecodeLimits::default(),
+            delta_depths: DashMap::new(),
+            failure: Mutex::new(None),
+            deltas_resolved: AtomicUsize::new(0),
+            cancellation: CancellationToken::new(),
+            zstd_dictionaries: DashMap::new(),
         });
         let obj = CacheObject {
             info: CacheObjectInfo::BaseObject(ObjectType::Blob, hash),
@@ -2825,6 +3200,8 @@ mod tests {
             mem_recorder: None,
             is_delta_in_pack: false,
             known_hash: None,
+            compressed_delta: None,
+            reusable_delta: None,
         };
 
         let remaining =
@@ -3449,6 +3826,8 @@ mod tests {
             mem_recorder: None,
             is_delta_in_pack: true,
             known_hash: None,
+            compressed_delta: None,
+            reusable_delta: None,
         };
 
         let rebuilt = Pack::rebuild_delta(delta, base);
@@ -3595,7 +3974,7 @@ mod tests {
             tracing::info!("Received: {}", cnt);
             count_c.store(cnt, Ordering::Release);
         });
-        let p = handle.await.unwrap();
+        let p = handle.await.unwrap().unwrap();
         consume.await.unwrap();
         assert_eq!(count.load(Ordering::Acquire), p.number);
         assert_eq!(p.number, 35031);
@@ -3629,7 +4008,7 @@ mod tests {
         while let Some(_entry) = rx.recv().await {
             cnt += 1; //use entry here
         }
-        let p = handle.join().unwrap();
+        let p = handle.join().unwrap().unwrap();
         assert_eq!(cnt, p.number);
     }
     #[cfg_attr(coverage, ignore)]
@@ -3776,4 +4155,275 @@ mod tests {
             "stats_pack should return Err for invalid pack magic"
         );
     }
+
+    /// With a small memory limit, evicted bases are rebuilt from the mapped pack instead of being
+    /// spilled to the temp directory, and every delta still resolves.
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_pack_decode_file_mmap_rebuilds_evicted_bases() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let dir = tempdir().unwrap();
+        let blob = |seed: usize| -> Vec<u8> {
+            (0..3000)
+                .flat_map(|i| format!("{seed}:{i:08}\n").into_bytes())
+                .collect()
+        };
+
+        let mut builder = TestPackBuilder::new();
+        let mut expected = Vec::new();
+        let bases: Vec<_> = (0..3)
+            .map(|seed| {
+                let data = blob(seed);
+                let built = builder.add_base(ObjectType::Blob, &data);
+                expected.push((built.hash, data.clone()));
+                (built, data)
+            })
+            .collect();
+        for (round, (base, base_data)) in bases.iter().enumerate() {
+            let mut target = base_data.clone();
+            target.extend_from_slice(format!("round {round}\n").as_bytes());
+            let built = builder.add_ofs_delta(*base, base_data, ObjectType::Blob, &target);
+            expected.push((built.hash, target));
+        }
+        let pack_path = builder.write_to(dir.path(), "evict").await;
+        let tmp = dir.path().join("tmp");
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_cb = seen.clone();
+        let mut pack = Pack::new(Some(2), Some(64 * 1024), Some(tmp.clone()), false);
+        pack.decode_file(
+            &pack_path,
+            move |entry| {
+                seen_cb
+                    .lock()
+                    .unwrap()
+                    .push((entry.inner.hash, entry.inner.data))
+            },
+            None::<fn(ObjectHash)>,
+        )
+        .unwrap();
+
+        let mut seen = Arc::try_unwrap(seen).unwrap().into_inner().unwrap();
+        seen.sort();
+        expected.sort();
+        assert_eq!(seen, expected);
+
+        let spilled = walk_files(&tmp);
+        assert!(spilled.is_empty(), "unexpected spill files: {spilled:?}");
+    }
+
+    fn decode_with_limits(pack: &[u8], limits: DecodeLimits) -> Result<usize, GitError> {
+        let (_dir, tmp) = pack_test_tmp();
+        let decoded = Arc::new(AtomicUsize::new(0));
+        let counter = decoded.clone();
+        let mut pack_decoder = Pack::new(Some(2), None, Some(tmp), true).with_decode_limits(limits);
+        pack_decoder.decode(
+            &mut Cursor::new(pack),
+            move |_| {
+                counter.fetch_add(1, Ordering::Relaxed);
+            },
+            None::<fn(ObjectHash)>,
+        )?;
+        Ok(decoded.load(Ordering::Relaxed))
+    }
+
+    fn exceeded(result: Result<usize, GitError>) -> Option<DecodeLimit> {
+        match result {
+            Err(GitError::DecodeLimitExceeded { limit, .. }) => Some(limit),
+            _ => None,
+        }
+    }
+
+    /// Each limit rejects a pack that goes over it with a typed error, and a pack within all of
+    /// them still decodes.
+    #[test]
+    fn test_decode_limits() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let revision = |n: usize| -> Vec<u8> {
+            (0..100)
+                .flat_map(|line| format!("line {line} rev {}\n", line % (n + 2)).into_bytes())
+                .collect()
+        };
+        let mut builder = TestPackBuilder::new();
+        let mut base = builder.add_base(ObjectType::Blob, &revision(0));
+        for n in 1..4 {
+            base = builder.add_ofs_delta(base, &revision(n - 1), ObjectType::Blob, &revision(n));
+        }
+        let pack = builder.pack_bytes();
+        let size = revision(3).len();
+
+        let limits = DecodeLimits::unlimited();
+        assert_eq!(decode_with_limits(&pack, limits).unwrap(), 4);
+        let generous = limits
+            .with_max_objects(4)
+            .with_max_entry_size(size)
+            .with_max_delta_result_size(size)
+            .with_max_delta_depth(3);
+        assert_eq!(decode_with_limits(&pack, generous).unwrap(), 4);
+
+        for (limits, expected) in [
+            (limits.with_max_objects(3), DecodeLimit::ObjectCount),
+            (limits.with_max_entry_size(size - 1), DecodeLimit::EntrySize),
+            (
+                limits.with_max_total_inflated_size(size as u64),
+                DecodeLimit::TotalInflatedSize,
+            ),
+            (
+                limits.with_max_delta_result_size(size - 1),
+                DecodeLimit::DeltaResultSize,
+            ),
+            (limits.with_max_delta_depth(2), DecodeLimit::DeltaDepth),
+        ] {
+            assert_eq!(exceeded(decode_with_limits(&pack, limits)), Some(expected));
+        }
+    }
+
+    /// A limit breach in `decode_stream` comes back as an error instead of panicking the
+    /// blocking task.
+    #[tokio::test]
+    async fn test_decode_stream_limit_exceeded() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let mut builder = TestPackBuilder::new();
+        for n in 0..4 {
+            builder.add_base(ObjectType::Blob, format!("object {n}\n").as_bytes());
+        }
+        let stream = futures::stream::iter(vec![Ok(bytes::Bytes::from(builder.pack_bytes()))]);
+
+        let (_dir, tmp) = pack_test_tmp();
+        let pack_decoder = Pack::new(Some(2), None, Some(tmp), true)
+            .with_decode_limits(DecodeLimits::unlimited().with_max_objects(3));
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let result = pack_decoder.decode_stream(stream, tx, None).await;
+        assert!(matches!(
+            result,
+            Err(GitError::DecodeLimitExceeded {
+                limit: DecodeLimit::ObjectCount,
+                ..
+            })
+        ));
+    }
+
+    /// A delta that claims a huge result is rejected from its header, before anything the size
+    /// of the claim is allocated.
+    #[test]
+    fn test_decode_limits_reject_delta_bomb() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let mut builder = TestPackBuilder::new();
+        let base = builder.add_base(ObjectType::Blob, b"base");
+        // Delta header: base size 4, result size 2^40, followed by no instructions.
+        let mut delta = vec![4u8];
+        let mut result_size = 1u64 << 40;
+        while result_size >= 0x80 {
+            delta.push((result_size as u8 & 0x7f) | 0x80);
+            result_size >>= 7;
+        }
+        delta.push(result_size as u8);
+        let distance = builder.pack_bytes().len() as u64 - base.offset - 20;
+        builder.add_raw(
+            6,
+            delta.len(),
+            &[distance as u8],
+            &delta,
+            ObjectHash::default(),
+        );
+
+        let limits = DecodeLimits::unlimited().with_max_delta_result_size(1 << 20);
+        assert_eq!(
+            exceeded(decode_with_limits(&builder.pack_bytes(), limits)),
+            Some(DecodeLimit::DeltaResultSize)
+        );
+    }
+
+    /// Cancelling from the callback stops the decode with `GitError::Cancelled` and removes its
+    /// temp directory.
+    #[test]
+    fn test_decode_cancelled() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let mut builder = TestPackBuilder::new();
+        for n in 0..20 {
+            builder.add_base(ObjectType::Blob, format!("object {n}\n").as_bytes());
+        }
+        let pack = builder.pack_bytes();
+
+        let (_dir, tmp) = pack_test_tmp();
+        let token = CancellationToken::new();
+        let mut pack_decoder = Pack::new(Some(2), Some(4096), Some(tmp.clone()), false)
+            .with_cancellation(token.clone());
+        let decoded = Arc::new(AtomicUsize::new(0));
+        let counter = decoded.clone();
+        let (probe, spilled) = (tmp.clone(), Arc::new(AtomicBool::new(false)));
+        let seen_spill = spilled.clone();
+        let result = pack_decoder.decode(
+            &mut Cursor::new(&pack),
+            move |_| {
+                seen_spill.fetch_or(probe.exists(), Ordering::Relaxed);
+                if counter.fetch_add(1, Ordering::Relaxed) == 10 {
+                    token.cancel();
+                }
+            },
+            None::<fn(ObjectHash)>,
+        );
+        assert!(matches!(result, Err(GitError::Cancelled)));
+        assert!(decoded.load(Ordering::Relaxed) < 20);
+        // The small memory limit makes the cache spill, so there was a temp directory to remove.
+        assert!(spilled.load(Ordering::Relaxed));
+        assert!(!tmp.exists());
+    }
+
+    /// The observer sees "Receiving objects" end at the object count and pack size, followed by
+    /// "Resolving deltas" with every delta in the pack.
+    #[test]
+    fn test_decode_reports_progress() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let mut builder = TestPackBuilder::new();
+        let mut base = builder.add_base(ObjectType::Blob, b"revision 0\n");
+        for n in 1..4 {
+            base = builder.add_ofs_delta(
+                base,
+                format!("revision {}\n", n - 1).as_bytes(),
+                ObjectType::Blob,
+                format!("revision {n}\n").as_bytes(),
+            );
+        }
+        builder.add_base(ObjectType::Blob, b"unrelated\n");
+        let pack = builder.pack_bytes();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let (_dir, tmp) = pack_test_tmp();
+        let mut pack_decoder = Pack::new(Some(2), None, Some(tmp), true)
+            .with_progress(Arc::new(move |p: &Progress| sink.lock().unwrap().push(*p)));
+        pack_decoder
+            .decode(&mut Cursor::new(&pack), |_| {}, None::<fn(ObjectHash)>)
+            .unwrap();
+
+        let seen = seen.lock().unwrap();
+        let finished: Vec<_> = seen.iter().filter(|p| p.finished).collect();
+        assert_eq!(finished.len(), 2);
+        assert_eq!(finished[0].phase, ProgressPhase::ReceivingObjects);
+        assert_eq!((finished[0].done, finished[0].total), (5, Some(5)));
+        assert_eq!(finished[0].bytes, pack.len() as u64);
+        assert_eq!(finished[1].phase, ProgressPhase::ResolvingDeltas);
+        assert_eq!(finished[1].deltas_resolved, 3);
+        assert!(
+            seen.iter()
+                .filter(|p| p.phase == ProgressPhase::ReceivingObjects)
+                .is_sorted_by_key(|p| p.done)
+        );
+    }
+
+    fn walk_files(dir: &Path) -> Vec<PathBuf> {
+        let Ok(read) = fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        read.flat_map(|e| {
+            let path = e.unwrap().path();
+            if path.is_dir() {
+                walk_files(&path)
+            } else {
+                vec![path]
+            }
+        })
+        .collect()
+    }
 }
@@ -0,0 +1,178 @@
+//! Zlib settings for pack entries.
+//!
+//! Pack entries default to zlib level 6, like Git's `core.compression`. [`PackCompression`] lets
+//! an encoder trade speed for size as a whole or per object type, and can store blobs that are
+//! already compressed (images, archives) instead of deflating them a second time.
+
+use flate2::Compression;
+
+use crate::internal::{object::types::ObjectType, pack::entry::Entry};
+
+/// Leading bytes of formats whose payload is already compressed.
+const COMPRESSED_MAGICS: &[&[u8]] = &[
+    b"\x89PNG\r\n\x1a\n", // PNG
+    b"\xff\xd8\xff",      // JPEG
+    b"GIF87a",
+    b"GIF89a",
+    b"PK\x03\x04",         // zip, jar, apk, docx, ...
+    b"\x1f\x8b",           // gzip
+    b"\x28\xb5\x2f\xfd",   // zstd
+    b"\xfd7zXZ\x00",       // xz
+    b"7z\xbc\xaf\x27\x1c", // 7z
+    b"\x04\x22\x4d\x18",   // lz4 frame
+    b"wOFF",               // WOFF
+    b"wOF2",               // WOFF2
+    b"\x1a\x45\xdf\xa3",   // Matroska, WebM
+    b"OggS",               // Ogg
+    b"fLaC",               // FLAC
+];
+
+/// Whether `data` starts like a file format that is already compressed, so deflating it again
+/// costs time without saving space.
+pub fn is_precompressed(data: &[u8]) -> bool {
+    if COMPRESSED_MAGICS
+        .iter()
+        .any(|magic| data.starts_with(magic))
+    {
+        return true;
+    }
+    // RIFF containers are only compressed for WebP; ISO media files name their brand at offset 4.
+    (data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP"))
+        || data.get(4..8) == Some(b"ftyp")
+        || is_bzip2(data)
+        || is_id3(data)
+}
+
+/// bzip2: `BZh`, the block size digit, then the magic of the first block (the digits of pi).
+fn is_bzip2(data: &[u8]) -> bool {
+    data.starts_with(b"BZh")
+        && matches!(data.get(3), Some(b'1'..=b'9'))
+        && data.get(4..10) == Some(b"1AY&SY")
+}
+
+/// MP3 with an ID3v2 tag: `ID3`, a major version of 2 to 4, a revision below 0xff, flags, and a
+/// four-byte size whose bytes each keep their high bit clear.
+fn is_id3(data: &[u8]) -> bool {
+    match data.get(..10) {
+        Some([b'I', b'D', b'3', major, revision, _flags, size @ ..]) => {
+            (2..=4).contains(major) && *revision != 0xff && size.iter().all(|b| b & 0x80 == 0)
+        }
+        _ => false,
+    }
+}
+
+/// Zlib levels used when writing pack entries; see
+/// [`PackEncoder::with_compression`](super::PackEncoder::with_compression).
+///
+/// Levels run from 0 (store) to 9 (best), as for Git's `pack.compression`. A delta entry uses the
+/// level of the object it reconstructs. Kept deltas copied from a source pack are not recompressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackCompression {
+    level: u32,
+    commit: Option<u32>,
+    tree: Option<u32>,
+    blob: Option<u32>,
+    tag: Option<u32>,
+    store_precompressed: bool,
+}
+
+impl Default for PackCompression {
+    fn default() -> Self {
+        Self::new(Compression::default().level())
+    }
+}
+
+impl PackCompression {
+    /// Use `level` for every object type. Levels above 9 are clamped.
+    pub fn new(level: u32) -> Self {
+        PackCompression {
+            level: level.min(9),
+            commit: None,
+            tree: None,
+            blob: None,
+            tag: None,
+            store_precompressed: false,
+        }
+    }
+
+    /// Fastest compression, for interactive fetches.
+    pub fn fast() -> Self {
+        Self::new(Compression::fast().level())
+    }
+
+    /// Smallest output, for archives.
+    pub fn best() -> Self {
+        Self::new(Compression::best().level())
+    }
+
+    /// Use `level` for objects of `obj_type` instead of the encoder-wide level. Delta and AI object
+    /// types have no level of their own and are ignored.
+    pub fn with_type_level(mut self, obj_type: ObjectType, level: u32) -> Self {
+        let level = Some(level.min(9));
+        match obj_type {
+            ObjectType::Commit => self.commit = level,
+            ObjectType::Tree => self.tree = level,
+            ObjectType::Blob => self.blob = level,
+            ObjectType::Tag => self.tag = level,
+            _ => {}
+        }
+        self
+    }
+
+    /// Store blobs that [`is_precompressed`] recognises at level 0 instead of deflating them.
+    pub fn with_store_precompressed(mut self, enabled: bool) -> Self {
+        self.store_precompressed = enabled;
+        self
+    }
+
+    /// Level for `entry`, which must still hold its object type and full content.
+    pub(crate) fn level_for(&self, entry: &Entry) -> Compression {
+        let level = match entry.obj_type {
+            ObjectType::Blob if self.store_precompressed && is_precompressed(&entry.data) => 0,
+            ObjectType::Commit => self.commit.unwrap_or(self.level),
+            ObjectType::Tree => self.tree.unwrap_or(self.level),
+            ObjectType::Blob => self.blob.unwrap_or(self.level),
+            ObjectType::Tag => self.tag.unwrap_or(self.level),
+            _ => self.level,
+        };
+        Compression::new(level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::object::blob::Blob;
+
+    #[test]
+    fn test_is_precompressed() {
+        assert!(is_precompressed(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"));
+        assert!(is_precompressed(b"PK\x03\x04\x14\0\0\0"));
+        assert!(is_precompressed(b"RIFF\0\0\0\0WEBPVP8 "));
+        assert!(is_precompressed(b"\0\0\0\x18ftypmp42"));
+        assert!(!is_precompressed(b"RIFF\0\0\0\0WAVEfmt "));
+        assert!(is_precompressed(b"BZh91AY&SY\x12\x34"));
+        assert!(!is_precompressed(b"BZh is how this text file starts"));
+        assert!(!is_precompressed(b"BZh01AY&SY"));
+        assert!(is_precompressed(b"ID3\x04\x00\x00\x00\x00\x02\x01"));
+        assert!(!is_precompressed(b"ID3 tags are described below"));
+        assert!(!is_precompressed(b"ID3\x09\x00\x00\x00\x00\x02\x01"));
+        assert!(!is_precompressed(b"fn main() {}\n"));
+        assert!(!is_precompressed(b""));
+    }
+
+    #[test]
+    fn test_level_for() {
+        let png: Entry = Blob::from_content_bytes(b"\x89PNG\r\n\x1a\n pixels".to_vec()).into();
+        let text: Entry = Blob::from_content("plain text").into();
+
+        let compression = PackCompression::fast().with_type_level(ObjectType::Blob, 12);
+        assert_eq!(compression.level_for(&text).level(), 9);
+        assert_eq!(compression.level_for(&png).level(), 9);
+
+        let compression = compression.with_store_precompressed(true);
+        assert_eq!(compression.level_for(&text).level(), 9);
+        assert_eq!(compression.level_for(&png).level(), 0);
+        assert_eq!(PackCompression::default().level_for(&text).level(), 6);
+    }
+}
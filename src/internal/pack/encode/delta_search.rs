@@ -11,39 +11,102 @@
 //! | `diff_rabin`      | Rabin fingerprint | Lazy indexed, scores every candidate |
 //! | (default)         | Myers / Patience  | Similarity pre-filter → single delta |
 
-use std::{collections::VecDeque, sync::Arc};
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Arc,
+};
 
 #[cfg(not(feature = "diff_rabin"))]
 use rayon::prelude::*;
 
-use super::header::encode_one_object;
 #[cfg(feature = "diff_rabin")]
 use super::sort::multi_point_similar;
 #[cfg(not(feature = "diff_rabin"))]
 use super::sort::{calc_hash, cheap_similar};
+use super::{
+    compression::PackCompression,
+    dictionary::ZstdDictionary,
+    header::{DeltaBase, encode_offset_delta_parts, encode_one_object, join_offset_delta},
+};
 use crate::{
     delta,
     errors::GitError,
+    hash::ObjectHash,
     internal::{
         object::types::ObjectType,
-        pack::{entry::Entry, index_entry::IndexEntry},
+        pack::{cancel::CancellationToken, entry::Entry, index_entry::IndexEntry},
     },
     zstdelta,
 };
 
-const MAX_CHAIN_LEN: usize = 50;
+pub(super) const MAX_CHAIN_LEN: usize = 50;
 /// A delta must save at least half of the target payload to be selected.
 const MIN_DELTA_RATE: f64 = 0.5;
+/// Bytes counted for the OFS distance to a dictionary when choosing whether to store an entry
+/// against it, before that distance is known. Five bytes cover every pack below 32 GiB.
+const DICTIONARY_DISTANCE_RESERVE: usize = 5;
+
+/// What an OFS_DELTA produced by delta search points at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum OffsetBase {
+    /// An earlier entry of the same bucket, by its position in the search results.
+    Entry(usize),
+    /// A trained dictionary, by its position in the dictionary list.
+    Dictionary(usize),
+}
+
+/// One entry produced by delta search.
+///
+/// OFS_DELTA distances depend on where the bucket lands in the pack and on the length of every
+/// entry in between, so offset deltas keep their base symbolic until the serial write loop
+/// places them, the same way copied deltas are resolved.
+pub(super) enum SearchedEntry {
+    /// A base object or REF_DELTA, as the complete pack entry.
+    Encoded(Vec<u8>),
+    /// An offset delta, as the entry header and the compressed payload that go around the
+    /// encoded distance to `base`.
+    OffsetDelta {
+        base: OffsetBase,
+        header: Vec<u8>,
+        payload: Vec<u8>,
+    },
+}
+
+impl SearchedEntry {
+    /// The complete pack entry when written at absolute pack offset `offset`, given the offsets
+    /// already assigned to the earlier entries of its bucket.
+    pub(super) fn into_bytes(
+        self,
+        offset: usize,
+        placed: &[usize],
+        dictionaries: &[ZstdDictionary],
+    ) -> Vec<u8> {
+        match self {
+            SearchedEntry::Encoded(bytes) => bytes,
+            SearchedEntry::OffsetDelta {
+                base,
+                header,
+                payload,
+            } => {
+                let base_offset = match base {
+                    OffsetBase::Entry(pos) => placed[pos],
+                    OffsetBase::Dictionary(pos) => dictionaries[pos].offset,
+                };
+                join_offset_delta(&header, offset - base_offset, &payload)
+            }
+        }
+    }
+}
 
 /// One previously encoded object that may serve as a delta base.
 ///
-/// `offset` is relative to the beginning of the current bucket, which is sufficient to compute the
-/// backwards OFS_DELTA distance. With `diff_rabin`, the source index is created lazily and reused
-/// while this entry remains in the window.
+/// `position` is the entry's index in the bucket's search results, which the write loop turns
+/// into an OFS_DELTA distance; external bases produce no result and have none. With `diff_rabin`,
+/// the source index is created lazily and reused while this entry remains in the window.
 #[cfg(feature = "diff_rabin")]
 pub(crate) struct DeltaWindowEntry {
     pub(crate) entry: Entry,
-    pub(crate) offset: usize,
+    pub(crate) position: Option<usize>,
     /// Shared source bytes retained when the lazy Rabin index is built.
     pub(crate) data_arc: Option<Arc<[u8]>>,
     /// Reusable lookup index for evaluating multiple targets against this base.
@@ -54,14 +117,14 @@ pub(crate) struct DeltaWindowEntry {
 #[cfg(not(feature = "diff_rabin"))]
 pub(crate) struct DeltaWindowEntry {
     pub(crate) entry: Entry,
-    pub(crate) offset: usize,
+    pub(crate) position: Option<usize>,
 }
 
 impl DeltaWindowEntry {
-    pub(crate) fn new(entry: Entry, offset: usize) -> Self {
+    pub(crate) fn new(entry: Entry, position: Option<usize>) -> Self {
         Self {
             entry,
-            offset,
+            position,
             #[cfg(feature = "diff_rabin")]
             data_arc: None,
             #[cfg(feature = "diff_rabin")]
@@ -79,26 +142,52 @@ impl super::PackEncoder {
     /// 2. Scores the survivors and selects the most profitable base.
     /// 3. Replaces the target payload with a Git-compatible delta stream when savings exceed
     ///    [`MIN_DELTA_RATE`]; otherwise leaves it as a base object.
-    /// 4. Calls [`encode_one_object`] to add the pack-entry header and zlib compression.
+    /// 4. Adds the pack-entry header and zlib compression, leaving the OFS_DELTA distance of an
+    ///    offset delta to [`SearchedEntry::into_bytes`].
     /// 5. Adds the original target bytes to the window so later entries can use them as a base.
     ///
     /// The returned `IndexEntry` offsets are placeholders. `inner_encode` assigns absolute pack
     /// offsets when it merges bucket results into the final output order.
+    ///
+    /// Entries whose hash is in `external_bases` only join the window: they produce no output, and
+    /// targets that pick them as a base become REF_DELTA entries. With `ref_delta`, every delta is
+    /// written as REF_DELTA. Neither applies to zstdelta, which only has an offset form.
+    ///
+    /// Each entry is compressed at the level `compression` picks for its original object.
+    ///
+    /// A zstdelta entry that finds no base is written against its type's entry in `dictionaries`
+    /// when that is smaller.
+    ///
+    /// `cancellation` is checked before each entry, failing with [`GitError::Cancelled`].
     #[cfg_attr(not(feature = "diff_rabin"), allow(unused_variables))]
+    #[allow(clippy::too_many_arguments)]
     pub(super) fn try_as_offset_delta(
         mut bucket: Vec<Entry>,
         window_size: usize,
         enable_zstdelta: bool,
         enable_rabin: bool,
         disable_prefilter: bool,
-    ) -> Result<Vec<(Vec<u8>, IndexEntry)>, GitError> {
-        // Offsets are bucket-local here. Their differences remain valid OFS_DELTA distances when
-        // the whole bucket is later placed at any absolute pack position.
-        let mut current_offset = 0usize;
+        ref_delta: bool,
+        external_bases: &HashSet<ObjectHash>,
+        compression: &PackCompression,
+        dictionaries: &[ZstdDictionary],
+        cancellation: &CancellationToken,
+    ) -> Result<Vec<(SearchedEntry, IndexEntry)>, GitError> {
         let mut window: VecDeque<DeltaWindowEntry> = VecDeque::with_capacity(window_size);
-        let mut res: Vec<(Vec<u8>, IndexEntry)> = Vec::with_capacity(bucket.len());
+        let mut res: Vec<(SearchedEntry, IndexEntry)> = Vec::with_capacity(bucket.len());
 
         for entry in bucket.iter_mut() {
+            cancellation.check()?;
+            if external_bases.contains(&entry.hash) {
+                // Available to later targets, but the receiver already has it.
+                window.push_back(DeltaWindowEntry::new(entry.clone(), None));
+                if window.len() > window_size {
+                    window.pop_front();
+                }
+                continue;
+            }
+            // The level follows the object's own type and content, which a delta replaces.
+            let level = compression.level_for(entry);
             // best_rate is the estimated fraction of target bytes saved by delta encoding.
             let mut best_base: Option<&DeltaWindowEntry> = None;
             let mut best_rate: f64 = 0.0;
@@ -260,7 +349,10 @@ impl super::PackEncoder {
             // replaced below by its delta instruction stream.
             let mut entry_for_window = entry.clone();
 
-            let offset = best_base.map(|best_base| {
+            let delta_base = best_base.map(|best_base| {
+                // A base outside the pack can only be named by its hash.
+                let by_hash = !enable_zstdelta
+                    && (ref_delta || external_bases.contains(&best_base.entry.hash));
                 let delta = if enable_zstdelta {
                     entry.obj_type = ObjectType::OffsetZstdelta;
                     zstdelta::diff(&best_base.entry.data, &entry.data)
@@ -290,22 +382,78 @@ impl super::PackEncoder {
                 };
                 entry.data = delta;
                 entry.chain_len = best_base.entry.chain_len + 1;
-                // OFS_DELTA stores a positive backwards distance, not an absolute base offset.
-                current_offset - best_base.offset
+                if by_hash {
+                    entry.obj_type = ObjectType::HashDelta;
+                }
+                (by_hash, best_base.entry.hash, best_base.position)
             });
 
             // A future child of this target must observe the updated chain depth even though its
             // window copy retains the original, fully reconstructed bytes.
             entry_for_window.chain_len = entry.chain_len;
-            let obj_data = encode_one_object(entry, offset)?;
-            window.push_back(DeltaWindowEntry::new(entry_for_window, current_offset));
+            let obj_data = match delta_base {
+                Some((true, hash, _)) => SearchedEntry::Encoded(encode_one_object(
+                    entry,
+                    Some(DeltaBase::Hash(hash)),
+                    level,
+                )?),
+                Some((false, _, position)) => {
+                    // Only external bases lack a position, and those are always named by hash.
+                    let position = position.ok_or_else(|| {
+                        GitError::PackEncodeError(format!(
+                            "offset delta {} has a base outside the pack",
+                            entry.hash
+                        ))
+                    })?;
+                    let (header, payload) = encode_offset_delta_parts(entry, level)?;
+                    SearchedEntry::OffsetDelta {
+                        base: OffsetBase::Entry(position),
+                        header,
+                        payload,
+                    }
+                }
+                None => {
+                    let plain = encode_one_object(entry, None, level)?;
+                    match dictionaries
+                        .iter()
+                        .position(|d| d.obj_type == entry.obj_type)
+                    {
+                        Some(dictionary) => {
+                            let against_dictionary = Entry {
+                                obj_type: ObjectType::OffsetZstdelta,
+                                data: zstdelta::diff(&dictionaries[dictionary].data, &entry.data)
+                                    .map_err(|e| {
+                                    GitError::DeltaObjectError(format!("zstdelta diff failed: {e}"))
+                                })?,
+                                hash: entry.hash,
+                                chain_len: 1,
+                            };
+                            let (header, payload) =
+                                encode_offset_delta_parts(&against_dictionary, level)?;
+                            if header.len() + DICTIONARY_DISTANCE_RESERVE + payload.len()
+                                < plain.len()
+                            {
+                                *entry = against_dictionary;
+                                entry_for_window.chain_len = entry.chain_len;
+                                SearchedEntry::OffsetDelta {
+                                    base: OffsetBase::Dictionary(dictionary),
+                                    header,
+                                    payload,
+                                }
+                            } else {
+                                SearchedEntry::Encoded(plain)
+                            }
+                        }
+                        None => SearchedEntry::Encoded(plain),
+                    }
+                }
+            };
+            window.push_back(DeltaWindowEntry::new(entry_for_window, Some(res.len())));
             // Evict the oldest base once it falls outside the candidate window.
             if window.len() > window_size {
                 window.pop_front();
             }
-            let obj_data_len = obj_data.len();
             res.push((obj_data, IndexEntry::new(entry, 0)));
-            current_offset += obj_data_len;
         }
         Ok(res)
     }
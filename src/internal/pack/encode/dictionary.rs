@@ -0,0 +1,89 @@
+//! Trained zstd dictionaries for zstdelta packs.
+//!
+//! Small trees, commits and tags often have no good delta base, yet objects of one type share most
+//! of their structure. With [`PackEncoder::with_zstd_dictionaries`](super::PackEncoder::with_zstd_dictionaries)
+//! the encoder trains one dictionary per object type over a sample of the input and writes each
+//! one right after the pack header. Objects that find no delta base are then stored as zstdelta
+//! entries against their type's dictionary whenever that is smaller than the plain entry.
+//!
+//! A dictionary entry uses pack type 0, which Git leaves unused. Its payload is the pack type
+//! number of the objects it serves followed by the dictionary itself. The entry counts towards the
+//! object number in the pack header, but it has no object id and no `.idx` record.
+
+use std::sync::Arc;
+
+use super::header::encode_zstd_dictionary;
+use crate::{
+    errors::GitError,
+    internal::{object::types::ObjectType, pack::entry::Entry},
+    zstdelta,
+};
+
+/// Upper bound of a trained dictionary, zstd's own default.
+const MAX_DICTIONARY_SIZE: usize = 110 * 1024;
+/// zstd suggests about a hundred times the dictionary size of sample data.
+const MAX_SAMPLE_BYTES: usize = MAX_DICTIONARY_SIZE * 100;
+/// Larger objects rarely benefit from a shared dictionary and are left out of the sample.
+const MAX_SAMPLE_OBJECT_SIZE: usize = 64 * 1024;
+/// Types with fewer eligible objects get no dictionary.
+const MIN_SAMPLES: usize = 16;
+
+/// One trained dictionary, placed at a fixed pack offset before any object.
+pub(super) struct ZstdDictionary {
+    pub(super) obj_type: ObjectType,
+    pub(super) data: Arc<[u8]>,
+    /// Absolute pack offset of the dictionary entry.
+    pub(super) offset: usize,
+    /// The complete pack entry.
+    pub(super) encoded: Vec<u8>,
+}
+
+/// Train a dictionary for every object type of `entries` with enough small objects, and lay the
+/// dictionary entries out from `start_offset`.
+///
+/// The sample follows the order of `entries`, spreading evenly over all eligible objects when they
+/// exceed the sample budget, so sorted input gives the same dictionaries every time. A type whose
+/// training fails is simply left without a dictionary.
+pub(super) fn train_dictionaries<'a>(
+    entries: impl Iterator<Item = &'a Entry> + Clone,
+    start_offset: usize,
+) -> Result<Vec<ZstdDictionary>, GitError> {
+    let mut dictionaries = Vec::new();
+    let mut offset = start_offset;
+    for obj_type in [
+        ObjectType::Commit,
+        ObjectType::Tree,
+        ObjectType::Blob,
+        ObjectType::Tag,
+    ] {
+        let eligible = entries.clone().filter(|e| {
+            e.obj_type == obj_type && !e.data.is_empty() && e.data.len() <= MAX_SAMPLE_OBJECT_SIZE
+        });
+        let eligible_bytes: usize = eligible.clone().map(|e| e.data.len()).sum();
+        let step = eligible_bytes.div_ceil(MAX_SAMPLE_BYTES).max(1);
+        let samples: Vec<&[u8]> = eligible.step_by(step).map(|e| e.data.as_slice()).collect();
+        if samples.len() < MIN_SAMPLES {
+            continue;
+        }
+
+        let sample_bytes: usize = samples.iter().map(|s| s.len()).sum();
+        let max_size = (sample_bytes / 10).clamp(1024, MAX_DICTIONARY_SIZE);
+        let data = match zstdelta::train_dictionary(&samples, max_size) {
+            Ok(data) => data,
+            Err(e) => {
+                tracing::debug!("no zstd dictionary for {obj_type}: {e}");
+                continue;
+            }
+        };
+        let encoded = encode_zstd_dictionary(obj_type, &data)?;
+        let len = encoded.len();
+        dictionaries.push(ZstdDictionary {
+            obj_type,
+            data: data.into(),
+            offset,
+            encoded,
+        });
+        offset += len;
+    }
+    Ok(dictionaries)
+}
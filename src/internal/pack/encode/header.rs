@@ -1,17 +1,41 @@
 //! Git pack wire-format encoding helpers.
 //!
 //! Low-level functions that produce the binary pack header, OFS_DELTA offset encoding, and the
-//! variable-length object entry header followed by zlib-compressed payload.
+//! variable-length object entry header followed by the delta base (if any) and zlib-compressed
+//! payload.
 
 use std::io::Write;
 
-use flate2::write::ZlibEncoder;
+use flate2::{Compression, write::ZlibEncoder};
 
 use crate::{
     errors::GitError,
-    internal::{object::types::ObjectType, pack::entry::Entry},
+    hash::ObjectHash,
+    internal::{
+        metadata::ReusableDelta,
+        object::types::ObjectType,
+        pack::{entry::Entry, utils::ZSTD_DICTIONARY_PACK_TYPE},
+    },
 };
 
+/// How a delta entry names its base, written between the entry header and the payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DeltaBase {
+    /// OFS_DELTA: backwards distance from this entry to its base in the same pack.
+    Offset(usize),
+    /// REF_DELTA: object id of the base, which may live outside the pack.
+    Hash(ObjectHash),
+}
+
+impl DeltaBase {
+    fn encode(self) -> Vec<u8> {
+        match self {
+            DeltaBase::Offset(distance) => encode_offset(distance),
+            DeltaBase::Hash(hash) => hash.as_ref().to_vec(),
+        }
+    }
+}
+
 /// Build the fixed 12-byte pack header.
 ///
 /// Layout: `b"PACK"` + version `2` as big-endian `u32` + object count as big-endian `u32`.
@@ -47,23 +71,11 @@ pub(crate) fn encode_offset(mut value: usize) -> Vec<u8> {
     bytes
 }
 
-/// Encode one complete pack entry.
+/// Encode the variable-length header that starts every pack entry.
 ///
-/// The result contains the variable-length object header, an optional OFS_DELTA base distance, and
-/// the zlib-compressed payload. For a base object, `entry.data` is the object's raw content. For a
-/// delta object, it is already a Git delta instruction stream produced by the selected delta
-/// engine.
-///
-/// `offset` is required for offset-delta types and must be `None` for base objects.
-pub(crate) fn encode_one_object(entry: &Entry, offset: Option<usize>) -> Result<Vec<u8>, GitError> {
-    let obj_data = &entry.data;
-    let obj_data_len = obj_data.len();
-    let obj_type_number = entry.obj_type.to_pack_type_u8()?;
-
-    let mut encoded_data = Vec::new();
-
-    // The first byte stores 4 size bits, 3 type bits, and a continuation bit. Remaining size bits
-    // are emitted in seven-bit groups, least-significant group first.
+/// The first byte stores 4 size bits, 3 type bits, and a continuation bit. Remaining size bits are
+/// emitted in seven-bit groups, least-significant group first.
+fn encode_entry_header(obj_type_number: u8, obj_data_len: usize) -> Vec<u8> {
     let mut header_data = vec![(0x80 | (obj_type_number << 4)) + (obj_data_len & 0x0f) as u8];
     let mut size = obj_data_len >> 4;
     if size > 0 {
@@ -79,23 +91,118 @@ pub(crate) fn encode_one_object(entry: &Entry, offset: Option<usize>) -> Result<
     } else {
         header_data.push(0);
     }
-    encoded_data.extend(header_data);
-
-    // Offset deltas identify their base by backwards distance from this entry's pack position.
-    if entry.obj_type == ObjectType::OffsetDelta || entry.obj_type == ObjectType::OffsetZstdelta {
-        let offset_data = encode_offset(offset.unwrap());
-        encoded_data.extend(offset_data);
-    } else if entry.obj_type == ObjectType::HashDelta {
-        unreachable!("unsupported type")
+    header_data
+}
+
+/// Encode one complete pack entry.
+///
+/// The result contains the variable-length object header, the delta base for delta types, and the
+/// zlib-compressed payload. For a base object, `entry.data` is the object's raw content. For a
+/// delta object, it is already a Git delta instruction stream produced by the selected delta
+/// engine.
+///
+/// `base` must be [`DeltaBase::Offset`] for offset-delta types, [`DeltaBase::Hash`] for
+/// [`ObjectType::HashDelta`], and `None` for base objects. `level` is the zlib level of the
+/// payload.
+pub(crate) fn encode_one_object(
+    entry: &Entry,
+    base: Option<DeltaBase>,
+    level: Compression,
+) -> Result<Vec<u8>, GitError> {
+    let obj_data = &entry.data;
+    let mut encoded_data = encode_entry_header(entry.obj_type.to_pack_type_u8()?, obj_data.len());
+
+    match (entry.obj_type, base) {
+        (
+            ObjectType::OffsetDelta | ObjectType::OffsetZstdelta,
+            Some(base @ DeltaBase::Offset(_)),
+        )
+        | (ObjectType::HashDelta, Some(base @ DeltaBase::Hash(_))) => {
+            encoded_data.extend(base.encode());
+        }
+        (ObjectType::OffsetDelta | ObjectType::OffsetZstdelta | ObjectType::HashDelta, _) => {
+            return Err(GitError::PackEncodeError(format!(
+                "`{}` entry {} has no matching delta base",
+                entry.obj_type, entry.hash
+            )));
+        }
+        _ => {}
     }
 
     // Git zlib-compresses both raw object payloads and delta instruction streams.
-    let mut inflate = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoded_data.extend(zlib_compress(obj_data, level));
+    Ok(encoded_data)
+}
+
+/// Encode an offset-delta pack entry whose base position is not known yet, as the entry header
+/// and the zlib-compressed payload. [`join_offset_delta`] later puts the OFS distance between
+/// them, giving the same bytes as [`encode_one_object`].
+pub(crate) fn encode_offset_delta_parts(
+    entry: &Entry,
+    level: Compression,
+) -> Result<(Vec<u8>, Vec<u8>), GitError> {
+    if !matches!(
+        entry.obj_type,
+        ObjectType::OffsetDelta | ObjectType::OffsetZstdelta
+    ) {
+        return Err(GitError::PackEncodeError(format!(
+            "`{}` entry {} is not an offset delta",
+            entry.obj_type, entry.hash
+        )));
+    }
+    let header = encode_entry_header(entry.obj_type.to_pack_type_u8()?, entry.data.len());
+    Ok((header, zlib_compress(&entry.data, level)))
+}
+
+/// Join the parts from [`encode_offset_delta_parts`] into an entry `distance` bytes after its base.
+pub(crate) fn join_offset_delta(header: &[u8], distance: usize, payload: &[u8]) -> Vec<u8> {
+    let offset = encode_offset(distance);
+    let mut encoded_data = Vec::with_capacity(header.len() + offset.len() + payload.len());
+    encoded_data.extend_from_slice(header);
+    encoded_data.extend(offset);
+    encoded_data.extend_from_slice(payload);
+    encoded_data
+}
+
+/// Encode a trained zstd dictionary for objects of `obj_type` as a pack entry of type
+/// [`ZSTD_DICTIONARY_PACK_TYPE`].
+///
+/// The zlib-compressed payload is the pack type number of `obj_type` followed by `dictionary`.
+pub(crate) fn encode_zstd_dictionary(
+    obj_type: ObjectType,
+    dictionary: &[u8],
+) -> Result<Vec<u8>, GitError> {
+    let mut payload = Vec::with_capacity(dictionary.len() + 1);
+    payload.push(obj_type.to_pack_type_u8()?);
+    payload.extend_from_slice(dictionary);
+
+    let mut encoded_data = encode_entry_header(ZSTD_DICTIONARY_PACK_TYPE, payload.len());
+    encoded_data.extend(zlib_compress(&payload, Compression::default()));
+    Ok(encoded_data)
+}
+
+fn zlib_compress(data: &[u8], level: Compression) -> Vec<u8> {
+    let mut inflate = ZlibEncoder::new(Vec::new(), level);
     inflate
-        .write_all(obj_data)
+        .write_all(data)
         .expect("zlib compress should never failed");
     inflate.flush().expect("zlib flush should never failed");
-    let compressed_data = inflate.finish().expect("zlib compress should never failed");
-    encoded_data.extend(compressed_data);
-    Ok(encoded_data)
+    inflate.finish().expect("zlib compress should never failed")
+}
+
+/// Encode a delta kept from a source pack as an OFS_DELTA or REF_DELTA entry, depending on `base`.
+///
+/// The zlib stream is copied unchanged, so the entry costs no compression work.
+pub(crate) fn encode_reused_delta(delta: &ReusableDelta, base: DeltaBase) -> Vec<u8> {
+    // Pack type numbers of OFS_DELTA and REF_DELTA.
+    const OFS_DELTA: u8 = 6;
+    const REF_DELTA: u8 = 7;
+    let type_number = match base {
+        DeltaBase::Offset(_) => OFS_DELTA,
+        DeltaBase::Hash(_) => REF_DELTA,
+    };
+    let mut encoded_data = encode_entry_header(type_number, delta.delta_size);
+    encoded_data.extend(base.encode());
+    encoded_data.extend_from_slice(&delta.compressed);
+    encoded_data
 }
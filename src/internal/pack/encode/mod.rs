@@ -15,20 +15,40 @@
 //! sender is configured, the encoder also records each object's hash, CRC32, and pack offset so an
 //! `.idx` v2 file can be generated after the pack is complete.
 
+mod compression;
 mod delta_search;
+mod dictionary;
 mod header;
 mod parallel;
+mod reuse;
 mod sort;
 
+pub use compression::{PackCompression, is_precompressed};
 pub mod output;
-pub use output::encode_and_output_to_files;
+pub use output::{
+    OutputOptions, WrittenPack, encode_and_output_to_files,
+    encode_and_output_to_files_with_options, encode_and_output_to_files_with_progress,
+};
 
 #[cfg(test)]
 mod tests;
 
-use header::encode_header;
+use delta_search::SearchedEntry;
+use dictionary::ZstdDictionary;
+#[cfg(test)]
+pub(crate) use header::encode_offset;
+pub(crate) use header::encode_one_object;
+use header::{DeltaBase, encode_header, encode_reused_delta};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
 use rayon::prelude::*;
-use sort::magic_sort;
+use sort::magic_sort_preferring;
 use tokio::{sync::mpsc, task::JoinHandle};
 
 use crate::{
@@ -37,11 +57,21 @@ use crate::{
     internal::{
         metadata::{EntryMeta, MetaAttached},
         object::types::ObjectType,
-        pack::{entry::Entry, index_entry::IndexEntry, pack_index::IdxBuilder},
+        pack::{
+            cancel::CancellationToken,
+            entry::Entry,
+            index_entry::IndexEntry,
+            pack_index::IdxBuilder,
+            progress::{ProgressObserver, ProgressPhase, ProgressReporter},
+            reverse_index::build_reverse_index,
+        },
     },
     utils::HashAlgorithm,
 };
 
+/// Blobs per delta-search chunk in deterministic mode.
+const DETERMINISTIC_BLOB_CHUNK: usize = 1000;
+
 /// Stateful encoder for one Git pack stream.
 ///
 /// A `PackEncoder` is single-use. It tracks the current pack offset and checksum while encoded
@@ -73,6 +103,22 @@ pub struct PackEncoder {
     /// This can improve compression at the cost of more delta computation. The default Rabin path
     /// disables the pre-filter to follow Git's candidate-search behavior.
     pub disable_prefilter: bool,
+    /// Receives encode progress; see [`PackEncoder::with_progress`].
+    progress: Option<Arc<dyn ProgressObserver>>,
+    /// Stops encoding when cancelled; see [`PackEncoder::with_cancellation`].
+    cancellation: CancellationToken,
+    /// Write every delta as REF_DELTA; see [`PackEncoder::with_ref_delta`].
+    ref_delta: bool,
+    /// Delta bases the receiver already has; see [`PackEncoder::with_external_bases`].
+    external_bases: Vec<MetaAttached<Entry, EntryMeta>>,
+    /// Make the pack bytes depend only on the input set; see [`PackEncoder::with_deterministic`].
+    deterministic: bool,
+    /// Zlib levels of written entries; see [`PackEncoder::with_compression`].
+    compression: PackCompression,
+    /// Train zstd dictionaries for zstdelta encoding; see [`PackEncoder::with_zstd_dictionaries`].
+    zstd_dictionaries: bool,
+    /// Rayon pool for compression and delta search; see [`PackEncoder::with_thread_pool`].
+    thread_pool: Option<Arc<rayon::ThreadPool>>,
 }
 
 impl PackEncoder {
@@ -90,6 +136,14 @@ impl PackEncoder {
             final_hash: None,
             start_encoding: false,
             disable_prefilter: false,
+            progress: None,
+            cancellation: CancellationToken::new(),
+            ref_delta: false,
+            external_bases: Vec::new(),
+            deterministic: false,
+            compression: PackCompression::default(),
+            zstd_dictionaries: false,
+            thread_pool: None,
         }
     }
 
@@ -112,9 +166,97 @@ impl PackEncoder {
             final_hash: None,
             start_encoding: false,
             disable_prefilter: false,
+            progress: None,
+            cancellation: CancellationToken::new(),
+            ref_delta: false,
+            external_bases: Vec::new(),
+            deterministic: false,
+            compression: PackCompression::default(),
+            zstd_dictionaries: false,
+            thread_pool: None,
         }
     }
 
+    /// Report "Counting objects", "Compressing objects" and "Writing objects" progress to
+    /// `observer`. The zero-window path only reports "Writing objects".
+    pub fn with_progress(mut self, observer: Arc<dyn ProgressObserver>) -> Self {
+        self.progress = Some(observer);
+        self
+    }
+
+    /// Check `token` between objects. Once it is cancelled, encoding stops its delta-search
+    /// workers, closes the pack stream and returns [`GitError::Cancelled`]; the bytes sent so far
+    /// are not a valid pack.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = token;
+        self
+    }
+
+    /// Name delta bases by object id (REF_DELTA) instead of by pack offset (OFS_DELTA), for
+    /// receivers that did not advertise `ofs-delta`. Zstdelta encoding is unaffected.
+    pub fn with_ref_delta(mut self, enabled: bool) -> Self {
+        self.ref_delta = enabled;
+        self
+    }
+
+    /// Offer `bases` to delta search without writing them, as for a thin pack whose receiver
+    /// already has these objects. Entries that delta against one of them are written as
+    /// REF_DELTA, and the pack is not self-contained. Bases also present in the input are written
+    /// as usual. Only the windowed, non-zstdelta path uses them, and they do not count towards the
+    /// encoder's object number.
+    pub fn with_external_bases(mut self, bases: Vec<MetaAttached<Entry, EntryMeta>>) -> Self {
+        self.external_bases = bases;
+        self
+    }
+
+    /// Produce byte-identical packs for the same set of entries, whatever their input order and
+    /// the number of threads.
+    ///
+    /// The zero-window path then receives every entry before writing and orders them by object
+    /// id. The windowed path splits blobs for delta search into chunks of a fixed size instead of
+    /// one chunk per share of the thread pool, which can find fewer deltas on small machines.
+    pub fn with_deterministic(mut self, enabled: bool) -> Self {
+        self.deterministic = enabled;
+        self
+    }
+
+    /// Compress entries at the zlib levels of `compression` instead of the default level 6.
+    pub fn with_compression(mut self, compression: PackCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Train a zstd dictionary per object type over a sample of the input and write each one to
+    /// the pack, so objects without a delta base can be stored as zstdelta entries against it.
+    ///
+    /// Only [`PackEncoder::encode_with_zstdelta`] uses the dictionaries. Delta search still runs
+    /// in parallel; distances to a dictionary are filled in as entries are written.
+    /// Dictionary entries count towards the pack header's object number but get no index record,
+    /// and only this crate's [`Pack`](crate::internal::pack::Pack) decoder can read such a pack,
+    /// once enabled with [`Pack::with_zstd_dictionaries`](crate::internal::pack::Pack::with_zstd_dictionaries).
+    pub fn with_zstd_dictionaries(mut self, enabled: bool) -> Self {
+        self.zstd_dictionaries = enabled;
+        self
+    }
+
+    /// Run compression and delta search on `pool` instead of the global Rayon pool (or the pool
+    /// sized by `PACK_THREADS`).
+    pub fn with_thread_pool(mut self, pool: Arc<rayon::ThreadPool>) -> Self {
+        self.thread_pool = Some(pool);
+        self
+    }
+
+    /// Close the pack stream of a cancelled encode.
+    fn cancel_encode(&mut self) -> GitError {
+        self.drop_sender();
+        GitError::Cancelled
+    }
+
+    /// Start reporting `phase`, with the pack's object count as its total.
+    fn progress_reporter(&self, phase: ProgressPhase) -> ProgressReporter {
+        ProgressReporter::new(self.progress.clone(), phase, Some(self.object_number))
+    }
+
     /// Close the pack output stream by dropping the encoder's final sender.
     pub fn drop_sender(&mut self) {
         self.pack_sender.take();
@@ -136,7 +278,8 @@ impl PackEncoder {
     ///
     /// With `window_size == 0`, entries are independently zlib-compressed in parallel. With a
     /// non-zero window, entries are collected, sorted for delta locality, and passed through
-    /// sliding-window base selection.
+    /// sliding-window base selection; entries carrying a [`EntryMeta::reusable_delta`] whose base
+    /// is also in the stream keep that delta instead.
     ///
     /// When `diff_rabin` is enabled, the default delta engine is Rabin and all otherwise valid
     /// candidates are scored. Without that feature, the Myers/Patience engine is used after the
@@ -173,11 +316,14 @@ impl PackEncoder {
     ///
     /// This method deliberately separates CPU-heavy delta discovery from ordered pack output:
     ///
-    /// 1. Drain the input channel and partition entries by Git object type.
+    /// 1. Drain the input channel and partition entries by Git object type, setting aside entries
+    ///    whose kept delta ([`EntryMeta::reusable_delta`]) can be copied.
     /// 2. Sort each type so likely-related objects are close enough to share a delta window.
-    /// 3. Search for delta bases on blocking worker threads.
-    /// 4. Restore deterministic chunk order and write encoded entries serially, assigning their
-    ///    absolute pack offsets.
+    /// 3. Search for delta bases on blocking worker threads, after training zstd dictionaries if
+    ///    they are enabled.
+    /// 4. Restore deterministic chunk order and write encoded entries serially, after any
+    ///    dictionaries, assigning their absolute pack offsets, then append the copied deltas after
+    ///    their bases.
     /// 5. Append the pack checksum and close the output channel.
     ///
     /// Candidate-selection rules are based on Git's pack heuristics:
@@ -189,10 +335,11 @@ impl PackEncoder {
         enable_rabin: bool,
         disable_prefilter: bool,
     ) -> Result<(), GitError> {
-        // The trailer checksum covers the header and every encoded entry, but not the trailer.
-        let head = encode_header(self.object_number);
-        self.send_data(head.clone()).await;
-        self.inner_hash.update(&head);
+        // The header's object number includes the dictionaries, known only once they are trained.
+        let train = enable_zstdelta && self.zstd_dictionaries;
+        if !train {
+            self.write_header(self.object_number).await;
+        }
 
         // Reusing the same encoder would corrupt its running offset and checksum state.
         if self.start_encoding {
@@ -207,7 +354,14 @@ impl PackEncoder {
         let mut trees: Vec<MetaAttached<Entry, EntryMeta>> = Vec::new();
         let mut blobs: Vec<MetaAttached<Entry, EntryMeta>> = Vec::new();
         let mut tags: Vec<MetaAttached<Entry, EntryMeta>> = Vec::new();
+        let mut counting = self.progress_reporter(ProgressPhase::CountingObjects);
+        let mut counted = 0;
         while let Some(entry) = entry_rx.recv().await {
+            if self.cancellation.is_cancelled() {
+                return Err(self.cancel_encode());
+            }
+            counting.update(counted, 0, 0);
+            counted += 1;
             match entry.inner.obj_type {
                 ObjectType::Commit => {
                     commits.push(entry);
@@ -229,12 +383,60 @@ impl PackEncoder {
                 }
             }
         }
+        counting.finish(counted, 0, 0);
+
+        // Bases the receiver already has join delta search but are never written. Zstdelta has no
+        // REF_DELTA form to point at them, and an object that is also in the input is written
+        // like any other.
+        let mut external_bases = std::mem::take(&mut self.external_bases);
+        if enable_zstdelta {
+            external_bases.clear();
+        }
+        if !external_bases.is_empty() {
+            let input_hashes: HashSet<ObjectHash> = [&commits, &trees, &blobs, &tags]
+                .iter()
+                .flat_map(|group| group.iter().map(|e| e.inner.hash))
+                .collect();
+            external_bases.retain(|base| !input_hashes.contains(&base.inner.hash));
+        }
+        let external_hashes: Arc<HashSet<ObjectHash>> =
+            Arc::new(external_bases.iter().map(|base| base.inner.hash).collect());
+
+        // Which kept deltas survive depends on the order they are seen in.
+        if self.deterministic {
+            for group in [&mut commits, &mut trees, &mut blobs, &mut tags] {
+                group.sort_by_key(|e| e.inner.hash);
+            }
+        }
+
+        // Deltas kept from a source pack are copied when their base is written too (or is an
+        // external base); those entries skip delta search and are written after everything else.
+        let reused = reuse::take_reused_deltas(
+            [&mut commits, &mut trees, &mut blobs, &mut tags],
+            &external_hashes,
+        );
+
+        for base in external_bases {
+            match base.inner.obj_type {
+                ObjectType::Commit => commits.push(base),
+                ObjectType::Tree => trees.push(base),
+                ObjectType::Blob => blobs.push(base),
+                ObjectType::Tag => tags.push(base),
+                _ => {
+                    return Err(GitError::PackEncodeError(format!(
+                        "object type `{}` cannot be an external delta base",
+                        base.inner.obj_type
+                    )));
+                }
+            }
+        }
 
         // Sorting is the compression heuristic: nearby entries become eligible delta bases.
-        commits.sort_by(magic_sort);
-        trees.sort_by(magic_sort);
-        blobs.sort_by(magic_sort);
-        tags.sort_by(magic_sort);
+        let sort = |a: &_, b: &_| magic_sort_preferring(a, b, &external_hashes);
+        commits.sort_by(sort);
+        trees.sort_by(sort);
+        blobs.sort_by(sort);
+        tags.sort_by(sort);
         tracing::info!(
             "numbers :  commits: {:?} trees: {:?} blobs:{:?} tag :{:?}",
             commits.len(),
@@ -278,9 +480,18 @@ impl PackEncoder {
         // delta quality. Creating many chunks exposes enough parallelism for
         // Rayon's work-stealing scheduler.
         let total_blob_entries = blob_entries.len();
-        let num_threads = rayon::current_num_threads();
+        let num_threads = self
+            .thread_pool
+            .as_ref()
+            .map_or_else(rayon::current_num_threads, |pool| {
+                pool.current_num_threads()
+            });
         let chunks_per_thread: usize = 20;
-        let mut blob_chunk_count = if num_threads > 1 && total_blob_entries > (num_threads * 20) {
+        let mut blob_chunk_count = if self.deterministic {
+            // Chunk boundaries limit which bases a blob can see, so they must not follow the
+            // thread count.
+            total_blob_entries.div_ceil(DETERMINISTIC_BLOB_CHUNK).max(1)
+        } else if num_threads > 1 && total_blob_entries > (num_threads * 20) {
             num_threads * chunks_per_thread
         } else {
             1
@@ -336,82 +547,211 @@ impl PackEncoder {
         // Offload all delta search to Rayon inside a single spawn_blocking task.
         // This keeps CPU work off the async runtime while Rayon's work-stealing
         // balances load across the heterogeneous work items.
-        type ChunkResult = (usize, Result<Vec<(Vec<u8>, IndexEntry)>, GitError>);
+        type ChunkResult = (usize, Result<Vec<(SearchedEntry, IndexEntry)>, GitError>);
+        type SearchResult = Result<(Vec<ZstdDictionary>, Vec<ChunkResult>), GitError>;
 
         let ez = enable_zstdelta;
         let er = enable_rabin;
         let dp = disable_prefilter;
-
-        let run_delta_search = move || -> Vec<ChunkResult> {
-            work_items
-                .into_par_iter()
-                .map(|item| {
-                    (
-                        item.order,
-                        Self::try_as_offset_delta(item.entries, 10, ez, er, dp),
-                    )
-                })
-                .collect()
+        let rd = self.ref_delta;
+        let external = external_hashes.clone();
+        let compression = self.compression;
+        let cancellation = self.cancellation.clone();
+
+        // Work items finish in any order, so progress is counted across Rayon workers.
+        let compressing = Mutex::new(self.progress_reporter(ProgressPhase::CompressingObjects));
+        let compressed = AtomicUsize::new(reused.len());
+        let run_delta_search = move || -> SearchResult {
+            compressing.lock().unwrap().update(0, 0, 0);
+            // Dictionaries are written right after the 12-byte header.
+            let dictionaries = if train {
+                dictionary::train_dictionaries(
+                    work_items.iter().flat_map(|item| item.entries.iter()),
+                    12,
+                )?
+            } else {
+                Vec::new()
+            };
+            let search = |item: WorkItem| -> ChunkResult {
+                let len = item
+                    .entries
+                    .iter()
+                    .filter(|e| !external.contains(&e.hash))
+                    .count();
+                let result = Self::try_as_offset_delta(
+                    item.entries,
+                    10,
+                    ez,
+                    er,
+                    dp,
+                    rd,
+                    &external,
+                    &compression,
+                    &dictionaries,
+                    &cancellation,
+                );
+                let done = compressed.fetch_add(len, Ordering::Relaxed) + len;
+                compressing.lock().unwrap().update(done, 0, 0);
+                (item.order, result)
+            };
+            let results = work_items.into_par_iter().map(search).collect();
+            compressing
+                .lock()
+                .unwrap()
+                .finish(compressed.load(Ordering::Relaxed), 0, 0);
+            Ok((dictionaries, results))
         };
 
-        let mut chunk_results: Vec<ChunkResult> =
-            // When PACK_THREADS is set, build a dedicated Rayon pool with the
-            // requested thread count and run the delta search on it. Otherwise
-            // use the global Rayon pool (which respects RAYON_NUM_THREADS).
-            if let Some(n) = std::env::var("PACK_THREADS")
+        // Use the encoder's pool if one was given. Otherwise, when PACK_THREADS is set, build a
+        // dedicated Rayon pool with the requested thread count and run the delta search on it, or
+        // else use the global Rayon pool (which respects RAYON_NUM_THREADS).
+        let pool = match &self.thread_pool {
+            Some(pool) => Some(pool.clone()),
+            None => match std::env::var("PACK_THREADS")
                 .ok()
                 .and_then(|s| s.parse::<usize>().ok())
             {
-                let pool = rayon::ThreadPoolBuilder::new()
-                    .num_threads(n)
-                    .build()
-                    .map_err(|e| GitError::PackEncodeError(format!(
-                        "failed to build Rayon thread pool: {e}"
-                    )))?;
-                tokio::task::spawn_blocking(move || pool.install(run_delta_search))
-                    .await
-                    .map_err(|e| GitError::PackEncodeError(format!(
-                        "delta search task panicked: {e}"
-                    )))?
-            } else {
-                tokio::task::spawn_blocking(run_delta_search)
-                    .await
-                    .map_err(|e| GitError::PackEncodeError(format!(
-                        "delta search task panicked: {e}"
-                    )))?
-            };
+                Some(n) => Some(Arc::new(
+                    rayon::ThreadPoolBuilder::new()
+                        .num_threads(n)
+                        .build()
+                        .map_err(|e| {
+                            GitError::PackEncodeError(format!(
+                                "failed to build Rayon thread pool: {e}"
+                            ))
+                        })?,
+                )),
+                None => None,
+            },
+        };
+        let search = match pool {
+            Some(pool) => tokio::task::spawn_blocking(move || pool.install(run_delta_search)),
+            None => tokio::task::spawn_blocking(run_delta_search),
+        };
+        let (mut dictionaries, mut chunk_results) = search
+            .await
+            .map_err(|e| GitError::PackEncodeError(format!("delta search task panicked: {e}")))??;
 
         // Parallel search may finish out of order; restore the chosen pack order.
         chunk_results.sort_by_key(|(order, _)| *order);
 
-        let mut all_res: Vec<Vec<(Vec<u8>, IndexEntry)>> = Vec::with_capacity(chunk_results.len());
+        let mut all_res: Vec<Vec<(SearchedEntry, IndexEntry)>> =
+            Vec::with_capacity(chunk_results.len());
         for (_order, res) in chunk_results {
-            all_res.push(res?);
+            match res {
+                Ok(res) => all_res.push(res),
+                Err(GitError::Cancelled) => return Err(self.cancel_encode()),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if train {
+            self.write_header(self.object_number + dictionaries.len())
+                .await;
+        }
+        for dictionary in &mut dictionaries {
+            self.write_owned_and_update(std::mem::take(&mut dictionary.encoded))
+                .await;
         }
 
         // Writing is serialized so offsets, the running pack hash, and index records all describe
         // exactly the same byte order.
-        let total_entries = all_res.iter().map(Vec::len).sum();
+        let total_entries = all_res.iter().map(Vec::len).sum::<usize>() + reused.len();
         let mut idx_entries = Vec::with_capacity(total_entries);
+        let mut writing = self.progress_reporter(ProgressPhase::WritingObjects);
         for res in &mut all_res {
-            for (encoded_bytes, mut idx_entry) in res.drain(..) {
+            // Offset deltas name their base by position in the bucket; the distance follows from
+            // where both land.
+            let mut placed = Vec::with_capacity(res.len());
+            for (searched, mut idx_entry) in res.drain(..) {
+                if self.cancellation.is_cancelled() {
+                    return Err(self.cancel_encode());
+                }
+                writing.update(idx_entries.len(), self.inner_offset as u64, 0);
                 idx_entry.offset = self.inner_offset as u64;
+                placed.push(self.inner_offset);
+                let encoded_bytes = searched.into_bytes(self.inner_offset, &placed, &dictionaries);
+                // The index records the CRC32 of the entry as written, not of the object.
+                idx_entry.crc32 = crc32fast::hash(&encoded_bytes);
                 self.write_owned_and_update(encoded_bytes).await;
                 idx_entries.push(idx_entry);
             }
         }
 
+        self.write_reused_deltas(reused, &mut idx_entries, &external_hashes, &mut writing)
+            .await?;
+        let written = idx_entries.len();
+
         self.idx_entries = Some(idx_entries);
 
         // The checksum is both the pack trailer and the identifier used in pack-<hash>.pack.
         let hash_result = self.inner_hash.clone().finalize();
+        let pack_size = (self.inner_offset + hash_result.len()) as u64;
         self.final_hash = Some(ObjectHash::from_bytes(&hash_result).unwrap());
         self.send_data(hash_result).await;
+        writing.finish(written, pack_size, 0);
 
         self.drop_sender();
         Ok(())
     }
 
+    /// Write the kept deltas of `reused` after the entries already in `idx_entries`.
+    ///
+    /// A delta whose base is in the pack is written as an OFS_DELTA (or a REF_DELTA with
+    /// [`Self::with_ref_delta`]); one whose base is in `external_hashes` names it by id.
+    async fn write_reused_deltas(
+        &mut self,
+        reused: Vec<reuse::ReusedDelta>,
+        idx_entries: &mut Vec<IndexEntry>,
+        external_hashes: &HashSet<ObjectHash>,
+        writing: &mut ProgressReporter,
+    ) -> Result<(), GitError> {
+        // Reused deltas are ordered base-first, so every base in the pack already has an offset.
+        let mut offsets: HashMap<ObjectHash, usize> = idx_entries
+            .iter()
+            .map(|e| (e.hash, e.offset as usize))
+            .collect();
+        for reused in reused {
+            if self.cancellation.is_cancelled() {
+                return Err(self.cancel_encode());
+            }
+            writing.update(idx_entries.len(), self.inner_offset as u64, 0);
+            let base = match offsets.get(&reused.delta.base) {
+                Some(&base_offset) if !self.ref_delta => {
+                    DeltaBase::Offset(self.inner_offset - base_offset)
+                }
+                Some(_) => DeltaBase::Hash(reused.delta.base),
+                None if external_hashes.contains(&reused.delta.base) => {
+                    DeltaBase::Hash(reused.delta.base)
+                }
+                None => {
+                    return Err(GitError::PackEncodeError(format!(
+                        "base {} of reused delta {} was not written",
+                        reused.delta.base, reused.hash
+                    )));
+                }
+            };
+            let encoded_bytes = encode_reused_delta(&reused.delta, base);
+            let idx_entry = IndexEntry {
+                hash: reused.hash,
+                crc32: crc32fast::hash(&encoded_bytes),
+                offset: self.inner_offset as u64,
+            };
+            offsets.insert(reused.hash, self.inner_offset);
+            self.write_owned_and_update(encoded_bytes).await;
+            idx_entries.push(idx_entry);
+        }
+        Ok(())
+    }
+
+    /// Send the pack header for `object_number` entries.
+    async fn write_header(&mut self, object_number: usize) {
+        // The trailer checksum covers the header and every encoded entry, but not the trailer.
+        let head = encode_header(object_number);
+        self.send_data(head.clone()).await;
+        self.inner_hash.update(&head);
+    }
+
     /// Account for an encoded chunk, then forward it to the pack consumer.
     ///
     /// The caller must invoke this in final pack order because both `inner_offset` and `inner_hash`
@@ -446,16 +786,21 @@ impl PackEncoder {
     /// chooses the same zero-window versus delta-window path as [`PackEncoder::encode`].
     ///
     /// Encoding errors currently panic inside the spawned task and are therefore reported as a
-    /// `JoinError` when the returned handle is awaited.
+    /// `JoinError` when the returned handle is awaited. Cancellation is the exception: the task
+    /// just ends after closing the pack stream.
     pub async fn encode_async(
         mut self,
         rx: mpsc::Receiver<MetaAttached<Entry, EntryMeta>>,
     ) -> Result<JoinHandle<()>, GitError> {
         Ok(tokio::spawn(async move {
-            if self.window_size == 0 {
-                self.parallel_encode(rx).await.unwrap()
+            let result = if self.window_size == 0 {
+                self.parallel_encode(rx).await
             } else {
-                self.encode(rx).await.unwrap()
+                self.encode(rx).await
+            };
+            // A cancelled encode has already closed its pack stream; that is not a failure.
+            if !matches!(result, Err(GitError::Cancelled)) {
+                result.unwrap()
             }
         }))
     }
@@ -469,7 +814,10 @@ impl PackEncoder {
         rx: mpsc::Receiver<MetaAttached<Entry, EntryMeta>>,
     ) -> Result<JoinHandle<()>, GitError> {
         Ok(tokio::spawn(async move {
-            self.encode_with_zstdelta(rx).await.unwrap()
+            let result = self.encode_with_zstdelta(rx).await;
+            if !matches!(result, Err(GitError::Cancelled)) {
+                result.unwrap()
+            }
         }))
     }
 
@@ -488,4 +836,19 @@ impl PackEncoder {
         self.idx_sender.take();
         Ok(())
     }
+
+    /// Build the `.rev` reverse index for the finalized pack.
+    ///
+    /// Like [`PackEncoder::encode_idx_file`], this needs the final offsets and pack checksum, so
+    /// it must run after pack encoding. It only needs the recorded index entries, not an index
+    /// sender.
+    pub fn encode_rev_file(&self) -> Result<Vec<u8>, GitError> {
+        let final_hash = self.final_hash.ok_or(GitError::PackEncodeError(
+            "The pack file must be generated before the reverse index is produced.".into(),
+        ))?;
+        let idx_entries = self.idx_entries.as_ref().ok_or(GitError::PackEncodeError(
+            "The pack file must be generated before the reverse index is produced.".into(),
+        ))?;
+        Ok(build_reverse_index(idx_entries, final_hash))
+    }
 }
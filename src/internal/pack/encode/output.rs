@@ -1,30 +1,87 @@
-//! Top-level convenience: encode entries directly to a `.pack` / `.idx` file pair.
+//! Top-level convenience: encode entries directly to `.pack` / `.idx` file pairs.
 //!
 //! The pack is first written to a temporary file because its final name contains the checksum,
 //! which is not known until encoding completes. A background task drains the encoder's pack
 //! channel while the caller-facing task performs encoding. After the pack is finalized and
-//! renamed, a second writer drains the generated index bytes.
+//! renamed, a second writer drains the generated index bytes, and the `.rev` reverse index is
+//! written last.
+//!
+//! With [`OutputOptions::max_pack_size`] set, the entries are encoded into one scratch pack, which
+//! is then cut into packs by the bytes each entry takes once written.
 
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    io::{BufWriter, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use chrono::Utc;
+use tempfile::NamedTempFile;
 use tokio::{fs::File, io::AsyncWriteExt as TokioAsyncWriteExt, sync::mpsc};
 
-use super::PackEncoder;
+use super::{
+    PackCompression, PackEncoder,
+    header::{encode_header, encode_offset, encode_one_object},
+};
 use crate::{
     errors::GitError,
+    hash::{ObjectHash, get_hash_kind, set_hash_kind},
     internal::{
         metadata::{EntryMeta, MetaAttached},
-        pack::entry::Entry,
+        pack::{
+            entry::Entry,
+            index_entry::IndexEntry,
+            pack_index::IdxBuilder,
+            progress::ProgressObserver,
+            reader::{EntryKind, PackReader},
+            reverse_index::build_reverse_index,
+        },
     },
+    utils::HashAlgorithm,
 };
 
-/// Consume entries and write a matching `.pack`/`.idx` pair into `output_dir`.
+const PACK_HEADER_SIZE: u64 = 12;
+
+/// Options for [`encode_and_output_to_files_with_options`].
+#[derive(Clone, Default)]
+pub struct OutputOptions {
+    /// Largest pack file to write, in bytes, like Git's `pack.packSizeLimit`. `None` writes a
+    /// single pack.
+    ///
+    /// Entries are assigned to packs in pack order by the bytes they take once written, so each
+    /// pack is filled close to the limit and only exceeds it when a single object does. As in Git,
+    /// a delta whose base lands in an earlier pack is written as the full object.
+    pub max_pack_size: Option<u64>,
+    /// Receives encoding progress, as [`PackEncoder::with_progress`] does. Each pack reports its
+    /// own phases.
+    pub progress: Option<Arc<dyn ProgressObserver>>,
+    /// Write byte-identical packs for the same set of entries, as
+    /// [`PackEncoder::with_deterministic`] does, which with a size limit also makes the split
+    /// deterministic.
+    pub deterministic: bool,
+    /// Zlib levels of the written entries, as [`PackEncoder::with_compression`] takes.
+    pub compression: PackCompression,
+}
+
+/// Files of one pack written by [`encode_and_output_to_files`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WrittenPack {
+    pub pack: PathBuf,
+    pub idx: PathBuf,
+    pub rev: PathBuf,
+    /// Pack checksum, which also names the files.
+    pub hash: ObjectHash,
+}
+
+/// Consume entries and write a matching `.pack`/`.idx`/`.rev` set into `output_dir`.
 ///
 /// The pack is first written to a temporary file because its final name contains the checksum,
 /// which is not known until encoding completes. A background task drains the encoder's pack
 /// channel while the caller-facing task performs encoding. After the pack is finalized and
-/// renamed, a second writer drains the generated index bytes.
+/// renamed, a second writer drains the generated index bytes, and the `.rev` reverse index is
+/// written last.
 ///
 /// `object_number` must equal the number of entries eventually received. A `window_size` of zero
 /// disables delta compression; any non-zero value selects the delta-search path. The default build
@@ -34,10 +91,320 @@ pub async fn encode_and_output_to_files(
     object_number: usize,
     output_dir: PathBuf,
     window_size: usize,
-) -> Result<(), GitError> {
+) -> Result<Vec<WrittenPack>, GitError> {
+    encode_and_output_to_files_with_options(
+        raw_entries_rx,
+        object_number,
+        output_dir,
+        window_size,
+        OutputOptions::default(),
+    )
+    .await
+}
+
+/// [`encode_and_output_to_files`] that reports encoding progress to `progress`, as
+/// [`PackEncoder::with_progress`] does.
+pub async fn encode_and_output_to_files_with_progress(
+    raw_entries_rx: mpsc::Receiver<MetaAttached<Entry, EntryMeta>>,
+    object_number: usize,
+    output_dir: PathBuf,
+    window_size: usize,
+    progress: Arc<dyn ProgressObserver>,
+) -> Result<Vec<WrittenPack>, GitError> {
+    encode_and_output_to_files_with_options(
+        raw_entries_rx,
+        object_number,
+        output_dir,
+        window_size,
+        OutputOptions {
+            progress: Some(progress),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// [`encode_and_output_to_files`] configured by `options`, returning the packs in the order they
+/// were written.
+///
+/// With a size limit, the entries are first encoded into a scratch pack in a temporary directory
+/// under `output_dir`, which is then split; a pack that already fits is moved as is.
+pub async fn encode_and_output_to_files_with_options(
+    raw_entries_rx: mpsc::Receiver<MetaAttached<Entry, EntryMeta>>,
+    object_number: usize,
+    output_dir: PathBuf,
+    window_size: usize,
+    options: OutputOptions,
+) -> Result<Vec<WrittenPack>, GitError> {
+    let Some(max_pack_size) = options.max_pack_size else {
+        let written = output_to_files(
+            raw_entries_rx,
+            object_number,
+            &output_dir,
+            window_size,
+            &options,
+        )
+        .await?;
+        return Ok(vec![written]);
+    };
+
+    // Encode everything once into a scratch pack, then cut that pack up by the bytes each entry
+    // actually takes, as `git pack-objects` does for `pack.packSizeLimit`.
+    let scratch = tempfile::TempDir::new_in(&output_dir)?;
+    let whole = output_to_files(
+        raw_entries_rx,
+        object_number,
+        scratch.path(),
+        window_size,
+        &options,
+    )
+    .await?;
+    if tokio::fs::metadata(&whole.pack).await?.len() <= max_pack_size {
+        return Ok(vec![move_pack(whole, &output_dir).await?]);
+    }
+
+    let kind = get_hash_kind();
+    let compression = options.compression;
+    let dir = output_dir.clone();
+    let split = tokio::task::spawn_blocking(move || {
+        set_hash_kind(kind);
+        let reader = PackReader::open(&whole.pack)?;
+        let plan = plan_split(&reader, max_pack_size, &compression)?;
+        plan.into_iter()
+            .map(|entries| write_split_pack(&reader, entries, &dir))
+            .collect::<Result<Vec<_>, GitError>>()
+    })
+    .await
+    .map_err(|e| GitError::PackEncodeError(format!("pack split task join error: {e}")))??;
+
+    let mut packs = Vec::with_capacity(split.len());
+    for (file, mut idx_entries, hash) in split {
+        let pack = output_dir.join(format!("pack-{hash}.pack"));
+        file.persist(&pack)
+            .map_err(|e| GitError::IOError(e.error))?;
+        let rev = output_dir.join(format!("pack-{hash}.rev"));
+        tokio::fs::write(&rev, build_reverse_index(&idx_entries, hash)).await?;
+
+        idx_entries.sort_by_key(|e| e.hash);
+        let (idx_tx, mut idx_rx) = mpsc::channel::<Vec<u8>>(1024);
+        let mut builder = IdxBuilder::new(idx_entries.len(), idx_tx, hash);
+        let write = builder.write_idx(idx_entries);
+        let collect = async {
+            let mut index = Vec::new();
+            while let Some(chunk) = idx_rx.recv().await {
+                index.extend_from_slice(&chunk);
+            }
+            index
+        };
+        let (written, index) = tokio::join!(write, collect);
+        written?;
+        let idx = output_dir.join(format!("pack-{hash}.idx"));
+        tokio::fs::write(&idx, index).await?;
+        packs.push(WrittenPack {
+            pack,
+            idx,
+            rev,
+            hash,
+        });
+    }
+    Ok(packs)
+}
+
+/// Move the files of `written` into `output_dir`, keeping their names.
+async fn move_pack(written: WrittenPack, output_dir: &Path) -> Result<WrittenPack, GitError> {
+    let mut moved = written.clone();
+    for (from, to) in [
+        (&written.pack, &mut moved.pack),
+        (&written.idx, &mut moved.idx),
+        (&written.rev, &mut moved.rev),
+    ] {
+        *to = output_dir.join(from.file_name().expect("pack files are named"));
+        tokio::fs::rename(from, &*to).await?;
+    }
+    Ok(moved)
+}
+
+/// How an entry of the scratch pack is written into one of the split packs.
+enum SplitEntry {
+    /// Copied byte for byte: a full object, or a REF_DELTA whose base is in the same pack.
+    Copy {
+        hash: ObjectHash,
+        range: Range<usize>,
+    },
+    /// An OFS_DELTA whose base is in the same pack, with a new header for the new distance.
+    Rebased {
+        hash: ObjectHash,
+        header: Vec<u8>,
+        payload: Range<usize>,
+    },
+    /// A delta whose base went to an earlier pack, written as the full object.
+    Whole { hash: ObjectHash, bytes: Vec<u8> },
+}
+
+impl SplitEntry {
+    fn len(&self) -> usize {
+        match self {
+            SplitEntry::Copy { range, .. } => range.len(),
+            SplitEntry::Rebased {
+                header, payload, ..
+            } => header.len() + payload.len(),
+            SplitEntry::Whole { bytes, .. } => bytes.len(),
+        }
+    }
+}
+
+/// Assign the entries of `reader` to packs of at most `max_pack_size` bytes, in pack order.
+///
+/// Each entry is sized as it will be written into the pack being filled: deltas whose base is in
+/// that pack keep their compressed payload, and deltas whose base went to an earlier pack are
+/// compressed in full. An entry that does not fit starts the next pack; a single entry larger
+/// than the limit gets a pack of its own.
+fn plan_split(
+    reader: &PackReader,
+    max_pack_size: u64,
+    compression: &PackCompression,
+) -> Result<Vec<Vec<SplitEntry>>, GitError> {
+    let pack = reader.mapped_pack();
+    let bytes = pack.bytes();
+    let mut entries: Vec<(u64, ObjectHash)> =
+        reader.index().iter().map(|e| (e.offset, e.hash)).collect();
+    entries.sort();
+    let hash_at: HashMap<u64, ObjectHash> = entries.iter().copied().collect();
+    let trailer = reader.hash_kind().size() as u64;
+
+    let mut packs = Vec::new();
+    let mut current: Vec<SplitEntry> = Vec::new();
+    let mut placed: HashMap<ObjectHash, u64> = HashMap::new();
+    let mut size = PACK_HEADER_SIZE;
+    for (i, &(offset, hash)) in entries.iter().enumerate() {
+        let end = entries
+            .get(i + 1)
+            .map_or(pack.body_end(), |&(next, _)| next) as usize;
+        let header = pack.read_entry_header(offset)?;
+        let planned = loop {
+            let base = match header.kind {
+                EntryKind::Base(_) => None,
+                EntryKind::OffsetDelta(base) | EntryKind::OffsetZstdelta(base) => {
+                    hash_at.get(&base).copied()
+                }
+                EntryKind::HashDelta(base) => Some(base),
+            };
+            let planned = match (header.kind, base.and_then(|b| placed.get(&b))) {
+                (EntryKind::Base(_), _) | (EntryKind::HashDelta(_), Some(_)) => SplitEntry::Copy {
+                    hash,
+                    range: offset as usize..end,
+                },
+                (EntryKind::OffsetDelta(_), Some(&base_offset)) => {
+                    let start = offset as usize;
+                    // The size header ends at the first byte without a continuation bit.
+                    let size_len = bytes[start..].iter().position(|b| b & 0x80 == 0).unwrap() + 1;
+                    let mut entry_header = bytes[start..start + size_len].to_vec();
+                    entry_header.extend(encode_offset((size - base_offset) as usize));
+                    SplitEntry::Rebased {
+                        hash,
+                        header: entry_header,
+                        payload: header.data_offset as usize..end,
+                    }
+                }
+                _ => {
+                    let object = reader.read_object_at(offset)?;
+                    let bytes = encode_one_object(&object, None, compression.level_for(&object))?;
+                    SplitEntry::Whole { hash, bytes }
+                }
+            };
+            if !current.is_empty() && size + planned.len() as u64 + trailer > max_pack_size {
+                packs.push(std::mem::take(&mut current));
+                placed.clear();
+                size = PACK_HEADER_SIZE;
+                continue;
+            }
+            break planned;
+        };
+        placed.insert(hash, size);
+        size += planned.len() as u64;
+        current.push(planned);
+    }
+    if !current.is_empty() {
+        packs.push(current);
+    }
+    Ok(packs)
+}
+
+/// Write one planned pack to a temporary file in `output_dir`, returning the file, its index
+/// entries in pack order, and its checksum.
+fn write_split_pack(
+    reader: &PackReader,
+    entries: Vec<SplitEntry>,
+    output_dir: &Path,
+) -> Result<(NamedTempFile, Vec<IndexEntry>, ObjectHash), GitError> {
+    let bytes = reader.mapped_pack().bytes();
+    let mut file = NamedTempFile::new_in(output_dir)?;
+    let mut writer = BufWriter::new(file.as_file_mut());
+    let mut hasher = HashAlgorithm::new_with_kind(reader.hash_kind());
+    let mut write = |chunk: &[u8]| -> Result<(), GitError> {
+        hasher.update(chunk);
+        writer.write_all(chunk)?;
+        Ok(())
+    };
+
+    write(&encode_header(entries.len()))?;
+    let mut offset = PACK_HEADER_SIZE;
+    let mut idx_entries = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let (hash, crc32) = match &entry {
+            SplitEntry::Copy { hash, range } => {
+                write(&bytes[range.clone()])?;
+                (*hash, crc32fast::hash(&bytes[range.clone()]))
+            }
+            SplitEntry::Rebased {
+                hash,
+                header,
+                payload,
+            } => {
+                write(header)?;
+                write(&bytes[payload.clone()])?;
+                let mut crc = crc32fast::Hasher::new();
+                crc.update(header);
+                crc.update(&bytes[payload.clone()]);
+                (*hash, crc.finalize())
+            }
+            SplitEntry::Whole { hash, bytes } => {
+                write(bytes)?;
+                (*hash, crc32fast::hash(bytes))
+            }
+        };
+        idx_entries.push(IndexEntry {
+            hash,
+            crc32,
+            offset,
+        });
+        offset += entry.len() as u64;
+    }
+    let trailer = hasher.finalize();
+    writer.write_all(&trailer)?;
+    writer.flush()?;
+    drop(writer);
+    let hash = ObjectHash::from_bytes_with_kind(&trailer, reader.hash_kind())
+        .map_err(GitError::InvalidHashValue)?;
+    Ok((file, idx_entries, hash))
+}
+
+/// Encode `raw_entries_rx` into one pack in `output_dir` and write its index files.
+async fn output_to_files(
+    raw_entries_rx: mpsc::Receiver<MetaAttached<Entry, EntryMeta>>,
+    object_number: usize,
+    output_dir: &Path,
+    window_size: usize,
+    options: &OutputOptions,
+) -> Result<WrittenPack, GitError> {
     let (pack_tx, mut pack_rx) = mpsc::channel(1024);
     let (idx_tx, mut idx_rx) = mpsc::channel(1024);
-    let mut pack_encoder = PackEncoder::new_with_idx(object_number, window_size, pack_tx, idx_tx);
+    let mut pack_encoder = PackEncoder::new_with_idx(object_number, window_size, pack_tx, idx_tx)
+        .with_deterministic(options.deterministic)
+        .with_compression(options.compression);
+    if let Some(progress) = options.progress.clone() {
+        pack_encoder = pack_encoder.with_progress(progress);
+    }
 
     // The checksum-based final filename is unknown until the complete pack has been hashed.
     let now = Utc::now();
@@ -62,9 +429,9 @@ pub async fn encode_and_output_to_files(
         .map_err(|e| GitError::PackEncodeError(format!("pack writer task join error: {e}")))?;
     pack_write_result?;
 
-    let final_pack_name =
-        output_dir.join(format!("pack-{}.pack", pack_encoder.final_hash.unwrap()));
-    let final_idx_name = output_dir.join(format!("pack-{}.idx", pack_encoder.final_hash.unwrap()));
+    let hash = pack_encoder.final_hash.unwrap();
+    let final_pack_name = output_dir.join(format!("pack-{hash}.pack"));
+    let final_idx_name = output_dir.join(format!("pack-{hash}.idx"));
     tokio::fs::rename(tmp_path, &final_pack_name).await?;
 
     let mut idx_file = File::create(&final_idx_name).await?;
@@ -84,5 +451,13 @@ pub async fn encode_and_output_to_files(
         .map_err(|e| GitError::PackEncodeError(format!("idx writer task join error: {e}")))?;
     idx_write_result?;
 
-    Ok(())
+    let final_rev_name = output_dir.join(format!("pack-{hash}.rev"));
+    tokio::fs::write(&final_rev_name, pack_encoder.encode_rev_file()?).await?;
+
+    Ok(WrittenPack {
+        pack: final_pack_name,
+        idx: final_idx_name,
+        rev: final_rev_name,
+        hash,
+    })
 }
@@ -3,19 +3,29 @@
 //! When `window_size == 0`, entries are independently zlib-compressed in parallel via Rayon.
 //! Input is read in bounded batches to balance memory usage and parallelism.
 //!
-//! This path is not compatible with delta compression — use the delta path in
-//! [`super::inner_encode`] for windowed encoding.
+//! This path does not search for deltas — use the delta path in [`super::inner_encode`] for
+//! windowed encoding. Deltas kept from a source pack are still copied when their base is written,
+//! so entries that carry one are held back and written after the rest.
+
+use std::collections::HashSet;
 
 use rayon::prelude::*;
 use tokio::sync::mpsc;
 
-use super::header::{encode_header, encode_one_object};
+use super::{
+    header::{encode_header, encode_one_object},
+    reuse,
+};
 use crate::{
     errors::GitError,
     hash::ObjectHash,
     internal::{
         metadata::{EntryMeta, MetaAttached},
-        pack::{entry::Entry, index_entry::IndexEntry},
+        pack::{
+            entry::Entry,
+            index_entry::IndexEntry,
+            progress::{ProgressPhase, ProgressReporter},
+        },
     },
     time_it,
 };
@@ -27,6 +37,10 @@ impl super::PackEncoder {
     /// zlib compression concurrently. Rayon collection preserves input order, after which chunks
     /// are written serially so pack offsets and the running checksum remain correct.
     ///
+    /// Entries with a kept delta ([`EntryMeta::reusable_delta`]) are held until the input ends.
+    /// Those whose base was written (or is held too) are copied as deltas after everything else;
+    /// the rest are compressed in full like any other entry.
+    ///
     /// This path is valid only when `window_size == 0`.
     pub async fn parallel_encode(
         &mut self,
@@ -50,11 +64,33 @@ impl super::PackEncoder {
             ));
         }
 
+        if self.deterministic {
+            // Order by object id, which needs the whole input before the first entry is written.
+            let mut entries = Vec::with_capacity(self.object_number);
+            while let Some(entry) = entry_rx.recv().await {
+                entries.push(entry);
+            }
+            entries.sort_by_key(|e| e.inner.hash);
+            let (sorted_tx, sorted_rx) = mpsc::channel(entries.len().max(1));
+            for entry in entries {
+                sorted_tx.try_send(entry).map_err(|e| {
+                    GitError::PackEncodeError(format!("failed to reorder entries: {e}"))
+                })?;
+            }
+            entry_rx = sorted_rx;
+        }
+
         let mut idx_entries = Vec::with_capacity(self.object_number);
+        let mut held = Vec::new();
+        let mut writing = self.progress_reporter(ProgressPhase::WritingObjects);
         // Batching bounds temporary memory while giving Rayon enough work to distribute.
         let batch_size = usize::max(1000, entry_rx.max_capacity() / 10); // Temporary heuristic.
         tracing::info!("encode with batch size: {}", batch_size);
-        loop {
+        let mut received_all = false;
+        while !received_all {
+            if self.cancellation.is_cancelled() {
+                return Err(self.cancel_encode());
+            }
             let mut batch_entries = Vec::with_capacity(batch_size);
             time_it!("parallel encode: receive batch", {
                 for _ in 0..batch_size {
@@ -66,41 +102,37 @@ impl super::PackEncoder {
                                     entry.inner.obj_type
                                 )));
                             }
-                            batch_entries.push(entry.inner);
                             self.process_index += 1;
+                            if entry.meta.reusable_delta.is_some() {
+                                held.push(entry);
+                            } else {
+                                batch_entries.push(entry.inner);
+                            }
+                        }
+                        None => {
+                            received_all = true;
+                            break;
                         }
-                        None => break,
                     }
                 }
             });
 
-            if batch_entries.is_empty() {
-                break;
-            }
-
-            // Indexed parallel collection retains batch order even though compression finishes on
-            // different Rayon workers.
-            let batch_result: Vec<Result<(Vec<u8>, IndexEntry), GitError>> =
-                time_it!("parallel encode: encode batch", {
-                    batch_entries
-                        .par_iter()
-                        .map(|entry| {
-                            encode_one_object(entry, None)
-                                .map(|encoded| (encoded, IndexEntry::new(entry, 0)))
-                        })
-                        .collect()
-                });
-
-            time_it!("parallel encode: write batch", {
-                for obj_data in batch_result {
-                    let (encoded_bytes, mut idx_entry) = obj_data?;
-                    idx_entry.offset = self.inner_offset as u64;
-                    self.write_owned_and_update(encoded_bytes).await;
-                    idx_entries.push(idx_entry);
-                }
-            });
+            self.write_batch(batch_entries, &mut idx_entries, &mut writing)
+                .await?;
         }
 
+        // The written entries and the held ones are every base a kept delta can use here.
+        let written: HashSet<ObjectHash> = idx_entries.iter().map(|e| e.hash).collect();
+        let reused = reuse::take_reused_deltas(
+            [&mut Vec::new(), &mut Vec::new(), &mut held, &mut Vec::new()],
+            &written,
+        );
+        let rest = held.into_iter().map(|entry| entry.inner).collect();
+        self.write_batch(rest, &mut idx_entries, &mut writing)
+            .await?;
+        self.write_reused_deltas(reused, &mut idx_entries, &HashSet::new(), &mut writing)
+            .await?;
+
         tracing::debug!("parallel encode idx entries: {:?}", idx_entries.len());
         if self.process_index != self.object_number {
             panic!(
@@ -111,11 +143,54 @@ impl super::PackEncoder {
 
         // Append the checksum trailer only after every encoded entry has updated the running hash.
         let hash_result = self.inner_hash.clone().finalize();
+        let pack_size = (self.inner_offset + hash_result.len()) as u64;
         self.final_hash = Some(ObjectHash::from_bytes(&hash_result).unwrap());
         self.send_data(hash_result).await;
+        writing.finish(idx_entries.len(), pack_size, 0);
         self.drop_sender();
 
         self.idx_entries = Some(idx_entries);
         Ok(())
     }
+
+    /// Compress `batch` in parallel and write it in order, recording its index entries.
+    async fn write_batch(
+        &mut self,
+        batch: Vec<Entry>,
+        idx_entries: &mut Vec<IndexEntry>,
+        writing: &mut ProgressReporter,
+    ) -> Result<(), GitError> {
+        // Indexed parallel collection retains batch order even though compression finishes on
+        // different Rayon workers.
+        let compression = self.compression;
+        let batch_result: Vec<Result<(Vec<u8>, IndexEntry), GitError>> =
+            time_it!("parallel encode: encode batch", {
+                let encode = || {
+                    batch
+                        .par_iter()
+                        .map(|entry| {
+                            encode_one_object(entry, None, compression.level_for(entry))
+                                .map(|encoded| (encoded, IndexEntry::new(entry, 0)))
+                        })
+                        .collect()
+                };
+                match &self.thread_pool {
+                    Some(pool) => pool.install(encode),
+                    None => encode(),
+                }
+            });
+
+        time_it!("parallel encode: write batch", {
+            for obj_data in batch_result {
+                let (encoded_bytes, mut idx_entry) = obj_data?;
+                writing.update(idx_entries.len(), self.inner_offset as u64, 0);
+                idx_entry.offset = self.inner_offset as u64;
+                // The index records the CRC32 of the entry as written, not of the object.
+                idx_entry.crc32 = crc32fast::hash(&encoded_bytes);
+                self.write_owned_and_update(encoded_bytes).await;
+                idx_entries.push(idx_entry);
+            }
+        });
+        Ok(())
+    }
 }
@@ -0,0 +1,174 @@
+//! Delta reuse when repacking decoded entries.
+//!
+//! Entries decoded with [`Pack::with_delta_reuse`](crate::internal::pack::Pack::with_delta_reuse)
+//! carry the delta they were stored as. If that delta's base is written to the same pack, the
+//! encoder copies the compressed delta instead of searching for a new base, which saves both the
+//! delta computation and the zlib pass. Entries whose base is absent go through delta search as
+//! usual.
+
+use std::collections::{HashMap, HashSet};
+
+use super::delta_search::MAX_CHAIN_LEN;
+use crate::{
+    hash::ObjectHash,
+    internal::{
+        metadata::{EntryMeta, MetaAttached, ReusableDelta},
+        pack::entry::Entry,
+    },
+};
+
+/// An entry that will be written by copying its kept delta.
+pub(super) struct ReusedDelta {
+    pub(super) hash: ObjectHash,
+    pub(super) delta: ReusableDelta,
+}
+
+/// Remove the entries of `groups` whose kept delta can be copied, and return them ordered so that
+/// every base comes before the deltas that use it.
+///
+/// A kept delta is copied only if its base is among the entries or in `external_bases`. Deltas
+/// that would form a cycle or a chain longer than [`MAX_CHAIN_LEN`] are dropped, leaving those
+/// entries to delta search.
+pub(super) fn take_reused_deltas(
+    groups: [&mut Vec<MetaAttached<Entry, EntryMeta>>; 4],
+    external_bases: &HashSet<ObjectHash>,
+) -> Vec<ReusedDelta> {
+    let mut hashes: HashSet<ObjectHash> = groups
+        .iter()
+        .flat_map(|group| group.iter().map(|e| e.inner.hash))
+        .collect();
+    hashes.extend(external_bases);
+
+    // Candidates in input order, so the same input always keeps the same deltas.
+    let mut order = Vec::new();
+    let mut bases = HashMap::new();
+    for entry in groups.iter().flat_map(|group| group.iter()) {
+        if let Some(delta) = &entry.meta.reusable_delta
+            && delta.base != entry.inner.hash
+            && hashes.contains(&delta.base)
+            && bases.insert(entry.inner.hash, delta.base).is_none()
+        {
+            order.push(entry.inner.hash);
+        }
+    }
+
+    // Depth of each kept delta counted in kept deltas only; full objects are depth 0.
+    let mut depths: HashMap<ObjectHash, usize> = HashMap::new();
+    let mut rejected: HashSet<ObjectHash> = HashSet::new();
+    for start in order {
+        let mut path = Vec::new();
+        let mut on_path = HashSet::new();
+        let mut cur = start;
+        let mut depth = loop {
+            if let Some(&depth) = depths.get(&cur) {
+                break depth;
+            }
+            if on_path.contains(&cur) {
+                // Writing `cur` in full breaks the cycle.
+                rejected.insert(cur);
+                break 0;
+            }
+            match bases.get(&cur) {
+                Some(&base) if !rejected.contains(&cur) => {
+                    path.push(cur);
+                    on_path.insert(cur);
+                    cur = base;
+                }
+                _ => break 0,
+            }
+        };
+        for hash in path.into_iter().rev() {
+            if rejected.contains(&hash) {
+                depth = 0;
+                continue;
+            }
+            depth += 1;
+            if depth > MAX_CHAIN_LEN {
+                rejected.insert(hash);
+                depth = 0;
+            } else {
+                depths.insert(hash, depth);
+            }
+        }
+    }
+
+    let mut reused = Vec::with_capacity(depths.len());
+    for group in groups {
+        let mut kept = Vec::with_capacity(group.len());
+        for mut entry in group.drain(..) {
+            match entry.meta.reusable_delta.take() {
+                Some(delta) if depths.contains_key(&entry.inner.hash) => {
+                    reused.push((
+                        depths[&entry.inner.hash],
+                        ReusedDelta {
+                            hash: entry.inner.hash,
+                            delta,
+                        },
+                    ));
+                }
+                _ => kept.push(entry),
+            }
+        }
+        *group = kept;
+    }
+    // A base is either written in full or is a kept delta one level shallower.
+    reused.sort_by_key(|(depth, _)| *depth);
+    reused.into_iter().map(|(_, reused)| reused).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        hash::{HashKind, set_hash_kind_for_test},
+        internal::object::blob::Blob,
+    };
+
+    fn entry(content: &str, base: Option<ObjectHash>) -> MetaAttached<Entry, EntryMeta> {
+        let inner: Entry = Blob::from_content(content).into();
+        let meta = EntryMeta {
+            reusable_delta: base.map(|base| ReusableDelta {
+                base,
+                delta_size: 1,
+                compressed: Arc::from(&b"x"[..]),
+            }),
+            ..Default::default()
+        };
+        MetaAttached { inner, meta }
+    }
+
+    #[test]
+    fn test_take_reused_deltas_orders_bases_and_breaks_cycles() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let hash = |content: &str| Blob::from_content(content).id;
+        let (a, b, c, d) = (hash("a"), hash("b"), hash("c"), hash("d"));
+
+        // c -> b -> a is a chain; d points at an object that is not written.
+        let mut blobs = vec![
+            entry("c", Some(b)),
+            entry("b", Some(a)),
+            entry("a", None),
+            entry("d", Some(hash("missing"))),
+        ];
+        let reused = take_reused_deltas(
+            [&mut vec![], &mut vec![], &mut blobs, &mut vec![]],
+            &HashSet::new(),
+        );
+        let reused: Vec<_> = reused.iter().map(|r| r.hash).collect();
+        assert_eq!(reused, vec![b, c]);
+        let left: Vec<_> = blobs.iter().map(|e| e.inner.hash).collect();
+        assert_eq!(left, vec![a, d]);
+
+        // a <-> b: one of them must be written in full.
+        let mut blobs = vec![entry("a", Some(b)), entry("b", Some(a))];
+        let reused = take_reused_deltas(
+            [&mut vec![], &mut vec![], &mut blobs, &mut vec![]],
+            &HashSet::new(),
+        );
+        assert_eq!(reused.len(), 1);
+        assert_eq!(blobs.len(), 1);
+        assert_eq!(reused[0].delta.base, blobs[0].inner.hash);
+    }
+}
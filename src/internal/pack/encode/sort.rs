@@ -6,6 +6,7 @@
 
 use std::{
     cmp::Ordering,
+    collections::HashSet,
     hash::{Hash, Hasher},
     path::Path,
 };
@@ -14,6 +15,7 @@ use ahash::AHasher;
 
 use crate::{
     delta,
+    hash::ObjectHash,
     internal::{
         metadata::{EntryMeta, MetaAttached},
         pack::entry::Entry,
@@ -38,6 +40,15 @@ fn content_sort_key(data: &[u8]) -> u64 {
     key
 }
 
+/// [`magic_sort_preferring`] without preferred bases.
+#[cfg(test)]
+pub(crate) fn magic_sort(
+    a: &MetaAttached<Entry, EntryMeta>,
+    b: &MetaAttached<Entry, EntryMeta>,
+) -> Ordering {
+    magic_sort_preferring(a, b, &HashSet::new())
+}
+
 /// Order entries so likely delta pairs become neighbors.
 ///
 /// Entries with path metadata come first. They are clustered by parent directory and Git's
@@ -45,11 +56,16 @@ fn content_sort_key(data: &[u8]) -> u64 {
 /// base for following smaller objects. Entries without paths are grouped by the first 8 bytes
 /// (type signature) so structurally-similar files sit close, then by decreasing size.
 ///
-/// The final pointer comparison is only a tie-breaker; it gives `sort_by` a total ordering when all
-/// semantic keys are equal.
-pub(crate) fn magic_sort(
+/// Within a cluster, entries in `preferred_bases` come before the others regardless of size. Like
+/// Git's preferred bases for thin packs, objects the receiver already has then enter the delta
+/// window ahead of the objects that may use them.
+///
+/// The final object id comparison is only a tie-breaker; it makes the order independent of the
+/// input order when all semantic keys are equal.
+pub(crate) fn magic_sort_preferring(
     a: &MetaAttached<Entry, EntryMeta>,
     b: &MetaAttached<Entry, EntryMeta>,
+    preferred_bases: &HashSet<ObjectHash>,
 ) -> Ordering {
     let path_a = a.meta.file_path.as_ref();
     let path_b = b.meta.file_path.as_ref();
@@ -88,14 +104,21 @@ pub(crate) fn magic_sort(
         }
     }
 
+    let ord = preferred_bases
+        .contains(&b.inner.hash)
+        .cmp(&preferred_bases.contains(&a.inner.hash));
+    if ord != Ordering::Equal {
+        return ord;
+    }
+
     // Larger entries appear first because later entries can refer backwards to them as bases.
     let ord = b.inner.data.len().cmp(&a.inner.data.len());
     if ord != Ordering::Equal {
         return ord;
     }
 
-    // Supply a deterministic ordering within this in-memory collection when all keys tie.
-    (a as *const MetaAttached<Entry, EntryMeta>).cmp(&(b as *const MetaAttached<Entry, EntryMeta>))
+    // Break remaining ties by object id so the order does not depend on input order.
+    a.inner.hash.cmp(&b.inner.hash)
 }
 
 /// Hash a small sample used by the cheap candidate pre-filters.
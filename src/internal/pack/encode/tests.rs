@@ -14,6 +14,12 @@ use crate::{
         object::{blob::Blob, types::ObjectType},
         pack::{
             Pack,
+            cache_object::{CacheObject, CacheObjectInfo},
+            cancel::CancellationToken,
+            limits::DecodeLimit,
+            pack_index::PackIndex,
+            reverse_index::ReverseIndex,
+            test_pack_builder::TestPackBuilder,
             test_pack_download::{PackFileGuard, download_pack_file},
             tests::init_logger,
             utils::read_offset_encoding,
@@ -157,11 +163,23 @@ fn test_try_as_offset_delta_keeps_one_result_per_input() {
     .collect();
     let expected_hashes: Vec<ObjectHash> = entries.iter().map(|entry| entry.hash).collect();
 
-    let results = PackEncoder::try_as_offset_delta(entries, 0, false, false, false)
-        .expect("offset delta encoding should succeed");
+    let results = PackEncoder::try_as_offset_delta(
+        entries,
+        0,
+        false,
+        false,
+        false,
+        false,
+        &HashSet::new(),
+        &PackCompression::default(),
+        &[],
+        &CancellationToken::new(),
+    )
+    .expect("offset delta encoding should succeed");
 
     assert_eq!(results.len(), expected_hashes.len());
-    for ((encoded, idx_entry), expected_hash) in results.iter().zip(expected_hashes) {
+    for ((searched, idx_entry), expected_hash) in results.into_iter().zip(expected_hashes) {
+        let encoded = searched.into_bytes(12, &[], &[]);
         assert!(!encoded.is_empty(), "encoded object should not be empty");
         assert_eq!(idx_entry.hash, expected_hash);
     }
@@ -172,8 +190,19 @@ fn test_try_as_offset_delta_accepts_empty_bucket() {
     let _guard = set_hash_kind_for_test(HashKind::Sha1);
     let entries = Vec::new();
 
-    let results = PackEncoder::try_as_offset_delta(entries, 0, false, false, false)
-        .expect("empty bucket should encode successfully");
+    let results = PackEncoder::try_as_offset_delta(
+        entries,
+        0,
+        false,
+        false,
+        false,
+        false,
+        &HashSet::new(),
+        &PackCompression::default(),
+        &[],
+        &CancellationToken::new(),
+    )
+    .expect("empty bucket should encode successfully");
 
     assert!(results.is_empty());
 }
@@ -338,6 +367,362 @@ async fn test_pack_encoder_rejects_unencodable_ai_type_delta_window() {
     assert!(matches!(err, GitError::PackEncodeError(_)));
 }
 
+/// A cancelled encoder stops with `GitError::Cancelled` and closes its pack stream on both paths.
+#[tokio::test]
+async fn test_pack_encoder_cancelled() {
+    let _guard = set_hash_kind_for_test(HashKind::Sha1);
+    for window_size in [0, 10] {
+        let (tx, mut rx) = mpsc::channel(8);
+        let (entry_tx, entry_rx) = mpsc::channel::<MetaAttached<Entry, EntryMeta>>(4);
+        let token = CancellationToken::new();
+        let mut encoder = PackEncoder::new(2, window_size, tx).with_cancellation(token.clone());
+
+        for content in ["first", "second"] {
+            entry_tx
+                .send(MetaAttached {
+                    inner: Blob::from_content(content).into(),
+                    meta: EntryMeta::new(),
+                })
+                .await
+                .expect("send entry");
+        }
+        drop(entry_tx);
+        token.cancel();
+
+        let err = encoder
+            .encode(entry_rx)
+            .await
+            .expect_err("must be cancelled");
+        assert!(matches!(err, GitError::Cancelled));
+        let mut sent = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            sent.extend(chunk);
+        }
+        assert_eq!(sent.len(), 12, "only the pack header is sent");
+    }
+}
+
+/// Decode `pack` keeping stored deltas, returning the entries in callback order.
+fn decode_with_delta_reuse(pack: &[u8]) -> Vec<MetaAttached<Entry, EntryMeta>> {
+    decode_with(pack, |p| p.with_delta_reuse(true)).expect("decode")
+}
+
+/// Decode `pack` with a decoder adjusted by `configure`, collecting every entry.
+fn decode_with(
+    pack: &[u8],
+    configure: impl FnOnce(Pack) -> Pack,
+) -> Result<Vec<MetaAttached<Entry, EntryMeta>>, GitError> {
+    let entries = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let sink = entries.clone();
+    let mut p = configure(Pack::new(
+        None,
+        None,
+        Some(PathBuf::from("/tmp/.cache_temp")),
+        true,
+    ));
+    p.decode(
+        &mut Cursor::new(pack),
+        move |entry| sink.lock().unwrap().push(entry),
+        None::<fn(ObjectHash)>,
+    )?;
+    drop(p);
+    Ok(Arc::try_unwrap(entries).unwrap().into_inner().unwrap())
+}
+
+async fn encode_entries(entries: Vec<MetaAttached<Entry, EntryMeta>>) -> Vec<u8> {
+    encode_entries_with(entries, |encoder| encoder).await
+}
+
+/// Encode `entries` with a window of 10 through an encoder adjusted by `configure`.
+async fn encode_entries_with(
+    entries: Vec<MetaAttached<Entry, EntryMeta>>,
+    configure: impl FnOnce(PackEncoder) -> PackEncoder,
+) -> Vec<u8> {
+    let (tx, mut rx) = mpsc::channel(64);
+    let (entry_tx, entry_rx) = mpsc::channel::<MetaAttached<Entry, EntryMeta>>(64);
+    let encoder = configure(PackEncoder::new(entries.len(), 10, tx));
+    encoder.encode_async(entry_rx).await.unwrap();
+    for entry in entries {
+        entry_tx.send(entry).await.unwrap();
+    }
+    drop(entry_tx);
+    let mut result = Vec::new();
+    while let Some(chunk) = rx.recv().await {
+        result.extend(chunk);
+    }
+    result
+}
+
+/// Deltas kept by the decoder are copied byte for byte when their base is re-encoded too, and
+/// recomputed otherwise.
+#[tokio::test]
+async fn test_pack_encoder_reuses_kept_deltas() {
+    let _guard = set_hash_kind_for_test(HashKind::Sha1);
+    let base: Vec<u8> = (0..4096u32).flat_map(|i| (i * 7).to_le_bytes()).collect();
+    let mut first = base.clone();
+    first[100..120].copy_from_slice(b"first edit of a blob");
+    let mut second = first.clone();
+    second.extend_from_slice(b"appended by the second edit");
+
+    let mut builder = TestPackBuilder::new();
+    let base_entry = builder.add_base(ObjectType::Blob, &base);
+    let first_entry = builder.add_ofs_delta(base_entry, &base, ObjectType::Blob, &first);
+    let second_entry = builder.add_ref_delta(first_entry.hash, &first, ObjectType::Blob, &second);
+
+    let decoded = decode_with_delta_reuse(&builder.pack_bytes());
+    assert_eq!(decoded.len(), 3);
+    let kept = |hash: ObjectHash| {
+        decoded
+            .iter()
+            .find(|e| e.inner.hash == hash)
+            .and_then(|e| e.meta.reusable_delta.clone())
+    };
+    assert!(kept(base_entry.hash).is_none());
+    let first_delta = kept(first_entry.hash).expect("ofs delta kept");
+    assert_eq!(first_delta.base, base_entry.hash);
+    let second_delta = kept(second_entry.hash).expect("ref delta kept");
+    assert_eq!(second_delta.base, first_entry.hash);
+
+    let contains =
+        |haystack: &[u8], needle: &[u8]| haystack.windows(needle.len()).any(|w| w == needle);
+
+    // Every base is present: both deltas are copied and the pack still decodes.
+    let repacked = encode_entries(decoded.clone()).await;
+    assert!(contains(&repacked, &first_delta.compressed));
+    assert!(contains(&repacked, &second_delta.compressed));
+    let roundtrip = decode_with_delta_reuse(&repacked);
+    let mut hashes: Vec<_> = roundtrip.iter().map(|e| e.inner.hash).collect();
+    hashes.sort();
+    let mut expected = vec![base_entry.hash, first_entry.hash, second_entry.hash];
+    expected.sort();
+    assert_eq!(hashes, expected);
+
+    // Without the original base, the first object is written without its kept delta while the
+    // second still reuses its delta against the first.
+    let without_base: Vec<_> = decoded
+        .into_iter()
+        .filter(|e| e.inner.hash != base_entry.hash)
+        .collect();
+    let repacked = encode_entries(without_base).await;
+    assert!(!contains(&repacked, &first_delta.compressed));
+    assert!(contains(&repacked, &second_delta.compressed));
+    check_format(&repacked);
+}
+
+/// The parallel path (no delta search) copies kept deltas as well.
+#[tokio::test]
+async fn test_parallel_encode_reuses_kept_deltas() {
+    let _guard = set_hash_kind_for_test(HashKind::Sha1);
+    let base: Vec<u8> = (0..4096u32).flat_map(|i| (i * 7).to_le_bytes()).collect();
+    let mut first = base.clone();
+    first[100..120].copy_from_slice(b"first edit of a blob");
+    let mut second = first.clone();
+    second.extend_from_slice(b"appended by the second edit");
+
+    let mut builder = TestPackBuilder::new();
+    let base_entry = builder.add_base(ObjectType::Blob, &base);
+    let first_entry = builder.add_ofs_delta(base_entry, &base, ObjectType::Blob, &first);
+    builder.add_ref_delta(first_entry.hash, &first, ObjectType::Blob, &second);
+    let mut decoded = decode_with_delta_reuse(&builder.pack_bytes());
+    // Deltas first, so their bases arrive after them.
+    decoded.reverse();
+    let kept: Vec<_> = decoded
+        .iter()
+        .filter_map(|e| e.meta.reusable_delta.clone())
+        .collect();
+    assert_eq!(kept.len(), 2);
+
+    let (tx, mut rx) = mpsc::channel(64);
+    let (entry_tx, entry_rx) = mpsc::channel(64);
+    let encoder = PackEncoder::new(decoded.len(), 0, tx);
+    encoder.encode_async(entry_rx).await.unwrap();
+    for entry in decoded {
+        entry_tx.send(entry).await.unwrap();
+    }
+    drop(entry_tx);
+    let mut repacked = Vec::new();
+    while let Some(chunk) = rx.recv().await {
+        repacked.extend(chunk);
+    }
+
+    for delta in &kept {
+        assert!(
+            repacked
+                .windows(delta.compressed.len())
+                .any(|w| w == &delta.compressed[..])
+        );
+    }
+    check_format(&repacked);
+    assert_eq!(decode_with_delta_reuse(&repacked).len(), 3);
+}
+
+/// Packs written by either encode path, with their indexes, pass `git verify-pack`, which also
+/// checks each entry's CRC32 against the index.
+#[tokio::test]
+async fn test_pack_encoder_output_passes_git_verify_pack() {
+    let _guard = set_hash_kind_for_test(HashKind::Sha1);
+    let (base, edited) = similar_blobs();
+    let entries = vec![
+        plain(base),
+        plain(edited),
+        plain(Blob::from_content("an unrelated blob").into()),
+    ];
+    for window_size in [0, 10] {
+        let (entry_tx, entry_rx) = mpsc::channel(entries.len());
+        for entry in entries.clone() {
+            entry_tx.send(entry).await.unwrap();
+        }
+        drop(entry_tx);
+        let dir = tempdir().unwrap();
+        let packs = encode_and_output_to_files(
+            entry_rx,
+            entries.len(),
+            dir.path().to_path_buf(),
+            window_size,
+        )
+        .await
+        .unwrap();
+
+        let Ok(output) = std::process::Command::new("git")
+            .arg("verify-pack")
+            .arg(&packs[0].idx)
+            .output()
+        else {
+            return;
+        };
+        assert!(
+            output.status.success(),
+            "window size {window_size}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+/// Parse every entry of `pack` without resolving deltas.
+fn raw_pack_entries(pack: &[u8]) -> Vec<CacheObject> {
+    let count = u32::from_be_bytes(pack[8..12].try_into().unwrap());
+    let mut reader = Cursor::new(pack);
+    reader.set_position(12);
+    let mut offset = 12;
+    (0..count)
+        .map(|_| {
+            Pack::decode_pack_object(&mut reader, &mut offset)
+                .unwrap()
+                .unwrap()
+        })
+        .collect()
+}
+
+fn similar_blobs() -> (Entry, Entry) {
+    let base: Vec<u8> = (0..4096u32).flat_map(|i| (i * 7).to_le_bytes()).collect();
+    let mut edited = base.clone();
+    edited[2000..2010].copy_from_slice(b"small edit");
+    (
+        Blob::from_content_bytes(base).into(),
+        Blob::from_content_bytes(edited).into(),
+    )
+}
+
+fn plain(entry: Entry) -> MetaAttached<Entry, EntryMeta> {
+    MetaAttached {
+        inner: entry,
+        meta: EntryMeta::new(),
+    }
+}
+
+/// Recognised precompressed blobs are stored verbatim, while other objects keep their level.
+#[tokio::test]
+async fn test_pack_encoder_stores_precompressed_blobs() {
+    let _guard = set_hash_kind_for_test(HashKind::Sha1);
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    png.extend(std::iter::repeat_n(b'p', 512));
+    let text = vec![b't'; 512];
+    let entries = vec![
+        plain(Blob::from_content_bytes(png.clone()).into()),
+        plain(Blob::from_content_bytes(text.clone()).into()),
+    ];
+
+    let contains =
+        |haystack: &[u8], needle: &[u8]| haystack.windows(needle.len()).any(|w| w == needle);
+    let pack = encode_entries_with(entries.clone(), |encoder| {
+        encoder.with_compression(PackCompression::best().with_store_precompressed(true))
+    })
+    .await;
+    assert!(contains(&pack, &png));
+    assert!(!contains(&pack, &text));
+    check_format(&pack);
+
+    let pack = encode_entries_with(entries, |encoder| {
+        encoder.with_compression(PackCompression::best())
+    })
+    .await;
+    assert!(!contains(&pack, &png));
+}
+
+/// REF_DELTA mode names bases by hash, and the pack still decodes on its own.
+#[tokio::test]
+async fn test_pack_encoder_ref_delta_mode() {
+    let _guard = set_hash_kind_for_test(HashKind::Sha1);
+    let (base, edited) = similar_blobs();
+    let entries = vec![plain(base.clone()), plain(edited)];
+
+    let pack = encode_entries_with(entries.clone(), |e| e.with_ref_delta(true)).await;
+    let types: Vec<_> = raw_pack_entries(&pack)
+        .iter()
+        .map(|obj| obj.info.clone())
+        .collect();
+    assert!(types.contains(&CacheObjectInfo::HashDelta(base.hash, 4096 * 4)));
+    assert!(
+        !types
+            .iter()
+            .any(|info| matches!(info, CacheObjectInfo::OffsetDelta(..)))
+    );
+    check_format(&pack);
+
+    let pack = encode_entries_with(entries, |e| e.with_ref_delta(false)).await;
+    assert!(
+        raw_pack_entries(&pack)
+            .iter()
+            .any(|obj| matches!(obj.info, CacheObjectInfo::OffsetDelta(..)))
+    );
+}
+
+/// External bases are searched but not written, and deltas against them use REF_DELTA.
+#[tokio::test]
+async fn test_pack_encoder_external_bases() {
+    let _guard = set_hash_kind_for_test(HashKind::Sha1);
+    let (base, edited) = similar_blobs();
+
+    let pack = encode_entries_with(vec![plain(edited.clone())], |e| {
+        e.with_external_bases(vec![plain(base.clone())])
+    })
+    .await;
+    let objs = raw_pack_entries(&pack);
+    assert_eq!(objs.len(), 1);
+    assert_eq!(
+        objs[0].info,
+        CacheObjectInfo::HashDelta(base.hash, edited.data.len())
+    );
+    assert!(pack.len() < 200, "thin pack is {} bytes", pack.len());
+
+    let base_obj = Arc::new(CacheObject::new_for_undeltified(
+        ObjectType::Blob,
+        base.data.clone(),
+        0,
+        0,
+    ));
+    let rebuilt = Pack::rebuild_delta(objs.into_iter().next().unwrap(), base_obj);
+    assert_eq!(rebuilt.data_decompressed, edited.data);
+
+    // A base that is also in the input is written in full.
+    let pack = encode_entries_with(vec![plain(base.clone()), plain(edited)], |e| {
+        e.with_external_bases(vec![plain(base)])
+    })
+    .await;
+    assert_eq!(raw_pack_entries(&pack).len(), 2);
+    check_format(&pack);
+}
+
 #[tokio::test]
 async fn test_pack_encoder_parallel_large_file() {
     let _guard = set_hash_kind_for_test(HashKind::Sha1);
@@ -715,6 +1100,78 @@ async fn test_pack_encoder_with_zstdelta_sha256() {
     check_format(&result);
 }
 
+/// Objects without a delta base are stored against the trained dictionary of their type, and the
+/// decoder loads the dictionary to rebuild them.
+#[tokio::test]
+async fn test_pack_encoder_zstd_dictionaries() {
+    let _guard = set_hash_kind_for_test(HashKind::Sha1);
+    // Small records that share their layout but little of their content.
+    let entries: Vec<Entry> = (0..64u32)
+        .map(|i| {
+            let record = format!(
+                "{{\"id\": \"{:08x}\", \"kind\": \"session\", \"owner\": \"user-{}\", \
+                 \"status\": \"{}\", \"payload\": \"{}\"}}\n",
+                i.wrapping_mul(2_654_435_761),
+                i * 37 % 101,
+                ["open", "closed", "merged"][i as usize % 3],
+                noise(i + 1, 24)
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect::<String>(),
+            );
+            Blob::from_content(&record).into()
+        })
+        .collect();
+
+    let (tx, mut rx) = mpsc::channel(64);
+    let (entry_tx, entry_rx) = mpsc::channel(entries.len());
+    let encoder = PackEncoder::new(entries.len(), 10, tx).with_zstd_dictionaries(true);
+    encoder.encode_async_with_zstdelta(entry_rx).await.unwrap();
+    for entry in &entries {
+        entry_tx.send(plain(entry.clone())).await.unwrap();
+    }
+    drop(entry_tx);
+    let mut pack = Vec::new();
+    while let Some(chunk) = rx.recv().await {
+        pack.extend(chunk);
+    }
+
+    let raw = raw_pack_entries(&pack);
+    assert_eq!(raw.len(), entries.len() + 1);
+    assert_eq!(
+        raw[0].info,
+        CacheObjectInfo::ZstdDictionary(ObjectType::Blob)
+    );
+    assert!(raw[1..].iter().any(|obj| matches!(
+        obj.info,
+        CacheObjectInfo::OffsetZstdelta(base, _) if base == raw[0].offset
+    )));
+
+    let dictionary_size = raw[0].data_decompressed.len() + 1;
+    let decoded = decode_with(&pack, |p| p.with_zstd_dictionaries(dictionary_size)).unwrap();
+    let mut decoded: Vec<_> = decoded
+        .into_iter()
+        .map(|e| (e.inner.hash, e.inner.data))
+        .collect();
+    decoded.sort();
+    let mut expected: Vec<_> = entries.into_iter().map(|e| (e.hash, e.data)).collect();
+    expected.sort();
+    assert_eq!(decoded, expected);
+
+    // Dictionaries need an explicit opt-in, and count against its size cap.
+    assert!(matches!(
+        decode_with(&pack, |p| p),
+        Err(GitError::InvalidPackFile(_))
+    ));
+    assert!(matches!(
+        decode_with(&pack, |p| p.with_zstd_dictionaries(dictionary_size - 1)),
+        Err(GitError::DecodeLimitExceeded {
+            limit: DecodeLimit::ZstdDictionarySize,
+            ..
+        })
+    ));
+}
+
 #[test]
 fn test_encode_offset() {
     // let value = 11013;
@@ -897,6 +1354,7 @@ async fn test_pack_encoder_output_to_files() {
 
     let mut pack_file = None;
     let mut idx_file = None;
+    let mut rev_file = None;
     for entry in std::fs::read_dir(path).unwrap() {
         let entry = entry.unwrap();
         let file_name = entry.file_name();
@@ -906,15 +1364,22 @@ async fn test_pack_encoder_output_to_files() {
             pack_file = Some(entry.path());
         } else if file_name.ends_with(".idx") {
             idx_file = Some(entry.path());
+        } else if file_name.ends_with(".rev") {
+            rev_file = Some(entry.path());
         }
     }
     let pack_file = pack_file.expect("pack file not generated");
     let idx_file = idx_file.expect("idx file not generated");
+    let rev_file = rev_file.expect("rev file not generated");
     assert!(
         pack_file.metadata().unwrap().len() > 0,
         "pack file is empty"
     );
     assert!(idx_file.metadata().unwrap().len() > 0, "idx file is empty");
+    let index = PackIndex::open(&idx_file).unwrap();
+    let rev = ReverseIndex::open(&rev_file).unwrap();
+    assert_eq!(rev.object_count(), entries_number);
+    assert_eq!(rev.pack_hash(), index.pack_hash());
 
     let duration = start.elapsed();
     tracing::info!("test executed in: {:.2?}", duration);
@@ -987,6 +1452,216 @@ async fn test_pack_encoder_output_to_files_with_delta() {
     tracing::info!("original total size: {}", total_original_size);
 }
 
+/// Deterministic mode writes the same bytes whatever order the entries arrive in and however many
+/// threads encode them.
+#[tokio::test]
+async fn test_pack_encoder_deterministic() {
+    let _guard = set_hash_kind_for_test(HashKind::Sha1);
+
+    async fn encode_once(
+        entries: Vec<MetaAttached<Entry, EntryMeta>>,
+        window_size: usize,
+        threads: usize,
+    ) -> Vec<u8> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        let (tx, mut rx) = mpsc::channel(64);
+        let (entry_tx, entry_rx) = mpsc::channel(entries.len());
+        let encoder = PackEncoder::new(entries.len(), window_size, tx)
+            .with_deterministic(true)
+            .with_thread_pool(Arc::new(pool));
+        encoder.encode_async(entry_rx).await.unwrap();
+        for entry in entries {
+            entry_tx.send(entry).await.unwrap();
+        }
+        drop(entry_tx);
+        let mut result = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            result.extend(chunk);
+        }
+        result
+    }
+
+    // Equal sizes and no paths, so only the object id tells these blobs apart when sorting. More
+    // than 20 blobs per thread, which is when the non-deterministic path splits by thread count.
+    let base: Vec<u8> = (0..4096u32).flat_map(|i| (i * 7).to_le_bytes()).collect();
+    let entries: Vec<_> = (0..100u8)
+        .map(|i| {
+            let mut data = base.clone();
+            data[i as usize * 100] = i;
+            plain(Blob::from_content_bytes(data).into())
+        })
+        .collect();
+    let mut reversed = entries.clone();
+    reversed.reverse();
+
+    for window_size in [0, 10] {
+        let single = encode_once(entries.clone(), window_size, 1).await;
+        let forward = encode_once(entries.clone(), window_size, 4).await;
+        let backward = encode_once(reversed.clone(), window_size, 4).await;
+        assert_eq!(single, forward, "window size {window_size}, 1 vs 4 threads");
+        assert_eq!(forward, backward, "window size {window_size}");
+        check_format(&forward);
+    }
+}
+
+/// Bytes that zlib cannot shrink, so the pack size of an object is close to its bound.
+fn noise(seed: u32, len: usize) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(2_654_435_761).max(1);
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// A size limit splits the output into packs no larger than the limit, each decodable on its own,
+/// with every object in exactly one pack.
+#[tokio::test]
+async fn test_pack_encoder_output_to_files_with_size_limit() {
+    let _guard = set_hash_kind_for_test(HashKind::Sha1);
+    let base = noise(0, 2048);
+    let mut first = base.clone();
+    first[100..120].copy_from_slice(b"first edit of a blob");
+    let mut second = first.clone();
+    second.extend_from_slice(b"appended by the second edit");
+
+    let mut builder = TestPackBuilder::new();
+    let base_entry = builder.add_base(ObjectType::Blob, &base);
+    let first_entry = builder.add_ofs_delta(base_entry, &base, ObjectType::Blob, &first);
+    let second_entry = builder.add_ref_delta(first_entry.hash, &first, ObjectType::Blob, &second);
+    let chain = [base_entry.hash, first_entry.hash, second_entry.hash];
+
+    // The chain comes last so it cannot simply share the first pack with nothing else.
+    let mut entries: Vec<_> = (1..=8)
+        .map(|seed| plain(Blob::from_content_bytes(noise(seed, 2048)).into()))
+        .collect();
+    entries.extend(decode_with_delta_reuse(&builder.pack_bytes()));
+    let object_number = entries.len();
+
+    let (entry_tx, entry_rx) = mpsc::channel(object_number);
+    for entry in entries {
+        entry_tx.send(entry).await.unwrap();
+    }
+    drop(entry_tx);
+
+    let max_pack_size = 6 * 1024;
+    let dir = tempdir().unwrap();
+    let packs = encode_and_output_to_files_with_options(
+        entry_rx,
+        object_number,
+        dir.path().to_path_buf(),
+        10,
+        OutputOptions {
+            max_pack_size: Some(max_pack_size),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(packs.len() > 1);
+
+    let mut total = 0;
+    let mut chain_seen = HashMap::new();
+    for written in &packs {
+        let bytes = std::fs::read(&written.pack).unwrap();
+        assert!(bytes.len() as u64 <= max_pack_size);
+        check_format(&bytes);
+        let index = PackIndex::open(&written.idx).unwrap();
+        assert_eq!(index.pack_hash(), written.hash);
+        assert!(written.rev.exists());
+        total += index.object_count();
+        for hash in chain {
+            if index.contains(&hash) {
+                *chain_seen.entry(hash).or_insert(0) += 1;
+            }
+        }
+    }
+    assert_eq!(total, object_number);
+    assert!(chain.iter().all(|hash| chain_seen.get(hash) == Some(&1)));
+    // Only the packs are left behind, not the scratch pack they were cut from.
+    assert_eq!(
+        std::fs::read_dir(dir.path()).unwrap().count(),
+        packs.len() * 3
+    );
+}
+
+/// Packs of compressible, delta-heavy content are filled close to the limit rather than split by
+/// a worst-case estimate of each entry.
+#[tokio::test]
+async fn test_pack_encoder_size_limit_fills_packs() {
+    let _guard = set_hash_kind_for_test(HashKind::Sha1);
+    // Revisions of a text file, each adding a few lines: small deltas against compressible blobs.
+    let mut text = String::new();
+    let mut entries = Vec::new();
+    for revision in 0..200 {
+        for line in 0..5 {
+            text.push_str(&format!(
+                "revision {revision} line {line}: {}\n",
+                noise_word(revision * 5 + line)
+            ));
+        }
+        entries.push(MetaAttached {
+            inner: Blob::from_content(&text).into(),
+            meta: EntryMeta {
+                file_path: Some("notes.txt".to_string()),
+                ..Default::default()
+            },
+        });
+    }
+    let object_number = entries.len();
+    let (entry_tx, entry_rx) = mpsc::channel(object_number);
+    for entry in entries {
+        entry_tx.send(entry).await.unwrap();
+    }
+    drop(entry_tx);
+
+    let max_pack_size = 16 * 1024;
+    let dir = tempdir().unwrap();
+    let packs = encode_and_output_to_files_with_options(
+        entry_rx,
+        object_number,
+        dir.path().to_path_buf(),
+        10,
+        OutputOptions {
+            max_pack_size: Some(max_pack_size),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert!(packs.len() > 1);
+
+    let mut total = 0;
+    for (i, written) in packs.iter().enumerate() {
+        let bytes = std::fs::read(&written.pack).unwrap();
+        assert!(bytes.len() as u64 <= max_pack_size);
+        if i + 1 < packs.len() {
+            assert!(
+                bytes.len() as u64 >= max_pack_size * 9 / 10,
+                "pack {i} holds only {} of {max_pack_size} bytes",
+                bytes.len()
+            );
+        }
+        check_format(&bytes);
+        total += PackIndex::open(&written.idx).unwrap().object_count();
+    }
+    assert_eq!(total, object_number);
+}
+
+/// A short word that varies with `seed`, so each added line is new text.
+fn noise_word(seed: u32) -> String {
+    noise(seed + 1, 6)
+        .iter()
+        .map(|b| (b'a' + b % 26) as char)
+        .collect()
+}
+
 // ── Sort / similarity tests ────────────────────────────────────────────
 
 fn sort_entry(path: Option<&str>, size: usize) -> MetaAttached<Entry, EntryMeta> {
@@ -1034,10 +1709,13 @@ fn test_magic_sort_path_ordering() {
     let b = sort_entry(None, 100);
     assert_eq!(magic_sort(&a, &b), std::cmp::Ordering::Greater);
 
-    // No path, equal size — pointer tiebreaker
+    // No path, equal size — object id tiebreaker
     let a = sort_entry(None, 100);
-    let b = sort_entry(None, 100);
-    assert_ne!(magic_sort(&a, &b), std::cmp::Ordering::Equal);
+    let mut b = sort_entry(None, 100);
+    assert_eq!(magic_sort(&a, &b), std::cmp::Ordering::Equal);
+    b.inner.hash = sort_entry(None, 101).inner.hash;
+    assert_eq!(magic_sort(&a, &b), a.inner.hash.cmp(&b.inner.hash));
+    assert_eq!(magic_sort(&b, &a), b.inner.hash.cmp(&a.inner.hash));
 }
 
 #[test]
@@ -47,6 +47,9 @@ impl TryFrom<&MetaAttached<Entry, EntryMeta>> for IndexEntry {
 
 impl IndexEntry {
     /// Create a new IndexEntry from a pack Entry and its offset in the pack file.
+    ///
+    /// The CRC32 covers `entry.data`; whoever writes the entry into a pack must replace it with
+    /// the CRC32 of the bytes written, which is what `.idx` files record.
     pub fn new(entry: &Entry, offset: usize) -> Self {
         IndexEntry {
             hash: entry.hash,
@@ -0,0 +1,180 @@
+//! Resource limits for decoding packs from untrusted sources.
+//!
+//! A pack declares object counts and sizes up front, and a few kilobytes of zlib or delta data can
+//! claim gigabytes of output. [`DecodeLimits`] caps those claims so that a hostile push fails with
+//! [`GitError::DecodeLimitExceeded`] before the decoder allocates for it. Every limit is off by
+//! default, which keeps the behaviour of trusted, local decoding unchanged.
+
+use std::fmt;
+
+use crate::errors::GitError;
+
+/// Which [`DecodeLimits`] field a pack exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DecodeLimit {
+    ObjectCount,
+    EntrySize,
+    TotalInflatedSize,
+    DeltaResultSize,
+    DeltaDepth,
+    ZstdDictionarySize,
+}
+
+impl fmt::Display for DecodeLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DecodeLimit::ObjectCount => "object count",
+            DecodeLimit::EntrySize => "entry size",
+            DecodeLimit::TotalInflatedSize => "total inflated size",
+            DecodeLimit::DeltaResultSize => "delta result size",
+            DecodeLimit::DeltaDepth => "delta chain depth",
+            DecodeLimit::ZstdDictionarySize => "zstd dictionary size",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Caps on what a pack may ask the decoder to allocate or compute; `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Number of objects declared in the pack header.
+    pub max_objects: Option<u32>,
+    /// Inflated size of a single entry (a full object or a delta payload), as declared in the
+    /// entry header.
+    pub max_entry_size: Option<usize>,
+    /// Sum of the inflated sizes of all entries.
+    pub max_total_inflated_size: Option<u64>,
+    /// Size of an object rebuilt from a delta, as declared in the delta header.
+    pub max_delta_result_size: Option<usize>,
+    /// Number of deltas between an object and its base.
+    pub max_delta_depth: Option<usize>,
+}
+
+impl DecodeLimits {
+    /// No limits, the default.
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// Limits for packs pushed by clients: 5 million objects, 512 MiB per entry or delta result,
+    /// 8 GiB inflated in total, and Git's own maximum delta depth of 4095.
+    pub fn server() -> Self {
+        Self::default()
+            .with_max_objects(5_000_000)
+            .with_max_entry_size(512 << 20)
+            .with_max_total_inflated_size(8 << 30)
+            .with_max_delta_result_size(512 << 20)
+            .with_max_delta_depth(4095)
+    }
+
+    pub fn with_max_objects(mut self, max: u32) -> Self {
+        self.max_objects = Some(max);
+        self
+    }
+
+    pub fn with_max_entry_size(mut self, max: usize) -> Self {
+        self.max_entry_size = Some(max);
+        self
+    }
+
+    pub fn with_max_total_inflated_size(mut self, max: u64) -> Self {
+        self.max_total_inflated_size = Some(max);
+        self
+    }
+
+    pub fn with_max_delta_result_size(mut self, max: usize) -> Self {
+        self.max_delta_result_size = Some(max);
+        self
+    }
+
+    pub fn with_max_delta_depth(mut self, max: usize) -> Self {
+        self.max_delta_depth = Some(max);
+        self
+    }
+
+    pub(crate) fn check_object_count(&self, count: u32) -> Result<(), GitError> {
+        check(
+            DecodeLimit::ObjectCount,
+            count as u64,
+            self.max_objects.map(u64::from),
+        )
+    }
+
+    pub(crate) fn check_delta_result_size(&self, size: usize) -> Result<(), GitError> {
+        check(
+            DecodeLimit::DeltaResultSize,
+            size as u64,
+            self.max_delta_result_size.map(|max| max as u64),
+        )
+    }
+
+    pub(crate) fn check_delta_depth(&self, depth: usize) -> Result<(), GitError> {
+        check(
+            DecodeLimit::DeltaDepth,
+            depth as u64,
+            self.max_delta_depth.map(|max| max as u64),
+        )
+    }
+}
+
+/// Running totals checked against [`DecodeLimits`] while one pack is decoded.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DecodeBudget {
+    pub(crate) limits: DecodeLimits,
+    inflated: u64,
+    /// Total size of zstd dictionaries the pack may carry; `None` rejects them.
+    dictionary_allowance: Option<u64>,
+    dictionaries: u64,
+}
+
+impl DecodeBudget {
+    pub(crate) fn new(limits: DecodeLimits) -> Self {
+        DecodeBudget {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    /// Accept zstd dictionary entries up to `max` inflated bytes in total.
+    pub(crate) fn with_zstd_dictionaries(mut self, max: Option<usize>) -> Self {
+        self.dictionary_allowance = max.map(|max| max as u64);
+        self
+    }
+
+    pub(crate) fn accepts_zstd_dictionaries(&self) -> bool {
+        self.dictionary_allowance.is_some()
+    }
+
+    /// Account for a zstd dictionary entry that declares `size` inflated bytes, on top of
+    /// [`Self::charge_entry`].
+    pub(crate) fn charge_dictionary(&mut self, size: usize) -> Result<(), GitError> {
+        self.dictionaries = self.dictionaries.saturating_add(size as u64);
+        check(
+            DecodeLimit::ZstdDictionarySize,
+            self.dictionaries,
+            self.dictionary_allowance,
+        )
+    }
+
+    /// Account for an entry that declares `size` inflated bytes, before inflating it.
+    pub(crate) fn charge_entry(&mut self, size: usize) -> Result<(), GitError> {
+        check(
+            DecodeLimit::EntrySize,
+            size as u64,
+            self.limits.max_entry_size.map(|max| max as u64),
+        )?;
+        self.inflated = self.inflated.saturating_add(size as u64);
+        check(
+            DecodeLimit::TotalInflatedSize,
+            self.inflated,
+            self.limits.max_total_inflated_size,
+        )
+    }
+}
+
+fn check(limit: DecodeLimit, value: u64, max: Option<u64>) -> Result<(), GitError> {
+    match max {
+        Some(max) if value > max => Err(GitError::DecodeLimitExceeded { limit, value, max }),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,164 @@
+//! Read-only memory mapping of pack and index files, so decoders and random-access readers can
+//! slice file contents directly instead of copying them through buffered readers.
+
+use std::{fs::File, io, ops::Deref, path::Path};
+
+/// Immutable bytes of a file, memory-mapped on Unix and read into memory elsewhere.
+///
+/// Owned buffers can be wrapped with [`From<Vec<u8>>`] so callers that already hold the bytes
+/// (tests, network input) can share the same code paths.
+///
+/// The mapping is private and read-only. As with any mmap, truncating the file from another
+/// process while it is mapped makes later accesses fault, so only map files that are not being
+/// rewritten in place (pack files are immutable once written).
+pub struct MappedFile {
+    storage: Storage,
+}
+
+enum Storage {
+    #[cfg(unix)]
+    Mapped {
+        ptr: *mut libc::c_void,
+        len: usize,
+    },
+    Owned(Vec<u8>),
+}
+
+// SAFETY: the mapping is read-only and never handed out mutably, so sharing the pointer across
+// threads is equivalent to sharing an immutable `&[u8]`.
+unsafe impl Send for MappedFile {}
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    /// Map the whole file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Self::map(&file)
+    }
+
+    /// Map the whole of an already opened file.
+    #[cfg(unix)]
+    pub fn map(file: &File) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file too large to map"))?;
+        if len == 0 {
+            // mmap rejects empty mappings.
+            return Ok(Vec::new().into());
+        }
+        // SAFETY: a fresh private read-only mapping of `len` bytes of a valid descriptor; the
+        // result is checked against MAP_FAILED before use.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(MappedFile {
+            storage: Storage::Mapped { ptr, len },
+        })
+    }
+
+    /// Read the whole of an already opened file (platforms without mmap support here).
+    #[cfg(not(unix))]
+    pub fn map(file: &File) -> io::Result<Self> {
+        use std::io::Read;
+
+        let mut data = Vec::new();
+        let mut file = file;
+        file.read_to_end(&mut data)?;
+        Ok(data.into())
+    }
+
+    /// Whether the bytes are backed by a memory mapping (as opposed to an owned buffer).
+    pub fn is_mapped(&self) -> bool {
+        !matches!(self.storage, Storage::Owned(_))
+    }
+
+    /// Take the bytes out as an owned buffer; only mappings are copied.
+    pub fn into_vec(mut self) -> Vec<u8> {
+        match &mut self.storage {
+            Storage::Owned(data) => std::mem::take(data),
+            #[cfg(unix)]
+            Storage::Mapped { .. } => self.to_vec(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for MappedFile {
+    fn from(data: Vec<u8>) -> Self {
+        MappedFile {
+            storage: Storage::Owned(data),
+        }
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.storage {
+            #[cfg(unix)]
+            // SAFETY: `ptr` maps `len` readable bytes for as long as `self` is alive.
+            Storage::Mapped { ptr, len } => unsafe {
+                std::slice::from_raw_parts(*ptr as *const u8, *len)
+            },
+            Storage::Owned(data) => data,
+        }
+    }
+}
+
+impl AsRef<[u8]> for MappedFile {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Storage::Mapped { ptr, len } = self.storage {
+            // SAFETY: unmapping exactly the region returned by mmap, once.
+            unsafe {
+                libc::munmap(ptr, len);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+
+    use super::MappedFile;
+
+    /// Mapped contents match the file, and empty files map to an empty slice.
+    #[test]
+    fn test_mapped_file_contents() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"PACK mapped bytes").unwrap();
+        file.flush().unwrap();
+
+        let mapped = MappedFile::open(file.path()).unwrap();
+        assert_eq!(&mapped[..], b"PACK mapped bytes");
+        assert_eq!(mapped.is_mapped(), cfg!(unix));
+
+        let empty = NamedTempFile::new().unwrap();
+        let mapped = MappedFile::open(empty.path()).unwrap();
+        assert!(mapped.is_empty());
+
+        let owned = MappedFile::from(vec![1, 2, 3]);
+        assert_eq!(&owned[..], &[1, 2, 3]);
+        assert!(!owned.is_mapped());
+    }
+}
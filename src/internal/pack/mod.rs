@@ -1,16 +1,27 @@
 //! Pack file encoder/decoder implementations, caches, waitlists, and stream wrappers that faithfully
 //! follow the [pack-format spec](https://git-scm.com/docs/pack-format).
 
+pub mod bitmap;
 pub mod cache;
 pub mod cache_object;
+pub mod cancel;
 pub mod channel_reader;
 pub mod decode;
 pub mod encode;
 pub mod entry;
 mod index_entry;
+pub mod limits;
+pub mod mmap;
+pub mod multi_pack_index;
 pub mod pack_index;
+pub mod progress;
+pub mod reader;
+pub mod reverse_index;
+pub mod salvage;
 pub mod stats;
+pub mod thin;
 pub mod utils;
+pub mod verify;
 pub mod waitlist;
 pub mod wrapper;
 use std::sync::{Arc, atomic::AtomicUsize};
@@ -21,7 +32,10 @@ use crate::{
     hash::ObjectHash,
     internal::{
         object::ObjectTrait,
-        pack::{cache::Caches, waitlist::Waitlist},
+        pack::{
+            cache::Caches, cancel::CancellationToken, limits::DecodeLimits,
+            progress::ProgressObserver, waitlist::Waitlist,
+        },
     },
 };
 
@@ -38,8 +52,20 @@ pub struct Pack {
     pub mem_limit: Option<usize>,
     pub cache_objs_mem: Arc<AtomicUsize>,
     pub clean_tmp: bool,
+    /// Caps applied while decoding; see [`Pack::with_decode_limits`].
+    pub limits: DecodeLimits,
+    /// Receives decode progress; see [`Pack::with_progress`].
+    pub progress: Option<Arc<dyn ProgressObserver>>,
+    /// Stops decoding when cancelled; see [`Pack::with_cancellation`].
+    pub cancellation: CancellationToken,
+    /// Keep stored deltas for re-encoding; see [`Pack::with_delta_reuse`].
+    pub delta_reuse: bool,
+    /// Total size of zstd dictionaries a pack may carry; see [`Pack::with_zstd_dictionaries`].
+    pub zstd_dictionary_limit: Option<usize>,
 }
 
+#[cfg(test)]
+pub(crate) mod test_pack_builder;
 #[cfg(test)]
 pub(crate) mod test_pack_download;
 
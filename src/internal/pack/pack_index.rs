@@ -1,10 +1,26 @@
-//! Builder for Git pack index (.idx) files that streams fanout tables, CRCs, offsets, and trailer
-//! hashes through an async channel.
+//! Builder and random-access reader for Git pack index (.idx) v2 files. The builder streams fanout
+//! tables, CRCs, offsets, and trailer hashes through an async channel; [`PackIndex`] answers
+//! hash → offset/CRC32 lookups with a fanout bucket plus binary search.
+
+use std::{cmp::Ordering, ops::Range, path::Path};
 
 use tokio::sync::mpsc;
 
 pub use crate::internal::pack::index_entry::IndexEntry;
-use crate::{errors::GitError, hash::ObjectHash, utils::HashAlgorithm};
+use crate::{
+    errors::GitError,
+    hash::{HashKind, ObjectHash, get_hash_kind},
+    internal::pack::mmap::MappedFile,
+    utils::HashAlgorithm,
+};
+
+/// `\377tOc` magic that starts every v2 pack index.
+const IDX_MAGIC: [u8; 4] = [0xFF, 0x74, 0x4F, 0x63];
+const IDX_VERSION: u32 = 2;
+const IDX_HEADER_SIZE: usize = 8;
+const IDX_FANOUT_SIZE: usize = 256 * 4;
+/// MSB of a 4-byte offset entry marks an index into the 8-byte large offset table.
+const IDX_LARGE_OFFSET_FLAG: u32 = 0x8000_0000;
 
 /// Builder for Git pack index (.idx) files that streams data through an async channel.
 /// # Arguments
@@ -77,8 +93,9 @@ impl IdxBuilder {
     async fn write_header(&mut self) -> Result<(), GitError> {
         // .idx v2 header (used for both SHA1 and SHA256)
         // magic: FF 74 4F 63, version: 2
-        let header: [u8; 8] = [0xFF, 0x74, 0x4F, 0x63, 0, 0, 0, 2];
-        self.send_data(header.to_vec()).await
+        let mut header = IDX_MAGIC.to_vec();
+        header.extend_from_slice(&IDX_VERSION.to_be_bytes());
+        self.send_data(header).await
     }
 
     /// Write the fanout table for the index.
@@ -142,13 +159,12 @@ impl IdxBuilder {
 
     /// Write the idx trailer containing the pack hash and idx file hash.
     async fn write_trailer(&mut self) -> Result<(), GitError> {
-        // pack hash
-        self.send_data_without_update_hash(self.pack_hash.to_data().clone())
-            .await?;
+        // pack hash (covered by the idx checksum, as Git expects)
+        self.send_data(self.pack_hash.to_data().clone()).await?;
 
         let idx_hash = self.inner_hash.clone().finalize();
         // idx file hash
-        self.send_data(idx_hash).await?;
+        self.send_data_without_update_hash(idx_hash).await?;
         Ok(())
     }
 
@@ -175,13 +191,332 @@ impl IdxBuilder {
     }
 }
 
+/// Random-access reader for a Git pack index (.idx) v2 file.
+///
+/// The index is memory-mapped (or held in memory when built from bytes) and validated once on open (magic, version, monotonic fanout,
+/// table sizes, large offset references, and trailer checksum). Lookups afterwards are a fanout
+/// bucket selection plus a binary search over the sorted object names, so the pack itself never
+/// has to be decoded to answer "is object X in this pack and where?".
+///
+/// The hash kind is taken from the thread-local setting when the index is opened and remembered,
+/// so a `PackIndex` can be shared with threads configured for a different kind.
+pub struct PackIndex {
+    data: MappedFile,
+    kind: HashKind,
+    object_count: usize,
+    names_start: usize,
+    crc_start: usize,
+    offsets_start: usize,
+    large_offsets_start: usize,
+}
+
+impl PackIndex {
+    /// Open and validate the `.idx` file at `path` using the current thread-local hash kind.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, GitError> {
+        let path = path.as_ref();
+        let data = MappedFile::open(path).map_err(|e| {
+            GitError::InvalidIdxFile(format!("failed to read {}: {e}", path.display()))
+        })?;
+        Self::parse(data, get_hash_kind())
+    }
+
+    /// Parse and validate an in-memory `.idx` file using the current thread-local hash kind.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, GitError> {
+        Self::from_bytes_with_kind(data, get_hash_kind())
+    }
+
+    /// Parse and validate an in-memory `.idx` file written with the given hash kind.
+    pub fn from_bytes_with_kind(data: Vec<u8>, kind: HashKind) -> Result<Self, GitError> {
+        Self::parse(data.into(), kind)
+    }
+
+    fn parse(data: MappedFile, kind: HashKind) -> Result<Self, GitError> {
+        let hash_size = kind.size();
+        let min_len = IDX_HEADER_SIZE + IDX_FANOUT_SIZE + 2 * hash_size;
+        if data.len() < min_len {
+            return Err(GitError::InvalidIdxFile(format!(
+                "file is {} bytes, shorter than the minimum {min_len}",
+                data.len()
+            )));
+        }
+        if data[..4] != IDX_MAGIC {
+            return Err(GitError::InvalidIdxFile(
+                "missing pack index v2 magic".to_string(),
+            ));
+        }
+        let version = read_u32(&data, 4);
+        if version != IDX_VERSION {
+            return Err(GitError::InvalidIdxFile(format!(
+                "unsupported pack index version {version}"
+            )));
+        }
+
+        let mut prev = 0u32;
+        for i in 0..256 {
+            let count = read_u32(&data, IDX_HEADER_SIZE + i * 4);
+            if count < prev {
+                return Err(GitError::InvalidIdxFile(format!(
+                    "fanout table is not monotonic at entry {i}"
+                )));
+            }
+            prev = count;
+        }
+        let object_count = prev as usize;
+
+        let names_start = IDX_HEADER_SIZE + IDX_FANOUT_SIZE;
+        let crc_start = names_start + object_count * hash_size;
+        let offsets_start = crc_start + object_count * 4;
+        let large_offsets_start = offsets_start + object_count * 4;
+        let trailer_start = data.len() - 2 * hash_size;
+        if trailer_start < large_offsets_start
+            || !(trailer_start - large_offsets_start).is_multiple_of(8)
+        {
+            return Err(GitError::InvalidIdxFile(format!(
+                "file size {} does not match {object_count} objects",
+                data.len()
+            )));
+        }
+        let large_offset_count = (trailer_start - large_offsets_start) / 8;
+
+        let checksum_start = data.len() - hash_size;
+        let mut hasher = HashAlgorithm::new_with_kind(kind);
+        hasher.update(&data[..checksum_start]);
+        if hasher.finalize() != data[checksum_start..] {
+            return Err(GitError::InvalidIdxFile(
+                "trailer checksum does not match index contents".to_string(),
+            ));
+        }
+
+        let index = PackIndex {
+            data,
+            kind,
+            object_count,
+            names_start,
+            crc_start,
+            offsets_start,
+            large_offsets_start,
+        };
+        for pos in 0..object_count {
+            let raw = index.raw_offset(pos);
+            if raw & IDX_LARGE_OFFSET_FLAG != 0
+                && (raw & !IDX_LARGE_OFFSET_FLAG) as usize >= large_offset_count
+            {
+                return Err(GitError::InvalidIdxFile(format!(
+                    "object {pos} references missing large offset {}",
+                    raw & !IDX_LARGE_OFFSET_FLAG
+                )));
+            }
+        }
+        Ok(index)
+    }
+
+    /// Hash kind the index was written with.
+    pub fn hash_kind(&self) -> HashKind {
+        self.kind
+    }
+
+    /// Number of objects described by the index.
+    pub fn object_count(&self) -> usize {
+        self.object_count
+    }
+
+    /// Checksum of the pack this index belongs to (first trailer hash).
+    pub fn pack_hash(&self) -> ObjectHash {
+        let start = self.data.len() - 2 * self.kind.size();
+        self.hash_from(start)
+    }
+
+    /// Checksum of the index file itself (second trailer hash).
+    pub fn index_hash(&self) -> ObjectHash {
+        let start = self.data.len() - self.kind.size();
+        self.hash_from(start)
+    }
+
+    /// Object name stored at sorted position `pos`.
+    ///
+    /// # Panics
+    /// Panics if `pos >= object_count()`.
+    pub fn hash_at(&self, pos: usize) -> ObjectHash {
+        assert!(pos < self.object_count, "index position out of range");
+        self.hash_from(self.names_start + pos * self.kind.size())
+    }
+
+    /// CRC32 of the packed (compressed) entry at sorted position `pos`.
+    ///
+    /// # Panics
+    /// Panics if `pos >= object_count()`.
+    pub fn crc32_at(&self, pos: usize) -> u32 {
+        assert!(pos < self.object_count, "index position out of range");
+        read_u32(&self.data, self.crc_start + pos * 4)
+    }
+
+    /// Pack offset of the entry at sorted position `pos`, resolving 64-bit large offsets.
+    ///
+    /// # Panics
+    /// Panics if `pos >= object_count()`.
+    pub fn offset_at(&self, pos: usize) -> u64 {
+        assert!(pos < self.object_count, "index position out of range");
+        let raw = self.raw_offset(pos);
+        if raw & IDX_LARGE_OFFSET_FLAG == 0 {
+            raw as u64
+        } else {
+            let slot = (raw & !IDX_LARGE_OFFSET_FLAG) as usize;
+            read_u64(&self.data, self.large_offsets_start + slot * 8)
+        }
+    }
+
+    /// Index entry (hash, CRC32, offset) at sorted position `pos`.
+    pub fn entry_at(&self, pos: usize) -> IndexEntry {
+        IndexEntry {
+            hash: self.hash_at(pos),
+            crc32: self.crc32_at(pos),
+            offset: self.offset_at(pos),
+        }
+    }
+
+    /// Sorted position of `hash`, or `None` if the object is not in the pack.
+    pub fn find(&self, hash: &ObjectHash) -> Option<usize> {
+        if hash.kind() != self.kind {
+            return None;
+        }
+        let target = hash.as_ref();
+        let (mut lo, mut hi) = self.fanout_bucket(target[0]);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.name_bytes(mid).cmp(target) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    /// Whether `hash` is present in the pack.
+    pub fn contains(&self, hash: &ObjectHash) -> bool {
+        self.find(hash).is_some()
+    }
+
+    /// Pack offset of `hash`, if present.
+    pub fn offset_of(&self, hash: &ObjectHash) -> Option<u64> {
+        self.find(hash).map(|pos| self.offset_at(pos))
+    }
+
+    /// CRC32 of the packed entry for `hash`, if present.
+    pub fn crc32_of(&self, hash: &ObjectHash) -> Option<u32> {
+        self.find(hash).map(|pos| self.crc32_at(pos))
+    }
+
+    /// Iterate over all entries in sorted hash order.
+    pub fn iter(&self) -> impl Iterator<Item = IndexEntry> + '_ {
+        (0..self.object_count).map(|pos| self.entry_at(pos))
+    }
+
+    /// Sorted positions of every object whose hex name starts with `hex_prefix`.
+    ///
+    /// Matches are contiguous in the sorted name table, so the result is a range; an empty range
+    /// means no object matches. Odd-length prefixes are supported.
+    pub fn lookup_prefix(&self, hex_prefix: &str) -> Result<Range<usize>, GitError> {
+        let prefix = HexPrefix::parse(hex_prefix, self.kind)?;
+        let lo = self.partition_point(|name| prefix.cmp_name(name) == Ordering::Less);
+        let hi = self.partition_point(|name| prefix.cmp_name(name) != Ordering::Greater);
+        Ok(lo..hi)
+    }
+
+    fn partition_point(&self, pred: impl Fn(&[u8]) -> bool) -> usize {
+        let (mut lo, mut hi) = (0, self.object_count);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if pred(self.name_bytes(mid)) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    }
+
+    fn fanout_bucket(&self, first_byte: u8) -> (usize, usize) {
+        let end = read_u32(&self.data, IDX_HEADER_SIZE + first_byte as usize * 4) as usize;
+        let start = if first_byte == 0 {
+            0
+        } else {
+            read_u32(&self.data, IDX_HEADER_SIZE + (first_byte as usize - 1) * 4) as usize
+        };
+        (start, end)
+    }
+
+    fn name_bytes(&self, pos: usize) -> &[u8] {
+        let size = self.kind.size();
+        let start = self.names_start + pos * size;
+        &self.data[start..start + size]
+    }
+
+    fn raw_offset(&self, pos: usize) -> u32 {
+        read_u32(&self.data, self.offsets_start + pos * 4)
+    }
+
+    fn hash_from(&self, start: usize) -> ObjectHash {
+        ObjectHash::from_bytes_with_kind(&self.data[start..start + self.kind.size()], self.kind)
+            .expect("slice length matches hash kind")
+    }
+}
+
+/// A hex object-name prefix split into whole bytes plus an optional trailing high nibble.
+pub(crate) struct HexPrefix {
+    bytes: Vec<u8>,
+    nibble: Option<u8>,
+}
+
+impl HexPrefix {
+    /// Parse a (possibly odd-length) hex prefix no longer than a full name of `kind`.
+    pub(crate) fn parse(hex_prefix: &str, kind: HashKind) -> Result<Self, GitError> {
+        if hex_prefix.is_empty() || hex_prefix.len() > kind.hex_len() {
+            return Err(GitError::InvalidHashValue(hex_prefix.to_string()));
+        }
+        let even = hex_prefix.len() & !1;
+        let bytes = hex::decode(&hex_prefix[..even])
+            .map_err(|_| GitError::InvalidHashValue(hex_prefix.to_string()))?;
+        let nibble = match hex_prefix[even..].chars().next() {
+            Some(c) => Some(
+                c.to_digit(16)
+                    .ok_or_else(|| GitError::InvalidHashValue(hex_prefix.to_string()))?
+                    as u8,
+            ),
+            None => None,
+        };
+        Ok(HexPrefix { bytes, nibble })
+    }
+
+    /// Order of `name` relative to the set of names matching this prefix.
+    pub(crate) fn cmp_name(&self, name: &[u8]) -> Ordering {
+        let head = &name[..self.bytes.len()];
+        match head.cmp(&self.bytes) {
+            Ordering::Equal => match self.nibble {
+                Some(nibble) => (name[self.bytes.len()] >> 4).cmp(&nibble),
+                None => Ordering::Equal,
+            },
+            other => other,
+        }
+    }
+}
+
+pub(crate) fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+pub(crate) fn read_u64(data: &[u8], at: usize) -> u64 {
+    u64::from_be_bytes(data[at..at + 8].try_into().unwrap())
+}
+
 #[cfg(test)]
 mod tests {
     use tokio::sync::mpsc;
 
+    use super::PackIndex;
     use crate::{
         errors::GitError,
-        hash::ObjectHash,
+        hash::{HashKind, ObjectHash, set_hash_kind_for_test},
         internal::pack::{index_entry::IndexEntry, pack_index::IdxBuilder},
     };
 
@@ -307,4 +642,139 @@ mod tests {
 
         Ok(())
     }
+
+    /// Run entries through `IdxBuilder` and collect the produced idx bytes.
+    async fn build_idx(entries: Vec<IndexEntry>, pack_hash: ObjectHash) -> Vec<u8> {
+        let (tx, mut rx) = mpsc::channel::<Vec<u8>>(4096);
+        let mut builder = IdxBuilder::new(entries.len(), tx, pack_hash);
+        builder.write_idx(entries).await.unwrap();
+        let mut out = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            out.extend_from_slice(&chunk);
+        }
+        out
+    }
+
+    /// Entries with distinct, unsorted hashes spread across fanout buckets.
+    fn scattered_entries(kind: HashKind, n: usize) -> Vec<IndexEntry> {
+        (0..n)
+            .map(|i| {
+                let mut bytes = vec![0u8; kind.size()];
+                bytes[0] = (i * 37 % 256) as u8;
+                bytes[1] = (i / 7) as u8;
+                bytes[kind.size() - 1] = i as u8;
+                IndexEntry {
+                    hash: ObjectHash::from_bytes(&bytes).unwrap(),
+                    crc32: 0xC0DE_0000 + i as u32,
+                    offset: 12 + (i as u64) * 100,
+                }
+            })
+            .collect()
+    }
+
+    /// Reader finds every entry written by the builder and iterates in sorted order.
+    #[tokio::test]
+    async fn test_pack_index_lookup_roundtrip() {
+        for kind in [HashKind::Sha1, HashKind::Sha256] {
+            let _guard = set_hash_kind_for_test(kind);
+            let entries = scattered_entries(kind, 300);
+            let pack_hash = ObjectHash::from_bytes(&vec![0xAB; kind.size()]).unwrap();
+            let index = PackIndex::from_bytes(build_idx(entries.clone(), pack_hash).await).unwrap();
+
+            assert_eq!(index.hash_kind(), kind);
+            assert_eq!(index.object_count(), entries.len());
+            assert_eq!(index.pack_hash(), pack_hash);
+            for e in &entries {
+                assert_eq!(index.offset_of(&e.hash), Some(e.offset));
+                assert_eq!(index.crc32_of(&e.hash), Some(e.crc32));
+            }
+            let hashes: Vec<ObjectHash> = index.iter().map(|e| e.hash).collect();
+            let mut sorted: Vec<ObjectHash> = entries.iter().map(|e| e.hash).collect();
+            sorted.sort();
+            assert_eq!(hashes, sorted);
+
+            let missing = ObjectHash::from_bytes(&vec![0xFF; kind.size()]).unwrap();
+            assert!(!index.contains(&missing));
+        }
+    }
+
+    /// Offsets above 31 bits go through the large offset table.
+    #[tokio::test]
+    async fn test_pack_index_large_offsets() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let entries = vec![
+            IndexEntry {
+                hash: fake_sha1(1),
+                crc32: 1,
+                offset: 12,
+            },
+            IndexEntry {
+                hash: fake_sha1(2),
+                crc32: 2,
+                offset: 0x8000_0000,
+            },
+            IndexEntry {
+                hash: fake_sha1(3),
+                crc32: 3,
+                offset: 0x1_2345_6789,
+            },
+        ];
+        let index =
+            PackIndex::from_bytes(build_idx(entries.clone(), fake_sha1(0xAA)).await).unwrap();
+        for e in &entries {
+            assert_eq!(index.offset_of(&e.hash), Some(e.offset));
+        }
+    }
+
+    /// Prefix lookups return contiguous ranges and honour odd-length prefixes.
+    #[tokio::test]
+    async fn test_pack_index_lookup_prefix() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let mk = |head: [u8; 2]| {
+            let mut bytes = [0u8; 20];
+            bytes[..2].copy_from_slice(&head);
+            ObjectHash::Sha1(bytes)
+        };
+        let entries: Vec<IndexEntry> = [[0xab, 0x10], [0xab, 0x1f], [0xab, 0x20], [0xac, 0x00]]
+            .into_iter()
+            .enumerate()
+            .map(|(i, head)| IndexEntry {
+                hash: mk(head),
+                crc32: 0,
+                offset: 12 + i as u64,
+            })
+            .collect();
+        let index = PackIndex::from_bytes(build_idx(entries, fake_sha1(0)).await).unwrap();
+
+        assert_eq!(index.lookup_prefix("ab").unwrap().len(), 3);
+        assert_eq!(index.lookup_prefix("ab1").unwrap().len(), 2);
+        assert_eq!(index.lookup_prefix("AB20").unwrap().len(), 1);
+        assert!(index.lookup_prefix("ad").unwrap().is_empty());
+        assert!(index.lookup_prefix("zz").is_err());
+        assert!(index.lookup_prefix("").is_err());
+    }
+
+    /// Corrupted or truncated indexes are rejected instead of producing bogus lookups.
+    #[tokio::test]
+    async fn test_pack_index_rejects_corruption() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let bytes = build_idx(build_entries_sha1(3), fake_sha1(0xAA)).await;
+
+        let mut flipped = bytes.clone();
+        flipped[8 + 256 * 4] ^= 0xFF;
+        assert!(matches!(
+            PackIndex::from_bytes(flipped),
+            Err(GitError::InvalidIdxFile(_))
+        ));
+
+        let truncated = bytes[..bytes.len() - 1].to_vec();
+        assert!(PackIndex::from_bytes(truncated).is_err());
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = 0;
+        assert!(PackIndex::from_bytes(bad_magic).is_err());
+
+        // Opening with the wrong hash kind fails the size/checksum checks.
+        assert!(PackIndex::from_bytes_with_kind(bytes, HashKind::Sha256).is_err());
+    }
 }
@@ -0,0 +1,239 @@
+//! Progress reporting for long pack decodes and encodes.
+//!
+//! [`Pack`](super::Pack) and [`PackEncoder`](super::encode::PackEncoder) accept a
+//! [`ProgressObserver`] that is called as each phase advances, using the same phases Git prints
+//! ("Counting objects", "Compressing objects", "Writing objects", "Receiving objects" and
+//! "Resolving deltas"). [`Progress::to_git_line`] renders an update the way Git does on stderr,
+//! which is what the smart protocol forwards on side-band channel 2.
+
+use std::{fmt, sync::Arc};
+
+/// A stage of decoding or encoding a pack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProgressPhase {
+    /// Encoder: entries received from the input channel.
+    CountingObjects,
+    /// Encoder: entries that went through delta search.
+    CompressingObjects,
+    /// Encoder: entries written to the pack stream.
+    WritingObjects,
+    /// Decoder: entries parsed from the pack stream.
+    ReceivingObjects,
+    /// Decoder: deltas rebuilt into full objects.
+    ResolvingDeltas,
+}
+
+impl fmt::Display for ProgressPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let title = match self {
+            ProgressPhase::CountingObjects => "Counting objects",
+            ProgressPhase::CompressingObjects => "Compressing objects",
+            ProgressPhase::WritingObjects => "Writing objects",
+            ProgressPhase::ReceivingObjects => "Receiving objects",
+            ProgressPhase::ResolvingDeltas => "Resolving deltas",
+        };
+        f.write_str(title)
+    }
+}
+
+/// One progress update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub phase: ProgressPhase,
+    /// Objects (or deltas, for [`ProgressPhase::ResolvingDeltas`]) handled so far in this phase.
+    pub done: usize,
+    /// Expected value of `done` when the phase ends, if known.
+    pub total: Option<usize>,
+    /// Pack bytes read or written so far; zero for phases that do not touch the pack stream.
+    pub bytes: u64,
+    /// Deltas rebuilt so far while decoding; always zero while encoding.
+    pub deltas_resolved: usize,
+    /// Set on the last update of a phase.
+    pub finished: bool,
+}
+
+impl Progress {
+    /// Render the update as Git prints it, e.g. `"Receiving objects:  50% (5/10), 1.20 KiB\r"`,
+    /// ending in `", done.\n"` once the phase has finished.
+    pub fn to_git_line(&self) -> String {
+        let mut line = format!("{}: ", self.phase);
+        match self.total {
+            Some(total) if total > 0 => {
+                let percent = self.done.min(total) * 100 / total;
+                line.push_str(&format!("{percent:3}% ({}/{total})", self.done));
+            }
+            _ => line.push_str(&self.done.to_string()),
+        }
+        if self.bytes > 0 {
+            line.push_str(", ");
+            line.push_str(&humanize_bytes(self.bytes));
+        }
+        if self.finished {
+            line.push_str(", done.\n");
+        } else {
+            line.push('\r');
+        }
+        line
+    }
+}
+
+/// Receives [`Progress`] updates. Implemented for any `Fn(&Progress) + Send + Sync`.
+///
+/// Updates may arrive from worker threads and are throttled to roughly one per percent, but
+/// observers should still return quickly because they run on the decode or encode path.
+pub trait ProgressObserver: Send + Sync {
+    fn on_progress(&self, progress: &Progress);
+}
+
+impl<F> ProgressObserver for F
+where
+    F: Fn(&Progress) + Send + Sync,
+{
+    fn on_progress(&self, progress: &Progress) {
+        self(progress)
+    }
+}
+
+/// Tracks one phase and forwards updates to an optional observer when they are worth reporting.
+pub(crate) struct ProgressReporter {
+    observer: Option<Arc<dyn ProgressObserver>>,
+    phase: ProgressPhase,
+    total: Option<usize>,
+    last_reported: Option<usize>,
+}
+
+/// Without a total, report every this many objects.
+const UNKNOWN_TOTAL_STEP: usize = 1024;
+
+impl ProgressReporter {
+    pub(crate) fn new(
+        observer: Option<Arc<dyn ProgressObserver>>,
+        phase: ProgressPhase,
+        total: Option<usize>,
+    ) -> Self {
+        ProgressReporter {
+            observer,
+            phase,
+            total,
+            last_reported: None,
+        }
+    }
+
+    /// Report `done` if it moved the percentage (or, without a total, crossed a step).
+    pub(crate) fn update(&mut self, done: usize, bytes: u64, deltas_resolved: usize) {
+        if self.observer.is_none() {
+            return;
+        }
+        let bucket = |n: usize| match self.total {
+            Some(total) if total > 0 => n.min(total) * 100 / total,
+            _ => n / UNKNOWN_TOTAL_STEP,
+        };
+        if self
+            .last_reported
+            .is_some_and(|last| bucket(last) == bucket(done))
+        {
+            return;
+        }
+        self.last_reported = Some(done);
+        self.emit(done, bytes, deltas_resolved, false);
+    }
+
+    /// Report the final state of the phase.
+    pub(crate) fn finish(&self, done: usize, bytes: u64, deltas_resolved: usize) {
+        self.emit(done, bytes, deltas_resolved, true);
+    }
+
+    fn emit(&self, done: usize, bytes: u64, deltas_resolved: usize, finished: bool) {
+        if let Some(observer) = &self.observer {
+            observer.on_progress(&Progress {
+                phase: self.phase,
+                done,
+                total: self.total,
+                bytes,
+                deltas_resolved,
+                finished,
+            });
+        }
+    }
+}
+
+/// Format a byte count the way Git's progress meter does.
+fn humanize_bytes(bytes: u64) -> String {
+    const KIB: u64 = 1 << 10;
+    const MIB: u64 = 1 << 20;
+    const GIB: u64 = 1 << 30;
+    let scaled = |unit: u64| {
+        let x = bytes * 100 / unit;
+        format!("{}.{:02}", x / 100, x % 100)
+    };
+    if bytes >= GIB {
+        format!("{} GiB", scaled(GIB))
+    } else if bytes >= MIB {
+        format!("{} MiB", scaled(MIB))
+    } else if bytes >= KIB {
+        format!("{} KiB", scaled(KIB))
+    } else if bytes == 1 {
+        "1 byte".to_string()
+    } else {
+        format!("{bytes} bytes")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[test]
+    fn test_git_line_format() {
+        let mut progress = Progress {
+            phase: ProgressPhase::ReceivingObjects,
+            done: 5,
+            total: Some(10),
+            bytes: 1229,
+            deltas_resolved: 0,
+            finished: false,
+        };
+        assert_eq!(
+            progress.to_git_line(),
+            "Receiving objects:  50% (5/10), 1.20 KiB\r"
+        );
+        progress.done = 10;
+        progress.finished = true;
+        assert_eq!(
+            progress.to_git_line(),
+            "Receiving objects: 100% (10/10), 1.20 KiB, done.\n"
+        );
+
+        let counting = Progress {
+            phase: ProgressPhase::CountingObjects,
+            done: 3,
+            total: None,
+            bytes: 0,
+            deltas_resolved: 0,
+            finished: true,
+        };
+        assert_eq!(counting.to_git_line(), "Counting objects: 3, done.\n");
+    }
+
+    #[test]
+    fn test_reporter_throttles_to_percent_steps() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let observer: Arc<dyn ProgressObserver> =
+            Arc::new(move |p: &Progress| sink.lock().unwrap().push((p.done, p.finished)));
+        let mut reporter =
+            ProgressReporter::new(Some(observer), ProgressPhase::WritingObjects, Some(1000));
+        for done in 1..=1000 {
+            reporter.update(done, 0, 0);
+        }
+        reporter.finish(1000, 0, 0);
+
+        let seen = seen.lock().unwrap();
+        // 1 (0%), then the first object of each percent from 1% to 100%, then the final update.
+        assert_eq!(seen.len(), 102);
+        assert_eq!(seen[1], (10, false));
+        assert_eq!(seen.last(), Some(&(1000, true)));
+    }
+}
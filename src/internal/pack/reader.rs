@@ -0,0 +1,574 @@
+//! Random-access reader that serves single objects out of an indexed pack (`.pack` + `.idx`),
+//! resolving offset, hash, and zstd delta chains on demand with a bounded LRU of rebuilt bases.
+//!
+//! Both files are memory-mapped, so entry headers and compressed payloads are sliced straight out
+//! of the page cache instead of being copied through buffered readers.
+
+use std::{
+    io::{self, Cursor, Read},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use lru_mem::{HeapSize, LruCache};
+
+use crate::{
+    delta,
+    errors::GitError,
+    hash::{HashKind, ObjectHash},
+    internal::{
+        object::types::ObjectType,
+        pack::{Pack, entry::Entry, mmap::MappedFile, pack_index::PackIndex, utils},
+        zlib::stream::inflate::ReadBoxed,
+    },
+    utils::HashAlgorithm,
+    zstdelta,
+};
+
+/// Default memory budget for rebuilt delta bases kept by a [`PackReader`].
+pub const DEFAULT_BASE_CACHE_SIZE: usize = 64 * 1024 * 1024;
+const PACK_HEADER_SIZE: u64 = 12;
+/// Initial buffer for an inflated entry; larger entries grow it as data arrives.
+const INFLATE_INITIAL_CAPACITY: usize = 64 * 1024;
+/// Enough for the type/size varint plus an offset encoding or a SHA-256 base name.
+const ENTRY_HEADER_READ_SIZE: u64 = 96;
+
+/// How a pack entry stores its content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EntryKind {
+    Base(ObjectType),
+    OffsetDelta(u64),
+    OffsetZstdelta(u64),
+    HashDelta(ObjectHash),
+}
+
+/// Parsed entry header: representation, declared (inflated) size, and where the zlib stream starts.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct EntryHeader {
+    pub(crate) kind: EntryKind,
+    pub(crate) size: usize,
+    pub(crate) data_offset: u64,
+}
+
+/// Entry-level access to a mapped pack file: header parsing and payload inflation.
+///
+/// Shared by [`PackReader`] and the decode cache, which re-reads evicted bases from the mapping
+/// instead of spilling them to disk.
+pub(crate) struct MappedPack {
+    data: MappedFile,
+    kind: HashKind,
+}
+
+impl MappedPack {
+    pub(crate) fn new(data: MappedFile, kind: HashKind) -> Self {
+        MappedPack { data, kind }
+    }
+
+    /// Raw bytes of the whole pack, including header and trailer.
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Give the pack bytes back, see [`MappedFile::into_vec`].
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.data.into_vec()
+    }
+
+    /// End of the entry area (start of the trailing checksum).
+    pub(crate) fn body_end(&self) -> u64 {
+        (self.data.len() as u64).saturating_sub(self.kind.size() as u64)
+    }
+
+    /// Parse the entry header at `offset` without inflating the payload.
+    pub(crate) fn read_entry_header(&self, offset: u64) -> Result<EntryHeader, GitError> {
+        let body_end = self.body_end();
+        if offset < PACK_HEADER_SIZE || offset >= body_end {
+            return Err(GitError::InvalidPackFile(format!(
+                "object offset {offset} is outside the pack body"
+            )));
+        }
+        let end = (offset + ENTRY_HEADER_READ_SIZE).min(body_end);
+        let mut cursor = Cursor::new(&self.data[offset as usize..end as usize]);
+
+        let mut header_len = 0;
+        let (type_bits, size) = utils::read_type_and_varint_size(&mut cursor, &mut header_len)
+            .map_err(|e| {
+                GitError::InvalidPackFile(format!("bad entry header at offset {offset}: {e}"))
+            })?;
+        let kind = match ObjectType::from_pack_type_u8(type_bits)? {
+            delta_type @ (ObjectType::OffsetDelta | ObjectType::OffsetZstdelta) => {
+                let (distance, _) = utils::read_offset_encoding(&mut cursor).map_err(|e| {
+                    GitError::InvalidPackFile(format!("bad delta offset at offset {offset}: {e}"))
+                })?;
+                let base = offset
+                    .checked_sub(distance)
+                    .filter(|b| *b >= PACK_HEADER_SIZE);
+                let base = base.ok_or_else(|| {
+                    GitError::InvalidPackFile(format!(
+                        "delta at offset {offset} points before the pack start"
+                    ))
+                })?;
+                if delta_type == ObjectType::OffsetDelta {
+                    EntryKind::OffsetDelta(base)
+                } else {
+                    EntryKind::OffsetZstdelta(base)
+                }
+            }
+            ObjectType::HashDelta => {
+                let mut name = vec![0u8; self.kind.size()];
+                cursor.read_exact(&mut name).map_err(|e| {
+                    GitError::InvalidPackFile(format!("bad delta base at offset {offset}: {e}"))
+                })?;
+                let base = ObjectHash::from_bytes_with_kind(&name, self.kind)
+                    .map_err(GitError::InvalidHashValue)?;
+                EntryKind::HashDelta(base)
+            }
+            base_type => EntryKind::Base(base_type),
+        };
+        Ok(EntryHeader {
+            kind,
+            size,
+            data_offset: offset + cursor.position(),
+        })
+    }
+
+    /// Inflate the zlib payload described by `header`, returning the data and the number of
+    /// compressed bytes consumed.
+    ///
+    /// The declared size comes from the pack, so the buffer grows with the data actually inflated
+    /// instead of being allocated up front.
+    pub(crate) fn inflate(&self, header: &EntryHeader) -> Result<(Vec<u8>, usize), GitError> {
+        let input = &self.data[header.data_offset as usize..self.body_end() as usize];
+        let mut inflater = ReadBoxed::new_for_delta(input);
+        let mut data = Vec::with_capacity(header.size.min(INFLATE_INITIAL_CAPACITY));
+        let read = (&mut inflater)
+            .take(header.size as u64)
+            .read_to_end(&mut data)
+            .map_err(|e| {
+                GitError::InvalidPackFile(format!(
+                    "failed to inflate entry data at offset {}: {e}",
+                    header.data_offset
+                ))
+            })?;
+        if read != header.size {
+            return Err(GitError::InvalidPackFile(format!(
+                "entry data at offset {} inflates to {read} bytes instead of the declared {}",
+                header.data_offset, header.size
+            )));
+        }
+        Ok((data, inflater.decompressor.total_in() as usize))
+    }
+
+    /// Check that the entry's data inflates to its declared size without keeping it, and
+    /// return how many compressed bytes it spans.
+    pub(crate) fn skip(&self, header: &EntryHeader) -> Result<usize, GitError> {
+        let input = &self.data[header.data_offset as usize..self.body_end() as usize];
+        let mut inflater = ReadBoxed::new_for_delta(input);
+        let read = io::copy(
+            &mut (&mut inflater).take(header.size as u64),
+            &mut io::sink(),
+        )
+        .map_err(|e| {
+            GitError::InvalidPackFile(format!(
+                "failed to inflate entry data at offset {}: {e}",
+                header.data_offset
+            ))
+        })?;
+        if read != header.size as u64 {
+            return Err(GitError::InvalidPackFile(format!(
+                "entry data at offset {} inflates to {read} bytes instead of the declared {}",
+                header.data_offset, header.size
+            )));
+        }
+        Ok(inflater.decompressor.total_in() as usize)
+    }
+}
+
+/// Apply the delta payload of an entry of `kind` to `base`.
+pub(crate) fn apply_delta(kind: EntryKind, base: &[u8], delta: &[u8]) -> Result<Vec<u8>, GitError> {
+    match kind {
+        EntryKind::OffsetZstdelta(_) => {
+            zstdelta::apply(base, delta).map_err(|e| GitError::DeltaObjectError(e.to_string()))
+        }
+        EntryKind::OffsetDelta(_) | EntryKind::HashDelta(_) => {
+            delta::delta_decode(&mut &delta[..], base)
+                .map_err(|e| GitError::DeltaObjectError(e.to_string()))
+        }
+        EntryKind::Base(_) => Err(GitError::DeltaObjectError(
+            "base objects carry no delta".to_string(),
+        )),
+    }
+}
+
+/// A fully rebuilt object kept around so later reads of the same chain can start from it.
+struct CachedBase {
+    obj_type: ObjectType,
+    data: Arc<Vec<u8>>,
+    depth: usize,
+}
+
+impl HeapSize for CachedBase {
+    fn heap_size(&self) -> usize {
+        self.data.len()
+    }
+}
+
+/// Random-access reader over a pack and its `.idx`.
+///
+/// Unlike [`Pack::decode`], which streams the whole pack through a callback, `PackReader` looks an
+/// object up in the index, inflates just that entry from the mapped pack, and walks
+/// OffsetDelta / HashDelta / OffsetZstdelta chains back to a base object. Rebuilt objects are kept
+/// in a memory-bounded LRU keyed by pack offset, so reading neighbouring revisions of the same file
+/// only has to apply the last few deltas.
+///
+/// The reader only needs `&self`, so it can be shared between threads (e.g. behind an `Arc`).
+pub struct PackReader {
+    pack: MappedPack,
+    index: PackIndex,
+    base_cache: Mutex<LruCache<u64, CachedBase>>,
+}
+
+impl PackReader {
+    /// Open `pack_path` together with the `.idx` file next to it.
+    pub fn open(pack_path: impl AsRef<Path>) -> Result<Self, GitError> {
+        let pack_path = pack_path.as_ref();
+        let index = PackIndex::open(pack_path.with_extension("idx"))?;
+        Self::with_index(pack_path, index)
+    }
+
+    /// Open `pack_path` using an already loaded index.
+    ///
+    /// The pack header and trailer are checked against the index (object count and pack
+    /// checksum); the pack body is not hashed here.
+    pub fn with_index(pack_path: impl AsRef<Path>, index: PackIndex) -> Result<Self, GitError> {
+        let pack_path = pack_path.as_ref();
+        let data = MappedFile::open(pack_path).map_err(|e| {
+            GitError::InvalidPackFile(format!("failed to open {}: {e}", pack_path.display()))
+        })?;
+        Self::from_parts(data, index)
+    }
+
+    /// Build a reader over pack bytes that are already in memory.
+    pub fn from_bytes(pack: Vec<u8>, index: PackIndex) -> Result<Self, GitError> {
+        Self::from_parts(pack.into(), index)
+    }
+
+    fn from_parts(data: MappedFile, index: PackIndex) -> Result<Self, GitError> {
+        let hash_size = index.hash_kind().size();
+        if data.len() < PACK_HEADER_SIZE as usize + hash_size {
+            return Err(GitError::InvalidPackFile(format!(
+                "pack is {} bytes, too short for header and trailer",
+                data.len()
+            )));
+        }
+        let (object_count, _) = Pack::check_header(&mut &data[..PACK_HEADER_SIZE as usize])?;
+        if object_count as usize != index.object_count() {
+            return Err(GitError::InvalidPackFile(format!(
+                "pack declares {object_count} objects but index has {}",
+                index.object_count()
+            )));
+        }
+        if data[data.len() - hash_size..] != *index.pack_hash().as_ref() {
+            return Err(GitError::InvalidPackFile(
+                "pack checksum does not match its index".to_string(),
+            ));
+        }
+
+        Ok(Self::from_parts_unchecked(data, index))
+    }
+
+    /// Build a reader without checking the pack against the index, for callers such as pack
+    /// verification that report those mismatches themselves.
+    pub(crate) fn from_parts_unchecked(data: MappedFile, index: PackIndex) -> Self {
+        PackReader {
+            pack: MappedPack::new(data, index.hash_kind()),
+            index,
+            base_cache: Mutex::new(LruCache::new(DEFAULT_BASE_CACHE_SIZE)),
+        }
+    }
+
+    /// The mapped pack entries are read from.
+    pub(crate) fn mapped_pack(&self) -> &MappedPack {
+        &self.pack
+    }
+
+    /// Set the memory budget (in bytes) for cached delta bases; `0` disables caching.
+    pub fn with_cache_size(self, bytes: usize) -> Self {
+        self.base_cache.lock().unwrap().set_max_size(bytes);
+        self
+    }
+
+    /// The index this reader resolves hashes with.
+    pub fn index(&self) -> &PackIndex {
+        &self.index
+    }
+
+    /// Hash kind of the pack.
+    pub fn hash_kind(&self) -> HashKind {
+        self.index.hash_kind()
+    }
+
+    /// Whether the pack contains `hash`.
+    pub fn contains(&self, hash: &ObjectHash) -> bool {
+        self.index.contains(hash)
+    }
+
+    /// Read and fully resolve the object named `hash`.
+    ///
+    /// The rebuilt content is hashed and compared against `hash`, so corruption surfaces as an
+    /// error instead of wrong data.
+    pub fn read_object(&self, hash: &ObjectHash) -> Result<Entry, GitError> {
+        let offset = self
+            .index
+            .offset_of(hash)
+            .ok_or_else(|| GitError::ObjectNotFound(hash.to_string()))?;
+        let entry = self.read_object_at(offset)?;
+        if entry.hash != *hash {
+            return Err(GitError::InvalidPackFile(format!(
+                "object at offset {offset} hashes to {} instead of {hash}",
+                entry.hash
+            )));
+        }
+        Ok(entry)
+    }
+
+    /// Type and size of the object named `hash`, without rebuilding it.
+    ///
+    /// The size comes from the entry header, or for a delta from the result size that starts
+    /// its payload; the type comes from the base at the end of the chain. Zstd deltas do not
+    /// record their result size up front and are resolved in full.
+    pub fn read_header(&self, hash: &ObjectHash) -> Result<(ObjectType, usize), GitError> {
+        let offset = self
+            .index
+            .offset_of(hash)
+            .ok_or_else(|| GitError::ObjectNotFound(hash.to_string()))?;
+        if let Some(cached) = self.base_cache.lock().unwrap().get(&offset) {
+            return Ok((cached.obj_type, cached.data.len()));
+        }
+        let header = self.pack.read_entry_header(offset)?;
+        let size = match header.kind {
+            EntryKind::Base(obj_type) => return Ok((obj_type, header.size)),
+            EntryKind::OffsetZstdelta(_) => {
+                let entry = self.read_object_at(offset)?;
+                return Ok((entry.obj_type, entry.data.len()));
+            }
+            EntryKind::OffsetDelta(_) | EntryKind::HashDelta(_) => {
+                let (delta, _) = self.pack.inflate(&header)?;
+                utils::read_delta_object_size(&mut &delta[..])?.1
+            }
+        };
+
+        let mut kind = header.kind;
+        for _ in 0..=self.index.object_count() {
+            let current = match kind {
+                EntryKind::Base(obj_type) => return Ok((obj_type, size)),
+                EntryKind::OffsetDelta(base) | EntryKind::OffsetZstdelta(base) => base,
+                EntryKind::HashDelta(base_hash) => {
+                    self.index.offset_of(&base_hash).ok_or_else(|| {
+                        GitError::ObjectNotFound(format!(
+                            "base {base_hash} of hash delta for {hash} is not in the pack"
+                        ))
+                    })?
+                }
+            };
+            if let Some(cached) = self.base_cache.lock().unwrap().get(&current) {
+                return Ok((cached.obj_type, size));
+            }
+            kind = self.pack.read_entry_header(current)?.kind;
+        }
+        Err(GitError::DeltaObjectError(format!(
+            "delta chain of {hash} does not terminate"
+        )))
+    }
+
+    /// Read and fully resolve the object stored at pack `offset`.
+    pub fn read_object_at(&self, offset: u64) -> Result<Entry, GitError> {
+        let (obj_type, data, chain_len) = self.resolve(offset)?;
+        let hash = self.object_hash(obj_type, &data);
+        let data = Arc::try_unwrap(data).unwrap_or_else(|shared| (*shared).clone());
+        Ok(Entry {
+            obj_type,
+            data,
+            hash,
+            chain_len,
+        })
+    }
+
+    /// Walk the delta chain starting at `offset` down to a base (or a cached object), then apply
+    /// the deltas back up. Returns the final type, content, and delta depth of the object.
+    fn resolve(&self, offset: u64) -> Result<(ObjectType, Arc<Vec<u8>>, usize), GitError> {
+        let mut chain: Vec<(u64, EntryKind, Vec<u8>)> = Vec::new();
+        let mut current = offset;
+        let (obj_type, mut data, base_depth) = loop {
+            if let Some(cached) = self.base_cache.lock().unwrap().get(&current) {
+                break (cached.obj_type, cached.data.clone(), cached.depth);
+            }
+            // Offset deltas always point backwards, but a corrupt pack could make hash deltas
+            // loop; no valid chain can be longer than the pack itself.
+            if chain.len() > self.index.object_count() {
+                return Err(GitError::DeltaObjectError(format!(
+                    "delta chain starting at offset {offset} does not terminate"
+                )));
+            }
+
+            let header = self.pack.read_entry_header(current)?;
+            let (payload, _) = self.pack.inflate(&header)?;
+            match header.kind {
+                EntryKind::Base(obj_type) => break (obj_type, Arc::new(payload), 0),
+                EntryKind::OffsetDelta(base) | EntryKind::OffsetZstdelta(base) => {
+                    chain.push((current, header.kind, payload));
+                    current = base;
+                }
+                EntryKind::HashDelta(base_hash) => {
+                    chain.push((current, header.kind, payload));
+                    current = self.index.offset_of(&base_hash).ok_or_else(|| {
+                        GitError::ObjectNotFound(format!(
+                            "base {base_hash} of hash delta at offset {current} is not in the pack"
+                        ))
+                    })?;
+                }
+            }
+        };
+
+        let mut depth = base_depth;
+        if chain.is_empty() {
+            self.cache_base(offset, obj_type, &data, depth);
+        }
+        for (entry_offset, kind, delta_data) in chain.into_iter().rev() {
+            data = Arc::new(apply_delta(kind, &data, &delta_data)?);
+            depth += 1;
+            self.cache_base(entry_offset, obj_type, &data, depth);
+        }
+        Ok((obj_type, data, depth))
+    }
+
+    fn cache_base(&self, offset: u64, obj_type: ObjectType, data: &Arc<Vec<u8>>, depth: usize) {
+        let mut cache = self.base_cache.lock().unwrap();
+        // Objects larger than the whole budget are simply not cached.
+        let _ = cache.insert(
+            offset,
+            CachedBase {
+                obj_type,
+                data: data.clone(),
+                depth,
+            },
+        );
+    }
+
+    fn object_hash(&self, obj_type: ObjectType, data: &[u8]) -> ObjectHash {
+        let mut hasher = HashAlgorithm::new_with_kind(self.index.hash_kind());
+        hasher.update(obj_type.to_bytes().unwrap_or_default());
+        hasher.update(b" ");
+        hasher.update(data.len().to_string().as_bytes());
+        hasher.update(b"\0");
+        hasher.update(data);
+        ObjectHash::from_bytes_with_kind(&hasher.finalize(), self.index.hash_kind())
+            .expect("hash output matches its kind")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::PackReader;
+    use crate::{
+        errors::GitError,
+        hash::{HashKind, ObjectHash, set_hash_kind_for_test},
+        internal::{object::types::ObjectType, pack::test_pack_builder::TestPackBuilder},
+    };
+
+    fn revision(n: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        for line in 0..200 {
+            data.extend_from_slice(
+                format!("line {line} of revision {}\n", line % (n + 3)).as_bytes(),
+            );
+        }
+        data
+    }
+
+    /// Every delta kind resolves back to the original content, including chains and caches, and
+    /// headers read without resolving agree with it.
+    #[tokio::test]
+    async fn test_read_object_resolves_delta_chains() {
+        for kind in [HashKind::Sha1, HashKind::Sha256] {
+            let _guard = set_hash_kind_for_test(kind);
+            let dir = tempdir().unwrap();
+            let (r0, r1, r2, r3) = (revision(0), revision(1), revision(2), revision(3));
+
+            let mut builder = TestPackBuilder::new();
+            let e0 = builder.add_base(ObjectType::Blob, &r0);
+            let e1 = builder.add_ofs_delta(e0, &r0, ObjectType::Blob, &r1);
+            let e2 = builder.add_ref_delta(e1.hash, &r1, ObjectType::Blob, &r2);
+            let e3 = builder.add_zstd_delta(e2, &r2, ObjectType::Blob, &r3);
+            let commit = builder.add_base(ObjectType::Commit, b"tree 0\n\nmsg\n");
+            let pack_path = builder.write_to(dir.path(), "chain").await;
+
+            let reader = PackReader::open(&pack_path).unwrap();
+            // Read the deepest object first (cold cache), then again (warm cache).
+            for _ in 0..2 {
+                let entry = reader.read_object(&e3.hash).unwrap();
+                assert_eq!(entry.data, r3);
+                assert_eq!(entry.chain_len, 3);
+            }
+            for (built, data) in [(e0, &r0), (e1, &r1), (e2, &r2)] {
+                let entry = reader.read_object(&built.hash).unwrap();
+                assert_eq!(entry.obj_type, ObjectType::Blob);
+                assert_eq!(&entry.data, data);
+            }
+            let entry = reader.read_object_at(commit.offset).unwrap();
+            assert_eq!(entry.obj_type, ObjectType::Commit);
+            assert_eq!(entry.hash, commit.hash);
+
+            let uncached = PackReader::open(&pack_path).unwrap().with_cache_size(0);
+            for (built, data) in [(e0, &r0), (e1, &r1), (e2, &r2), (e3, &r3)] {
+                assert_eq!(
+                    uncached.read_header(&built.hash).unwrap(),
+                    (ObjectType::Blob, data.len())
+                );
+            }
+            assert_eq!(uncached.read_object(&e3.hash).unwrap().data, r3);
+        }
+    }
+
+    /// Missing objects and external REF_DELTA bases are reported as errors.
+    #[tokio::test]
+    async fn test_read_object_missing_and_external_base() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let dir = tempdir().unwrap();
+        let base = revision(0);
+        let target = revision(1);
+        let external = ObjectHash::Sha1([7; 20]);
+
+        let mut builder = TestPackBuilder::new();
+        let thin = builder.add_ref_delta(external, &base, ObjectType::Blob, &target);
+        let pack_path = builder.write_to(dir.path(), "thin").await;
+
+        let reader = PackReader::open(&pack_path).unwrap();
+        assert!(matches!(
+            reader.read_object(&ObjectHash::Sha1([9; 20])),
+            Err(GitError::ObjectNotFound(_))
+        ));
+        assert!(matches!(
+            reader.read_object(&thin.hash),
+            Err(GitError::ObjectNotFound(_))
+        ));
+    }
+
+    /// A pack that does not belong to the index is rejected on open.
+    #[tokio::test]
+    async fn test_open_rejects_mismatched_pack() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let dir = tempdir().unwrap();
+        let mut builder = TestPackBuilder::new();
+        builder.add_base(ObjectType::Blob, b"hello");
+        let pack_path = builder.write_to(dir.path(), "one").await;
+
+        let mut other = TestPackBuilder::new();
+        other.add_base(ObjectType::Blob, b"world");
+        std::fs::write(&pack_path, other.pack_bytes()).unwrap();
+
+        assert!(PackReader::open(&pack_path).is_err());
+    }
+}
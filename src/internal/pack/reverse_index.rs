@@ -0,0 +1,295 @@
+//! Writer and reader for Git's pack
+//! [reverse index](https://git-scm.com/docs/gitformat-pack#_pack_rev_files_have_the_format) (`.rev`)
+//! files, which list a pack's objects in pack (offset) order as positions into the `.idx`.
+//!
+//! With a reverse index, pack position ↔ index position is a table lookup or binary search, and
+//! an object's on-disk size is the distance to the next object's offset.
+
+use std::path::Path;
+
+use crate::{
+    errors::GitError,
+    hash::{HashKind, ObjectHash},
+    internal::pack::{
+        index_entry::IndexEntry,
+        mmap::MappedFile,
+        pack_index::{PackIndex, read_u32},
+    },
+    utils::HashAlgorithm,
+};
+
+const RIDX_MAGIC: [u8; 4] = *b"RIDX";
+const RIDX_VERSION: u32 = 1;
+const RIDX_HEADER_SIZE: usize = 12;
+
+fn hash_id(kind: HashKind) -> u32 {
+    match kind {
+        HashKind::Sha1 => 1,
+        HashKind::Sha256 => 2,
+    }
+}
+
+/// Serialize a `.rev` file for a pack with the given index entries (in any order).
+///
+/// Entries are numbered by sorted hash, as in the `.idx`, and listed by ascending offset. The
+/// trailer carries `pack_hash` followed by the checksum of the `.rev` contents.
+pub fn build_reverse_index(entries: &[IndexEntry], pack_hash: ObjectHash) -> Vec<u8> {
+    let mut by_hash: Vec<&IndexEntry> = entries.iter().collect();
+    by_hash.sort_by_key(|e| e.hash);
+    let mut order: Vec<u32> = (0..by_hash.len() as u32).collect();
+    order.sort_by_key(|&pos| by_hash[pos as usize].offset);
+    encode(&order, pack_hash)
+}
+
+fn encode(order: &[u32], pack_hash: ObjectHash) -> Vec<u8> {
+    let kind = pack_hash.kind();
+    let mut out = Vec::with_capacity(RIDX_HEADER_SIZE + order.len() * 4 + 2 * kind.size());
+    out.extend_from_slice(&RIDX_MAGIC);
+    out.extend_from_slice(&RIDX_VERSION.to_be_bytes());
+    out.extend_from_slice(&hash_id(kind).to_be_bytes());
+    for pos in order {
+        out.extend_from_slice(&pos.to_be_bytes());
+    }
+    out.extend_from_slice(pack_hash.as_ref());
+    let mut hasher = HashAlgorithm::new_with_kind(kind);
+    hasher.update(&out);
+    out.extend(hasher.finalize());
+    out
+}
+
+/// Reader for a pack reverse index.
+///
+/// Position `n` in pack order maps to the sorted `.idx` position of the `n`-th object in the
+/// pack. Methods that need offsets take the matching [`PackIndex`]; pairing a reverse index with
+/// the index of a different pack gives meaningless results (compare [`Self::pack_hash`] with
+/// [`PackIndex::pack_hash`] when in doubt).
+pub struct ReverseIndex {
+    data: MappedFile,
+    kind: HashKind,
+    object_count: usize,
+}
+
+impl ReverseIndex {
+    /// Open and validate the `.rev` file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, GitError> {
+        let path = path.as_ref();
+        let data = MappedFile::open(path).map_err(|e| {
+            GitError::InvalidRevFile(format!("failed to read {}: {e}", path.display()))
+        })?;
+        Self::parse(data)
+    }
+
+    /// Parse and validate an in-memory `.rev` file.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, GitError> {
+        Self::parse(data.into())
+    }
+
+    /// Compute the reverse index of an opened `.idx`, for packs written without a `.rev`.
+    pub fn from_pack_index(index: &PackIndex) -> Self {
+        let mut order: Vec<u32> = (0..index.object_count() as u32).collect();
+        order.sort_by_key(|&pos| index.offset_at(pos as usize));
+        Self::parse(encode(&order, index.pack_hash()).into())
+            .expect("freshly encoded reverse index is valid")
+    }
+
+    fn parse(data: MappedFile) -> Result<Self, GitError> {
+        let invalid = |msg: &str| GitError::InvalidRevFile(msg.to_string());
+        if data.len() < RIDX_HEADER_SIZE {
+            return Err(invalid("file too short"));
+        }
+        if data[..4] != RIDX_MAGIC {
+            return Err(invalid("missing RIDX magic"));
+        }
+        if read_u32(&data, 4) != RIDX_VERSION {
+            return Err(invalid("unsupported version"));
+        }
+        let kind = match read_u32(&data, 8) {
+            1 => HashKind::Sha1,
+            2 => HashKind::Sha256,
+            _ => return Err(invalid("unknown hash id")),
+        };
+        let body = data.len().checked_sub(RIDX_HEADER_SIZE + 2 * kind.size());
+        let object_count = match body {
+            Some(body) if body.is_multiple_of(4) => body / 4,
+            _ => return Err(invalid("file size does not match the table")),
+        };
+
+        let checksum_start = data.len() - kind.size();
+        let mut hasher = HashAlgorithm::new_with_kind(kind);
+        hasher.update(&data[..checksum_start]);
+        if hasher.finalize() != data[checksum_start..] {
+            return Err(invalid("trailer checksum does not match contents"));
+        }
+
+        let mut seen = vec![false; object_count];
+        for n in 0..object_count {
+            let pos = read_u32(&data, RIDX_HEADER_SIZE + n * 4) as usize;
+            if pos >= object_count || std::mem::replace(&mut seen[pos], true) {
+                return Err(invalid("table is not a permutation of index positions"));
+            }
+        }
+        Ok(ReverseIndex {
+            data,
+            kind,
+            object_count,
+        })
+    }
+
+    /// Hash kind recorded in the header.
+    pub fn hash_kind(&self) -> HashKind {
+        self.kind
+    }
+
+    /// Number of objects in the pack.
+    pub fn object_count(&self) -> usize {
+        self.object_count
+    }
+
+    /// Checksum of the pack this reverse index belongs to.
+    pub fn pack_hash(&self) -> ObjectHash {
+        self.hash_from(self.data.len() - 2 * self.kind.size())
+    }
+
+    /// Checksum of the `.rev` file itself.
+    pub fn checksum(&self) -> ObjectHash {
+        self.hash_from(self.data.len() - self.kind.size())
+    }
+
+    /// Sorted `.idx` position of the `pack_pos`-th object in the pack.
+    ///
+    /// # Panics
+    /// Panics if `pack_pos >= object_count()`.
+    pub fn index_pos_at(&self, pack_pos: usize) -> usize {
+        assert!(pack_pos < self.object_count, "pack position out of range");
+        read_u32(&self.data, RIDX_HEADER_SIZE + pack_pos * 4) as usize
+    }
+
+    /// Pack position of the object at sorted `.idx` position `index_pos`.
+    pub fn pack_pos_of(&self, index: &PackIndex, index_pos: usize) -> Option<usize> {
+        if index_pos >= index.object_count() {
+            return None;
+        }
+        let target = index.offset_at(index_pos);
+        self.pack_pos_of_offset(index, target)
+    }
+
+    /// Pack position of the object starting at `offset`, if one does.
+    pub fn pack_pos_of_offset(&self, index: &PackIndex, offset: u64) -> Option<usize> {
+        let (mut lo, mut hi) = (0, self.object_count);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let at = index.offset_at(self.index_pos_at(mid));
+            match at.cmp(&offset) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    /// Size in bytes of the packed (compressed) entry at `pack_pos`, header included.
+    ///
+    /// `pack_len` is the length of the whole `.pack` file; the last entry ends where the trailing
+    /// checksum begins.
+    ///
+    /// # Panics
+    /// Panics if `pack_pos >= object_count()`.
+    pub fn disk_size_at(&self, index: &PackIndex, pack_pos: usize, pack_len: u64) -> u64 {
+        let start = index.offset_at(self.index_pos_at(pack_pos));
+        let end = if pack_pos + 1 < self.object_count {
+            index.offset_at(self.index_pos_at(pack_pos + 1))
+        } else {
+            pack_len - self.kind.size() as u64
+        };
+        end - start
+    }
+
+    /// On-disk size of `hash`'s packed entry, if the pack contains it.
+    pub fn disk_size_of(&self, index: &PackIndex, hash: &ObjectHash, pack_len: u64) -> Option<u64> {
+        let pos = index.find(hash)?;
+        let pack_pos = self.pack_pos_of(index, pos)?;
+        Some(self.disk_size_at(index, pack_pos, pack_len))
+    }
+
+    fn hash_from(&self, start: usize) -> ObjectHash {
+        ObjectHash::from_bytes_with_kind(&self.data[start..start + self.kind.size()], self.kind)
+            .expect("slice length matches hash kind")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReverseIndex, build_reverse_index, encode};
+    use crate::{
+        errors::GitError,
+        hash::{HashKind, ObjectHash, set_hash_kind_for_test},
+        internal::{
+            object::types::ObjectType,
+            pack::{pack_index::PackIndex, test_pack_builder::TestPackBuilder},
+        },
+    };
+
+    /// Built and derived reverse indexes agree, map positions both ways, and report on-disk
+    /// sizes that tile the pack exactly.
+    #[tokio::test]
+    async fn test_reverse_index_roundtrip() {
+        for kind in [HashKind::Sha1, HashKind::Sha256] {
+            let _guard = set_hash_kind_for_test(kind);
+            let mut builder = TestPackBuilder::new();
+            let base = builder.add_base(ObjectType::Blob, b"reverse index base content");
+            builder.add_ofs_delta(
+                base,
+                b"reverse index base content",
+                ObjectType::Blob,
+                b"reverse index base content, extended",
+            );
+            builder.add_base(ObjectType::Blob, b"another object");
+            let pack = builder.pack_bytes();
+            let index = PackIndex::from_bytes(builder.idx_bytes().await).unwrap();
+
+            let entries: Vec<_> = index.iter().collect();
+            let built = build_reverse_index(&entries, index.pack_hash());
+            let rev = ReverseIndex::from_bytes(built.clone()).unwrap();
+            let derived = ReverseIndex::from_pack_index(&index);
+            assert_eq!(rev.hash_kind(), kind);
+            assert_eq!(rev.pack_hash(), index.pack_hash());
+            assert_eq!(rev.checksum(), derived.checksum());
+
+            let mut total = 12;
+            let mut prev_offset = 0;
+            for pack_pos in 0..rev.object_count() {
+                let index_pos = rev.index_pos_at(pack_pos);
+                let offset = index.offset_at(index_pos);
+                assert!(offset > prev_offset);
+                prev_offset = offset;
+                assert_eq!(rev.pack_pos_of(&index, index_pos), Some(pack_pos));
+                total += rev.disk_size_at(&index, pack_pos, pack.len() as u64);
+            }
+            assert_eq!(total as usize, pack.len() - kind.size());
+            assert_eq!(
+                rev.disk_size_of(&index, &base.hash, pack.len() as u64),
+                Some(rev.disk_size_at(&index, 0, pack.len() as u64))
+            );
+        }
+    }
+
+    /// Tables that are not a permutation, or have a bad checksum, are rejected.
+    #[test]
+    fn test_reverse_index_rejects_invalid() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let pack_hash = ObjectHash::Sha1([7; 20]);
+        let mut data = encode(&[0, 0], pack_hash);
+        assert!(matches!(
+            ReverseIndex::from_bytes(data.clone()),
+            Err(GitError::InvalidRevFile(_))
+        ));
+        data = encode(&[1, 0], pack_hash);
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(matches!(
+            ReverseIndex::from_bytes(data),
+            Err(GitError::InvalidRevFile(_))
+        ));
+    }
+}
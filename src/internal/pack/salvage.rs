@@ -0,0 +1,530 @@
+//! Recovery decoding for truncated or damaged packs.
+//!
+//! [`Pack::decode`] stops at the first bad byte, which loses every intact object behind it. The
+//! salvage decoder instead checks each entry on its own (header, a zlib stream that ends where
+//! its checksum says, and an inflated size matching the header), skips over entries that fail,
+//! and resyncs on the next offset that holds a plausible entry. Objects are then rebuilt from the
+//! surviving entries, so deltas are delivered whenever their whole chain survived.
+
+use std::{collections::HashMap, io::Read, ops::Range};
+
+use flate2::{Decompress, FlushDecompress, Status};
+
+use crate::{
+    hash::{HashKind, ObjectHash, get_hash_kind},
+    internal::{
+        metadata::{EntryMeta, MetaAttached},
+        object::types::ObjectType,
+        pack::{
+            Pack,
+            entry::Entry,
+            mmap::MappedFile,
+            progress::{ProgressPhase, ProgressReporter},
+            reader::{EntryHeader, EntryKind, MappedPack, apply_delta},
+            utils::calculate_object_hash,
+        },
+    },
+    utils::HashAlgorithm,
+};
+
+const PACK_HEADER_SIZE: usize = 12;
+/// zlib cannot expand data by more than about 1032:1, so larger declared sizes are garbage.
+const MAX_INFLATE_RATIO: usize = 1100;
+
+/// A part of the pack that did not yield an object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedEntry {
+    /// Offset of the entry, or of the first damaged byte.
+    pub offset: u64,
+    /// Offset where decoding resumed (the end of the damaged range, or of the entry).
+    pub end: u64,
+    pub reason: String,
+}
+
+/// Outcome of [`Pack::decode_salvage`].
+#[derive(Debug, Clone, Default)]
+pub struct SalvageReport {
+    /// Object count from the pack header, if the header is intact.
+    pub declared_objects: Option<u32>,
+    /// Number of objects delivered to the callback.
+    pub recovered: usize,
+    /// Damaged byte ranges and entries whose objects could not be rebuilt, by offset.
+    pub skipped: Vec<SkippedEntry>,
+    /// Whether the pack ended with a checksum matching its contents.
+    pub trailer_valid: bool,
+}
+
+impl SalvageReport {
+    /// Whether the pack decoded without losing anything.
+    pub fn is_complete(&self) -> bool {
+        self.skipped.is_empty()
+            && self.trailer_valid
+            && self.declared_objects == Some(self.recovered as u32)
+    }
+}
+
+/// An entry whose compressed data checked out.
+struct ScannedEntry {
+    offset: u64,
+    header: EntryHeader,
+    end: u64,
+}
+
+impl Pack {
+    /// Decode as much of a truncated or corrupt pack as possible.
+    ///
+    /// Unlike [`Pack::decode`], damage does not abort the decode: bad entries are skipped, the
+    /// decoder resyncs on the next plausible entry header, and every object that can be fully
+    /// rebuilt (including deltas whose whole base chain survived) is passed to `callback` with
+    /// its pack offset and CRC32. Objects are delivered base first, so the order differs from
+    /// pack order. Read errors are treated as the end of the pack.
+    ///
+    /// Everything that was lost is listed in the returned report. `self.number` is set from the
+    /// pack header and `self.signature` from the trailer when they are intact.
+    pub fn decode_salvage<F>(&mut self, pack: &mut impl Read, mut callback: F) -> SalvageReport
+    where
+        F: FnMut(MetaAttached<Entry, EntryMeta>),
+    {
+        let kind = get_hash_kind();
+        let mut data = Vec::new();
+        let mut report = SalvageReport::default();
+        if let Err(e) = pack.read_to_end(&mut data) {
+            report.skipped.push(SkippedEntry {
+                offset: data.len() as u64,
+                end: data.len() as u64,
+                reason: format!("pack stream ended with a read error: {e}"),
+            });
+        }
+
+        let data_len = data.len();
+        if data.len() >= PACK_HEADER_SIZE {
+            match Pack::check_header(&mut &data[..PACK_HEADER_SIZE]) {
+                Ok((count, _)) => {
+                    self.number = count as usize;
+                    report.declared_objects = Some(count);
+                }
+                Err(e) => report.skipped.push(SkippedEntry {
+                    offset: 0,
+                    end: PACK_HEADER_SIZE as u64,
+                    reason: e.to_string(),
+                }),
+            }
+        }
+
+        let body_end = match data.len().checked_sub(kind.size()) {
+            Some(body_end) if body_end >= PACK_HEADER_SIZE => {
+                let mut hasher = HashAlgorithm::new_with_kind(kind);
+                hasher.update(&data[..body_end]);
+                if hasher.finalize() == data[body_end..] {
+                    report.trailer_valid = true;
+                    self.signature = ObjectHash::from_bytes_with_kind(&data[body_end..], kind)
+                        .expect("trailer has the hash size");
+                    body_end
+                } else {
+                    data.len()
+                }
+            }
+            _ => data.len(),
+        };
+        // Without a valid trailer every byte may belong to an entry; pad so the mapped pack
+        // still has room for the trailer it expects.
+        data.truncate(body_end);
+        data.resize(body_end + kind.size(), 0);
+        let mapped = MappedPack::new(MappedFile::from(data), kind);
+
+        let entries = scan_entries(&mapped, kind, body_end as u64, &mut report.skipped);
+        ProgressReporter::new(
+            self.progress.clone(),
+            ProgressPhase::ReceivingObjects,
+            report.declared_objects.map(|n| n as usize),
+        )
+        .finish(entries.len(), data_len as u64, 0);
+        // Bytes after the last entry that are too few to hold another one are the remains of a
+        // trailer that did not verify (already reported), provided the entries and the damaged
+        // ranges before them can account for every declared object.
+        let entries_end = entries.last().map_or(PACK_HEADER_SIZE as u64, |e| e.end);
+        if !report.trailer_valid
+            && let Some((tail, earlier)) = report.skipped.split_last()
+            && tail.offset == entries_end
+            && tail.end - tail.offset <= kind.size() as u64
+            && report
+                .declared_objects
+                .is_some_and(|n| entries.len() + earlier.len() >= n as usize)
+        {
+            report.skipped.pop();
+        }
+        let mut salvager = Salvager::new(&mapped, &entries);
+        for (i, entry) in entries.iter().enumerate() {
+            if let EntryKind::Base(obj_type) = entry.header.kind {
+                salvager.resolve_tree(i, obj_type, &mut report, &mut callback);
+            }
+        }
+        let deltas = entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| !matches!(entry.header.kind, EntryKind::Base(_)));
+        let total_deltas = deltas.clone().count();
+        let deltas_resolved = deltas.filter(|(i, _)| salvager.resolved[*i]).count();
+        ProgressReporter::new(
+            self.progress.clone(),
+            ProgressPhase::ResolvingDeltas,
+            Some(total_deltas),
+        )
+        .finish(deltas_resolved, 0, deltas_resolved);
+        for (i, entry) in entries.iter().enumerate() {
+            if salvager.resolved[i] {
+                continue;
+            }
+            let reason = match entry.header.kind {
+                EntryKind::OffsetDelta(base) | EntryKind::OffsetZstdelta(base) => {
+                    format!("delta base at offset {base} was not recovered")
+                }
+                EntryKind::HashDelta(base) => format!("delta base {base} was not recovered"),
+                EntryKind::Base(_) => "object could not be rebuilt".to_string(),
+            };
+            report.skipped.push(SkippedEntry {
+                offset: entry.offset,
+                end: entry.end,
+                reason: salvager.failures.remove(&i).unwrap_or(reason),
+            });
+        }
+        report.skipped.sort_by_key(|s| s.offset);
+        report
+    }
+}
+
+/// Walk the entry area, collecting intact entries and recording damaged ranges in `skipped`.
+fn scan_entries(
+    pack: &MappedPack,
+    kind: HashKind,
+    body_end: u64,
+    skipped: &mut Vec<SkippedEntry>,
+) -> Vec<ScannedEntry> {
+    let mut entries: Vec<ScannedEntry> = Vec::new();
+    let mut offset = PACK_HEADER_SIZE as u64;
+    while offset < body_end {
+        let reason = match check_entry(pack, kind, offset, body_end) {
+            Ok(entry) if base_plausible(&entry, &entries, skipped, None) => {
+                offset = entry.end;
+                entries.push(entry);
+                continue;
+            }
+            Ok(entry) => format!(
+                "delta base offset {} is not the start of an entry",
+                entry_base(&entry).unwrap_or_default()
+            ),
+            Err(e) => e,
+        };
+
+        // Resync on the next offset holding a checkable entry.
+        let damaged_from = offset;
+        let resumed = (damaged_from + 1..body_end).find_map(|candidate| {
+            let entry = check_entry(pack, kind, candidate, body_end).ok()?;
+            base_plausible(&entry, &entries, skipped, Some(damaged_from..candidate))
+                .then_some(entry)
+        });
+        skipped.push(SkippedEntry {
+            offset: damaged_from,
+            end: resumed.as_ref().map_or(body_end, |entry| entry.offset),
+            reason,
+        });
+        match resumed {
+            Some(entry) => {
+                offset = entry.end;
+                entries.push(entry);
+            }
+            None => break,
+        }
+    }
+    entries
+}
+
+/// An offset delta must point at an entry seen before, or into a damaged range (including
+/// `damaging`, the one being skipped) where its base was lost.
+fn base_plausible(
+    entry: &ScannedEntry,
+    entries: &[ScannedEntry],
+    damaged: &[SkippedEntry],
+    damaging: Option<Range<u64>>,
+) -> bool {
+    entry_base(entry).is_none_or(|base| {
+        entries.binary_search_by_key(&base, |e| e.offset).is_ok()
+            || damaged.iter().any(|s| (s.offset..s.end).contains(&base))
+            || damaging.is_some_and(|range| range.contains(&base))
+    })
+}
+
+fn entry_base(entry: &ScannedEntry) -> Option<u64> {
+    match entry.header.kind {
+        EntryKind::OffsetDelta(base) | EntryKind::OffsetZstdelta(base) => Some(base),
+        EntryKind::Base(_) | EntryKind::HashDelta(_) => None,
+    }
+}
+
+/// Parse the entry at `offset` and check that its zlib stream ends cleanly and inflates to the
+/// declared size.
+fn check_entry(
+    pack: &MappedPack,
+    kind: HashKind,
+    offset: u64,
+    body_end: u64,
+) -> Result<ScannedEntry, String> {
+    let header = pack.read_entry_header(offset).map_err(|e| e.to_string())?;
+    if let EntryKind::HashDelta(base) = header.kind
+        && base.kind() != kind
+    {
+        return Err(format!("bad delta base at offset {offset}"));
+    }
+    if header.data_offset >= body_end {
+        return Err(format!("entry at offset {offset} is truncated"));
+    }
+    let input = &pack.bytes()[header.data_offset as usize..body_end as usize];
+    let (_, used) =
+        inflate_exact(input, header.size).map_err(|e| format!("entry at offset {offset}: {e}"))?;
+    Ok(ScannedEntry {
+        offset,
+        header,
+        end: header.data_offset + used as u64,
+    })
+}
+
+/// Inflate one complete zlib stream from the start of `input`, which must produce exactly `size`
+/// bytes. Returns the data and the number of compressed bytes used.
+fn inflate_exact(input: &[u8], size: usize) -> Result<(Vec<u8>, usize), String> {
+    if input.len() < 2
+        || input[0] & 0x0f != 8
+        || !u16::from_be_bytes([input[0], input[1]]).is_multiple_of(31)
+    {
+        return Err("no zlib stream".to_string());
+    }
+    if size > input.len().saturating_mul(MAX_INFLATE_RATIO) {
+        return Err(format!("declared size {size} is implausible"));
+    }
+    let mut inflater = Decompress::new(true);
+    let mut out = Vec::with_capacity(size + 1);
+    let status = inflater
+        .decompress_vec(input, &mut out, FlushDecompress::Finish)
+        .map_err(|e| format!("decompression error: {e}"))?;
+    if out.len() > size {
+        return Err(format!(
+            "data inflates to more than the declared {size} bytes"
+        ));
+    }
+    if status != Status::StreamEnd {
+        return Err("compressed data is truncated".to_string());
+    }
+    if out.len() != size {
+        return Err(format!(
+            "data inflates to {} bytes instead of the declared {size}",
+            out.len()
+        ));
+    }
+    Ok((out, inflater.total_in() as usize))
+}
+
+/// Rebuilds objects from scanned entries, depth-first from each base.
+struct Salvager<'a> {
+    pack: &'a MappedPack,
+    entries: &'a [ScannedEntry],
+    /// Offset deltas keyed by the offset of their base.
+    offset_children: HashMap<u64, Vec<usize>>,
+    /// Hash deltas keyed by the name of their base.
+    hash_children: HashMap<ObjectHash, Vec<usize>>,
+    resolved: Vec<bool>,
+    /// Why an entry that checked out still could not be rebuilt.
+    failures: HashMap<usize, String>,
+}
+
+impl<'a> Salvager<'a> {
+    fn new(pack: &'a MappedPack, entries: &'a [ScannedEntry]) -> Self {
+        let mut offset_children: HashMap<u64, Vec<usize>> = HashMap::new();
+        let mut hash_children: HashMap<ObjectHash, Vec<usize>> = HashMap::new();
+        for (i, entry) in entries.iter().enumerate().rev() {
+            match entry.header.kind {
+                EntryKind::Base(_) => {}
+                EntryKind::OffsetDelta(base) | EntryKind::OffsetZstdelta(base) => {
+                    offset_children.entry(base).or_default().push(i)
+                }
+                EntryKind::HashDelta(base) => hash_children.entry(base).or_default().push(i),
+            }
+        }
+        Salvager {
+            pack,
+            entries,
+            offset_children,
+            hash_children,
+            resolved: vec![false; entries.len()],
+            failures: HashMap::new(),
+        }
+    }
+
+    /// Deliver the base entry `root` and every delta that builds on it.
+    fn resolve_tree<F>(
+        &mut self,
+        root: usize,
+        obj_type: ObjectType,
+        report: &mut SalvageReport,
+        callback: &mut F,
+    ) where
+        F: FnMut(MetaAttached<Entry, EntryMeta>),
+    {
+        let mut stack: Vec<(usize, Vec<u8>, usize)> = Vec::new();
+        let mut pending = vec![(root, None::<usize>)];
+        while let Some((i, parent)) = pending.pop() {
+            // Unwind to the parent so only one chain of rebuilt objects is held at a time.
+            while let Some(&(top, ..)) = stack.last()
+                && Some(top) != parent
+            {
+                stack.pop();
+            }
+            let entry = &self.entries[i];
+            let rebuilt = inflate_exact(
+                &self.pack.bytes()[entry.header.data_offset as usize..entry.end as usize],
+                entry.header.size,
+            )
+            .map_err(|e| e.to_string())
+            .and_then(|(payload, _)| match stack.last() {
+                Some((_, base, ..)) => {
+                    apply_delta(entry.header.kind, base, &payload).map_err(|e| e.to_string())
+                }
+                None => Ok(payload),
+            });
+            let data = match rebuilt {
+                Ok(data) => data,
+                Err(e) => {
+                    self.failures.insert(i, e);
+                    continue;
+                }
+            };
+            let depth = stack.last().map_or(0, |(_, _, depth)| depth + 1);
+            let hash = calculate_object_hash(obj_type, &data);
+            self.resolved[i] = true;
+
+            let mut children = self
+                .offset_children
+                .remove(&entry.offset)
+                .unwrap_or_default();
+            children.extend(self.hash_children.remove(&hash).unwrap_or_default());
+            pending.extend(children.iter().map(|&child| (child, Some(i))));
+
+            let crc32 =
+                crc32fast::hash(&self.pack.bytes()[entry.offset as usize..entry.end as usize]);
+            let meta = EntryMeta {
+                pack_offset: Some(entry.offset as usize),
+                crc32: Some(crc32),
+                is_delta: Some(!matches!(entry.header.kind, EntryKind::Base(_))),
+                ..Default::default()
+            };
+            if children.is_empty() {
+                callback(MetaAttached {
+                    inner: Entry {
+                        obj_type,
+                        data,
+                        hash,
+                        chain_len: depth,
+                    },
+                    meta,
+                });
+            } else {
+                callback(MetaAttached {
+                    inner: Entry {
+                        obj_type,
+                        data: data.clone(),
+                        hash,
+                        chain_len: depth,
+                    },
+                    meta,
+                });
+                stack.push((i, data, depth));
+            }
+            report.recovered += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::{
+        hash::{HashKind, ObjectHash, set_hash_kind_for_test},
+        internal::{
+            object::types::ObjectType,
+            pack::{Pack, test_pack_builder::TestPackBuilder},
+        },
+    };
+
+    fn revision(n: usize) -> Vec<u8> {
+        (0..80)
+            .flat_map(|line| format!("line {line} of revision {}\n", line % (n + 2)).into_bytes())
+            .collect()
+    }
+
+    fn salvage(bytes: &[u8]) -> (HashSet<ObjectHash>, super::SalvageReport) {
+        let mut pack = Pack::new(Some(1), None, None, true);
+        let mut recovered = HashSet::new();
+        let report = pack.decode_salvage(&mut &bytes[..], |entry| {
+            assert_eq!(
+                ObjectHash::from_type_and_data(entry.inner.obj_type, &entry.inner.data),
+                entry.inner.hash
+            );
+            recovered.insert(entry.inner.hash);
+        });
+        (recovered, report)
+    }
+
+    /// A damaged entry in the middle loses only itself and the deltas built on it.
+    #[test]
+    fn test_salvage_skips_corrupt_entry() {
+        for kind in [HashKind::Sha1, HashKind::Sha256] {
+            let _guard = set_hash_kind_for_test(kind);
+            let (r0, r1, r2) = (revision(0), revision(1), revision(2));
+            let mut builder = TestPackBuilder::new();
+            let b0 = builder.add_base(ObjectType::Blob, &r0);
+            let d1 = builder.add_ofs_delta(b0, &r0, ObjectType::Blob, &r1);
+            let lost = builder.add_base(ObjectType::Blob, b"an object that gets damaged");
+            let lost_child = builder.add_ref_delta(
+                lost.hash,
+                b"an object that gets damaged",
+                ObjectType::Blob,
+                b"an object that gets damaged twice",
+            );
+            let d2 = builder.add_ref_delta(d1.hash, &r1, ObjectType::Blob, &r2);
+            let commit = builder.add_base(ObjectType::Commit, b"tree 0\n\nmsg\n");
+            let mut bytes = builder.pack_bytes();
+
+            let (recovered, report) = salvage(&bytes);
+            assert!(report.is_complete(), "{:?}", report.skipped);
+            assert_eq!(recovered.len(), 6);
+
+            bytes[lost.offset as usize + 4] ^= 0x55;
+            let (recovered, report) = salvage(&bytes);
+            assert_eq!(
+                recovered,
+                HashSet::from([b0.hash, d1.hash, d2.hash, commit.hash])
+            );
+            assert!(!report.trailer_valid);
+            assert_eq!(report.declared_objects, Some(6));
+            let offsets: Vec<u64> = report.skipped.iter().map(|s| s.offset).collect();
+            assert_eq!(offsets, vec![lost.offset, lost_child.offset]);
+            assert!(report.skipped[1].reason.contains("was not recovered"));
+        }
+    }
+
+    /// A pack cut off mid-entry keeps every object before the cut.
+    #[test]
+    fn test_salvage_truncated_pack() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let mut builder = TestPackBuilder::new();
+        let first = builder.add_base(ObjectType::Blob, &revision(0));
+        let second = builder.add_base(ObjectType::Blob, &revision(1));
+        let third = builder.add_base(ObjectType::Blob, &revision(2));
+        let bytes = builder.pack_bytes();
+
+        let (recovered, report) = salvage(&bytes[..third.offset as usize + 10]);
+        assert_eq!(recovered, HashSet::from([first.hash, second.hash]));
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].offset, third.offset);
+        assert!(!report.is_complete());
+    }
+}
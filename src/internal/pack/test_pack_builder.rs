@@ -0,0 +1,171 @@
+//! Test helper: assemble small packs (and their `.idx`) entry by entry, including offset, hash, and
+//! zstd deltas, so pack readers can be exercised without downloading fixtures.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use flate2::{Compression, write::ZlibEncoder};
+use tokio::sync::mpsc;
+
+use crate::{
+    delta,
+    hash::{ObjectHash, get_hash_kind},
+    internal::{
+        object::types::ObjectType,
+        pack::{
+            encode::encode_offset, index_entry::IndexEntry, pack_index::IdxBuilder,
+            utils::calculate_object_hash,
+        },
+    },
+    utils::HashAlgorithm,
+    zstdelta,
+};
+
+/// One written entry: where it starts and the hash of the object it resolves to.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BuiltEntry {
+    pub offset: u64,
+    pub hash: ObjectHash,
+}
+
+/// Writes pack entries in order using the thread-local hash kind.
+#[derive(Default)]
+pub(crate) struct TestPackBuilder {
+    body: Vec<u8>,
+    entries: Vec<IndexEntry>,
+}
+
+impl TestPackBuilder {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    fn next_offset(&self) -> u64 {
+        12 + self.body.len() as u64
+    }
+
+    /// Append a raw entry: type bits, declared size, bytes between header and zlib payload, and
+    /// the payload to compress.
+    pub(crate) fn add_raw(
+        &mut self,
+        type_bits: u8,
+        size: usize,
+        extra: &[u8],
+        payload: &[u8],
+        hash: ObjectHash,
+    ) -> BuiltEntry {
+        let offset = self.next_offset();
+        let mut raw = Vec::new();
+        let mut first = (type_bits << 4) | (size & 0x0f) as u8;
+        let mut rest = size >> 4;
+        if rest > 0 {
+            first |= 0x80;
+        }
+        raw.push(first);
+        while rest > 0 {
+            let mut byte = (rest & 0x7f) as u8;
+            rest >>= 7;
+            if rest > 0 {
+                byte |= 0x80;
+            }
+            raw.push(byte);
+        }
+        raw.extend_from_slice(extra);
+        let mut z = ZlibEncoder::new(Vec::new(), Compression::default());
+        z.write_all(payload).unwrap();
+        raw.extend(z.finish().unwrap());
+
+        self.entries.push(IndexEntry {
+            hash,
+            crc32: crc32fast::hash(&raw),
+            offset,
+        });
+        self.body.extend(raw);
+        BuiltEntry { offset, hash }
+    }
+
+    /// Append a full (non-delta) object.
+    pub(crate) fn add_base(&mut self, obj_type: ObjectType, data: &[u8]) -> BuiltEntry {
+        let hash = calculate_object_hash(obj_type, data);
+        let type_bits = obj_type.to_pack_type_u8().unwrap();
+        self.add_raw(type_bits, data.len(), &[], data, hash)
+    }
+
+    /// Append an OFS_DELTA that turns the object at `base` (with content `base_data`) into `target`.
+    pub(crate) fn add_ofs_delta(
+        &mut self,
+        base: BuiltEntry,
+        base_data: &[u8],
+        obj_type: ObjectType,
+        target: &[u8],
+    ) -> BuiltEntry {
+        let delta = delta::encode(base_data, target);
+        let distance = (self.next_offset() - base.offset) as usize;
+        let hash = calculate_object_hash(obj_type, target);
+        self.add_raw(6, delta.len(), &encode_offset(distance), &delta, hash)
+    }
+
+    /// Append an OFS_ZSTDELTA (type 5) that turns `base_data` into `target`.
+    pub(crate) fn add_zstd_delta(
+        &mut self,
+        base: BuiltEntry,
+        base_data: &[u8],
+        obj_type: ObjectType,
+        target: &[u8],
+    ) -> BuiltEntry {
+        let delta = zstdelta::diff(base_data, target).unwrap();
+        let distance = (self.next_offset() - base.offset) as usize;
+        let hash = calculate_object_hash(obj_type, target);
+        self.add_raw(5, delta.len(), &encode_offset(distance), &delta, hash)
+    }
+
+    /// Append a REF_DELTA against `base_hash`, which may or may not be in this pack.
+    pub(crate) fn add_ref_delta(
+        &mut self,
+        base_hash: ObjectHash,
+        base_data: &[u8],
+        obj_type: ObjectType,
+        target: &[u8],
+    ) -> BuiltEntry {
+        let delta = delta::encode(base_data, target);
+        let hash = calculate_object_hash(obj_type, target);
+        self.add_raw(7, delta.len(), base_hash.as_ref(), &delta, hash)
+    }
+
+    /// Finish the pack: header, body, and trailing checksum.
+    pub(crate) fn pack_bytes(&self) -> Vec<u8> {
+        let mut pack = b"PACK".to_vec();
+        pack.extend_from_slice(&2u32.to_be_bytes());
+        pack.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        pack.extend_from_slice(&self.body);
+        let mut hasher = HashAlgorithm::new();
+        hasher.update(&pack);
+        pack.extend(hasher.finalize());
+        pack
+    }
+
+    /// Build the `.idx` for [`Self::pack_bytes`].
+    pub(crate) async fn idx_bytes(&self) -> Vec<u8> {
+        let pack = self.pack_bytes();
+        let hash_size = get_hash_kind().size();
+        let pack_hash = ObjectHash::from_bytes(&pack[pack.len() - hash_size..]).unwrap();
+        let (tx, mut rx) = mpsc::channel::<Vec<u8>>(4096);
+        let mut builder = IdxBuilder::new(self.entries.len(), tx, pack_hash);
+        builder.write_idx(self.entries.clone()).await.unwrap();
+        let mut out = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            out.extend(chunk);
+        }
+        out
+    }
+
+    /// Write `<name>.pack` and `<name>.idx` into `dir`, returning the pack path.
+    pub(crate) async fn write_to(&self, dir: &Path, name: &str) -> PathBuf {
+        let pack_path = dir.join(format!("{name}.pack"));
+        std::fs::write(&pack_path, self.pack_bytes()).unwrap();
+        std::fs::write(pack_path.with_extension("idx"), self.idx_bytes().await).unwrap();
+        pack_path
+    }
+}
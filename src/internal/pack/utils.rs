@@ -14,6 +14,12 @@ use crate::{
     internal::object::types::ObjectType,
 };
 
+/// Pack type bits of a trained zstd dictionary entry, written by
+/// [`PackEncoder::with_zstd_dictionaries`](crate::internal::pack::encode::PackEncoder::with_zstd_dictionaries).
+///
+/// Git never writes type 0, so such packs are only readable by this crate's decoder.
+pub const ZSTD_DICTIONARY_PACK_TYPE: u8 = 0;
+
 /// Checks if the reader has reached EOF (end of file).
 ///
 /// It attempts to read a single byte from the reader into a buffer.
@@ -63,6 +63,8 @@ mod tests {
             mem_recorder: None,
             is_delta_in_pack: false,
             known_hash: None,
+            compressed_delta: None,
+            reusable_delta: None,
         }
     }
 
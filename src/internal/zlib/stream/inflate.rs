@@ -5,7 +5,7 @@ use std::{io, io::BufRead};
 
 use flate2::{Decompress, FlushDecompress, Status};
 
-use crate::{internal::object::types::ObjectType, utils::HashAlgorithm};
+use crate::{hash::HashKind, internal::object::types::ObjectType, utils::HashAlgorithm};
 
 /// ReadBoxed is to unzip information from a  DEFLATE stream,
 /// which hash [`BufRead`] trait.
@@ -48,6 +48,18 @@ where
         }
     }
 
+    /// New a ReadBoxed for a loose object file, whose `<type> <size>\0` header is part of the
+    /// compressed stream. Every inflated byte, header included, is hashed with `kind`, so the
+    /// final hash is the object id.
+    pub fn new_for_loose(inner: R, kind: HashKind) -> Self {
+        ReadBoxed {
+            inner,
+            hash: HashAlgorithm::new_with_kind(kind),
+            count_hash: true,
+            decompressor: Box::new(Decompress::new(true)),
+        }
+    }
+
     /// New a ReadBoxed for zlib read, the Output ReadBoxed is for the Delta Object,
     /// which does not need to calculate the hash value.
     pub fn new_for_delta(inner: R) -> Self {
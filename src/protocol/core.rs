@@ -10,7 +10,7 @@ use futures::stream::StreamExt;
 
 use crate::{
     hash::ObjectHash,
-    internal::object::ObjectTrait,
+    internal::{object::ObjectTrait, pack::limits::DecodeLimits},
     protocol::{
         smart::SmartProtocol,
         types::{Capability, ProtocolError, ProtocolStream, ServiceType, SideBand},
@@ -235,6 +235,11 @@ impl<R: RepositoryAccess, A: AuthenticationService> GitProtocol<R, A> {
         self.smart_protocol.set_transport_protocol(protocol);
     }
 
+    /// Set the limits applied to pushed packs
+    pub fn set_decode_limits(&mut self, limits: DecodeLimits) {
+        self.smart_protocol.set_decode_limits(limits);
+    }
+
     /// Handle git info-refs request
     pub async fn info_refs(&self, service: &str) -> Result<Vec<u8>, ProtocolError> {
         let service_type = match service {
@@ -257,8 +262,10 @@ impl<R: RepositoryAccess, A: AuthenticationService> GitProtocol<R, A> {
         const SIDE_BAND_HEADER_LEN: usize = 5; // 4-byte length + 1-byte band
 
         let request_bytes = bytes::Bytes::from(request_data.to_vec());
-        let (pack_stream, protocol_buf) =
-            self.smart_protocol.git_upload_pack(request_bytes).await?;
+        let (pack_stream, protocol_buf, progress_stream) = self
+            .smart_protocol
+            .git_upload_pack_with_progress(request_bytes)
+            .await?;
         let ack_bytes = protocol_buf.freeze();
 
         let ack_stream: ProtocolStream = if ack_bytes.is_empty() {
@@ -284,10 +291,34 @@ impl<R: RepositoryAccess, A: AuthenticationService> GitProtocol<R, A> {
         };
 
         let data_stream: ProtocolStream = if let Some(max_payload) = sideband_max {
-            let stream = pack_stream.flat_map(move |chunk| {
-                let packets = build_side_band_packets(&chunk, max_payload);
+            let stream: ProtocolStream = Box::pin(pack_stream.flat_map(move |chunk| {
+                let packets = build_side_band_packets(&chunk, max_payload, SideBand::PackfileData);
                 futures::stream::iter(packets.into_iter().map(Ok))
-            });
+            }));
+            // Progress lines travel on channel 2, interleaved with the pack data on channel 1. The
+            // stream still opens with the pack header; progress from before it (counting objects)
+            // is queued and follows right after.
+            let stream: ProtocolStream = match progress_stream {
+                Some(progress_stream) => {
+                    let progress = progress_stream.flat_map(move |line| {
+                        let packets = build_side_band_packets(
+                            line.as_bytes(),
+                            max_payload,
+                            SideBand::ProgressInfo,
+                        );
+                        futures::stream::iter(packets.into_iter().map(Ok))
+                    });
+                    Box::pin(
+                        futures::stream::once(async move {
+                            let (first, rest) = stream.into_future().await;
+                            futures::stream::iter(first)
+                                .chain(futures::stream::select(rest, progress))
+                        })
+                        .flatten(),
+                    )
+                }
+                None => stream,
+            };
             let stream = stream.chain(futures::stream::once(async {
                 Ok(Bytes::from_static(b"0000"))
             }));
@@ -331,7 +362,8 @@ impl<R: RepositoryAccess, A: AuthenticationService> GitProtocol<R, A> {
 
         // Wrap report-status in side-band if negotiated by the client.
         if let Some(max_payload) = sideband_max {
-            let packets = build_side_band_packets(result_bytes.as_ref(), max_payload);
+            let packets =
+                build_side_band_packets(result_bytes.as_ref(), max_payload, SideBand::PackfileData);
             let stream = futures::stream::iter(packets.into_iter().map(Ok)).chain(
                 futures::stream::once(async { Ok(Bytes::from_static(b"0000")) }),
             );
@@ -343,7 +375,7 @@ impl<R: RepositoryAccess, A: AuthenticationService> GitProtocol<R, A> {
     }
 }
 
-fn build_side_band_packets(chunk: &[u8], max_payload: usize) -> Vec<Bytes> {
+fn build_side_band_packets(chunk: &[u8], max_payload: usize, band: SideBand) -> Vec<Bytes> {
     if chunk.is_empty() {
         return Vec::new();
     }
@@ -357,7 +389,7 @@ fn build_side_band_packets(chunk: &[u8], max_payload: usize) -> Vec<Bytes> {
         let length = payload.len() + 5; // 4-byte length + 1-byte band
         let mut pkt = BytesMut::with_capacity(length);
         pkt.put(Bytes::from(format!("{length:04x}")));
-        pkt.put_u8(SideBand::PackfileData.value());
+        pkt.put_u8(band.value());
         pkt.put(payload);
         out.push(pkt.freeze());
         offset = end;
@@ -624,6 +656,105 @@ mod tests {
         assert!(raw.ends_with(b"0000"), "side-band stream should flush");
     }
 
+    /// With no-progress, the side-band stream carries only pack data on channel 1.
+    #[tokio::test]
+    async fn upload_pack_sideband_no_progress() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let (repo, commit) = build_repo_with_objects();
+
+        let mut proto = GitProtocol::new(repo, MockAuth);
+        let advertisement = proto.info_refs("git-upload-pack").await.expect("info_refs");
+        let nul = advertisement
+            .iter()
+            .position(|b| *b == 0)
+            .expect("capabilities");
+        let end = nul
+            + advertisement[nul..]
+                .iter()
+                .position(|b| *b == b'\n')
+                .unwrap();
+        let advertised = String::from_utf8(advertisement[nul + 1..end].to_vec()).expect("utf8");
+        let caps: Vec<&str> = advertised
+            .split_whitespace()
+            .filter(|cap| ["side-band-64k", "no-progress"].contains(cap))
+            .collect();
+        assert_eq!(caps.len(), 2, "{advertised}");
+
+        let mut request = BytesMut::new();
+        utils::add_pkt_line_string(
+            &mut request,
+            format!("want {} {}\n", commit.id, caps.join(" ")),
+        );
+        utils::add_pkt_line_string(&mut request, "done\n".to_string());
+
+        let mut stream = proto.upload_pack(&request).await.expect("upload-pack");
+        let mut out = BytesMut::new();
+        while let Some(chunk) = stream.next().await {
+            out.extend_from_slice(&chunk.expect("stream chunk"));
+        }
+
+        let mut out_bytes = out.freeze();
+        let (_len, line) = utils::read_pkt_line(&mut out_bytes);
+        assert_eq!(line, Bytes::from_static(b"NAK\n"));
+
+        let mut pack = Vec::new();
+        loop {
+            let (len, mut pkt) = utils::read_pkt_line(&mut out_bytes);
+            if len == 0 || pkt.is_empty() {
+                break;
+            }
+            let band = pkt.split_to(1)[0];
+            assert_eq!(band, SideBand::PackfileData.value(), "unexpected side-band");
+            pack.extend_from_slice(&pkt);
+        }
+        assert_eq!(&pack[..4], b"PACK");
+        assert!(out_bytes.is_empty(), "flush should end the stream");
+    }
+
+    /// Without no-progress, encoder progress is sent on side-band channel 2 next to the pack.
+    #[tokio::test]
+    async fn upload_pack_sideband_forwards_progress() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let (repo, commit) = build_repo_with_objects();
+
+        let mut proto = GitProtocol::new(repo, MockAuth);
+        let mut request = BytesMut::new();
+        utils::add_pkt_line_string(&mut request, format!("want {} side-band-64k\n", commit.id));
+        utils::add_pkt_line_string(&mut request, "done\n".to_string());
+
+        let mut stream = proto.upload_pack(&request).await.expect("upload-pack");
+        let mut out = BytesMut::new();
+        while let Some(chunk) = stream.next().await {
+            out.extend_from_slice(&chunk.expect("stream chunk"));
+        }
+
+        let mut out_bytes = out.freeze();
+        let (_len, line) = utils::read_pkt_line(&mut out_bytes);
+        assert_eq!(line, Bytes::from_static(b"NAK\n"));
+
+        let mut pack = Vec::new();
+        let mut progress = String::new();
+        loop {
+            let (len, mut pkt) = utils::read_pkt_line(&mut out_bytes);
+            if len == 0 || pkt.is_empty() {
+                break;
+            }
+            let band = pkt.split_to(1)[0];
+            if band == SideBand::PackfileData.value() {
+                pack.extend_from_slice(&pkt);
+            } else if band == SideBand::ProgressInfo.value() {
+                progress.push_str(std::str::from_utf8(&pkt).expect("utf8 progress"));
+            } else {
+                panic!("unexpected side-band {band}");
+            }
+        }
+        assert_eq!(&pack[..4], b"PACK");
+        assert!(progress.contains("Counting objects: 100% (3/3), done.\n"));
+        assert!(progress.contains("Compressing objects: 100% (3/3), done.\n"));
+        assert!(progress.contains("Writing objects: 100% (3/3)"));
+        assert!(out_bytes.is_empty(), "flush should end the stream");
+    }
+
     /// info_refs should include refs, capabilities, and object-format.
     #[tokio::test]
     async fn info_refs_includes_refs_and_caps() {
@@ -3,13 +3,18 @@
 
 pub mod core;
 pub mod http;
+pub mod odb;
 pub mod pack;
 pub mod smart;
+pub mod sqlite;
 pub mod ssh;
+#[cfg(test)]
+pub(crate) mod test_history;
 pub mod types;
 pub mod utils;
 
 // Re-export main interfaces
 pub use core::{AuthenticationService, GitProtocol, RepositoryAccess};
+pub use odb::MemoryRepository;
 
 pub use types::*;
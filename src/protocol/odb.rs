@@ -0,0 +1,545 @@
+//! [`RepositoryAccess`] on top of an [`ObjectDatabase`] and a [`RefStore`].
+//!
+//! The protocol layer works with hex strings and mixes objects, refs and hooks in one trait. An
+//! [`OdbRepository`] splits those concerns again: objects come from any object database (loose,
+//! packed, alternates or in memory), refs from a small compare-and-swap store, so a server only
+//! has to pick the two.
+
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+
+use super::{core::RepositoryAccess, types::ProtocolError};
+use crate::{
+    errors::GitError,
+    hash::{ObjectHash, set_hash_kind},
+    internal::{
+        object::{
+            ObjectTrait,
+            blob::Blob,
+            commit::Commit,
+            tag::Tag,
+            tree::{Tree, TreeItemMode},
+            types::ObjectType,
+        },
+        odb::{MemoryObjectDatabase, ObjectDatabase},
+        pack::{entry::Entry, thin::ThinPackCompleter},
+    },
+};
+
+/// Storage for references.
+///
+/// Updates are compare-and-swap: they only apply when the ref still has the expected value, which
+/// keeps concurrent pushes from overwriting each other.
+pub trait RefStore: Send + Sync {
+    /// All refs as (full name, target) pairs, sorted by name.
+    fn refs(&self) -> Result<Vec<(String, ObjectHash)>, GitError>;
+
+    /// Point `name` at `new`, or delete it when `new` is `None`.
+    ///
+    /// `old` is the value the ref must currently have, `None` meaning it must not exist yet.
+    /// Otherwise the update fails with [`GitError::RefUpdateRejected`] and nothing changes.
+    fn update(
+        &self,
+        name: &str,
+        old: Option<&ObjectHash>,
+        new: Option<&ObjectHash>,
+    ) -> Result<(), GitError>;
+}
+
+/// Refs kept in memory.
+#[derive(Debug, Default)]
+pub struct MemoryRefStore {
+    refs: Mutex<BTreeMap<String, ObjectHash>>,
+}
+
+impl MemoryRefStore {
+    /// An empty ref store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current target of `name`.
+    pub fn get(&self, name: &str) -> Option<ObjectHash> {
+        self.refs.lock().unwrap().get(name).copied()
+    }
+}
+
+impl RefStore for MemoryRefStore {
+    fn refs(&self) -> Result<Vec<(String, ObjectHash)>, GitError> {
+        Ok(self
+            .refs
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, hash)| (name.clone(), *hash))
+            .collect())
+    }
+
+    fn update(
+        &self,
+        name: &str,
+        old: Option<&ObjectHash>,
+        new: Option<&ObjectHash>,
+    ) -> Result<(), GitError> {
+        let mut refs = self.refs.lock().unwrap();
+        let current = refs.get(name);
+        if current != old {
+            return Err(GitError::RefUpdateRejected(match current {
+                Some(current) => format!("{name} is at {current}"),
+                None => format!("{name} does not exist"),
+            }));
+        }
+        match new {
+            Some(new) => refs.insert(name.to_string(), *new),
+            None => refs.remove(name),
+        };
+        Ok(())
+    }
+}
+
+/// A repository served from an object database and a ref store.
+///
+/// Cloning is cheap; clones share the same storage. Storage calls are synchronous, so the
+/// [`RepositoryAccess`] methods run them on Tokio's blocking pool.
+pub struct OdbRepository<D, R> {
+    odb: Arc<D>,
+    refs: Arc<R>,
+}
+
+impl<D, R> Clone for OdbRepository<D, R> {
+    fn clone(&self) -> Self {
+        OdbRepository {
+            odb: self.odb.clone(),
+            refs: self.refs.clone(),
+        }
+    }
+}
+
+impl<D: ObjectDatabase + Default + 'static, R: RefStore + Default + 'static> Default
+    for OdbRepository<D, R>
+{
+    fn default() -> Self {
+        Self::new(D::default(), R::default())
+    }
+}
+
+/// A repository held entirely in memory: objects by id in a [`MemoryObjectDatabase`], refs in a
+/// [`MemoryRefStore`].
+///
+/// It is a complete [`RepositoryAccess`], so a [`GitProtocol`](super::GitProtocol) can serve
+/// clones, fetches and pushes from it without touching disk. Start from
+/// `MemoryRepository::default()`, which uses the current thread's hash kind.
+pub type MemoryRepository = OdbRepository<MemoryObjectDatabase, MemoryRefStore>;
+
+impl<D: ObjectDatabase + 'static, R: RefStore + 'static> OdbRepository<D, R> {
+    /// Serve the objects of `odb` and the refs of `refs`.
+    pub fn new(odb: D, refs: R) -> Self {
+        OdbRepository {
+            odb: Arc::new(odb),
+            refs: Arc::new(refs),
+        }
+    }
+
+    /// The object database.
+    pub fn odb(&self) -> &D {
+        &self.odb
+    }
+
+    /// The ref store.
+    pub fn refs(&self) -> &R {
+        &self.refs
+    }
+
+    /// Every object reachable from `wants` but not from `haves`, in traversal order.
+    ///
+    /// Commits lead to their parents and tree, trees to their entries, tags to their target.
+    /// Submodule entries are not followed, and `haves` the database does not know are ignored.
+    pub fn reachable_objects(
+        &self,
+        wants: &[ObjectHash],
+        haves: &[ObjectHash],
+    ) -> Result<Vec<ObjectHash>, GitError> {
+        let mut excluded = HashSet::new();
+        let known_haves: Vec<ObjectHash> = haves
+            .iter()
+            .filter(|hash| self.odb.contains(hash).unwrap_or(false))
+            .copied()
+            .collect();
+        self.walk(&known_haves, &mut excluded)?;
+        self.walk(wants, &mut excluded)
+    }
+
+    /// Collect the objects reachable from `roots` that are not in `seen`, adding them to it.
+    fn walk(
+        &self,
+        roots: &[ObjectHash],
+        seen: &mut HashSet<ObjectHash>,
+    ) -> Result<Vec<ObjectHash>, GitError> {
+        let mut found = Vec::new();
+        let mut queue: VecDeque<ObjectHash> = roots.iter().copied().collect();
+        while let Some(hash) = queue.pop_front() {
+            if !seen.insert(hash) {
+                continue;
+            }
+            found.push(hash);
+            let (obj_type, data) = self.odb.read(&hash)?;
+            queue.extend(object_references(obj_type, &data, hash)?);
+        }
+        Ok(found)
+    }
+
+    fn parse_hash(&self, hash: &str) -> Result<ObjectHash, ProtocolError> {
+        let parsed = ObjectHash::from_str(hash)
+            .map_err(|e| ProtocolError::invalid_request(&format!("Invalid hash {hash}: {e}")))?;
+        if parsed.kind() != self.odb.hash_kind() {
+            return Err(ProtocolError::invalid_request(&format!(
+                "{hash} is not a {} object id",
+                self.odb.hash_kind()
+            )));
+        }
+        Ok(parsed)
+    }
+
+    /// Run storage work on the blocking pool: object databases and ref stores do file I/O and
+    /// decompression synchronously, which must not stall the async workers.
+    async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Self) -> Result<T, ProtocolError> + Send + 'static,
+    ) -> Result<T, ProtocolError> {
+        let repo = self.clone();
+        let kind = self.odb.hash_kind();
+        tokio::task::spawn_blocking(move || {
+            set_hash_kind(kind);
+            f(&repo)
+        })
+        .await
+        .map_err(|e| ProtocolError::repository_error(format!("Storage task failed: {e}")))?
+    }
+
+    fn write_object(&self, object: &impl ObjectTrait) -> Result<(), ProtocolError> {
+        let data = object.to_data().map_err(storage_error)?;
+        self.odb
+            .write(object.get_type(), &data)
+            .map_err(storage_error)?;
+        Ok(())
+    }
+}
+
+/// Objects `data` points to for a have/want walk: a commit's tree and parents, a tree's entries
+/// except submodules, a tag's target.
+pub(crate) fn object_references(
+    obj_type: ObjectType,
+    data: &[u8],
+    hash: ObjectHash,
+) -> Result<Vec<ObjectHash>, GitError> {
+    Ok(match obj_type {
+        ObjectType::Commit => {
+            let commit = Commit::from_bytes(data, hash)?;
+            let mut references = vec![commit.tree_id];
+            references.extend(commit.parent_commit_ids);
+            references
+        }
+        ObjectType::Tree => Tree::from_bytes(data, hash)?
+            .tree_items
+            .iter()
+            .filter(|item| item.mode != TreeItemMode::Commit)
+            .map(|item| item.id)
+            .collect(),
+        ObjectType::Tag => vec![Tag::from_bytes(data, hash)?.object_hash],
+        _ => Vec::new(),
+    })
+}
+
+fn storage_error(e: GitError) -> ProtocolError {
+    match e {
+        GitError::ObjectNotFound(hash) => ProtocolError::ObjectNotFound(hash),
+        e => ProtocolError::repository_error(e.to_string()),
+    }
+}
+
+#[async_trait]
+impl<D: ObjectDatabase + 'static, R: RefStore + 'static> RepositoryAccess for OdbRepository<D, R> {
+    async fn get_repository_refs(&self) -> Result<Vec<(String, String)>, ProtocolError> {
+        self.blocking(|repo| {
+            Ok(repo
+                .refs
+                .refs()
+                .map_err(storage_error)?
+                .into_iter()
+                .map(|(name, hash)| (name, hash.to_string()))
+                .collect())
+        })
+        .await
+    }
+
+    async fn has_object(&self, object_hash: &str) -> Result<bool, ProtocolError> {
+        let hash = self.parse_hash(object_hash)?;
+        self.blocking(move |repo| repo.odb.contains(&hash).map_err(storage_error))
+            .await
+    }
+
+    async fn get_object(&self, object_hash: &str) -> Result<Vec<u8>, ProtocolError> {
+        let hash = self.parse_hash(object_hash)?;
+        self.blocking(move |repo| {
+            let (_, data) = repo.odb.read(&hash).map_err(storage_error)?;
+            Ok(data)
+        })
+        .await
+    }
+
+    /// Index `pack_data` and hand it to [`ObjectDatabase::write_pack`]. Thin packs are completed
+    /// from the database first.
+    async fn store_pack_data(&self, pack_data: &[u8]) -> Result<(), ProtocolError> {
+        let pack_data = pack_data.to_vec();
+        let completer = self
+            .blocking(move |repo| {
+                let mut completer = ThinPackCompleter::new(pack_data)
+                    .map_err(|e| ProtocolError::Pack(format!("Failed to scan pack: {e}")))?;
+                completer
+                    .add_bases_from(|hash| {
+                        let (obj_type, data) = repo.odb.read(hash).ok()?;
+                        Some(Entry {
+                            obj_type,
+                            data,
+                            hash: *hash,
+                            chain_len: 0,
+                        })
+                    })
+                    .map_err(|e| ProtocolError::Pack(format!("Failed to add thin base: {e}")))?;
+                Ok(completer)
+            })
+            .await?;
+        let fixed = completer
+            .finish()
+            .await
+            .map_err(|e| ProtocolError::Pack(format!("Failed to complete thin pack: {e}")))?;
+        self.blocking(move |repo| repo.odb.write_pack(fixed).map_err(storage_error))
+            .await
+    }
+
+    /// A zero `new_hash` deletes the ref.
+    async fn update_reference(
+        &self,
+        ref_name: &str,
+        old_hash: Option<&str>,
+        new_hash: &str,
+    ) -> Result<(), ProtocolError> {
+        let old = old_hash.map(|hash| self.parse_hash(hash)).transpose()?;
+        let new = self.parse_hash(new_hash)?;
+        let new = (new_hash != ObjectHash::zero_str(new.kind())).then_some(new);
+        let ref_name = ref_name.to_string();
+        self.blocking(move |repo| {
+            repo.refs
+                .update(&ref_name, old.as_ref(), new.as_ref())
+                .map_err(storage_error)
+        })
+        .await
+    }
+
+    async fn get_objects_for_pack(
+        &self,
+        wants: &[String],
+        haves: &[String],
+    ) -> Result<Vec<String>, ProtocolError> {
+        let wants = wants
+            .iter()
+            .map(|hash| self.parse_hash(hash))
+            .collect::<Result<Vec<_>, _>>()?;
+        let haves = haves
+            .iter()
+            .map(|hash| self.parse_hash(hash))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.blocking(move |repo| {
+            Ok(repo
+                .reachable_objects(&wants, &haves)
+                .map_err(storage_error)?
+                .iter()
+                .map(ObjectHash::to_string)
+                .collect())
+        })
+        .await
+    }
+
+    async fn has_default_branch(&self) -> Result<bool, ProtocolError> {
+        self.blocking(|repo| {
+            Ok(repo
+                .refs
+                .refs()
+                .map_err(storage_error)?
+                .iter()
+                .any(|(name, _)| name.starts_with("refs/heads/")))
+        })
+        .await
+    }
+
+    async fn post_receive_hook(&self) -> Result<(), ProtocolError> {
+        Ok(())
+    }
+
+    /// Write the objects straight to the database.
+    async fn handle_pack_objects(
+        &self,
+        commits: Vec<Commit>,
+        trees: Vec<Tree>,
+        blobs: Vec<Blob>,
+    ) -> Result<(), ProtocolError> {
+        self.blocking(move |repo| {
+            for blob in &blobs {
+                repo.write_object(blob)?;
+            }
+            for tree in &trees {
+                repo.write_object(tree)?;
+            }
+            for commit in &commits {
+                repo.write_object(commit)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::{MemoryRefStore, OdbRepository, RefStore};
+    use crate::{
+        errors::GitError,
+        hash::{HashKind, ObjectHash, set_hash_kind_for_test},
+        internal::{
+            object::{ObjectTrait, blob::Blob, commit::Commit, types::ObjectType},
+            odb::{MemoryObjectDatabase, ObjectDatabase},
+        },
+        protocol::{RepositoryAccess, pack::PackGenerator, test_history::TestHistory},
+    };
+
+    /// Two commits; returns (first, second, blobs of the second tree).
+    fn history(odb: &MemoryObjectDatabase) -> (Commit, Commit, Vec<Blob>) {
+        let history = TestHistory::new();
+        for blob in &history.blobs {
+            odb.write_object(blob).unwrap();
+        }
+        for tree in &history.trees {
+            odb.write_object(tree).unwrap();
+        }
+        odb.write_object(&history.first).unwrap();
+        odb.write_object(&history.second).unwrap();
+        let [_, new] = history.blobs;
+        (history.first, history.second, vec![new])
+    }
+
+    #[test]
+    fn test_memory_ref_store_compare_and_swap() {
+        let refs = MemoryRefStore::new();
+        let a = ObjectHash::Sha1([1; 20]);
+        let b = ObjectHash::Sha1([2; 20]);
+
+        refs.update("refs/heads/main", None, Some(&a)).unwrap();
+        assert!(matches!(
+            refs.update("refs/heads/main", None, Some(&b)),
+            Err(GitError::RefUpdateRejected(_))
+        ));
+        assert!(matches!(
+            refs.update("refs/heads/main", Some(&b), Some(&a)),
+            Err(GitError::RefUpdateRejected(_))
+        ));
+        refs.update("refs/heads/main", Some(&a), Some(&b)).unwrap();
+        assert_eq!(refs.get("refs/heads/main"), Some(b));
+        refs.update("refs/heads/main", Some(&b), None).unwrap();
+        assert!(refs.refs().unwrap().is_empty());
+    }
+
+    /// Objects, refs and the have/want walk work through `RepositoryAccess`.
+    #[tokio::test]
+    async fn test_odb_repository_access() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let repo = OdbRepository::new(MemoryObjectDatabase::new(), MemoryRefStore::new());
+        let (c1, c2, new_blobs) = history(repo.odb());
+        let zero = ObjectHash::zero_str(HashKind::Sha1);
+
+        assert!(!repo.has_default_branch().await.unwrap());
+        repo.update_reference("refs/heads/main", None, &c2.id.to_string())
+            .await
+            .unwrap();
+        assert!(repo.has_default_branch().await.unwrap());
+        assert_eq!(
+            repo.get_repository_refs().await.unwrap(),
+            vec![("refs/heads/main".to_string(), c2.id.to_string())]
+        );
+        assert!(
+            repo.update_reference("refs/heads/main", Some(&c1.id.to_string()), &zero)
+                .await
+                .is_err()
+        );
+
+        assert!(repo.has_object(&c1.id.to_string()).await.unwrap());
+        assert!(repo.commit_exists(&c2.id.to_string()).await.unwrap());
+        assert_eq!(
+            repo.get_blob(&new_blobs[0].id.to_string())
+                .await
+                .unwrap()
+                .data,
+            b"new"
+        );
+        assert!(repo.get_object(&"ab".repeat(32)).await.is_err());
+
+        let all = repo
+            .get_objects_for_pack(&[c2.id.to_string()], &[])
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 6);
+        let incremental = repo
+            .get_objects_for_pack(&[c2.id.to_string()], &[c1.id.to_string()])
+            .await
+            .unwrap();
+        assert_eq!(
+            incremental,
+            vec![
+                c2.id.to_string(),
+                c2.tree_id.to_string(),
+                new_blobs[0].id.to_string()
+            ]
+        );
+
+        repo.update_reference("refs/heads/main", Some(&c2.id.to_string()), &zero)
+            .await
+            .unwrap();
+        assert!(repo.get_repository_refs().await.unwrap().is_empty());
+    }
+
+    /// A pack generated from one repository is stored into another, object for object.
+    #[tokio::test]
+    async fn test_odb_repository_store_pack_data() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let source = OdbRepository::new(MemoryObjectDatabase::new(), MemoryRefStore::new());
+        let (_, c2, _) = history(source.odb());
+
+        let mut stream = PackGenerator::new(&source)
+            .generate_full_pack(vec![c2.id.to_string()])
+            .await
+            .unwrap();
+        let mut pack = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            pack.extend(chunk);
+        }
+
+        let target = OdbRepository::new(MemoryObjectDatabase::new(), MemoryRefStore::new());
+        target.store_pack_data(&pack).await.unwrap();
+        assert_eq!(
+            target.odb().hashes().unwrap(),
+            source.odb().hashes().unwrap()
+        );
+        assert_eq!(
+            target.odb().read_header(&c2.id).unwrap().0,
+            ObjectType::Commit
+        );
+        assert_eq!(target.odb().read(&c2.id).unwrap().1, c2.to_data().unwrap());
+        assert!(target.store_pack_data(b"PACK").await.is_err());
+    }
+}
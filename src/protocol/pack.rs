@@ -2,8 +2,9 @@
 //! trees/blobs, and either stream packs to clients or unpack uploads for server-side ingestion.
 
 use std::{
-    collections::{HashSet, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     io::Cursor,
+    sync::Arc,
 };
 
 use bytes::Bytes;
@@ -12,11 +13,26 @@ use tokio_stream::wrappers::ReceiverStream;
 
 use super::{core::RepositoryAccess, types::ProtocolError};
 use crate::{
+    errors::GitError,
     hash::ObjectHash,
     internal::{
         metadata::{EntryMeta, MetaAttached},
-        object::{ObjectTrait, blob::Blob, commit::Commit, tree::Tree, types::ObjectType},
-        pack::{Pack, encode::PackEncoder, entry::Entry},
+        object::{
+            ObjectTrait,
+            blob::Blob,
+            commit::Commit,
+            tree::{Tree, TreeItemMode},
+            types::ObjectType,
+        },
+        pack::{
+            Pack,
+            cancel::CancellationToken,
+            encode::PackEncoder,
+            entry::Entry,
+            limits::DecodeLimits,
+            progress::ProgressObserver,
+            thin::{FixedPack, ThinPackCompleter, ThinPackScan},
+        },
     },
 };
 
@@ -29,6 +45,28 @@ where
     R: RepositoryAccess,
 {
     repo_access: &'a R,
+    decode_limits: DecodeLimits,
+    thin_pack: bool,
+    stream_options: PackStreamOptions,
+}
+
+/// Encoder settings for generated packs.
+#[derive(Clone, Default)]
+struct PackStreamOptions {
+    progress: Option<Arc<dyn ProgressObserver>>,
+    ref_delta: bool,
+    /// Objects the client has that may serve as delta bases without being sent.
+    external_bases: Vec<MetaAttached<Entry, EntryMeta>>,
+}
+
+/// Objects reachable from a set of commits.
+#[derive(Default)]
+struct ReachableObjects {
+    commits: Vec<Commit>,
+    trees: Vec<Tree>,
+    blobs: Vec<Blob>,
+    /// Path of every tree and blob where the walk first met it; root trees have the empty path.
+    paths: HashMap<ObjectHash, String>,
 }
 
 impl<'a, R> PackGenerator<'a, R>
@@ -36,7 +74,39 @@ where
     R: RepositoryAccess,
 {
     pub fn new(repo_access: &'a R) -> Self {
-        Self { repo_access }
+        Self {
+            repo_access,
+            decode_limits: DecodeLimits::default(),
+            thin_pack: false,
+            stream_options: PackStreamOptions::default(),
+        }
+    }
+
+    /// Limits applied to packs received by [`Self::unpack_stream`] and
+    /// [`Self::fix_thin_pack`]. Servers accepting pushes should set them, since a pack is free to
+    /// declare sizes far beyond the memory of the machine decoding it.
+    pub fn with_decode_limits(mut self, limits: DecodeLimits) -> Self {
+        self.decode_limits = limits;
+        self
+    }
+
+    /// Report encoding progress of generated packs to `observer`.
+    pub fn with_progress(mut self, observer: Arc<dyn ProgressObserver>) -> Self {
+        self.stream_options.progress = Some(observer);
+        self
+    }
+
+    /// Write deltas in generated packs as REF_DELTA, for clients that did not ask for `ofs-delta`.
+    pub fn with_ref_delta(mut self, enabled: bool) -> Self {
+        self.stream_options.ref_delta = enabled;
+        self
+    }
+
+    /// Let [`Self::generate_incremental_pack`] produce a thin pack, for clients that sent
+    /// `thin-pack`: trees and blobs of the `have` commits may be delta bases without being sent.
+    pub fn with_thin_pack(mut self, enabled: bool) -> Self {
+        self.thin_pack = enabled;
+        self
     }
 
     /// Generate a full pack containing all requested objects
@@ -50,8 +120,9 @@ where
         let all_objects = self.collect_all_objects(want).await?;
 
         // Generate pack data
+        let options = self.stream_options.clone();
         tokio::spawn(async move {
-            if let Err(e) = Self::generate_pack_stream(all_objects, tx).await {
+            if let Err(e) = Self::generate_pack_stream(all_objects, tx, options).await {
                 tracing::error!("Failed to generate pack stream: {}", e);
             }
         });
@@ -71,14 +142,19 @@ where
         let wanted_objects = self.collect_all_objects(want).await?;
 
         // Collect objects for have commits (to exclude)
-        let have_objects = self.collect_all_objects(have).await?;
+        let have_objects = self.collect_all_objects(have.clone()).await?;
 
         // Filter out objects that are already in 'have'
-        let incremental_objects = Self::filter_objects(wanted_objects, have_objects);
+        let mut options = self.stream_options.clone();
+        let incremental_objects = Self::filter_objects(wanted_objects, &have_objects);
+        if self.thin_pack {
+            options.external_bases =
+                Self::thin_pack_bases(&have, &have_objects, &incremental_objects);
+        }
 
         // Generate pack data
         tokio::spawn(async move {
-            if let Err(e) = Self::generate_pack_stream(incremental_objects, tx).await {
+            if let Err(e) = Self::generate_pack_stream(incremental_objects, tx, options).await {
                 tracing::error!("Failed to generate incremental pack stream: {}", e);
             }
         });
@@ -101,9 +177,24 @@ where
         let trees_clone = trees.clone();
         let blobs_clone = blobs.clone();
 
+        // Thin packs refer to bases the repository already has; append those and let the decoder
+        // resolve every delta in one pass
+        let scan = ThinPackScan::new(Vec::from(pack_data), self.decode_limits)
+            .map_err(|e| ProtocolError::Pack(format!("Failed to scan pack: {e}")))?;
+        let mut bases = Vec::new();
+        for hash in scan.ref_delta_bases() {
+            if let Some(base) = self.read_base(hash).await? {
+                bases.push(base);
+            }
+        }
+        let pack_data = scan
+            .complete(&bases)
+            .map_err(|e| ProtocolError::Pack(format!("Failed to complete thin pack: {e}")))?;
+        drop(bases);
+
         // Create a Pack instance for decoding
-        let mut pack = Pack::new(None, None, None, true);
-        let mut cursor = Cursor::new(pack_data.to_vec());
+        let mut pack = Pack::new(None, None, None, true).with_decode_limits(self.decode_limits);
+        let mut cursor = Cursor::new(pack_data);
 
         // Decode the pack and collect entries
         pack.decode(
@@ -146,14 +237,84 @@ where
         Ok((commits_result, trees_result, blobs_result))
     }
 
+    /// Complete a thin pack against the repository, as `git index-pack --fix-thin` does, and
+    /// return the self-contained pack with its index.
+    ///
+    /// Missing bases are read with [`Self::read_base`].
+    pub async fn fix_thin_pack(&self, pack_data: Vec<u8>) -> Result<FixedPack, ProtocolError> {
+        let mut completer = ThinPackCompleter::with_limits(pack_data, self.decode_limits)
+            .map_err(|e| ProtocolError::Pack(format!("Failed to scan pack: {e}")))?;
+        self.resolve_thin_bases(&mut completer).await?;
+        Self::finish_thin_pack(completer).await
+    }
+
+    /// Feed every base the repository has into `completer`, until no more progress is made.
+    async fn resolve_thin_bases(
+        &self,
+        completer: &mut ThinPackCompleter,
+    ) -> Result<(), ProtocolError> {
+        loop {
+            let mut progress = false;
+            for hash in completer.missing_bases() {
+                if !completer.needs_base(&hash) {
+                    continue;
+                }
+                let Some(base) = self.read_base(&hash).await? else {
+                    continue;
+                };
+                completer
+                    .add_base(base)
+                    .map_err(|e| ProtocolError::Pack(format!("Failed to add thin base: {e}")))?;
+                progress = true;
+            }
+            if !progress || completer.is_complete() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Read a thin-pack base from the repository, or `None` if it does not have it.
+    ///
+    /// [`RepositoryAccess::get_object`] only returns object content, so the type is recovered by
+    /// matching the hash.
+    async fn read_base(&self, hash: &ObjectHash) -> Result<Option<Entry>, ProtocolError> {
+        let hash_str = hash.to_string();
+        if !self.repo_access.has_object(&hash_str).await? {
+            return Ok(None);
+        }
+        let data = self.repo_access.get_object(&hash_str).await?;
+        let obj_type = [
+            ObjectType::Commit,
+            ObjectType::Tree,
+            ObjectType::Blob,
+            ObjectType::Tag,
+        ]
+        .into_iter()
+        .find(|t| ObjectHash::from_type_and_data(*t, &data) == *hash)
+        .ok_or_else(|| {
+            ProtocolError::repository_error(format!("Object {hash_str} does not match its content"))
+        })?;
+        Ok(Some(Entry {
+            obj_type,
+            data,
+            hash: *hash,
+            chain_len: 0,
+        }))
+    }
+
+    async fn finish_thin_pack(completer: ThinPackCompleter) -> Result<FixedPack, ProtocolError> {
+        completer
+            .finish()
+            .await
+            .map_err(|e| ProtocolError::Pack(format!("Failed to complete thin pack: {e}")))
+    }
+
     /// Collect all objects reachable from the given commit hashes
     async fn collect_all_objects(
         &self,
         commit_hashes: Vec<String>,
-    ) -> Result<(Vec<Commit>, Vec<Tree>, Vec<Blob>), ProtocolError> {
-        let mut commits = Vec::new();
-        let mut trees = Vec::new();
-        let mut blobs = Vec::new();
+    ) -> Result<ReachableObjects, ProtocolError> {
+        let mut objects = ReachableObjects::default();
 
         let mut visited_commits = HashSet::new();
         let mut visited_trees = HashSet::new();
@@ -190,25 +351,25 @@ where
             // Collect tree objects
             Box::pin(self.collect_tree_objects(
                 &commit.tree_id.to_string(),
-                &mut trees,
-                &mut blobs,
+                "",
+                &mut objects,
                 &mut visited_trees,
                 &mut visited_blobs,
             ))
             .await?;
 
-            commits.push(commit);
+            objects.commits.push(commit);
         }
 
-        Ok((commits, trees, blobs))
+        Ok(objects)
     }
 
-    /// Recursively collect tree and blob objects
+    /// Recursively collect tree and blob objects below the tree at `path`
     async fn collect_tree_objects(
         &self,
         tree_hash: &str,
-        trees: &mut Vec<Tree>,
-        blobs: &mut Vec<Blob>,
+        path: &str,
+        objects: &mut ReachableObjects,
         visited_trees: &mut HashSet<String>,
         visited_blobs: &mut HashSet<String>,
     ) -> Result<(), ProtocolError> {
@@ -223,12 +384,13 @@ where
 
         for entry in &tree.tree_items {
             let entry_hash = entry.id.to_string();
+            let entry_path = join_path(path, &entry.name);
             match entry.mode {
                 crate::internal::object::tree::TreeItemMode::Tree => {
                     Box::pin(self.collect_tree_objects(
                         &entry_hash,
-                        trees,
-                        blobs,
+                        &entry_path,
+                        objects,
                         visited_trees,
                         visited_blobs,
                     ))
@@ -244,31 +406,99 @@ where
                             "Failed to get blob {entry_hash}: {e}"
                         ))
                     })?;
-                    blobs.push(blob);
+                    objects.paths.insert(blob.id, entry_path);
+                    objects.blobs.push(blob);
                 }
                 _ => {}
             }
         }
 
-        trees.push(tree);
+        objects.paths.insert(tree.id, path.to_string());
+        objects.trees.push(tree);
         Ok(())
     }
 
+    /// Trees and blobs of the `have` commits themselves that sit at a path where the pack sends
+    /// a different object, the boundary Git also uses as thin-pack bases. Their history is left
+    /// out since older versions rarely make better bases, and objects at unchanged paths would
+    /// only crowd the delta window.
+    fn thin_pack_bases(
+        have: &[String],
+        have_objects: &ReachableObjects,
+        sent: &ReachableObjects,
+    ) -> Vec<MetaAttached<Entry, EntryMeta>> {
+        let trees: HashMap<ObjectHash, &Tree> =
+            have_objects.trees.iter().map(|t| (t.id, t)).collect();
+        let blobs: HashMap<ObjectHash, &Blob> =
+            have_objects.blobs.iter().map(|b| (b.id, b)).collect();
+        let sent_paths: HashSet<&str> = sent
+            .trees
+            .iter()
+            .map(|t| t.id)
+            .chain(sent.blobs.iter().map(|b| b.id))
+            .filter_map(|id| sent.paths.get(&id).map(String::as_str))
+            .collect();
+        let base = |entry: Entry, path: &str| MetaAttached {
+            inner: entry,
+            meta: EntryMeta {
+                file_path: Some(path.to_string()),
+                ..EntryMeta::new()
+            },
+        };
+
+        let mut bases = Vec::new();
+        let mut visited = HashSet::new();
+        let mut queue: Vec<(ObjectHash, String)> = have_objects
+            .commits
+            .iter()
+            .filter(|c| have.contains(&c.id.to_string()))
+            .map(|c| (c.tree_id, String::new()))
+            .collect();
+        while let Some((tree_id, path)) = queue.pop() {
+            let Some(tree) = trees.get(&tree_id) else {
+                continue;
+            };
+            if !visited.insert(tree_id) {
+                continue;
+            }
+            for item in &tree.tree_items {
+                let item_path = join_path(&path, &item.name);
+                match item.mode {
+                    TreeItemMode::Tree => queue.push((item.id, item_path)),
+                    TreeItemMode::Blob | TreeItemMode::BlobExecutable => {
+                        if sent_paths.contains(item_path.as_str())
+                            && let Some(blob) = blobs.get(&item.id)
+                            && visited.insert(item.id)
+                        {
+                            bases.push(base(Entry::from((*blob).clone()), &item_path));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if sent_paths.contains(path.as_str()) {
+                bases.push(base(Entry::from((*tree).clone()), &path));
+            }
+        }
+        bases
+    }
+
     /// Filter objects to exclude those already in 'have'
-    fn filter_objects(
-        wanted: (Vec<Commit>, Vec<Tree>, Vec<Blob>),
-        have: (Vec<Commit>, Vec<Tree>, Vec<Blob>),
-    ) -> (Vec<Commit>, Vec<Tree>, Vec<Blob>) {
-        let (wanted_commits, wanted_trees, wanted_blobs) = wanted;
-        let (have_commits, have_trees, have_blobs) = have;
+    fn filter_objects(wanted: ReachableObjects, have: &ReachableObjects) -> ReachableObjects {
+        let ReachableObjects {
+            commits: wanted_commits,
+            trees: wanted_trees,
+            blobs: wanted_blobs,
+            paths,
+        } = wanted;
 
         // Create hash sets for efficient lookup
         let have_commit_hashes: HashSet<String> =
-            have_commits.iter().map(|c| c.id.to_string()).collect();
+            have.commits.iter().map(|c| c.id.to_string()).collect();
         let have_tree_hashes: HashSet<String> =
-            have_trees.iter().map(|t| t.id.to_string()).collect();
+            have.trees.iter().map(|t| t.id.to_string()).collect();
         let have_blob_hashes: HashSet<String> =
-            have_blobs.iter().map(|b| b.id.to_string()).collect();
+            have.blobs.iter().map(|b| b.id.to_string()).collect();
 
         // Filter out objects that are in 'have'
         let filtered_commits: Vec<Commit> = wanted_commits
@@ -286,54 +516,71 @@ where
             .filter(|b| !have_blob_hashes.contains(&b.id.to_string()))
             .collect();
 
-        (filtered_commits, filtered_trees, filtered_blobs)
+        ReachableObjects {
+            commits: filtered_commits,
+            trees: filtered_trees,
+            blobs: filtered_blobs,
+            paths,
+        }
     }
 
     /// Generate pack stream from objects
     async fn generate_pack_stream(
-        objects: (Vec<Commit>, Vec<Tree>, Vec<Blob>),
+        objects: ReachableObjects,
         tx: mpsc::Sender<Vec<u8>>,
+        options: PackStreamOptions,
     ) -> Result<(), ProtocolError> {
-        let (commits, trees, blobs) = objects;
-
-        // Convert objects to entries
+        let ReachableObjects {
+            commits,
+            trees,
+            blobs,
+            mut paths,
+        } = objects;
+
+        // Convert objects to entries; paths let the encoder group versions of the same file
         let mut entries = Vec::new();
 
         for commit in commits {
-            entries.push(Entry::from(commit));
-        }
-
-        for tree in trees {
-            entries.push(Entry::from(tree));
+            entries.push(MetaAttached {
+                inner: Entry::from(commit),
+                meta: EntryMeta::new(),
+            });
         }
 
-        for blob in blobs {
-            entries.push(Entry::from(blob));
+        let trees = trees.into_iter().map(Entry::from);
+        for entry in trees.chain(blobs.into_iter().map(Entry::from)) {
+            let meta = EntryMeta {
+                file_path: paths.remove(&entry.hash),
+                ..EntryMeta::new()
+            };
+            entries.push(MetaAttached { inner: entry, meta });
         }
 
         // Create PackEncoder and encode entries
         let (pack_tx, mut pack_rx) = mpsc::channel(1024);
         let (entry_tx, entry_rx) = mpsc::channel(1024);
-        let mut encoder = PackEncoder::new(entries.len(), 10, pack_tx); // window_size = 10
+        // Cancelled when the client goes away, so encoding stops instead of running to the end.
+        let cancellation = CancellationToken::new();
+        let mut encoder = PackEncoder::new(entries.len(), 10, pack_tx) // window_size = 10
+            .with_cancellation(cancellation.clone())
+            .with_ref_delta(options.ref_delta)
+            .with_external_bases(options.external_bases);
+        if let Some(progress) = options.progress {
+            encoder = encoder.with_progress(progress);
+        }
 
         // Spawn encoding task
         tokio::spawn(async move {
-            if let Err(e) = encoder.encode(entry_rx).await {
-                tracing::error!("Failed to encode pack: {}", e);
+            match encoder.encode(entry_rx).await {
+                Ok(()) | Err(GitError::Cancelled) => {}
+                Err(e) => tracing::error!("Failed to encode pack: {}", e),
             }
         });
 
         // Send entries to encoder
         tokio::spawn(async move {
             for entry in entries {
-                if entry_tx
-                    .send(MetaAttached {
-                        inner: entry,
-                        meta: EntryMeta::new(),
-                    })
-                    .await
-                    .is_err()
-                {
+                if entry_tx.send(entry).await.is_err() {
                     break; // Receiver dropped
                 }
             }
@@ -343,6 +590,7 @@ where
         // Forward pack data to output channel
         while let Some(chunk) = pack_rx.recv().await {
             if tx.send(chunk).await.is_err() {
+                cancellation.cancel();
                 break; // Receiver dropped
             }
         }
@@ -351,6 +599,15 @@ where
     }
 }
 
+/// `name` inside the directory at `path`, with the empty path as the root.
+fn join_path(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{path}/{name}")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use async_trait::async_trait;
@@ -359,12 +616,17 @@ mod tests {
     use super::*;
     use crate::{
         hash::{HashKind, set_hash_kind_for_test},
-        internal::object::{
-            blob::Blob,
-            commit::Commit,
-            signature::{Signature, SignatureType},
-            tree::{Tree, TreeItem, TreeItemMode},
+        internal::{
+            object::{
+                blob::Blob,
+                commit::Commit,
+                signature::{Signature, SignatureType},
+                tree::{Tree, TreeItem, TreeItemMode},
+            },
+            odb::ObjectDatabase,
+            pack::{cache_object::CacheObjectInfo, test_pack_builder::TestPackBuilder},
         },
+        protocol::MemoryRepository,
     };
     /// Dummy repository access for testing
     #[derive(Clone)]
@@ -433,12 +695,14 @@ mod tests {
 
         let (tx, mut rx) = mpsc::channel::<Vec<u8>>(64);
         PackGenerator::<DummyRepoAccess>::generate_pack_stream(
-            (
-                vec![commit.clone()],
-                vec![tree.clone()],
-                vec![blob1.clone(), blob2.clone()],
-            ),
+            ReachableObjects {
+                commits: vec![commit.clone()],
+                trees: vec![tree.clone()],
+                blobs: vec![blob1.clone(), blob2.clone()],
+                paths: HashMap::new(),
+            },
             tx,
+            PackStreamOptions::default(),
         )
         .await
         .unwrap();
@@ -478,4 +742,243 @@ mod tests {
         run_pack_roundtrip(HashKind::Sha1).await;
         run_pack_roundtrip(HashKind::Sha256).await;
     }
+
+    /// A thin pack whose REF_DELTA base lives in the repository unpacks after the base is
+    /// appended.
+    #[tokio::test]
+    async fn test_unpack_thin_pack_uses_repository_base() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let base = Blob::from_content("content the server already stores");
+        let target = b"content the server already stores, edited by the client";
+        let mut builder = TestPackBuilder::new();
+        let delta = builder.add_ref_delta(base.id, &base.data, ObjectType::Blob, target);
+
+        let repo = MemoryRepository::default();
+        repo.odb().write_object(&base).unwrap();
+        let generator = PackGenerator::new(&repo);
+        let fixed = generator.fix_thin_pack(builder.pack_bytes()).await.unwrap();
+        assert_eq!(fixed.appended, vec![base.id]);
+
+        let (_, _, blobs) = generator
+            .unpack_stream(Bytes::from(builder.pack_bytes()))
+            .await
+            .unwrap();
+        let mut ids: Vec<_> = blobs.iter().map(|b| b.id).collect();
+        ids.sort();
+        let mut expected = vec![base.id, delta.hash];
+        expected.sort();
+        assert_eq!(ids, expected);
+
+        let empty = DummyRepoAccess;
+        assert!(
+            PackGenerator::new(&empty)
+                .unpack_stream(Bytes::from(builder.pack_bytes()))
+                .await
+                .is_err()
+        );
+    }
+
+    /// With `thin-pack`, a small edit to a large blob is sent as a delta against the client's
+    /// copy, and the pack completes against the repository.
+    #[tokio::test]
+    async fn test_incremental_thin_pack_deltas_against_have() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let signature = |kind| Signature::new(kind, "tester".to_string(), "t@example.com".into());
+        let repo = MemoryRepository::default();
+        let commit_with = |content: Vec<u8>, parents: Vec<ObjectHash>| {
+            let blob = Blob::from_content_bytes(content);
+            let tree = Tree::from_tree_items(vec![TreeItem::new(
+                TreeItemMode::Blob,
+                blob.id,
+                "big.bin".to_string(),
+            )])
+            .unwrap();
+            let commit = Commit::new(
+                signature(SignatureType::Author),
+                signature(SignatureType::Committer),
+                tree.id,
+                parents,
+                "commit",
+            );
+            repo.odb().write_object(&blob).unwrap();
+            repo.odb().write_object(&tree).unwrap();
+            repo.odb().write_object(&commit).unwrap();
+            (commit, blob)
+        };
+        let content: Vec<u8> = (0..8192u32).flat_map(|i| (i * 31).to_le_bytes()).collect();
+        let (old, old_blob) = commit_with(content.clone(), vec![]);
+        let mut edited = content;
+        edited[5000..5011].copy_from_slice(b"a tiny edit");
+        let (new, new_blob) = commit_with(edited, vec![old.id]);
+
+        let fetch = |thin: bool| {
+            let repo = repo.clone();
+            let (want, have) = (vec![new.id.to_string()], vec![old.id.to_string()]);
+            async move {
+                let generator = PackGenerator::new(&repo).with_thin_pack(thin);
+                let mut stream = generator
+                    .generate_incremental_pack(want, have)
+                    .await
+                    .unwrap();
+                let mut pack = Vec::new();
+                while let Some(chunk) = tokio_stream::StreamExt::next(&mut stream).await {
+                    pack.extend(chunk);
+                }
+                pack
+            }
+        };
+
+        let full = fetch(false).await;
+        let thin = fetch(true).await;
+        assert!(full.len() > old_blob.data.len() / 2, "{} bytes", full.len());
+        assert!(thin.len() < 1024, "thin pack is {} bytes", thin.len());
+
+        let generator = PackGenerator::new(&repo);
+        let fixed = generator.fix_thin_pack(thin.clone()).await.unwrap();
+        assert_eq!(fixed.appended, vec![old_blob.id]);
+        let (commits, _, blobs) = generator.unpack_stream(Bytes::from(thin)).await.unwrap();
+        assert_eq!(commits[0].id, new.id);
+        assert!(blobs.iter().any(|b| b.id == new_blob.id));
+    }
+
+    /// Only the `have` versions of paths the pack changes are offered as thin-pack bases, and the
+    /// edited file still goes out as a REF_DELTA against the client's copy.
+    #[tokio::test]
+    async fn test_thin_pack_bases_follow_changed_paths() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let signature = |kind| Signature::new(kind, "tester".to_string(), "t@example.com".into());
+        let repo = MemoryRepository::default();
+        let content = |seed: u32| -> Vec<u8> {
+            (0..4096u32)
+                .flat_map(|i| (i.wrapping_mul(seed)).to_le_bytes())
+                .collect()
+        };
+        let commit_with = |edited: Vec<u8>, parents: Vec<ObjectHash>| {
+            let edited = Blob::from_content_bytes(edited);
+            let other = Blob::from_content_bytes(content(17));
+            let nested = Blob::from_content_bytes(content(29));
+            let dir = Tree::from_tree_items(vec![TreeItem::new(
+                TreeItemMode::Blob,
+                nested.id,
+                "nested.bin".to_string(),
+            )])
+            .unwrap();
+            let root = Tree::from_tree_items(vec![
+                TreeItem::new(TreeItemMode::Blob, edited.id, "edited.bin".to_string()),
+                TreeItem::new(TreeItemMode::Tree, dir.id, "dir".to_string()),
+                TreeItem::new(TreeItemMode::Blob, other.id, "other.bin".to_string()),
+            ])
+            .unwrap();
+            let commit = Commit::new(
+                signature(SignatureType::Author),
+                signature(SignatureType::Committer),
+                root.id,
+                parents,
+                "commit",
+            );
+            for blob in [&edited, &other, &nested] {
+                repo.odb().write_object(blob).unwrap();
+            }
+            repo.odb().write_object(&dir).unwrap();
+            repo.odb().write_object(&root).unwrap();
+            repo.odb().write_object(&commit).unwrap();
+            (commit, root, edited)
+        };
+        let original = content(31);
+        let (old, old_root, old_blob) = commit_with(original.clone(), vec![]);
+        let mut edited = original;
+        edited[9000..9011].copy_from_slice(b"a tiny edit");
+        let (new, _, new_blob) = commit_with(edited, vec![old.id]);
+
+        let generator = PackGenerator::new(&repo).with_thin_pack(true);
+        let (want, have) = (vec![new.id.to_string()], vec![old.id.to_string()]);
+        let have_objects = generator.collect_all_objects(have.clone()).await.unwrap();
+        let sent = PackGenerator::<MemoryRepository>::filter_objects(
+            generator.collect_all_objects(want.clone()).await.unwrap(),
+            &have_objects,
+        );
+        let bases = PackGenerator::<MemoryRepository>::thin_pack_bases(&have, &have_objects, &sent);
+        let mut offered: Vec<_> = bases
+            .iter()
+            .map(|b| (b.inner.hash, b.meta.file_path.clone().unwrap()))
+            .collect();
+        offered.sort();
+        let mut expected = vec![
+            (old_root.id, String::new()),
+            (old_blob.id, "edited.bin".to_string()),
+        ];
+        expected.sort();
+        assert_eq!(offered, expected);
+
+        let mut stream = generator
+            .generate_incremental_pack(want, have)
+            .await
+            .unwrap();
+        let mut pack = Vec::new();
+        while let Some(chunk) = tokio_stream::StreamExt::next(&mut stream).await {
+            pack.extend(chunk);
+        }
+        let count = u32::from_be_bytes(pack[8..12].try_into().unwrap());
+        let mut reader = Cursor::new(&pack[..]);
+        reader.set_position(12);
+        let mut offset = 12;
+        let ref_bases: Vec<ObjectHash> = (0..count)
+            .filter_map(|_| {
+                match Pack::decode_pack_object(&mut reader, &mut offset)
+                    .unwrap()
+                    .unwrap()
+                    .info
+                {
+                    CacheObjectInfo::HashDelta(base, _) => Some(base),
+                    _ => None,
+                }
+            })
+            .collect();
+        assert!(ref_bases.contains(&old_blob.id), "{ref_bases:?}");
+
+        let (_, _, blobs) = generator.unpack_stream(Bytes::from(pack)).await.unwrap();
+        assert!(blobs.iter().any(|b| b.id == new_blob.id));
+    }
+
+    /// Decode limits set on the generator apply to received packs, thin or not.
+    #[tokio::test]
+    async fn test_unpack_stream_applies_decode_limits() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let base = Blob::from_content("content the server already stores");
+        let target = b"content the server already stores, edited by the client";
+        let mut builder = TestPackBuilder::new();
+        builder.add_ref_delta(base.id, &base.data, ObjectType::Blob, target);
+        let mut full = TestPackBuilder::new();
+        full.add_base(ObjectType::Blob, target);
+
+        let repo = MemoryRepository::default();
+        repo.odb().write_object(&base).unwrap();
+        let generous = DecodeLimits::unlimited()
+            .with_max_entry_size(target.len())
+            .with_max_delta_result_size(target.len());
+        let generator = PackGenerator::new(&repo).with_decode_limits(generous);
+        for pack in [builder.pack_bytes(), full.pack_bytes()] {
+            assert!(generator.unpack_stream(Bytes::from(pack)).await.is_ok());
+        }
+
+        for (limits, pack, expected) in [
+            (
+                generous.with_max_delta_result_size(target.len() - 1),
+                builder.pack_bytes(),
+                "delta result size",
+            ),
+            (
+                generous.with_max_entry_size(target.len() - 1),
+                full.pack_bytes(),
+                "entry size",
+            ),
+        ] {
+            let generator = PackGenerator::new(&repo).with_decode_limits(limits);
+            let err = generator
+                .unpack_stream(Bytes::from(pack))
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains(expected), "{err}");
+        }
+    }
 }
@@ -1,10 +1,11 @@
 //! Implementation of the Git smart protocol state machine, handling capability negotiation, pkt
 //! exchanges, authentication delegation, and bridging repository storage to transport streams.
 
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use bytes::{BufMut, Bytes, BytesMut};
-use tokio_stream::wrappers::ReceiverStream;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, UnboundedReceiverStream};
 
 use super::{
     core::{AuthenticationService, RepositoryAccess},
@@ -16,7 +17,10 @@ use super::{
     },
     utils::{add_pkt_line_string, build_smart_reply, read_pkt_line, read_until_white_space},
 };
-use crate::hash::{HashKind, ObjectHash, get_hash_kind};
+use crate::{
+    hash::{HashKind, ObjectHash, get_hash_kind},
+    internal::pack::{limits::DecodeLimits, progress::Progress},
+};
 /// Smart Git Protocol implementation
 ///
 /// This struct handles the Git smart protocol operations for both HTTP and SSH transports.
@@ -33,6 +37,8 @@ where
     pub wire_hash_kind: HashKind,
     pub local_hash_kind: HashKind,
     pub zero_id: String,
+    /// Limits applied to packs received by receive-pack.
+    decode_limits: DecodeLimits,
     // Trait-based dependencies
     repo_storage: R,
     auth_service: A,
@@ -49,6 +55,11 @@ where
         self.zero_id = ObjectHash::zero_str(kind);
     }
 
+    /// Set the limits applied to pushed packs ([`DecodeLimits::server`] by default)
+    pub fn set_decode_limits(&mut self, limits: DecodeLimits) {
+        self.decode_limits = limits;
+    }
+
     /// Create a new SmartProtocol instance
     pub fn new(transport_protocol: TransportProtocol, repo_storage: R, auth_service: A) -> Self {
         Self {
@@ -61,6 +72,7 @@ where
             wire_hash_kind: HashKind::default(), // Default to SHA-1
             local_hash_kind: get_hash_kind(),
             zero_id: ObjectHash::zero_str(HashKind::default()),
+            decode_limits: DecodeLimits::server(),
         }
     }
 
@@ -158,6 +170,41 @@ where
         &mut self,
         upload_request: Bytes,
     ) -> Result<(ReceiverStream<Vec<u8>>, BytesMut), ProtocolError> {
+        let (pack_stream, protocol_buf, _) = self.upload_pack_inner(upload_request, false).await?;
+        Ok((pack_stream, protocol_buf))
+    }
+
+    /// Handle git-upload-pack request, also returning Git-style progress lines ("Counting
+    /// objects: ...") for side-band channel 2.
+    ///
+    /// The progress stream is `None` unless the client negotiated `side-band` or `side-band-64k`
+    /// without `no-progress`. It ends once the pack has been encoded.
+    pub async fn git_upload_pack_with_progress(
+        &mut self,
+        upload_request: Bytes,
+    ) -> Result<
+        (
+            ReceiverStream<Vec<u8>>,
+            BytesMut,
+            Option<UnboundedReceiverStream<String>>,
+        ),
+        ProtocolError,
+    > {
+        self.upload_pack_inner(upload_request, true).await
+    }
+
+    async fn upload_pack_inner(
+        &mut self,
+        upload_request: Bytes,
+        want_progress: bool,
+    ) -> Result<
+        (
+            ReceiverStream<Vec<u8>>,
+            BytesMut,
+            Option<UnboundedReceiverStream<String>>,
+        ),
+        ProtocolError,
+    > {
         self.capabilities.clear();
         self.set_wire_hash_kind(self.local_hash_kind);
         let mut upload_request = upload_request;
@@ -206,13 +253,26 @@ where
         let mut protocol_buf = BytesMut::new();
 
         // Create pack generator for this operation
-        let pack_generator = PackGenerator::new(&self.repo_storage);
+        let mut pack_generator = PackGenerator::new(&self.repo_storage)
+            .with_ref_delta(!self.capabilities.contains(&Capability::OfsDelta))
+            .with_thin_pack(self.capabilities.contains(&Capability::ThinPack));
+        let mut progress_stream = None;
+        let side_band = self.capabilities.contains(&Capability::SideBand)
+            || self.capabilities.contains(&Capability::SideBand64k);
+        if want_progress && side_band && !self.capabilities.contains(&Capability::NoProgress) {
+            let (progress_tx, progress_rx) = mpsc::unbounded_channel();
+            pack_generator = pack_generator.with_progress(Arc::new(move |progress: &Progress| {
+                // The client may already be gone; progress is best effort.
+                let _ = progress_tx.send(progress.to_git_line());
+            }));
+            progress_stream = Some(UnboundedReceiverStream::new(progress_rx));
+        }
 
         if have.is_empty() {
             // Full pack
             add_pkt_line_string(&mut protocol_buf, String::from("NAK\n"));
             let pack_stream = pack_generator.generate_full_pack(want).await?;
-            return Ok((pack_stream, protocol_buf));
+            return Ok((pack_stream, protocol_buf, progress_stream));
         }
 
         // Check for common commits
@@ -232,7 +292,7 @@ where
             // No common commits found
             add_pkt_line_string(&mut protocol_buf, String::from("NAK\n"));
             let pack_stream = pack_generator.generate_full_pack(want).await?;
-            return Ok((pack_stream, protocol_buf));
+            return Ok((pack_stream, protocol_buf, progress_stream));
         }
 
         // Generate incremental pack
@@ -243,7 +303,7 @@ where
 
         let pack_stream = pack_generator.generate_incremental_pack(want, have).await?;
 
-        Ok((pack_stream, protocol_buf))
+        Ok((pack_stream, protocol_buf, progress_stream))
     }
 
     /// Parse receive pack commands from protocol bytes
@@ -341,7 +401,8 @@ where
 
         if let Some(pack_data) = pack_data {
             // Create pack generator for unpacking
-            let pack_generator = PackGenerator::new(&self.repo_storage);
+            let pack_generator =
+                PackGenerator::new(&self.repo_storage).with_decode_limits(self.decode_limits);
             // Unpack the received data
             let (commits, trees, blobs) = pack_generator.unpack_stream(pack_data).await?;
 
@@ -871,4 +932,44 @@ mod tests {
             .unwrap_err();
         assert!(matches!(err, ProtocolError::InvalidRequest(_)));
     }
+
+    /// Pushed packs are decoded under the protocol's decode limits.
+    #[tokio::test]
+    async fn receive_pack_applies_decode_limits() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let blob = Blob::from_content("over the limit");
+        let (pack_tx, mut pack_rx) = mpsc::channel(1024);
+        let (entry_tx, entry_rx) = mpsc::channel(1024);
+        let mut encoder = PackEncoder::new(1, 10, pack_tx);
+        tokio::spawn(async move { encoder.encode(entry_rx).await.unwrap() });
+        entry_tx
+            .send(MetaAttached {
+                inner: Entry::from(blob),
+                meta: EntryMeta::new(),
+            })
+            .await
+            .unwrap();
+        drop(entry_tx);
+        let mut pack_bytes = Vec::new();
+        while let Some(chunk) = pack_rx.recv().await {
+            pack_bytes.extend_from_slice(&chunk);
+        }
+
+        let mut smart =
+            SmartProtocol::new(TransportProtocol::Http, TestRepoAccess::new(), TestAuth);
+        smart.set_wire_hash_kind(HashKind::Sha1);
+        smart.set_decode_limits(DecodeLimits::server().with_max_objects(0));
+
+        let zero = ObjectHash::zero_str(HashKind::Sha1);
+        let mut request = BytesMut::new();
+        add_pkt_line_string(&mut request, format!("{zero} {zero} refs/heads/main\n"));
+        request.put(&PKT_LINE_END_MARKER[..]);
+        request.extend_from_slice(&pack_bytes);
+        let request_stream = Box::pin(futures::stream::once(async { Ok(request.freeze()) }));
+        let err = smart
+            .git_receive_pack_stream(request_stream)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("object count"), "{err}");
+    }
 }
@@ -0,0 +1,171 @@
+//! Header id and UUID link fields of AI workflow objects, for the `ai_objects` and `ai_links`
+//! tables.
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use crate::{
+    errors::GitError,
+    hash::ObjectHash,
+    internal::object::{
+        ObjectTrait,
+        context::ContextSnapshot,
+        context_frame::ContextFrame,
+        decision::Decision,
+        evidence::Evidence,
+        intent::Intent,
+        intent_event::IntentEvent,
+        patchset::PatchSet,
+        plan::Plan,
+        plan_step_event::PlanStepEvent,
+        provenance::Provenance,
+        run::Run,
+        run_event::RunEvent,
+        run_usage::RunUsage,
+        task::Task,
+        task_event::TaskEvent,
+        tool::ToolInvocation,
+        types::{Header, ObjectType},
+    },
+};
+
+/// What the index records about one AI object.
+pub(super) struct AiIndexEntry {
+    pub(super) object_id: Uuid,
+    pub(super) created_at: DateTime<Utc>,
+    /// (field name, target id) pairs; field names follow the objects' accessors.
+    pub(super) links: Vec<(&'static str, Uuid)>,
+}
+
+impl AiIndexEntry {
+    fn new(header: &Header) -> Self {
+        AiIndexEntry {
+            object_id: header.object_id(),
+            created_at: header.created_at(),
+            links: Vec::new(),
+        }
+    }
+
+    fn link(mut self, field: &'static str, target: impl IntoIterator<Item = Uuid>) -> Self {
+        self.links.extend(target.into_iter().map(|id| (field, id)));
+        self
+    }
+}
+
+/// Parse `data` as an AI object of `obj_type` and collect its index entry, or `None` for Git
+/// object types.
+pub(super) fn index_entry(
+    obj_type: ObjectType,
+    data: &[u8],
+) -> Result<Option<AiIndexEntry>, GitError> {
+    // AI objects ignore the hash they are parsed with.
+    let hash = ObjectHash::default();
+    let entry = match obj_type {
+        ObjectType::ContextSnapshot => {
+            AiIndexEntry::new(ContextSnapshot::from_bytes(data, hash)?.header())
+        }
+        ObjectType::ContextFrame => {
+            let frame = ContextFrame::from_bytes(data, hash)?;
+            AiIndexEntry::new(frame.header())
+                .link("intent_id", frame.intent_id())
+                .link("run_id", frame.run_id())
+                .link("plan_id", frame.plan_id())
+                .link("step_id", frame.step_id())
+        }
+        ObjectType::Decision => {
+            let decision = Decision::from_bytes(data, hash)?;
+            AiIndexEntry::new(decision.header())
+                .link("run_id", [decision.run_id()])
+                .link("chosen_patchset_id", decision.chosen_patchset_id())
+        }
+        ObjectType::Evidence => {
+            let evidence = Evidence::from_bytes(data, hash)?;
+            AiIndexEntry::new(evidence.header())
+                .link("run_id", [evidence.run_id()])
+                .link("patchset_id", evidence.patchset_id())
+        }
+        ObjectType::Intent => {
+            let intent = Intent::from_bytes(data, hash)?;
+            AiIndexEntry::new(intent.header())
+                .link("parents", intent.parents().iter().copied())
+                .link(
+                    "analysis_context_frames",
+                    intent.analysis_context_frames().iter().copied(),
+                )
+        }
+        ObjectType::IntentEvent => {
+            let event = IntentEvent::from_bytes(data, hash)?;
+            AiIndexEntry::new(event.header())
+                .link("intent_id", [event.intent_id()])
+                .link("next_intent_id", event.next_intent_id())
+        }
+        ObjectType::PatchSet => {
+            let patchset = PatchSet::from_bytes(data, hash)?;
+            AiIndexEntry::new(patchset.header()).link("run", [patchset.run()])
+        }
+        ObjectType::Plan => {
+            let plan = Plan::from_bytes(data, hash)?;
+            AiIndexEntry::new(plan.header())
+                .link("intent", [plan.intent()])
+                .link("parents", plan.parents().iter().copied())
+                .link("context_frames", plan.context_frames().iter().copied())
+        }
+        ObjectType::PlanStepEvent => {
+            let event = PlanStepEvent::from_bytes(data, hash)?;
+            AiIndexEntry::new(event.header())
+                .link("plan_id", [event.plan_id()])
+                .link("step_id", [event.step_id()])
+                .link("run_id", [event.run_id()])
+                .link("consumed_frames", event.consumed_frames().iter().copied())
+                .link("produced_frames", event.produced_frames().iter().copied())
+                .link("spawned_task_id", event.spawned_task_id())
+        }
+        ObjectType::Provenance => {
+            let provenance = Provenance::from_bytes(data, hash)?;
+            AiIndexEntry::new(provenance.header()).link("run_id", [provenance.run_id()])
+        }
+        ObjectType::Run => {
+            let run = Run::from_bytes(data, hash)?;
+            AiIndexEntry::new(run.header())
+                .link("task", [run.task()])
+                .link("plan", run.plan())
+                .link("snapshot", run.snapshot())
+        }
+        ObjectType::RunEvent => {
+            let event = RunEvent::from_bytes(data, hash)?;
+            AiIndexEntry::new(event.header())
+                .link("run_id", [event.run_id()])
+                .link("patchset_id", event.patchset_id())
+        }
+        ObjectType::RunUsage => {
+            let usage = RunUsage::from_bytes(data, hash)?;
+            AiIndexEntry::new(usage.header()).link("run_id", [usage.run_id()])
+        }
+        ObjectType::Task => {
+            let task = Task::from_bytes(data, hash)?;
+            AiIndexEntry::new(task.header())
+                .link("parent", task.parent())
+                .link("intent", task.intent())
+                .link("origin_step_id", task.origin_step_id())
+                .link("dependencies", task.dependencies().iter().copied())
+        }
+        ObjectType::TaskEvent => {
+            let event = TaskEvent::from_bytes(data, hash)?;
+            AiIndexEntry::new(event.header())
+                .link("task_id", [event.task_id()])
+                .link("run_id", event.run_id())
+        }
+        ObjectType::ToolInvocation => {
+            let invocation = ToolInvocation::from_bytes(data, hash)?;
+            AiIndexEntry::new(invocation.header()).link("run_id", [invocation.run_id()])
+        }
+        ObjectType::Commit
+        | ObjectType::Tree
+        | ObjectType::Blob
+        | ObjectType::Tag
+        | ObjectType::OffsetZstdelta
+        | ObjectType::OffsetDelta
+        | ObjectType::HashDelta => return Ok(None),
+    };
+    Ok(Some(entry))
+}
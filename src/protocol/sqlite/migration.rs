@@ -0,0 +1,184 @@
+//! Versioned schema for [`SqliteRepository`](super::SqliteRepository).
+//!
+//! Each migration runs once, in its own transaction, and is recorded in `schema_migrations`.
+//! Migrations are append-only: released ones are never edited, changes get a new version.
+
+use chrono::Utc;
+use sea_orm::{
+    ActiveValue::Set,
+    ConnectionTrait, DbBackend, DbErr, EntityTrait, Statement, TransactionTrait,
+    sea_query::{ColumnDef, Index, Table},
+};
+
+use super::entity::{ai_link, ai_object, object, reference, schema_migration};
+
+/// One schema change.
+pub struct Migration {
+    /// Position in the schema history, starting at 1.
+    pub version: i64,
+    /// Short description, stored alongside the version.
+    pub name: &'static str,
+    statements: fn(DbBackend) -> Vec<Statement>,
+}
+
+/// Every migration, oldest first.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create objects and refs",
+        statements: objects_and_refs,
+    },
+    Migration {
+        version: 2,
+        name: "create ai objects and links",
+        statements: ai_objects_and_links,
+    },
+];
+
+/// Bring the schema of `db` up to date, applying the migrations it has not seen yet. Returns the
+/// number applied.
+pub async fn migrate<C: ConnectionTrait + TransactionTrait>(db: &C) -> Result<usize, DbErr> {
+    let backend = db.get_database_backend();
+    db.execute(
+        backend.build(
+            Table::create()
+                .table(schema_migration::Entity)
+                .if_not_exists()
+                .col(
+                    ColumnDef::new(schema_migration::Column::Version)
+                        .big_integer()
+                        .not_null()
+                        .primary_key(),
+                )
+                .col(
+                    ColumnDef::new(schema_migration::Column::Name)
+                        .string()
+                        .not_null(),
+                )
+                .col(
+                    ColumnDef::new(schema_migration::Column::AppliedAt)
+                        .timestamp_with_time_zone()
+                        .not_null(),
+                ),
+        ),
+    )
+    .await?;
+
+    let applied: Vec<i64> = schema_migration::Entity::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|m| m.version)
+        .collect();
+    let mut count = 0;
+    for migration in MIGRATIONS {
+        if applied.contains(&migration.version) {
+            continue;
+        }
+        let txn = db.begin().await?;
+        for statement in (migration.statements)(backend) {
+            txn.execute(statement).await?;
+        }
+        schema_migration::Entity::insert(schema_migration::ActiveModel {
+            version: Set(migration.version),
+            name: Set(migration.name.to_string()),
+            applied_at: Set(Utc::now()),
+        })
+        .exec_without_returning(&txn)
+        .await?;
+        txn.commit().await?;
+        tracing::debug!(
+            "applied migration {} ({})",
+            migration.version,
+            migration.name
+        );
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn objects_and_refs(backend: DbBackend) -> Vec<Statement> {
+    vec![
+        backend.build(
+            Table::create()
+                .table(object::Entity)
+                .col(
+                    ColumnDef::new(object::Column::Hash)
+                        .string()
+                        .not_null()
+                        .primary_key(),
+                )
+                .col(ColumnDef::new(object::Column::ObjType).string().not_null())
+                .col(
+                    ColumnDef::new(object::Column::Size)
+                        .big_integer()
+                        .not_null(),
+                )
+                .col(ColumnDef::new(object::Column::Data).blob().not_null()),
+        ),
+        backend.build(
+            Table::create()
+                .table(reference::Entity)
+                .col(
+                    ColumnDef::new(reference::Column::Name)
+                        .string()
+                        .not_null()
+                        .primary_key(),
+                )
+                .col(ColumnDef::new(reference::Column::Hash).string().not_null()),
+        ),
+    ]
+}
+
+fn ai_objects_and_links(backend: DbBackend) -> Vec<Statement> {
+    vec![
+        backend.build(
+            Table::create()
+                .table(ai_object::Entity)
+                .col(
+                    ColumnDef::new(ai_object::Column::ObjectId)
+                        .uuid()
+                        .not_null()
+                        .primary_key(),
+                )
+                .col(
+                    ColumnDef::new(ai_object::Column::ObjType)
+                        .string()
+                        .not_null(),
+                )
+                .col(ColumnDef::new(ai_object::Column::Hash).string().not_null())
+                .col(
+                    ColumnDef::new(ai_object::Column::CreatedAt)
+                        .timestamp_with_time_zone()
+                        .not_null(),
+                ),
+        ),
+        backend.build(
+            Index::create()
+                .name("idx_ai_objects_type")
+                .table(ai_object::Entity)
+                .col(ai_object::Column::ObjType)
+                .col(ai_object::Column::CreatedAt),
+        ),
+        backend.build(
+            Table::create()
+                .table(ai_link::Entity)
+                .col(ColumnDef::new(ai_link::Column::SourceId).uuid().not_null())
+                .col(ColumnDef::new(ai_link::Column::Field).string().not_null())
+                .col(ColumnDef::new(ai_link::Column::TargetId).uuid().not_null())
+                .primary_key(
+                    Index::create()
+                        .col(ai_link::Column::SourceId)
+                        .col(ai_link::Column::Field)
+                        .col(ai_link::Column::TargetId),
+                ),
+        ),
+        backend.build(
+            Index::create()
+                .name("idx_ai_links_target")
+                .table(ai_link::Entity)
+                .col(ai_link::Column::TargetId)
+                .col(ai_link::Column::Field),
+        ),
+    ]
+}
@@ -0,0 +1,932 @@
+//! [`RepositoryAccess`] backed by SQLite through sea-orm.
+//!
+//! A [`SqliteRepository`] keeps everything a server needs in one database: Git and AI objects
+//! (zlib-compressed, by id), refs with compare-and-swap updates, and an index of the AI workflow
+//! objects by header id and by the UUIDs they link to. The schema is created and upgraded by the
+//! [`migration`]s when the repository is opened.
+
+mod ai;
+pub mod entity;
+pub mod migration;
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Cursor, Read, Write},
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue::Set, ColumnTrait, ConnectionTrait, Database, DatabaseConnection,
+    DbErr, EntityTrait, IntoActiveModel, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect,
+    TransactionTrait, TryInsertResult, sea_query::Expr,
+};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use self::entity::{ai_link, ai_object, object, reference};
+use super::{core::RepositoryAccess, odb::object_references, types::ProtocolError};
+use crate::{
+    errors::GitError,
+    hash::{HashKind, ObjectHash, get_hash_kind, set_hash_kind},
+    internal::{
+        metadata::{EntryMeta, MetaAttached},
+        object::{ObjectTrait, blob::Blob, commit::Commit, tree::Tree, types::ObjectType},
+        odb::hash_object,
+        pack::{
+            Pack, cancel::CancellationToken, entry::Entry, limits::DecodeLimits, thin::ThinPackScan,
+        },
+    },
+};
+
+/// Rows per multi-row insert, well below SQLite's limit on bound parameters.
+const INSERT_BATCH: usize = 200;
+/// Ids per `IN (...)` lookup, also below that limit.
+const LOOKUP_BATCH: usize = 500;
+/// Objects per transaction when storing a received pack.
+const WRITE_BATCH: usize = 1000;
+
+/// A repository stored in a SQLite database.
+///
+/// Cloning is cheap; clones share the connection pool.
+#[derive(Clone, Debug)]
+pub struct SqliteRepository {
+    db: DatabaseConnection,
+    kind: HashKind,
+    decode_limits: DecodeLimits,
+}
+
+impl SqliteRepository {
+    /// Open the database file at `path`, creating it if needed, and migrate it.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, GitError> {
+        Self::connect(&format!("sqlite://{}?mode=rwc", path.as_ref().display())).await
+    }
+
+    /// Connect to the database at `url`, e.g. `sqlite::memory:`, and migrate it.
+    pub async fn connect(url: &str) -> Result<Self, GitError> {
+        let db = Database::connect(url).await.map_err(database_error)?;
+        Self::from_connection(db).await
+    }
+
+    /// Use an existing connection, migrating its schema. Objects are addressed with the current
+    /// thread's hash kind.
+    pub async fn from_connection(db: DatabaseConnection) -> Result<Self, GitError> {
+        migration::migrate(&db).await.map_err(database_error)?;
+        Ok(SqliteRepository {
+            db,
+            kind: get_hash_kind(),
+            decode_limits: DecodeLimits::server(),
+        })
+    }
+
+    /// Set the limits applied to received packs ([`DecodeLimits::server`] by default).
+    pub fn with_decode_limits(mut self, limits: DecodeLimits) -> Self {
+        self.decode_limits = limits;
+        self
+    }
+
+    /// The underlying connection.
+    pub fn connection(&self) -> &DatabaseConnection {
+        &self.db
+    }
+
+    /// Hash kind of the stored objects.
+    pub fn hash_kind(&self) -> HashKind {
+        self.kind
+    }
+
+    /// Whether the object `hash` is stored.
+    pub async fn contains(&self, hash: &ObjectHash) -> Result<bool, GitError> {
+        Ok(object::Entity::find_by_id(hash.to_string())
+            .count(&self.db)
+            .await
+            .map_err(database_error)?
+            > 0)
+    }
+
+    /// Type and size of the object `hash`, without decompressing it.
+    pub async fn read_header(&self, hash: &ObjectHash) -> Result<(ObjectType, usize), GitError> {
+        let (obj_type, size): (String, i64) = object::Entity::find_by_id(hash.to_string())
+            .select_only()
+            .columns([object::Column::ObjType, object::Column::Size])
+            .into_tuple()
+            .one(&self.db)
+            .await
+            .map_err(database_error)?
+            .ok_or_else(|| GitError::ObjectNotFound(hash.to_string()))?;
+        Ok((ObjectType::from_string(&obj_type)?, size as usize))
+    }
+
+    /// Type and content of the object `hash`.
+    pub async fn read(&self, hash: &ObjectHash) -> Result<(ObjectType, Vec<u8>), GitError> {
+        let row = object::Entity::find_by_id(hash.to_string())
+            .one(&self.db)
+            .await
+            .map_err(database_error)?
+            .ok_or_else(|| GitError::ObjectNotFound(hash.to_string()))?;
+        inflate_row(&row, hash)
+    }
+
+    /// Store an object and return its id. AI objects are indexed as well.
+    pub async fn write(&self, obj_type: ObjectType, data: &[u8]) -> Result<ObjectHash, GitError> {
+        let mut hashes = self.write_all(vec![(obj_type, data.to_vec())]).await?;
+        Ok(hashes.pop().unwrap())
+    }
+
+    /// Serialize and store a Git or AI object.
+    pub async fn write_object(&self, object: &impl ObjectTrait) -> Result<ObjectHash, GitError> {
+        self.write(object.get_type(), &object.to_data()?).await
+    }
+
+    /// Store objects in one transaction and return their ids, in order. Objects already stored
+    /// are left alone.
+    pub async fn write_all(
+        &self,
+        objects: Vec<(ObjectType, Vec<u8>)>,
+    ) -> Result<Vec<ObjectHash>, GitError> {
+        let mut hashes = Vec::with_capacity(objects.len());
+        let mut object_rows = Vec::with_capacity(objects.len());
+        let mut ai_rows = Vec::new();
+        let mut link_rows = Vec::new();
+        for (obj_type, data) in &objects {
+            let hash = hash_object(self.kind, *obj_type, data)?;
+            if let Some(entry) = ai::index_entry(*obj_type, data)? {
+                link_rows.extend(
+                    entry
+                        .links
+                        .iter()
+                        .map(|(field, target)| ai_link::ActiveModel {
+                            source_id: Set(entry.object_id),
+                            field: Set(field.to_string()),
+                            target_id: Set(*target),
+                        }),
+                );
+                ai_rows.push(ai_object::ActiveModel {
+                    object_id: Set(entry.object_id),
+                    obj_type: Set(obj_type.to_string()),
+                    hash: Set(hash.to_string()),
+                    created_at: Set(entry.created_at),
+                });
+            }
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data)?;
+            object_rows.push(object::ActiveModel {
+                hash: Set(hash.to_string()),
+                obj_type: Set(obj_type.to_string()),
+                size: Set(data.len() as i64),
+                data: Set(encoder.finish()?),
+            });
+            hashes.push(hash);
+        }
+
+        let txn = self.db.begin().await.map_err(database_error)?;
+        insert_ignoring_existing(&txn, object_rows).await?;
+        insert_ignoring_existing(&txn, ai_rows).await?;
+        insert_ignoring_existing(&txn, link_rows).await?;
+        txn.commit().await.map_err(database_error)?;
+        Ok(hashes)
+    }
+
+    /// The AI object with header id `object_id`, if stored.
+    pub async fn read_ai_object<T: ObjectTrait>(
+        &self,
+        object_id: Uuid,
+    ) -> Result<Option<T>, GitError> {
+        let Some(row) = ai_object::Entity::find_by_id(object_id)
+            .one(&self.db)
+            .await
+            .map_err(database_error)?
+        else {
+            return Ok(None);
+        };
+        let hash = ObjectHash::from_str(&row.hash).map_err(GitError::InvalidHashValue)?;
+        let (_, data) = self.read(&hash).await?;
+        Ok(Some(T::from_bytes(&data, hash)?))
+    }
+
+    /// Header ids of the AI objects whose link `field` points at `target`, e.g. the runs of a
+    /// task via `("task", task_id)`. Field names follow the objects' accessors. Sorted by id,
+    /// which for v7 UUIDs is creation order.
+    pub async fn linked_ai_objects(
+        &self,
+        field: &str,
+        target: Uuid,
+    ) -> Result<Vec<Uuid>, GitError> {
+        ai_link::Entity::find()
+            .select_only()
+            .column(ai_link::Column::SourceId)
+            .filter(ai_link::Column::TargetId.eq(target))
+            .filter(ai_link::Column::Field.eq(field))
+            .order_by_asc(ai_link::Column::SourceId)
+            .into_tuple()
+            .all(&self.db)
+            .await
+            .map_err(database_error)
+    }
+
+    /// All refs as (full name, target) pairs, sorted by name.
+    pub async fn refs(&self) -> Result<Vec<(String, ObjectHash)>, GitError> {
+        reference::Entity::find()
+            .order_by_asc(reference::Column::Name)
+            .all(&self.db)
+            .await
+            .map_err(database_error)?
+            .into_iter()
+            .map(|row| {
+                let hash = ObjectHash::from_str(&row.hash).map_err(GitError::InvalidHashValue)?;
+                Ok((row.name, hash))
+            })
+            .collect()
+    }
+
+    /// Point `name` at `new`, or delete it when `new` is `None`, with the semantics of
+    /// [`RefStore::update`](super::odb::RefStore::update): the ref must currently be at `old`,
+    /// or not exist when `old` is `None`.
+    pub async fn update_ref(
+        &self,
+        name: &str,
+        old: Option<&ObjectHash>,
+        new: Option<&ObjectHash>,
+    ) -> Result<(), GitError> {
+        // Each branch is a single conditional statement, so concurrent updates cannot interleave.
+        let updated = match (old, new) {
+            (None, Some(new)) => {
+                let result = reference::Entity::insert(reference::ActiveModel {
+                    name: Set(name.to_string()),
+                    hash: Set(new.to_string()),
+                })
+                .on_conflict_do_nothing()
+                .exec_without_returning(&self.db)
+                .await
+                .map_err(database_error)?;
+                matches!(result, TryInsertResult::Inserted(1))
+            }
+            (Some(old), Some(new)) => {
+                reference::Entity::update_many()
+                    .col_expr(reference::Column::Hash, Expr::value(new.to_string()))
+                    .filter(reference::Column::Name.eq(name))
+                    .filter(reference::Column::Hash.eq(old.to_string()))
+                    .exec(&self.db)
+                    .await
+                    .map_err(database_error)?
+                    .rows_affected
+                    == 1
+            }
+            (Some(old), None) => {
+                reference::Entity::delete_many()
+                    .filter(reference::Column::Name.eq(name))
+                    .filter(reference::Column::Hash.eq(old.to_string()))
+                    .exec(&self.db)
+                    .await
+                    .map_err(database_error)?
+                    .rows_affected
+                    == 1
+            }
+            (None, None) => false,
+        };
+        if updated {
+            return Ok(());
+        }
+        let current = reference::Entity::find_by_id(name)
+            .one(&self.db)
+            .await
+            .map_err(database_error)?;
+        Err(GitError::RefUpdateRejected(match current {
+            Some(current) => format!("{name} is at {}", current.hash),
+            None => format!("{name} does not exist"),
+        }))
+    }
+
+    /// Every object reachable from `wants` but not from `haves`, in traversal order, like
+    /// [`OdbRepository::reachable_objects`](super::odb::OdbRepository::reachable_objects).
+    pub async fn reachable_objects(
+        &self,
+        wants: &[ObjectHash],
+        haves: &[ObjectHash],
+    ) -> Result<Vec<ObjectHash>, GitError> {
+        let mut known_haves = Vec::new();
+        for chunk in haves.chunks(LOOKUP_BATCH) {
+            let stored = self.object_types(chunk).await?;
+            known_haves.extend(chunk.iter().filter(|have| stored.contains_key(have)));
+        }
+        let mut excluded = HashSet::new();
+        self.walk(&known_haves, &mut excluded).await?;
+        self.walk(wants, &mut excluded).await
+    }
+
+    /// Breadth-first walk from `roots`, skipping objects in `seen` and adding the rest to it.
+    /// Each level is read with a few queries per [`LOOKUP_BATCH`] objects rather than one per
+    /// object.
+    async fn walk(
+        &self,
+        roots: &[ObjectHash],
+        seen: &mut HashSet<ObjectHash>,
+    ) -> Result<Vec<ObjectHash>, GitError> {
+        let mut found = Vec::new();
+        let mut level = roots.to_vec();
+        while !level.is_empty() {
+            level.retain(|hash| seen.insert(*hash));
+            found.extend_from_slice(&level);
+            let mut next = Vec::new();
+            for chunk in level.chunks(LOOKUP_BATCH) {
+                next.extend(self.references_of(chunk).await?);
+            }
+            level = next;
+        }
+        Ok(found)
+    }
+
+    /// Types of the stored objects among `hashes`.
+    async fn object_types(
+        &self,
+        hashes: &[ObjectHash],
+    ) -> Result<HashMap<ObjectHash, ObjectType>, GitError> {
+        let rows: Vec<(String, String)> = object::Entity::find()
+            .select_only()
+            .columns([object::Column::Hash, object::Column::ObjType])
+            .filter(object::Column::Hash.is_in(hashes.iter().map(ObjectHash::to_string)))
+            .into_tuple()
+            .all(&self.db)
+            .await
+            .map_err(database_error)?;
+        rows.into_iter()
+            .map(|(hash, obj_type)| {
+                let hash = ObjectHash::from_str(&hash).map_err(GitError::InvalidHashValue)?;
+                Ok((hash, ObjectType::from_string(&obj_type)?))
+            })
+            .collect()
+    }
+
+    /// What the objects `hashes` point to, in order, as
+    /// [`object_references`] lists them. All of them must be stored.
+    async fn references_of(&self, hashes: &[ObjectHash]) -> Result<Vec<ObjectHash>, GitError> {
+        let types = self.object_types(hashes).await?;
+        let mut containers = Vec::new();
+        for hash in hashes {
+            match types.get(hash) {
+                None => return Err(GitError::ObjectNotFound(hash.to_string())),
+                // Blobs lead nowhere; skip loading and decompressing them.
+                Some(ObjectType::Blob) => {}
+                Some(_) => containers.push(*hash),
+            }
+        }
+        if containers.is_empty() {
+            return Ok(Vec::new());
+        }
+        let rows: HashMap<String, object::Model> = object::Entity::find()
+            .filter(object::Column::Hash.is_in(containers.iter().map(ObjectHash::to_string)))
+            .all(&self.db)
+            .await
+            .map_err(database_error)?
+            .into_iter()
+            .map(|row| (row.hash.clone(), row))
+            .collect();
+        let mut references = Vec::new();
+        for hash in containers {
+            let row = rows
+                .get(&hash.to_string())
+                .ok_or_else(|| GitError::ObjectNotFound(hash.to_string()))?;
+            let (obj_type, data) = inflate_row(row, &hash)?;
+            references.extend(object_references(obj_type, &data, hash)?);
+        }
+        Ok(references)
+    }
+
+    fn parse_hash(&self, hash: &str) -> Result<ObjectHash, ProtocolError> {
+        let parsed = ObjectHash::from_str(hash)
+            .map_err(|e| ProtocolError::invalid_request(&format!("Invalid hash {hash}: {e}")))?;
+        if parsed.kind() != self.kind {
+            return Err(ProtocolError::invalid_request(&format!(
+                "{hash} is not a {} object id",
+                self.kind
+            )));
+        }
+        Ok(parsed)
+    }
+
+    /// The REF_DELTA bases of a scanned pack that the database has, to complete a thin pack
+    /// with. Bases it lacks are left for the pack itself to provide.
+    async fn thin_pack_bases(&self, scan: &ThinPackScan) -> Result<Vec<Entry>, GitError> {
+        let mut bases = Vec::new();
+        for chunk in scan.ref_delta_bases().chunks(LOOKUP_BATCH) {
+            let stored = self.object_types(chunk).await?;
+            for hash in chunk.iter().filter(|hash| stored.contains_key(hash)) {
+                let (obj_type, data) = self.read(hash).await?;
+                bases.push(Entry {
+                    obj_type,
+                    data,
+                    hash: *hash,
+                    chain_len: 0,
+                });
+            }
+        }
+        Ok(bases)
+    }
+}
+
+/// Decompress a stored object and check its size.
+fn inflate_row(row: &object::Model, hash: &ObjectHash) -> Result<(ObjectType, Vec<u8>), GitError> {
+    let mut data = Vec::with_capacity(row.size as usize);
+    ZlibDecoder::new(row.data.as_slice()).read_to_end(&mut data)?;
+    if data.len() as i64 != row.size {
+        return Err(GitError::InvalidObjectInfo(format!(
+            "object {hash} has {} bytes, expected {}",
+            data.len(),
+            row.size
+        )));
+    }
+    Ok((ObjectType::from_string(&row.obj_type)?, data))
+}
+
+/// Insert `rows`, skipping those whose primary key is already present.
+async fn insert_ignoring_existing<A, C>(db: &C, rows: Vec<A>) -> Result<(), GitError>
+where
+    A: ActiveModelTrait,
+    <A::Entity as EntityTrait>::Model: IntoActiveModel<A>,
+    C: ConnectionTrait,
+{
+    let mut rows = rows.into_iter().peekable();
+    while rows.peek().is_some() {
+        A::Entity::insert_many(rows.by_ref().take(INSERT_BATCH))
+            .on_conflict_do_nothing()
+            .exec_without_returning(db)
+            .await
+            .map_err(database_error)?;
+    }
+    Ok(())
+}
+
+fn database_error(e: DbErr) -> GitError {
+    GitError::DatabaseError(e.to_string())
+}
+
+fn storage_error(e: GitError) -> ProtocolError {
+    match e {
+        GitError::ObjectNotFound(hash) => ProtocolError::ObjectNotFound(hash),
+        e => ProtocolError::repository_error(e.to_string()),
+    }
+}
+
+#[async_trait]
+impl RepositoryAccess for SqliteRepository {
+    async fn get_repository_refs(&self) -> Result<Vec<(String, String)>, ProtocolError> {
+        Ok(self
+            .refs()
+            .await
+            .map_err(storage_error)?
+            .into_iter()
+            .map(|(name, hash)| (name, hash.to_string()))
+            .collect())
+    }
+
+    async fn has_object(&self, object_hash: &str) -> Result<bool, ProtocolError> {
+        let hash = self.parse_hash(object_hash)?;
+        self.contains(&hash).await.map_err(storage_error)
+    }
+
+    async fn get_object(&self, object_hash: &str) -> Result<Vec<u8>, ProtocolError> {
+        let hash = self.parse_hash(object_hash)?;
+        let (_, data) = self.read(&hash).await.map_err(storage_error)?;
+        Ok(data)
+    }
+
+    /// Decode `pack_data` on the blocking pool and store its objects as they come, one
+    /// [`write_all`](SqliteRepository::write_all) transaction per [`WRITE_BATCH`] objects. Thin
+    /// packs are completed from the database.
+    async fn store_pack_data(&self, pack_data: &[u8]) -> Result<(), ProtocolError> {
+        let kind = self.kind;
+        let limits = self.decode_limits;
+        let pack_data = pack_data.to_vec();
+        let scan = tokio::task::spawn_blocking(move || {
+            set_hash_kind(kind);
+            ThinPackScan::new(pack_data, limits)
+        })
+        .await
+        .map_err(|e| ProtocolError::repository_error(format!("Pack scan failed: {e}")))?
+        .map_err(|e| ProtocolError::Pack(format!("Failed to scan pack: {e}")))?;
+        let bases = self.thin_pack_bases(&scan).await.map_err(storage_error)?;
+
+        let (tx, mut rx) = mpsc::channel::<Vec<(ObjectType, Vec<u8>)>>(2);
+        let cancellation = CancellationToken::new();
+        let token = cancellation.clone();
+        let decode = tokio::task::spawn_blocking(move || {
+            set_hash_kind(kind);
+            let pack_data = scan.complete(&bases)?;
+            drop(bases);
+            let batch = Arc::new(Mutex::new(Vec::with_capacity(WRITE_BATCH)));
+            let pending = batch.clone();
+            let sender = tx.clone();
+            let mut pack = Pack::new(None, None, None, true)
+                .with_decode_limits(limits)
+                .with_cancellation(token.clone());
+            pack.decode(
+                &mut Cursor::new(pack_data),
+                move |entry: MetaAttached<Entry, EntryMeta>| {
+                    let full = {
+                        let mut batch = pending.lock().unwrap();
+                        batch.push((entry.inner.obj_type, entry.inner.data));
+                        (batch.len() >= WRITE_BATCH).then(|| std::mem::take(&mut *batch))
+                    };
+                    // The receiver only goes away when storing failed; stop decoding then.
+                    if let Some(full) = full
+                        && sender.blocking_send(full).is_err()
+                    {
+                        token.cancel();
+                    }
+                },
+                None::<fn(ObjectHash)>,
+            )?;
+            let rest = std::mem::take(&mut *batch.lock().unwrap());
+            if !rest.is_empty() {
+                let _ = tx.blocking_send(rest);
+            }
+            Ok::<_, GitError>(())
+        });
+
+        let mut stored = Ok(());
+        while let Some(objects) = rx.recv().await {
+            if let Err(e) = self.write_all(objects).await {
+                cancellation.cancel();
+                stored = Err(e);
+                break;
+            }
+        }
+        drop(rx);
+        let decoded = decode
+            .await
+            .map_err(|e| ProtocolError::repository_error(format!("Pack decode failed: {e}")))?;
+        stored.map_err(storage_error)?;
+        decoded.map_err(|e| ProtocolError::Pack(format!("Failed to decode pack: {e}")))
+    }
+
+    /// A zero `new_hash` deletes the ref.
+    async fn update_reference(
+        &self,
+        ref_name: &str,
+        old_hash: Option<&str>,
+        new_hash: &str,
+    ) -> Result<(), ProtocolError> {
+        let old = old_hash.map(|hash| self.parse_hash(hash)).transpose()?;
+        let new = self.parse_hash(new_hash)?;
+        let new = (new_hash != ObjectHash::zero_str(new.kind())).then_some(new);
+        self.update_ref(ref_name, old.as_ref(), new.as_ref())
+            .await
+            .map_err(storage_error)
+    }
+
+    async fn get_objects_for_pack(
+        &self,
+        wants: &[String],
+        haves: &[String],
+    ) -> Result<Vec<String>, ProtocolError> {
+        let wants = wants
+            .iter()
+            .map(|hash| self.parse_hash(hash))
+            .collect::<Result<Vec<_>, _>>()?;
+        let haves = haves
+            .iter()
+            .map(|hash| self.parse_hash(hash))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self
+            .reachable_objects(&wants, &haves)
+            .await
+            .map_err(storage_error)?
+            .iter()
+            .map(ObjectHash::to_string)
+            .collect())
+    }
+
+    async fn has_default_branch(&self) -> Result<bool, ProtocolError> {
+        let branches = reference::Entity::find()
+            .filter(reference::Column::Name.starts_with("refs/heads/"))
+            .count(&self.db)
+            .await
+            .map_err(|e| storage_error(database_error(e)))?;
+        Ok(branches > 0)
+    }
+
+    async fn post_receive_hook(&self) -> Result<(), ProtocolError> {
+        Ok(())
+    }
+
+    /// Write the objects straight to the database, in one transaction.
+    async fn handle_pack_objects(
+        &self,
+        commits: Vec<Commit>,
+        trees: Vec<Tree>,
+        blobs: Vec<Blob>,
+    ) -> Result<(), ProtocolError> {
+        let mut objects = Vec::with_capacity(commits.len() + trees.len() + blobs.len());
+        for blob in &blobs {
+            objects.push((ObjectType::Blob, blob.to_data().map_err(storage_error)?));
+        }
+        for tree in &trees {
+            objects.push((ObjectType::Tree, tree.to_data().map_err(storage_error)?));
+        }
+        for commit in &commits {
+            objects.push((ObjectType::Commit, commit.to_data().map_err(storage_error)?));
+        }
+        self.write_all(objects).await.map_err(storage_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use sea_orm::{EntityTrait, PaginatorTrait};
+    use tempfile::tempdir;
+
+    use super::{
+        SqliteRepository, WRITE_BATCH,
+        entity::{object, schema_migration},
+        migration,
+    };
+    use crate::{
+        errors::GitError,
+        hash::{HashKind, ObjectHash, set_hash_kind_for_test},
+        internal::{
+            object::{
+                ObjectTrait,
+                blob::Blob,
+                commit::Commit,
+                run::Run,
+                task::Task,
+                types::{ActorRef, ObjectType},
+            },
+            pack::{limits::DecodeLimits, test_pack_builder::TestPackBuilder},
+        },
+        protocol::{RepositoryAccess, pack::PackGenerator, test_history::TestHistory},
+    };
+
+    /// Two commits; returns (first, second, blob of the second tree).
+    async fn history(repo: &SqliteRepository) -> (Commit, Commit, Blob) {
+        let TestHistory {
+            first,
+            second,
+            trees,
+            blobs,
+        } = TestHistory::new();
+        repo.handle_pack_objects(
+            vec![first.clone(), second.clone()],
+            trees.to_vec(),
+            blobs.to_vec(),
+        )
+        .await
+        .unwrap();
+        let [_, new] = blobs;
+        (first, second, new)
+    }
+
+    /// Reopening a database applies no migration twice.
+    #[tokio::test]
+    async fn test_migrations_are_recorded() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("repo.db");
+        let repo = SqliteRepository::open(&path).await.unwrap();
+        assert_eq!(migration::migrate(repo.connection()).await.unwrap(), 0);
+        drop(repo);
+
+        let repo = SqliteRepository::open(&path).await.unwrap();
+        let applied = schema_migration::Entity::find()
+            .all(repo.connection())
+            .await
+            .unwrap();
+        assert_eq!(
+            applied.iter().map(|m| m.version).collect::<Vec<_>>(),
+            migration::MIGRATIONS
+                .iter()
+                .map(|m| m.version)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    /// Objects, compare-and-swap refs and the have/want walk, persisted across reopening.
+    #[tokio::test]
+    async fn test_sqlite_repository_access() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("repo.db");
+        let repo = SqliteRepository::open(&path).await.unwrap();
+        let (c1, c2, new_blob) = history(&repo).await;
+        let zero = ObjectHash::zero_str(HashKind::Sha1);
+
+        assert!(!repo.has_default_branch().await.unwrap());
+        repo.update_reference("refs/heads/main", None, &c1.id.to_string())
+            .await
+            .unwrap();
+        assert!(
+            repo.update_reference("refs/heads/main", None, &c2.id.to_string())
+                .await
+                .is_err()
+        );
+        assert!(matches!(
+            repo.update_ref("refs/heads/main", Some(&c2.id), Some(&c1.id))
+                .await,
+            Err(GitError::RefUpdateRejected(_))
+        ));
+        repo.update_reference(
+            "refs/heads/main",
+            Some(&c1.id.to_string()),
+            &c2.id.to_string(),
+        )
+        .await
+        .unwrap();
+        repo.update_reference("refs/tags/v1", None, &c1.id.to_string())
+            .await
+            .unwrap();
+        drop(repo);
+
+        let repo = SqliteRepository::open(&path).await.unwrap();
+        assert!(repo.has_default_branch().await.unwrap());
+        assert_eq!(
+            repo.get_repository_refs().await.unwrap(),
+            vec![
+                ("refs/heads/main".to_string(), c2.id.to_string()),
+                ("refs/tags/v1".to_string(), c1.id.to_string()),
+            ]
+        );
+        assert_eq!(
+            repo.read_header(&new_blob.id).await.unwrap(),
+            (ObjectType::Blob, 3)
+        );
+        assert_eq!(
+            repo.get_object(&c2.id.to_string()).await.unwrap(),
+            c2.to_data().unwrap()
+        );
+        assert!(repo.get_object(&"ab".repeat(20)).await.is_err());
+
+        let incremental = repo
+            .get_objects_for_pack(&[c2.id.to_string()], &[c1.id.to_string()])
+            .await
+            .unwrap();
+        assert_eq!(
+            incremental,
+            vec![
+                c2.id.to_string(),
+                c2.tree_id.to_string(),
+                new_blob.id.to_string()
+            ]
+        );
+
+        repo.update_reference("refs/tags/v1", Some(&c1.id.to_string()), &zero)
+            .await
+            .unwrap();
+        assert!(
+            repo.update_reference("refs/tags/v1", Some(&c1.id.to_string()), &zero)
+                .await
+                .is_err()
+        );
+        assert_eq!(repo.get_repository_refs().await.unwrap().len(), 1);
+    }
+
+    /// A pack generated from one database is stored into another.
+    #[tokio::test]
+    async fn test_sqlite_repository_store_pack_data() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let dir = tempdir().unwrap();
+        let source = SqliteRepository::open(dir.path().join("source.db"))
+            .await
+            .unwrap();
+        let (_, c2, _) = history(&source).await;
+
+        let mut stream = PackGenerator::new(&source)
+            .generate_full_pack(vec![c2.id.to_string()])
+            .await
+            .unwrap();
+        let mut pack = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            pack.extend(chunk);
+        }
+
+        let target = SqliteRepository::open(dir.path().join("target.db"))
+            .await
+            .unwrap();
+        target.store_pack_data(&pack).await.unwrap();
+        let all = target
+            .get_objects_for_pack(&[c2.id.to_string()], &[])
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 6);
+        assert_eq!(target.read(&c2.id).await.unwrap().1, c2.to_data().unwrap());
+        assert!(target.store_pack_data(b"PACK").await.is_err());
+    }
+
+    /// Packs larger than one write batch are stored completely, thin bases come from the
+    /// database, and a missing base fails the push.
+    #[tokio::test]
+    async fn test_sqlite_store_pack_data_in_batches() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let repo = SqliteRepository::connect("sqlite::memory:").await.unwrap();
+        let base = repo.write(ObjectType::Blob, b"stored base").await.unwrap();
+
+        let mut builder = TestPackBuilder::new();
+        for i in 0..WRITE_BATCH * 2 + 7 {
+            builder.add_base(ObjectType::Blob, format!("blob {i}").as_bytes());
+        }
+        let thin = builder.add_ref_delta(
+            base,
+            b"stored base",
+            ObjectType::Blob,
+            b"stored base, edited",
+        );
+        repo.store_pack_data(&builder.pack_bytes()).await.unwrap();
+        assert_eq!(
+            object::Entity::find()
+                .count(repo.connection())
+                .await
+                .unwrap(),
+            (WRITE_BATCH * 2 + 9) as u64
+        );
+        assert_eq!(
+            repo.read(&thin.hash).await.unwrap().1,
+            b"stored base, edited"
+        );
+
+        let empty = SqliteRepository::connect("sqlite::memory:").await.unwrap();
+        assert!(empty.store_pack_data(&builder.pack_bytes()).await.is_err());
+    }
+
+    /// Received packs are decoded under the repository's limits.
+    #[tokio::test]
+    async fn test_sqlite_store_pack_data_applies_decode_limits() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let mut builder = TestPackBuilder::new();
+        for i in 0..3 {
+            builder.add_base(ObjectType::Blob, format!("blob {i}").as_bytes());
+        }
+        let pack = builder.pack_bytes();
+
+        let repo = SqliteRepository::connect("sqlite::memory:")
+            .await
+            .unwrap()
+            .with_decode_limits(DecodeLimits::server().with_max_objects(2));
+        assert!(repo.store_pack_data(&pack).await.is_err());
+        assert_eq!(
+            object::Entity::find()
+                .count(repo.connection())
+                .await
+                .unwrap(),
+            0
+        );
+
+        let repo = repo.with_decode_limits(DecodeLimits::server().with_max_objects(3));
+        repo.store_pack_data(&pack).await.unwrap();
+    }
+
+    /// AI objects are found by header id and by the ids they link to.
+    #[tokio::test]
+    async fn test_ai_objects_indexed_by_links() {
+        let _guard = set_hash_kind_for_test(HashKind::Sha1);
+        let dir = tempdir().unwrap();
+        let repo = SqliteRepository::open(dir.path().join("repo.db"))
+            .await
+            .unwrap();
+        let actor = ActorRef::agent("test-agent").unwrap();
+        let task = Task::new(actor.clone(), "Refactor", None).unwrap();
+        let commit = "a".repeat(64);
+        let first = Run::new(actor.clone(), task.header().object_id(), &commit).unwrap();
+        let second = Run::new(actor, task.header().object_id(), &commit).unwrap();
+
+        let task_hash = repo.write_object(&task).await.unwrap();
+        repo.write_object(&first).await.unwrap();
+        repo.write_object(&second).await.unwrap();
+        // Rewriting an object changes nothing.
+        repo.write_object(&second).await.unwrap();
+
+        assert_eq!(
+            repo.read_header(&task_hash).await.unwrap().0,
+            ObjectType::Task
+        );
+        let stored: Task = repo
+            .read_ai_object(task.header().object_id())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.header().object_id(), task.header().object_id());
+        assert!(
+            repo.read_ai_object::<Task>(uuid::Uuid::nil())
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let mut runs = vec![first.header().object_id(), second.header().object_id()];
+        runs.sort();
+        assert_eq!(
+            repo.linked_ai_objects("task", task.header().object_id())
+                .await
+                .unwrap(),
+            runs
+        );
+        assert!(
+            repo.linked_ai_objects("plan", task.header().object_id())
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
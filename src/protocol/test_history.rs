@@ -0,0 +1,59 @@
+//! Test helper: a two-commit history shared by the `RepositoryAccess` backend tests, which store
+//! it however their backend stores objects.
+
+use crate::{
+    hash::ObjectHash,
+    internal::object::{
+        blob::Blob,
+        commit::Commit,
+        signature::{Signature, SignatureType},
+        tree::{Tree, TreeItem, TreeItemMode},
+    },
+};
+
+/// A commit of `tree` on top of `parents`, by a fixed tester.
+pub(crate) fn commit(tree: &Tree, parents: Vec<ObjectHash>, message: &str) -> Commit {
+    let author = Signature::new(
+        SignatureType::Author,
+        "tester".to_string(),
+        "tester@example.com".to_string(),
+    );
+    let committer = Signature::new(
+        SignatureType::Committer,
+        "tester".to_string(),
+        "tester@example.com".to_string(),
+    );
+    Commit::new(author, committer, tree.id, parents, message)
+}
+
+/// Two commits that each hold one `file`: "old" in the first, "new" in the second.
+pub(crate) struct TestHistory {
+    pub first: Commit,
+    pub second: Commit,
+    /// The trees of the first and second commit.
+    pub trees: [Tree; 2],
+    /// The blobs of the first and second commit.
+    pub blobs: [Blob; 2],
+}
+
+impl TestHistory {
+    pub(crate) fn new() -> Self {
+        let blobs = [Blob::from_content("old"), Blob::from_content("new")];
+        let trees = blobs.each_ref().map(|blob| {
+            Tree::from_tree_items(vec![TreeItem::new(
+                TreeItemMode::Blob,
+                blob.id,
+                "file".to_string(),
+            )])
+            .unwrap()
+        });
+        let first = commit(&trees[0], vec![], "first");
+        let second = commit(&trees[1], vec![first.id], "second");
+        TestHistory {
+            first,
+            second,
+            trees,
+            blobs,
+        }
+    }
+}
@@ -380,10 +380,11 @@ pub const NUL: char = '\0';
 pub const PKT_LINE_END_MARKER: &[u8; 4] = b"0000";
 
 // Git protocol capability lists
-pub const RECEIVE_CAP_LIST: &str =
-    "report-status report-status-v2 delete-refs quiet atomic no-thin ";
+// receive-pack leaves out `no-thin`: pushed thin packs are completed with the repository's own
+// copies of their REF_DELTA bases before decoding.
+pub const RECEIVE_CAP_LIST: &str = "report-status report-status-v2 delete-refs quiet atomic ";
 pub const COMMON_CAP_LIST: &str = "side-band-64k ofs-delta agent=git-internal/0.1.0";
-pub const UPLOAD_CAP_LIST: &str = "multi_ack_detailed no-done include-tag ";
+pub const UPLOAD_CAP_LIST: &str = "multi_ack_detailed no-done include-tag thin-pack no-progress ";
 
 #[cfg(test)]
 mod tests {
@@ -89,7 +89,11 @@ impl HashAlgorithm {
     }
     /// Create a new hash algorithm instance based on the current hash kind.
     pub fn new() -> Self {
-        match get_hash_kind() {
+        Self::new_with_kind(get_hash_kind())
+    }
+    /// Create a new hash algorithm instance for an explicit hash kind.
+    pub fn new_with_kind(kind: HashKind) -> Self {
+        match kind {
             HashKind::Sha1 => HashAlgorithm::Sha1(Sha1::new()),
             HashKind::Sha256 => HashAlgorithm::Sha256(sha2::Sha256::new()),
         }
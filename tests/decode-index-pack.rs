@@ -2,8 +2,6 @@
 //! the originals for both SHA-1 and SHA-256 object formats.
 
 use std::{
-    collections::HashMap,
-    convert::TryInto,
     fs,
     io::BufReader,
     sync::{Arc, Mutex},
@@ -20,73 +18,12 @@ use git_internal::{
         pack::{
             Pack,
             entry::Entry,
-            pack_index::{IdxBuilder, IndexEntry},
+            pack_index::{IdxBuilder, IndexEntry, PackIndex},
         },
     },
 };
 use tokio::sync::mpsc;
 
-fn parse_idx_offsets(idx_bytes: &[u8], kind: HashKind) -> HashMap<Vec<u8>, u64> {
-    assert!(idx_bytes.len() >= 8, "idx too short");
-    assert_eq!(&idx_bytes[0..4], &[0xFF, 0x74, 0x4F, 0x63], "idx magic");
-    let version = u32::from_be_bytes(idx_bytes[4..8].try_into().unwrap());
-
-    assert_eq!(version, 2, "idx version must be 2 per pack-format spec");
-    let mut cursor = 8usize;
-
-    // Fanout
-    let mut fanout = [0u32; 256];
-    for i in 0..256 {
-        fanout[i] = u32::from_be_bytes(
-            idx_bytes[cursor + i * 4..cursor + i * 4 + 4]
-                .try_into()
-                .unwrap(),
-        );
-    }
-    cursor += 256 * 4;
-
-    let object_count = fanout[255] as usize;
-
-    let hash_len = kind.size();
-    let names_end = cursor + object_count * hash_len;
-    let names = &idx_bytes[cursor..names_end];
-    cursor = names_end;
-
-    // Skip CRCs
-    cursor += object_count * 4;
-
-    // Offsets table
-    let offsets_end = cursor + object_count * 4;
-    let offsets_bytes = &idx_bytes[cursor..offsets_end];
-    cursor = offsets_end;
-
-    let large_count = offsets_bytes
-        .chunks_exact(4)
-        .filter(|raw| u32::from_be_bytes((*raw).try_into().unwrap()) & 0x8000_0000 != 0)
-        .count();
-
-    let mut large_offsets = Vec::with_capacity(large_count);
-    for _ in 0..large_count {
-        let v = u64::from_be_bytes(idx_bytes[cursor..cursor + 8].try_into().unwrap());
-        large_offsets.push(v);
-        cursor += 8;
-    }
-
-    let mut map = HashMap::new();
-    for (i, raw) in offsets_bytes.chunks_exact(4).enumerate() {
-        let raw = u32::from_be_bytes(raw.try_into().unwrap());
-        let offset = if raw & 0x8000_0000 == 0 {
-            raw as u64
-        } else {
-            let idx = (raw & 0x7FFF_FFFF) as usize;
-            large_offsets[idx]
-        };
-        let hash = names[i * hash_len..(i + 1) * hash_len].to_vec();
-        map.insert(hash, offset);
-    }
-    map
-}
-
 type DecodePackResult = Result<(Vec<MetaAttached<Entry, EntryMeta>>, ObjectHash, usize), GitError>;
 
 fn decode_pack(filename: &str) -> DecodePackResult {
@@ -131,12 +68,13 @@ async fn roundtrip(filename: &str, kind: HashKind) -> Result<(), GitError> {
         idx_bytes.extend_from_slice(&chunk);
     }
 
-    let offsets_map = parse_idx_offsets(&idx_bytes, kind);
+    let index = PackIndex::from_bytes(idx_bytes)?;
+    assert_eq!(index.object_count(), count);
+    assert_eq!(index.pack_hash(), pack_hash);
     for meta in metas {
-        let hash = meta.inner.hash.to_data();
         let expected = meta.meta.pack_offset.expect("missing pack offset") as u64;
-        let actual = *offsets_map
-            .get(&hash)
+        let actual = index
+            .offset_of(&meta.inner.hash)
             .unwrap_or_else(|| panic!("hash missing in idx: {}", meta.inner.hash));
         assert_eq!(actual, expected, "offset mismatch for {}", meta.inner.hash);
     }
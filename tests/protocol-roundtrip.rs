@@ -0,0 +1,336 @@
+//! End-to-end clone, fetch and push through `GitProtocol`, with `MemoryRepository` on both sides.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::StreamExt;
+use git_internal::{
+    hash::{HashKind, ObjectHash, set_hash_kind_for_test},
+    internal::{
+        object::{
+            blob::Blob,
+            commit::Commit,
+            signature::{Signature, SignatureType},
+            tree::{Tree, TreeItem, TreeItemMode},
+        },
+        odb::ObjectDatabase,
+        pack::{limits::DecodeLimits, thin::ThinPackScan},
+    },
+    protocol::{
+        AuthenticationService, GitProtocol, MemoryRepository, ProtocolError, ProtocolStream,
+        RepositoryAccess, pack::PackGenerator, utils,
+    },
+};
+
+struct AllowAll;
+
+#[async_trait]
+impl AuthenticationService for AllowAll {
+    async fn authenticate_http(
+        &self,
+        _headers: &HashMap<String, String>,
+    ) -> Result<(), ProtocolError> {
+        Ok(())
+    }
+
+    async fn authenticate_ssh(
+        &self,
+        _username: &str,
+        _public_key: &[u8],
+    ) -> Result<(), ProtocolError> {
+        Ok(())
+    }
+}
+
+/// Write a commit with one file per `(name, content)` on top of `parents` into `repo`.
+fn write_commit(
+    repo: &MemoryRepository,
+    files: &[(&str, &str)],
+    parents: Vec<ObjectHash>,
+    message: &str,
+) -> Commit {
+    let mut items = Vec::new();
+    for (name, content) in files {
+        let blob = Blob::from_content(content);
+        repo.odb().write_object(&blob).unwrap();
+        items.push(TreeItem::new(TreeItemMode::Blob, blob.id, name.to_string()));
+    }
+    let tree = Tree::from_tree_items(items).unwrap();
+    repo.odb().write_object(&tree).unwrap();
+    let author = Signature::new(
+        SignatureType::Author,
+        "tester".to_string(),
+        "tester@example.com".to_string(),
+    );
+    let committer = Signature::new(
+        SignatureType::Committer,
+        "tester".to_string(),
+        "tester@example.com".to_string(),
+    );
+    let commit = Commit::new(author, committer, tree.id, parents, message);
+    repo.odb().write_object(&commit).unwrap();
+    commit
+}
+
+async fn collect(mut stream: ProtocolStream) -> Bytes {
+    let mut out = BytesMut::new();
+    while let Some(chunk) = stream.next().await {
+        out.extend_from_slice(&chunk.unwrap());
+    }
+    out.freeze()
+}
+
+/// Read pkt-lines until the flush or the end, as strings.
+fn pkt_lines(mut bytes: Bytes) -> Vec<String> {
+    let mut lines = Vec::new();
+    loop {
+        let (len, line) = utils::read_pkt_line(&mut bytes);
+        if len == 0 || line.is_empty() {
+            return lines;
+        }
+        lines.push(String::from_utf8(line.to_vec()).unwrap());
+    }
+}
+
+/// Run upload-pack on `server` for `wants`, announcing `haves` and requesting `caps` on the first
+/// want. Returns the ACK/NAK lines and the pack.
+async fn upload_pack(
+    server: &MemoryRepository,
+    caps: &str,
+    wants: &[ObjectHash],
+    haves: &[ObjectHash],
+) -> (Vec<String>, Bytes) {
+    let mut request = BytesMut::new();
+    for (i, want) in wants.iter().enumerate() {
+        let caps = if i == 0 { caps } else { "" };
+        utils::add_pkt_line_string(&mut request, format!("want {want}{caps}\n"));
+    }
+    for have in haves {
+        utils::add_pkt_line_string(&mut request, format!("have {have}\n"));
+    }
+    utils::add_pkt_line_string(&mut request, "done\n".to_string());
+
+    let mut protocol = GitProtocol::new(server.clone(), AllowAll);
+    let mut response = collect(protocol.upload_pack(&request).await.unwrap()).await;
+    let mut acks = Vec::new();
+    while !response.starts_with(b"PACK") {
+        let (_, line) = utils::read_pkt_line(&mut response);
+        acks.push(String::from_utf8(line.to_vec()).unwrap());
+    }
+    (acks, response)
+}
+
+/// Fetch `wants` from `server` into `client`, announcing `haves`. Returns the ACK/NAK lines.
+async fn fetch(
+    server: &MemoryRepository,
+    client: &MemoryRepository,
+    wants: &[ObjectHash],
+    haves: &[ObjectHash],
+) -> Vec<String> {
+    let (acks, pack) = upload_pack(server, " ofs-delta", wants, haves).await;
+    client.store_pack_data(&pack).await.unwrap();
+    acks
+}
+
+/// The capabilities behind the NUL on the first ref of an info/refs advertisement.
+fn advertised_capabilities(mut advertisement: Bytes) -> Vec<String> {
+    loop {
+        let (len, line) = utils::read_pkt_line(&mut advertisement);
+        assert_ne!(len, 0, "no capabilities advertised");
+        if let Some(pos) = line.iter().position(|b| *b == b'\0') {
+            return String::from_utf8(line[pos + 1..].to_vec())
+                .unwrap()
+                .split_whitespace()
+                .map(str::to_string)
+                .collect();
+        }
+    }
+}
+
+/// Push `new` to `refs/heads/main` of `server`, expecting the ref at `old`. Returns the
+/// report-status lines.
+async fn push(
+    server: &MemoryRepository,
+    client: &MemoryRepository,
+    old: Option<&ObjectHash>,
+    new: &ObjectHash,
+) -> Vec<String> {
+    let generator = PackGenerator::new(client);
+    let pack_stream = match old {
+        Some(old) => generator
+            .generate_incremental_pack(vec![new.to_string()], vec![old.to_string()])
+            .await
+            .unwrap(),
+        None => generator
+            .generate_full_pack(vec![new.to_string()])
+            .await
+            .unwrap(),
+    };
+    let pack: Vec<u8> = pack_stream.collect::<Vec<_>>().await.concat();
+
+    let zero = ObjectHash::zero_str(HashKind::Sha1);
+    let old = old.map_or(zero, |old| old.to_string());
+    let mut request = BytesMut::new();
+    utils::add_pkt_line_string(
+        &mut request,
+        format!("{old} {new} refs/heads/main\0report-status\n"),
+    );
+    request.put(&b"0000"[..]);
+    request.extend_from_slice(&pack);
+    let request: ProtocolStream = Box::pin(futures::stream::once(async { Ok(request.freeze()) }));
+
+    let mut protocol = GitProtocol::new(server.clone(), AllowAll);
+    pkt_lines(collect(protocol.receive_pack(request).await.unwrap()).await)
+}
+
+/// Clone a repository, push a commit back, and fetch it incrementally into a second clone.
+#[tokio::test]
+async fn clone_push_and_fetch() {
+    let _guard = set_hash_kind_for_test(HashKind::Sha1);
+    let server = MemoryRepository::default();
+    let c1 = write_commit(
+        &server,
+        &[("README.md", "hello\n"), ("main.rs", "fn main() {}\n")],
+        vec![],
+        "initial",
+    );
+    server
+        .update_reference("refs/heads/main", None, &c1.id.to_string())
+        .await
+        .unwrap();
+
+    let protocol = GitProtocol::new(server.clone(), AllowAll);
+    let advertised =
+        String::from_utf8(protocol.info_refs("git-upload-pack").await.unwrap()).unwrap();
+    assert!(advertised.contains(&format!("{} refs/heads/main", c1.id)));
+
+    // Clone.
+    let alice = MemoryRepository::default();
+    assert_eq!(fetch(&server, &alice, &[c1.id], &[]).await, vec!["NAK\n"]);
+    assert_eq!(
+        alice.odb().hashes().unwrap(),
+        server.odb().hashes().unwrap()
+    );
+    alice
+        .update_reference("refs/heads/main", None, &c1.id.to_string())
+        .await
+        .unwrap();
+    let bob = MemoryRepository::default();
+    fetch(&server, &bob, &[c1.id], &[]).await;
+
+    // Push a second commit.
+    let c2 = write_commit(
+        &alice,
+        &[
+            ("README.md", "hello, world\n"),
+            ("main.rs", "fn main() {}\n"),
+        ],
+        vec![c1.id],
+        "greet the world",
+    );
+    assert_eq!(
+        push(&server, &alice, Some(&c1.id), &c2.id).await,
+        vec!["unpack ok\n", "ok refs/heads/main"]
+    );
+    assert_eq!(
+        server.get_repository_refs().await.unwrap(),
+        vec![("refs/heads/main".to_string(), c2.id.to_string())]
+    );
+    assert_eq!(
+        alice.odb().hashes().unwrap(),
+        server.odb().hashes().unwrap()
+    );
+
+    // Fetch only what is new.
+    let acks = fetch(&server, &bob, &[c2.id], &[c1.id]).await;
+    assert_eq!(
+        acks,
+        vec![
+            format!("ACK {} common\n", c1.id),
+            format!("ACK {} ready\n", c1.id)
+        ]
+    );
+    assert_eq!(bob.odb().hashes().unwrap(), server.odb().hashes().unwrap());
+    assert_eq!(
+        bob.get_objects_for_pack(&[c2.id.to_string()], &[c1.id.to_string()])
+            .await
+            .unwrap()
+            .len(),
+        3
+    );
+}
+
+/// A push based on an outdated ref is refused and leaves the server ref alone.
+#[tokio::test]
+async fn push_with_stale_ref_is_rejected() {
+    let _guard = set_hash_kind_for_test(HashKind::Sha1);
+    let server = MemoryRepository::default();
+    let alice = MemoryRepository::default();
+    let c1 = write_commit(&alice, &[("a.txt", "a\n")], vec![], "first");
+    assert_eq!(
+        push(&server, &alice, None, &c1.id).await,
+        vec!["unpack ok\n", "ok refs/heads/main"]
+    );
+    assert!(server.has_default_branch().await.unwrap());
+
+    let bob = MemoryRepository::default();
+    fetch(&server, &bob, &[c1.id], &[]).await;
+    let c2 = write_commit(&alice, &[("a.txt", "alice\n")], vec![c1.id], "alice");
+    let c3 = write_commit(&bob, &[("a.txt", "bob\n")], vec![c1.id], "bob");
+    assert_eq!(
+        push(&server, &alice, Some(&c1.id), &c2.id).await[1],
+        "ok refs/heads/main"
+    );
+
+    // Bob still believes main is at c1, and creating it anew fails as well.
+    let report = push(&server, &bob, Some(&c1.id), &c3.id).await;
+    assert!(report[1].starts_with("ng refs/heads/main"), "{report:?}");
+    let report = push(&server, &bob, None, &c3.id).await;
+    assert!(report[1].starts_with("ng refs/heads/main"), "{report:?}");
+    assert_eq!(
+        server.get_repository_refs().await.unwrap(),
+        vec![("refs/heads/main".to_string(), c2.id.to_string())]
+    );
+}
+
+/// A fetch that negotiates the advertised `thin-pack` gets deltas against the objects it already
+/// has, and can still store the pack.
+#[tokio::test]
+async fn fetch_thin_pack_against_haves() {
+    let _guard = set_hash_kind_for_test(HashKind::Sha1);
+    let server = MemoryRepository::default();
+    let v1: String = (0..200)
+        .map(|i| format!("line {i} of the notes\n"))
+        .collect();
+    let v2 = v1.replace("line 100 ", "line one hundred ");
+    let c1 = write_commit(&server, &[("notes.txt", &v1)], vec![], "initial");
+    let c2 = write_commit(&server, &[("notes.txt", &v2)], vec![c1.id], "edit");
+    server
+        .update_reference("refs/heads/main", None, &c2.id.to_string())
+        .await
+        .unwrap();
+
+    let protocol = GitProtocol::new(server.clone(), AllowAll);
+    let advertisement = Bytes::from(protocol.info_refs("git-upload-pack").await.unwrap());
+    let caps = advertised_capabilities(advertisement);
+    assert!(caps.iter().any(|cap| cap == "thin-pack"), "{caps:?}");
+
+    let client = MemoryRepository::default();
+    fetch(&server, &client, &[c1.id], &[]).await;
+    let (_, pack) = upload_pack(&server, " ofs-delta thin-pack", &[c2.id], &[c1.id]).await;
+
+    let bases = ThinPackScan::new(pack.to_vec(), DecodeLimits::default())
+        .unwrap()
+        .ref_delta_bases()
+        .to_vec();
+    assert!(!bases.is_empty(), "expected REF_DELTA entries");
+    let have_objects = client.odb().hashes().unwrap();
+    assert!(bases.iter().all(|base| have_objects.contains(base)));
+
+    client.store_pack_data(&pack).await.unwrap();
+    assert_eq!(
+        client.odb().hashes().unwrap(),
+        server.odb().hashes().unwrap()
+    );
+}